@@ -7,7 +7,8 @@ use matrix_sdk::{
     utils::IntoRawStateEventContent,
 };
 use matrix_sdk_base::{
-    store::StoreConfig, BaseClient, RoomInfo, RoomState, SessionMeta, StateChanges, StateStore,
+    store::StoreConfig, BaseClient, RoomInfo, RoomMemberships, RoomState, SessionMeta,
+    StateChanges, StateStore,
 };
 use matrix_sdk_sqlite::SqliteStateStore;
 use matrix_sdk_test::{EventBuilder, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder};
@@ -15,7 +16,10 @@ use matrix_sdk_ui::{timeline::TimelineFocus, Timeline};
 use ruma::{
     api::client::membership::get_member_events,
     device_id,
-    events::room::member::{RoomMemberEvent, RoomMemberEventContent},
+    events::{
+        room::member::{RoomMemberEvent, RoomMemberEventContent},
+        AnySyncStateEvent,
+    },
     owned_room_id, owned_user_id,
     serde::Raw,
     user_id, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId,
@@ -111,6 +115,84 @@ pub fn receive_all_members_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+pub fn load_members_benchmark(c: &mut Criterion) {
+    const MEMBERS_IN_ROOM: usize = 20000;
+
+    let runtime = Builder::new_multi_thread().build().expect("Can't create runtime");
+    let room_id = owned_room_id!("!room:example.com");
+
+    let ev_builder = EventBuilder::new();
+    let member_content: Raw<RoomMemberEventContent> = json!({
+        "avatar_url": "mxc://example.org/SEsfnsuifSDFSSEF",
+        "displayname": "Alice Margatroid",
+        "membership": "join",
+    })
+    .into_raw_state_event_content()
+    .cast();
+
+    let mut changes = StateChanges::default();
+    changes.add_room(RoomInfo::new(&room_id, RoomState::Joined));
+    for i in 0..MEMBERS_IN_ROOM {
+        let user_id = OwnedUserId::try_from(format!("@user_{}:matrix.org", i)).unwrap();
+        let event: Raw<AnySyncStateEvent> = ev_builder
+            .make_state_event(
+                &user_id,
+                &room_id,
+                user_id.as_str(),
+                member_content.deserialize().unwrap(),
+                None,
+            )
+            .cast();
+        changes.add_state_event(&room_id, event.deserialize().unwrap(), event);
+    }
+
+    let sqlite_dir = tempfile::tempdir().unwrap();
+    let sqlite_store = runtime.block_on(SqliteStateStore::open(sqlite_dir.path(), None)).unwrap();
+    runtime
+        .block_on(sqlite_store.save_changes(&changes))
+        .expect("initial filling of sqlite failed");
+
+    let count = MEMBERS_IN_ROOM;
+    let mut group = c.benchmark_group("Test");
+    group.throughput(Throughput::Elements(count as u64));
+    group.sample_size(50);
+
+    for parallelism in [1, 4] {
+        let base_client = BaseClient::with_store_config(
+            StoreConfig::new()
+                .state_store(sqlite_store.clone())
+                .member_decode_parallelism(parallelism),
+        );
+
+        runtime
+            .block_on(base_client.set_session_meta(
+                SessionMeta {
+                    user_id: user_id!("@somebody:example.com").to_owned(),
+                    device_id: device_id!("DEVICE_ID").to_owned(),
+                },
+                None,
+            ))
+            .expect("Could not set session meta");
+
+        let room = base_client.get_room(&room_id).expect("the room should have been restored");
+
+        let name = format!("{count} members, {parallelism} threads");
+        group.bench_function(BenchmarkId::new("load_members", name), |b| {
+            b.to_async(&runtime).iter(|| async {
+                let members = room.members(RoomMemberships::JOIN).await.unwrap();
+                assert_eq!(members.len(), count);
+            });
+        });
+
+        {
+            let _guard = runtime.enter();
+            drop(base_client);
+        }
+    }
+
+    group.finish();
+}
+
 pub fn load_pinned_events_benchmark(c: &mut Criterion) {
     const PINNED_EVENTS_COUNT: usize = 100;
 
@@ -250,6 +332,6 @@ fn criterion() -> Criterion {
 criterion_group! {
     name = room;
     config = criterion();
-    targets = receive_all_members_benchmark, load_pinned_events_benchmark,
+    targets = receive_all_members_benchmark, load_members_benchmark, load_pinned_events_benchmark,
 }
 criterion_main!(room);
@@ -37,7 +37,7 @@ use matrix_sdk::{
             AnyInitialStateEvent, AnyToDeviceEvent, InitialStateEvent,
         },
         serde::Raw,
-        EventEncryptionAlgorithm, RoomId, TransactionId, UInt, UserId,
+        EventEncryptionAlgorithm, MilliSecondsSinceUnixEpoch, RoomId, TransactionId, UInt, UserId,
     },
     sliding_sync::Version as SdkSlidingSyncVersion,
     AuthApi, AuthSession, Client as MatrixClient, SessionChange, SessionTokens,
@@ -1332,6 +1332,9 @@ pub struct Session {
     ///
     /// [refreshing the access token]: https://spec.matrix.org/v1.3/client-server-api/#refreshing-access-tokens
     pub refresh_token: Option<String>,
+    /// The time at which the access token expires, in milliseconds since the
+    /// Unix epoch, if it does.
+    pub expires_at: Option<u64>,
     /// The user the access token was issued for.
     pub user_id: String,
     /// The ID of the client device.
@@ -1359,12 +1362,17 @@ impl Session {
                 let matrix_sdk::matrix_auth::MatrixSession {
                     meta: matrix_sdk::SessionMeta { user_id, device_id },
                     tokens:
-                        matrix_sdk::matrix_auth::MatrixSessionTokens { access_token, refresh_token },
+                        matrix_sdk::matrix_auth::MatrixSessionTokens {
+                            access_token,
+                            refresh_token,
+                            expires_at,
+                        },
                 } = a.session().context("Missing session")?;
 
                 Ok(Session {
                     access_token,
                     refresh_token,
+                    expires_at: expires_at.map(|ts| ts.0.into()),
                     user_id: user_id.to_string(),
                     device_id: device_id.to_string(),
                     homeserver_url,
@@ -1402,6 +1410,7 @@ impl Session {
                 Ok(Session {
                     access_token,
                     refresh_token,
+                    expires_at: None,
                     user_id: user_id.to_string(),
                     device_id: device_id.to_string(),
                     homeserver_url,
@@ -1420,6 +1429,7 @@ impl TryFrom<Session> for AuthSession {
         let Session {
             access_token,
             refresh_token,
+            expires_at,
             user_id,
             device_id,
             homeserver_url: _,
@@ -1460,6 +1470,10 @@ impl TryFrom<Session> for AuthSession {
             Ok(AuthSession::Oidc(session))
         } else {
             // Create a regular Matrix Session.
+            let expires_at = expires_at
+                .map(|ts| UInt::try_from(ts).map(MilliSecondsSinceUnixEpoch))
+                .transpose()
+                .context("The access token expiration time is invalid.")?;
             let session = matrix_sdk::matrix_auth::MatrixSession {
                 meta: matrix_sdk::SessionMeta {
                     user_id: user_id.try_into()?,
@@ -1468,6 +1482,7 @@ impl TryFrom<Session> for AuthSession {
                 tokens: matrix_sdk::matrix_auth::MatrixSessionTokens {
                     access_token,
                     refresh_token,
+                    expires_at,
                 },
             };
 
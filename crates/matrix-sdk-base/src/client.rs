@@ -14,9 +14,9 @@
 // limitations under the License.
 
 #[cfg(feature = "e2e-encryption")]
-use std::ops::Deref;
+use std::{collections::VecDeque, ops::Deref, sync::Mutex as StdMutex};
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet},
     fmt, iter,
     sync::Arc,
 };
@@ -26,6 +26,7 @@ use eyeball::{SharedObservable, Subscriber};
 use eyeball_im::{Vector, VectorDiff};
 #[cfg(not(target_arch = "wasm32"))]
 use futures_util::Stream;
+use matrix_sdk_common::executor::JoinHandle;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::{
     store::DynCryptoStore, CollectStrategy, EncryptionSettings, EncryptionSyncChanges, OlmError,
@@ -42,7 +43,9 @@ use ruma::{
     api::client as api,
     events::{
         ignored_user_list::IgnoredUserListEvent,
+        marked_unread::MarkedUnreadEventContent,
         push_rules::{PushRulesEvent, PushRulesEventContent},
+        relation::RelationType,
         room::{
             member::{MembershipState, RoomMemberEventContent, SyncRoomMemberEvent},
             power_levels::{
@@ -51,14 +54,17 @@ use ruma::{
         },
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
         AnySyncEphemeralRoomEvent, AnySyncMessageLikeEvent, AnySyncStateEvent,
-        AnySyncTimelineEvent, GlobalAccountDataEventType, StateEvent, StateEventType,
-        SyncStateEvent,
+        AnySyncTimelineEvent, EventContent as _, GlobalAccountDataEventType, StateEvent,
+        StateEventType, SyncStateEvent,
     },
     push::{Action, PushConditionRoomCtx, Ruleset},
     serde::Raw,
     time::Instant,
-    OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UInt, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
+    RoomVersionId, UInt, UserId,
 };
+use serde::Deserialize;
+use serde_json::value::RawValue as RawJsonValue;
 use tokio::sync::{broadcast, Mutex};
 #[cfg(feature = "e2e-encryption")]
 use tokio::sync::{RwLock, RwLockReadGuard};
@@ -67,9 +73,12 @@ use tracing::{debug, info, instrument, trace, warn};
 #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
 use crate::latest_event::{is_suitable_for_latest_event, LatestEvent, PossibleLatestEvent};
 #[cfg(feature = "e2e-encryption")]
-use crate::RoomMemberships;
+use crate::sync::DecryptedEvents;
 use crate::{
-    deserialized_responses::{RawAnySyncOrStrippedTimelineEvent, SyncTimelineEvent},
+    deserialized_responses::{
+        MemberEvent, RawAnySyncOrStrippedState, RawAnySyncOrStrippedTimelineEvent,
+        SyncTimelineEvent,
+    },
     error::{Error, Result},
     event_cache_store::DynEventCacheStore,
     rooms::{
@@ -77,13 +86,26 @@ use crate::{
         Room, RoomInfo, RoomState,
     },
     store::{
-        ambiguity_map::AmbiguityCache, DynStateStore, MemoryStore, Result as StoreResult,
-        StateChanges, StateStoreDataKey, StateStoreDataValue, StateStoreExt, Store, StoreConfig,
+        ambiguity_map::AmbiguityCache, CredentialBundle, DynStateStore, MemoryStore,
+        Result as StoreResult, RoomBundle, StateChanges, StateStoreDataKey, StateStoreDataValue,
+        StateStoreExt, Store, StoreConfig,
+    },
+    sync::{
+        JoinedRoomUpdate, LeftRoomUpdate, Notification, RoomUpdates, SyncResponse, Timeline,
+        UnreadNotificationsCount,
     },
-    sync::{JoinedRoomUpdate, LeftRoomUpdate, Notification, RoomUpdates, SyncResponse, Timeline},
-    RoomStateFilter, SessionMeta,
+    RoomMemberships, RoomStateFilter, SessionMeta,
 };
 
+/// The maximum number of events that couldn't be decrypted that are kept per
+/// room, waiting for their room key.
+#[cfg(feature = "e2e-encryption")]
+const MAX_UNDECRYPTABLE_EVENTS_PER_ROOM: usize = 100;
+
+/// Events that couldn't be decrypted, by room.
+#[cfg(feature = "e2e-encryption")]
+type UndecryptableEvents = BTreeMap<OwnedRoomId, VecDeque<Raw<AnySyncTimelineEvent>>>;
+
 /// A no IO Client implementation.
 ///
 /// This Client is a state machine that receives responses and events and
@@ -105,6 +127,10 @@ pub struct BaseClient {
     /// [`BaseClient::set_session_meta`]
     #[cfg(feature = "e2e-encryption")]
     olm_machine: Arc<RwLock<Option<OlmMachine>>>,
+    /// The timeline events that couldn't be decrypted when they were received,
+    /// to be retried in [`BaseClient::on_room_keys_received`].
+    #[cfg(feature = "e2e-encryption")]
+    undecryptable_events: Arc<StdMutex<UndecryptableEvents>>,
     /// Observable of when a user is ignored/unignored.
     pub(crate) ignore_user_list_changes: SharedObservable<Vec<String>>,
 
@@ -113,6 +139,12 @@ pub struct BaseClient {
     /// trigger a room list update.
     pub(crate) room_info_notable_update_sender: broadcast::Sender<RoomInfoNotableUpdate>,
 
+    /// Whether presence events received during sync are persisted.
+    store_presence: bool,
+
+    /// The maximum number of members whose member event is stored per room.
+    max_members_per_room: Option<usize>,
+
     /// The strategy to use for picking recipient devices, when sending an
     /// encrypted message.
     #[cfg(feature = "e2e-encryption")]
@@ -143,17 +175,31 @@ impl BaseClient {
     ///   previous login call.
     pub fn with_store_config(config: StoreConfig) -> Self {
         let (room_info_notable_update_sender, _room_info_notable_update_receiver) =
-            broadcast::channel(100);
+            broadcast::channel(config.room_info_notable_update_capacity);
 
         BaseClient {
-            store: Store::new(config.state_store),
+            store: Store::new(config.state_store)
+                .with_event_ttl(config.event_ttl)
+                .with_event_cache_store(config.event_cache_store.clone())
+                .with_member_decode_parallelism(config.member_decode_parallelism)
+                .with_event_verifier(config.event_verifier)
+                .with_maintenance_interval(config.maintenance_interval)
+                .with_compaction_threshold(config.compaction_threshold)
+                .with_open_check(config.open_check)
+                .with_open_timeout(config.open_timeout)
+                .with_check_member_indexes(config.check_member_indexes)
+                .with_max_background_tasks(config.max_background_tasks),
             event_cache_store: config.event_cache_store,
             #[cfg(feature = "e2e-encryption")]
             crypto_store: config.crypto_store,
             #[cfg(feature = "e2e-encryption")]
             olm_machine: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            undecryptable_events: Default::default(),
             ignore_user_list_changes: Default::default(),
             room_info_notable_update_sender,
+            store_presence: config.store_presence,
+            max_members_per_room: config.max_members_per_room,
             #[cfg(feature = "e2e-encryption")]
             room_key_recipient_strategy: Default::default(),
         }
@@ -163,7 +209,18 @@ impl BaseClient {
     /// different, in-memory store config, and resets transient state.
     #[cfg(feature = "e2e-encryption")]
     pub fn clone_with_in_memory_state_store(&self) -> Self {
-        let config = StoreConfig::new().state_store(MemoryStore::new());
+        let config = StoreConfig::new()
+            .state_store(MemoryStore::new())
+            .store_presence(self.store_presence)
+            .max_members_per_room(self.max_members_per_room)
+            .event_ttl(self.store.event_ttl)
+            .member_decode_parallelism(self.store.member_decode_parallelism)
+            .event_verifier(self.store.event_verifier.clone())
+            .maintenance_interval(self.store.maintenance_interval)
+            .compaction_threshold(self.store.compaction_threshold)
+            .open_check(self.store.open_check)
+            .open_timeout(self.store.open_timeout)
+            .check_member_indexes(self.store.check_member_indexes);
         let config = config.crypto_store(self.crypto_store.clone());
 
         let mut result = Self::with_store_config(config);
@@ -175,7 +232,18 @@ impl BaseClient {
     /// different, in-memory store config, and resets transient state.
     #[cfg(not(feature = "e2e-encryption"))]
     pub fn clone_with_in_memory_state_store(&self) -> Self {
-        let config = StoreConfig::new().state_store(MemoryStore::new());
+        let config = StoreConfig::new()
+            .state_store(MemoryStore::new())
+            .store_presence(self.store_presence)
+            .max_members_per_room(self.max_members_per_room)
+            .event_ttl(self.store.event_ttl)
+            .member_decode_parallelism(self.store.member_decode_parallelism)
+            .event_verifier(self.store.event_verifier.clone())
+            .maintenance_interval(self.store.maintenance_interval)
+            .compaction_threshold(self.store.compaction_threshold)
+            .open_check(self.store.open_check)
+            .open_timeout(self.store.open_timeout)
+            .check_member_indexes(self.store.check_member_indexes);
         Self::with_store_config(config)
     }
 
@@ -215,6 +283,123 @@ impl BaseClient {
         )
     }
 
+    /// Get the rooms that changed in the store after the given marker.
+    ///
+    /// Returns the IDs of the rooms that were written to since `seq`, and the
+    /// marker to pass to the next call to only get further changes. Passing
+    /// `0` returns all the rooms that changed since this client was created.
+    ///
+    /// All the writes are tracked, including the ones made directly through
+    /// [`BaseClient::store`]. The markers are only valid for the lifetime of
+    /// this client: they aren't persisted, so after a restart the markers of
+    /// the previous run must be dropped and all the rooms reloaded.
+    pub fn rooms_changed_since(&self, seq: u64) -> (Vec<OwnedRoomId>, u64) {
+        self.store.rooms_changed_since(seq)
+    }
+
+    /// Get the members of the given room that changed in the store after the
+    /// given marker.
+    ///
+    /// This works like [`BaseClient::rooms_changed_since`], with the same
+    /// markers, so a member list only needs to update the returned members.
+    /// Returns the member events of the changed members, the IDs of the
+    /// changed members that were removed from the store, like the ones
+    /// evicted by [`StoreConfig::max_members_per_room`], and the marker for
+    /// the next call. The member events saved directly through
+    /// [`BaseClient::store`] are reported too, and the markers don't survive a
+    /// restart either.
+    ///
+    /// [`StoreConfig::max_members_per_room`]: crate::store::StoreConfig::max_members_per_room
+    pub async fn members_changed_since(
+        &self,
+        room_id: &RoomId,
+        seq: u64,
+    ) -> Result<(Vec<MemberEvent>, Vec<OwnedUserId>, u64)> {
+        Ok(self.store.members_changed_since(room_id, seq).await?)
+    }
+
+    /// Get the users who sent one of the stored events of the given room.
+    ///
+    /// The senders are read from the state events of the room and from its
+    /// timeline in the event cache store, so that they can be suggested as
+    /// mentions without fetching the members of the room. Users who never
+    /// sent an event, or whose events aren't stored, are missing.
+    pub async fn get_room_senders(&self, room_id: &RoomId) -> Result<BTreeSet<OwnedUserId>> {
+        const TIMELINE_CHUNK_SIZE: usize = 100;
+
+        let mut senders: BTreeSet<OwnedUserId> = self
+            .store
+            .get_all_state_events(room_id)
+            .await?
+            .into_iter()
+            .filter_map(|raw| {
+                let sender = match raw {
+                    RawAnySyncOrStrippedState::Sync(raw) => raw.get_field::<OwnedUserId>("sender"),
+                    RawAnySyncOrStrippedState::Stripped(raw) => raw.get_field("sender"),
+                };
+                sender.ok().flatten()
+            })
+            .collect();
+
+        let mut from = 0;
+        loop {
+            let events =
+                self.event_cache_store.get_timeline(room_id, from, TIMELINE_CHUNK_SIZE).await?;
+            let count = events.len();
+
+            senders.extend(
+                events.into_iter().filter_map(|event| {
+                    event.event.get_field::<OwnedUserId>("sender").ok().flatten()
+                }),
+            );
+
+            if count < TIMELINE_CHUNK_SIZE {
+                break;
+            }
+            from += count as u64;
+        }
+
+        Ok(senders)
+    }
+
+    /// Remove the timeline events that are older than the configured
+    /// [`StoreConfig::event_ttl`] from the state store and the event cache
+    /// store.
+    ///
+    /// This is also done by the store maintenance, so this only needs to be
+    /// called by clients that don't enable it, or want expired events gone
+    /// sooner.
+    pub async fn prune_expired_events(&self) -> Result<()> {
+        Ok(self.store.prune_expired_events().await?)
+    }
+
+    /// Spawn the maintenance of the state store in a background task now.
+    ///
+    /// See [`StoreConfig::maintenance_interval`] for the cleanup steps it
+    /// runs. Returns `None` if the maintenance is already running. Otherwise
+    /// the returned handle resolves with the result of the maintenance once
+    /// it's done.
+    pub fn spawn_store_maintenance(&self) -> Option<JoinHandle<StoreResult<()>>> {
+        self.store.spawn_maintenance()
+    }
+
+    /// Register a handler to be called with every event of the given type,
+    /// once it has been saved in the store.
+    ///
+    /// The handler receives the ID of the event's room, if any, and the raw
+    /// event. It's called after the changes have been committed, so it doesn't
+    /// delay the store transaction, but it shouldn't block for long either.
+    ///
+    /// Only the events that are persisted are seen: state, stripped state,
+    /// account data, presence, redactions and edits.
+    pub fn add_event_type_handler(
+        &self,
+        event_type: impl fmt::Display,
+        handler: impl Fn(Option<&RoomId>, &RawJsonValue) + Send + Sync + 'static,
+    ) {
+        self.store.add_event_type_handler(event_type.to_string(), Arc::new(handler));
+    }
+
     /// Get a reference to the store.
     #[allow(unknown_lints, clippy::explicit_auto_deref)]
     pub fn store(&self) -> &DynStateStore {
@@ -301,6 +486,44 @@ impl BaseClient {
         self.store.sync_token.read().await.clone()
     }
 
+    /// Export the session of this client with the sync token and the IDs of
+    /// the given filters, to resume it with another store.
+    ///
+    /// The access token isn't known by the [`BaseClient`], it must be saved
+    /// separately.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter_names` - The names of the filters whose IDs are exported.
+    pub async fn export_credentials(&self, filter_names: &[&str]) -> Result<CredentialBundle> {
+        let mut bundle = self.store.export_credentials(filter_names).await?;
+        bundle.session_meta = self.session_meta().cloned();
+        Ok(bundle)
+    }
+
+    /// Import a [`CredentialBundle`] created with
+    /// [`BaseClient::export_credentials`] and restore its session.
+    ///
+    /// The next sync starts from the imported sync token instead of doing a
+    /// full initial sync.
+    ///
+    /// This method panics if a session was already restored, like
+    /// [`BaseClient::set_session_meta`].
+    pub async fn import_credentials(&self, bundle: &CredentialBundle) -> Result<()> {
+        self.store.import_credentials(bundle).await?;
+
+        if let Some(session_meta) = &bundle.session_meta {
+            self.set_session_meta(
+                session_meta.clone(),
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
     #[cfg(feature = "e2e-encryption")]
     async fn handle_verification_event(
         &self,
@@ -346,6 +569,49 @@ impl BaseClient {
         Ok(Some(event))
     }
 
+    /// Remember an event that couldn't be decrypted, so it can be retried once
+    /// its room key is received.
+    ///
+    /// Only the last [`MAX_UNDECRYPTABLE_EVENTS_PER_ROOM`] events are kept.
+    #[cfg(feature = "e2e-encryption")]
+    fn queue_undecryptable_event(&self, room_id: &RoomId, event: Raw<AnySyncTimelineEvent>) {
+        let mut undecryptable_events = self.undecryptable_events.lock().unwrap();
+        let events = undecryptable_events.entry(room_id.to_owned()).or_default();
+
+        if events.len() >= MAX_UNDECRYPTABLE_EVENTS_PER_ROOM {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Retry to decrypt the timeline events of the given room that couldn't be
+    /// decrypted when they were received.
+    ///
+    /// This is called by the sync when new room keys are received for the
+    /// room, and the decrypted events end up in
+    /// [`SyncResponse::decrypted_events`]. It can also be called when room
+    /// keys are received from elsewhere, for example from a key backup.
+    ///
+    /// Returns the events that could be decrypted. The others are kept for the
+    /// next call.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn on_room_keys_received(&self, room_id: &RoomId) -> Vec<SyncTimelineEvent> {
+        let Some(events) = self.undecryptable_events.lock().unwrap().remove(room_id) else {
+            return Vec::new();
+        };
+
+        let mut decrypted_events = Vec::new();
+
+        for event in events {
+            match self.decrypt_sync_room_event(&event, room_id).await {
+                Ok(Some(decrypted)) => decrypted_events.push(decrypted),
+                Ok(None) | Err(_) => self.queue_undecryptable_event(room_id, event),
+            }
+        }
+
+        decrypted_events
+    }
+
     #[allow(clippy::too_many_arguments)]
     #[instrument(skip_all, fields(room_id = ?room_info.room_id))]
     pub(crate) async fn handle_timeline(
@@ -363,12 +629,24 @@ impl BaseClient {
     ) -> Result<Timeline> {
         let mut timeline = Timeline::new(limited, prev_batch);
         let mut push_context = self.get_push_room_context(room, room_info, changes).await?;
+        let now = MilliSecondsSinceUnixEpoch::now();
 
         for event in events {
             let mut event: SyncTimelineEvent = event.into();
 
             match event.event.deserialize() {
                 Ok(e) => {
+                    if matches!(
+                        e,
+                        AnySyncTimelineEvent::MessageLike(
+                            AnySyncMessageLikeEvent::RoomMessage(_)
+                                | AnySyncMessageLikeEvent::RoomEncrypted(_)
+                        )
+                    ) && room.mark_recent_message(e.event_id())
+                    {
+                        room_info.recent_messages.record(e.origin_server_ts(), now);
+                    }
+
                     #[allow(clippy::single_match)]
                     match &e {
                         AnySyncTimelineEvent::State(s) => {
@@ -424,12 +702,17 @@ impl BaseClient {
                             AnySyncMessageLikeEvent::RoomEncrypted(
                                 SyncMessageLikeEvent::Original(_),
                             ) => {
-                                if let Ok(Some(e)) = Box::pin(
+                                match Box::pin(
                                     self.decrypt_sync_room_event(&event.event, room.room_id()),
                                 )
                                 .await
                                 {
-                                    event = e;
+                                    Ok(Some(e)) => event = e,
+                                    Ok(None) => (),
+                                    Err(_) => self.queue_undecryptable_event(
+                                        room.room_id(),
+                                        event.event.clone(),
+                                    ),
                                 }
                             }
                             AnySyncMessageLikeEvent::RoomMessage(
@@ -451,6 +734,10 @@ impl BaseClient {
                         AnySyncTimelineEvent::MessageLike(_) => (),
                     }
 
+                    if let Some(replaced_event_id) = replaced_event_id(&event.event) {
+                        changes.add_edit(room.room_id(), &replaced_event_id, event.event.clone());
+                    }
+
                     if let Some(context) = &mut push_context {
                         self.update_push_room_context(
                             context,
@@ -758,31 +1045,47 @@ impl BaseClient {
             OwnedRoomId,
             RoomInfoNotableUpdateReasons,
         >,
-    ) -> Result<Vec<Raw<ruma::events::AnyToDeviceEvent>>> {
-        if let Some(o) = self.olm_machine().await.as_ref() {
-            // Let the crypto machine handle the sync response, this
-            // decrypts to-device events, but leaves room events alone.
-            // This makes sure that we have the decryption keys for the room
-            // events at hand.
-            let (events, room_key_updates) =
-                o.receive_sync_changes(encryption_sync_changes).await?;
-
-            #[cfg(feature = "experimental-sliding-sync")]
-            for room_key_update in room_key_updates {
-                if let Some(room) = self.get_room(&room_key_update.room_id) {
-                    self.decrypt_latest_events(&room, changes, room_info_notable_updates).await;
-                }
-            }
-            #[cfg(not(feature = "experimental-sliding-sync"))]
-            drop(room_key_updates); // Silence unused variable warning
-
-            Ok(events)
-        } else {
+    ) -> Result<(Vec<Raw<ruma::events::AnyToDeviceEvent>>, DecryptedEvents)> {
+        let olm_machine = self.olm_machine().await;
+        let Some(o) = olm_machine.as_ref() else {
             // If we have no OlmMachine, just return the events that were passed in.
             // This should not happen unless we forget to set things up by calling
             // set_session_meta().
-            Ok(encryption_sync_changes.to_device_events)
+            return Ok((encryption_sync_changes.to_device_events, Default::default()));
+        };
+
+        // Let the crypto machine handle the sync response, this
+        // decrypts to-device events, but leaves room events alone.
+        // This makes sure that we have the decryption keys for the room
+        // events at hand.
+        let (events, room_key_updates) = o.receive_sync_changes(encryption_sync_changes).await?;
+        let room_ids: BTreeSet<OwnedRoomId> = room_key_updates
+            .iter()
+            .map(|room_key_update| room_key_update.room_id.clone())
+            .collect();
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        for room_key_update in room_key_updates {
+            if let Some(room) = self.get_room(&room_key_update.room_id) {
+                self.decrypt_latest_events(&room, changes, room_info_notable_updates).await;
+            }
+        }
+        #[cfg(not(feature = "experimental-sliding-sync"))]
+        drop(room_key_updates); // Silence unused variable warning
+
+        // The machine is locked while decrypting, release it first.
+        drop(olm_machine);
+
+        // Retry the events that were waiting for the keys that were just received.
+        let mut decrypted_events = DecryptedEvents::new();
+        for room_id in room_ids {
+            let events = self.on_room_keys_received(&room_id).await;
+            if !events.is_empty() {
+                decrypted_events.insert(room_id, events);
+            }
         }
+
+        Ok((events, decrypted_events))
     }
 
     /// Decrypt any of this room's latest_encrypted_events
@@ -929,7 +1232,7 @@ impl BaseClient {
             BTreeMap::<OwnedRoomId, RoomInfoNotableUpdateReasons>::new();
 
         #[cfg(feature = "e2e-encryption")]
-        let to_device = self
+        let (to_device, decrypted_events) = self
             .preprocess_to_device_events(
                 EncryptionSyncChanges {
                     to_device_events: response.to_device.events,
@@ -944,7 +1247,7 @@ impl BaseClient {
             .await?;
 
         #[cfg(not(feature = "e2e-encryption"))]
-        let to_device = response.to_device.events;
+        let (to_device, decrypted_events) = (response.to_device.events, Default::default());
 
         let mut ambiguity_cache = AmbiguityCache::new(self.store.inner.clone());
 
@@ -1163,24 +1466,53 @@ impl BaseClient {
             new_rooms.invite.insert(room_id, new_info);
         }
 
+        for (room_id, new_info) in response.rooms.knock {
+            let room = self.store.get_or_create_room(
+                &room_id,
+                RoomState::Knocked,
+                self.room_info_notable_update_sender.clone(),
+            );
+
+            let mut room_info = room.clone_info();
+            room_info.mark_as_knocked();
+            room_info.mark_state_fully_synced();
+
+            self.handle_invited_state(
+                &room,
+                &new_info.knock_state.events,
+                &push_rules,
+                &mut room_info,
+                &mut changes,
+                &mut notifications,
+            )
+            .await?;
+
+            changes.add_room(room_info);
+        }
+
         // TODO remove this, we're processing account data events here again
         // because we want to have the push rules in place before we process
         // rooms and their events, but we want to create the rooms before we
         // process the `m.direct` account data event.
         self.handle_account_data(&response.account_data.events, &mut changes).await;
 
-        changes.presence = response
-            .presence
-            .events
-            .iter()
-            .filter_map(|e| {
-                let event = e.deserialize().ok()?;
-                Some((event.sender, e.clone()))
-            })
-            .collect();
+        if self.store_presence {
+            changes.presence = response
+                .presence
+                .events
+                .iter()
+                .filter_map(|e| {
+                    let event = e.deserialize().ok()?;
+                    Some((event.sender, e.clone()))
+                })
+                .collect();
+        }
 
         changes.ambiguity_maps = ambiguity_cache.cache;
 
+        self.enforce_max_members_per_room(&mut changes).await?;
+        changes.last_sync_time = Some(MilliSecondsSinceUnixEpoch::now());
+
         {
             let _sync_lock = self.sync_lock().lock().await;
             self.store.save_changes(&changes).await?;
@@ -1203,6 +1535,7 @@ impl BaseClient {
             account_data: response.account_data.events,
             to_device,
             notifications,
+            decrypted_events,
         };
 
         Ok(response)
@@ -1340,12 +1673,137 @@ impl BaseClient {
         room_info.mark_members_synced();
         changes.add_room(room_info);
 
+        self.enforce_max_members_per_room(&mut changes).await?;
+
         self.store.save_changes(&changes).await?;
         self.apply_changes(&changes, Default::default());
 
         Ok(())
     }
 
+    /// Keep the number of stored members of the rooms of `changes` within
+    /// [`StoreConfig::max_members_per_room`].
+    ///
+    /// The member events of new members that aren't joined are dropped from
+    /// `changes` first. If that isn't enough, the stored members that aren't
+    /// part of `changes` are evicted from the store: the ones that aren't
+    /// joined first, then the least recently active ones, whose member event
+    /// is the oldest. The member event of the user of the client is never
+    /// evicted.
+    ///
+    /// The evicted members are only added to `changes`, with their profile and
+    /// display name, so they are removed along with the other changes.
+    pub(crate) async fn enforce_max_members_per_room(
+        &self,
+        changes: &mut StateChanges,
+    ) -> Result<()> {
+        let Some(max_members) = self.max_members_per_room else {
+            return Ok(());
+        };
+        let own_user_id = self.session_meta().map(|meta| meta.user_id.clone());
+
+        for (room_id, state) in &mut changes.state {
+            let Some(members) = state.get_mut(&StateEventType::RoomMember) else {
+                continue;
+            };
+
+            let stored: BTreeSet<String> = self
+                .store
+                .get_user_ids(room_id, RoomMemberships::empty())
+                .await?
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            let new_members: Vec<String> =
+                members.keys().filter(|state_key| !stored.contains(*state_key)).cloned().collect();
+
+            let mut member_count = stored.len() + new_members.len();
+
+            for state_key in new_members {
+                if member_count <= max_members {
+                    break;
+                }
+
+                let is_joined = members[&state_key]
+                    .deserialize_as::<SyncRoomMemberEvent>()
+                    .is_ok_and(|event| *event.membership() == MembershipState::Join);
+                if is_joined {
+                    continue;
+                }
+
+                members.remove(&state_key);
+                if let Some(profiles) = changes.profiles.get_mut(room_id) {
+                    profiles.retain(|user_id, _| user_id.as_str() != state_key);
+                }
+                member_count -= 1;
+            }
+
+            if member_count <= max_members {
+                continue;
+            }
+
+            let mut candidates = Vec::new();
+            for raw in self.store.get_state_events(room_id, StateEventType::RoomMember).await? {
+                let RawAnySyncOrStrippedState::Sync(raw) = raw else {
+                    continue;
+                };
+                let Ok(event) = raw.deserialize_as::<SyncRoomMemberEvent>() else {
+                    continue;
+                };
+
+                let user_id = event.state_key();
+                if members.contains_key(user_id.as_str()) || own_user_id.as_ref() == Some(user_id) {
+                    continue;
+                }
+
+                let is_joined = *event.membership() == MembershipState::Join;
+                candidates.push((is_joined, event.origin_server_ts(), user_id.clone(), event));
+            }
+
+            candidates.sort_by(|a, b| (a.0, a.1, &a.2).cmp(&(b.0, b.1, &b.2)));
+
+            for (_, _, user_id, event) in candidates {
+                if member_count <= max_members {
+                    break;
+                }
+
+                // The evicted member doesn't share its display name anymore.
+                if matches!(event.membership(), MembershipState::Join | MembershipState::Invite) {
+                    let display_name = match self.store.get_profile(room_id, &user_id).await? {
+                        Some(profile) => {
+                            profile.into_original().and_then(|p| p.content.displayname)
+                        }
+                        None => event.as_original().and_then(|e| e.content.displayname.clone()),
+                    }
+                    .unwrap_or_else(|| user_id.localpart().to_owned());
+
+                    let names = changes.ambiguity_maps.entry(room_id.clone()).or_default();
+                    let users = match names.entry(display_name) {
+                        btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                        btree_map::Entry::Vacant(entry) => {
+                            let users = self
+                                .store
+                                .get_users_with_display_name(room_id, entry.key())
+                                .await?;
+                            entry.insert(users)
+                        }
+                    };
+                    users.remove(&user_id);
+                }
+
+                changes
+                    .profiles_to_delete
+                    .entry(room_id.clone())
+                    .or_default()
+                    .push(user_id.clone());
+                changes.members_to_delete.entry(room_id.clone()).or_default().push(user_id);
+                member_count -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Receive a successful filter upload response, the filter id will be
     /// stored under the given name in the store.
     ///
@@ -1438,69 +1896,226 @@ impl BaseClient {
         self.store.room(room_id)
     }
 
-    /// Get the olm machine.
-    #[cfg(feature = "e2e-encryption")]
-    pub async fn olm_machine(&self) -> RwLockReadGuard<'_, Option<OlmMachine>> {
-        self.olm_machine.read().await
+    /// Export the given room as a [`RoomBundle`] serialized as JSON into
+    /// `writer`.
+    ///
+    /// The bundle contains the room's information, state events and member
+    /// profiles, but nothing about the current session, so it can be shared
+    /// for debugging purposes and loaded back with [`Self::import_room`].
+    ///
+    /// Returns `false` if the room is unknown, in which case nothing is
+    /// written.
+    pub async fn export_room(
+        &self,
+        room_id: &RoomId,
+        writer: impl std::io::Write,
+    ) -> StoreResult<bool> {
+        let Some(room) = self.get_room(room_id) else {
+            return Ok(false);
+        };
+
+        let bundle = RoomBundle::from_store(&*self.store, room.clone_info()).await?;
+        serde_json::to_writer(writer, &bundle)?;
+
+        Ok(true)
     }
 
-    /// Get the push rules.
+    /// Import a room previously exported with [`Self::export_room`] from
+    /// `reader`, and return it.
     ///
-    /// Gets the push rules from `changes` if they have been updated, otherwise
-    /// get them from the store. As a fallback, uses
-    /// `Ruleset::server_default` if the user is logged in.
-    pub async fn get_push_rules(&self, changes: &StateChanges) -> Result<Ruleset> {
-        if let Some(event) = changes
-            .account_data
-            .get(&GlobalAccountDataEventType::PushRules)
-            .and_then(|ev| ev.deserialize_as::<PushRulesEvent>().ok())
-        {
-            Ok(event.content.global)
-        } else if let Some(event) = self
-            .store
-            .get_account_data_event_static::<PushRulesEventContent>()
-            .await?
-            .and_then(|ev| ev.deserialize().ok())
-        {
-            Ok(event.content.global)
-        } else if let Some(session_meta) = self.store.session_meta() {
-            Ok(Ruleset::server_default(&session_meta.user_id))
-        } else {
-            Ok(Ruleset::new())
-        }
+    /// The info of the room, and the state events and member profiles of the
+    /// bundle replace the existing ones. The data of the room that isn't part
+    /// of the bundle, like the state events of the types that aren't exported
+    /// or the profiles of the members that aren't in it, is left as it is.
+    pub async fn import_room(&self, reader: impl std::io::Read) -> StoreResult<Room> {
+        let bundle: RoomBundle = serde_json::from_reader(reader)?;
+        let room_id = bundle.room_info.room_id.clone();
+        let room_state = bundle.room_info.state();
+        let changes = bundle.into_state_changes()?;
+
+        let room = self.store.get_or_create_room(
+            &room_id,
+            room_state,
+            self.room_info_notable_update_sender.clone(),
+        );
+
+        let _sync_lock = self.sync_lock().lock().await;
+        self.store.save_changes(&changes).await?;
+        self.apply_changes(&changes, Default::default());
+
+        Ok(room)
     }
 
-    /// Get the push context for the given room.
-    ///
-    /// Tries to get the data from `changes` or the up to date `room_info`.
-    /// Loads the data from the store otherwise.
+    /// Update the unread notification counts of many rooms at once.
     ///
-    /// Returns `None` if some data couldn't be found. This should only happen
-    /// in brand new rooms, while we process its state.
-    pub async fn get_push_room_context(
+    /// Only the counts of the rooms are changed, and all the rooms are saved
+    /// together. The rooms that aren't known by the client are ignored.
+    pub async fn update_notification_counts(
         &self,
-        room: &Room,
-        room_info: &RoomInfo,
-        changes: &StateChanges,
-    ) -> Result<Option<PushConditionRoomCtx>> {
-        let room_id = room.room_id();
-        let user_id = room.own_user_id();
+        counts: BTreeMap<OwnedRoomId, UnreadNotificationsCount>,
+    ) -> Result<()> {
+        let _sync_lock = self.sync_lock().lock().await;
 
-        let member_count = room_info.active_members_count();
+        let mut changes = StateChanges::default();
+        let mut updated_rooms = Vec::new();
 
-        // TODO: Use if let chain once stable
-        let user_display_name = if let Some(AnySyncStateEvent::RoomMember(member)) =
-            changes.state.get(room_id).and_then(|events| {
-                events.get(&StateEventType::RoomMember)?.get(user_id.as_str())?.deserialize().ok()
-            }) {
-            member
-                .as_original()
-                .and_then(|ev| ev.content.displayname.clone())
-                .unwrap_or_else(|| user_id.localpart().to_owned())
-        } else if let Some(AnyStrippedStateEvent::RoomMember(member)) =
-            changes.stripped_state.get(room_id).and_then(|events| {
-                events.get(&StateEventType::RoomMember)?.get(user_id.as_str())?.deserialize().ok()
-            })
+        for (room_id, room_counts) in counts {
+            let Some(room) = self.store.room(&room_id) else {
+                continue;
+            };
+
+            let mut room_info = room.clone_info();
+            room_info.update_notification_count(room_counts);
+            changes.add_room(room_info.clone());
+            updated_rooms.push((room, room_info));
+        }
+
+        self.store.save_changes(&changes).await?;
+
+        for (room, room_info) in updated_rooms {
+            room.set_room_info(room_info, RoomInfoNotableUpdateReasons::empty());
+        }
+
+        Ok(())
+    }
+
+    /// Save the "marked unread" flag of the given room, see
+    /// [`StateStoreExt::is_marked_unread`].
+    ///
+    /// The flag is stored as room account data, and on the info of the room if
+    /// the client knows it. This doesn't send the flag to the homeserver.
+    pub async fn set_marked_unread(&self, room_id: &RoomId, unread: bool) -> StoreResult<()> {
+        let content = MarkedUnreadEventContent::new(unread);
+        let raw: Raw<AnyRoomAccountDataEvent> = Raw::new(&serde_json::json!({
+            "type": content.event_type(),
+            "content": content,
+        }))?
+        .cast();
+
+        let _sync_lock = self.sync_lock().lock().await;
+
+        let mut changes = StateChanges::default();
+        changes.add_room_account_data(room_id, raw.deserialize()?, raw);
+
+        let mut updated_room = None;
+        if let Some(room) = self.store.room(room_id) {
+            let mut room_info = room.clone_info();
+            room_info.base_info.is_marked_unread = unread;
+            changes.add_room(room_info.clone());
+            updated_room = Some((room, room_info));
+        }
+
+        self.store.save_changes(&changes).await?;
+
+        if let Some((room, room_info)) = updated_room {
+            room.set_room_info(room_info, RoomInfoNotableUpdateReasons::UNREAD_MARKER);
+        }
+
+        Ok(())
+    }
+
+    /// Get the latest edit of the given event, i.e. the most recent event with
+    /// an `m.replace` relation to it, if any was received.
+    ///
+    /// Edits are persisted in the state store, so they are still known after a
+    /// restart.
+    pub async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>> {
+        Ok(self.store.latest_edit(room_id, event_id).await?)
+    }
+
+    /// Get the membership of the logged-in user in the given room, as recorded
+    /// by their own member event in the store.
+    ///
+    /// Returns `None` if the client isn't logged in or if the store doesn't
+    /// know about the user's membership in this room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room for which the membership should be
+    ///   fetched.
+    pub async fn get_own_membership(
+        &self,
+        room_id: &RoomId,
+    ) -> StoreResult<Option<MembershipState>> {
+        let Some(session_meta) = self.session_meta() else {
+            return Ok(None);
+        };
+
+        let Some(raw_event) = self.store.get_member_event(room_id, &session_meta.user_id).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(raw_event.deserialize()?.membership().clone()))
+    }
+
+    /// Get the olm machine.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn olm_machine(&self) -> RwLockReadGuard<'_, Option<OlmMachine>> {
+        self.olm_machine.read().await
+    }
+
+    /// Get the push rules.
+    ///
+    /// Gets the push rules from `changes` if they have been updated, otherwise
+    /// get them from the store. As a fallback, uses
+    /// `Ruleset::server_default` if the user is logged in.
+    pub async fn get_push_rules(&self, changes: &StateChanges) -> Result<Ruleset> {
+        if let Some(event) = changes
+            .account_data
+            .get(&GlobalAccountDataEventType::PushRules)
+            .and_then(|ev| ev.deserialize_as::<PushRulesEvent>().ok())
+        {
+            Ok(event.content.global)
+        } else if let Some(event) = self
+            .store
+            .get_account_data_event_static::<PushRulesEventContent>()
+            .await?
+            .and_then(|ev| ev.deserialize().ok())
+        {
+            Ok(event.content.global)
+        } else if let Some(session_meta) = self.store.session_meta() {
+            Ok(Ruleset::server_default(&session_meta.user_id))
+        } else {
+            Ok(Ruleset::new())
+        }
+    }
+
+    /// Get the push context for the given room.
+    ///
+    /// Tries to get the data from `changes` or the up to date `room_info`.
+    /// Loads the data from the store otherwise.
+    ///
+    /// Returns `None` if some data couldn't be found. This should only happen
+    /// in brand new rooms, while we process its state.
+    pub async fn get_push_room_context(
+        &self,
+        room: &Room,
+        room_info: &RoomInfo,
+        changes: &StateChanges,
+    ) -> Result<Option<PushConditionRoomCtx>> {
+        let room_id = room.room_id();
+        let user_id = room.own_user_id();
+
+        let member_count = room_info.active_members_count();
+
+        // TODO: Use if let chain once stable
+        let user_display_name = if let Some(AnySyncStateEvent::RoomMember(member)) =
+            changes.state.get(room_id).and_then(|events| {
+                events.get(&StateEventType::RoomMember)?.get(user_id.as_str())?.deserialize().ok()
+            }) {
+            member
+                .as_original()
+                .and_then(|ev| ev.content.displayname.clone())
+                .unwrap_or_else(|| user_id.localpart().to_owned())
+        } else if let Some(AnyStrippedStateEvent::RoomMember(member)) =
+            changes.stripped_state.get(room_id).and_then(|events| {
+                events.get(&StateEventType::RoomMember)?.get(user_id.as_str())?.deserialize().ok()
+            })
         {
             member.content.displayname.unwrap_or_else(|| user_id.localpart().to_owned())
         } else if let Some(member) = Box::pin(room.get_member(user_id)).await? {
@@ -1602,6 +2217,15 @@ impl BaseClient {
     /// Returns a new receiver that gets future room info notable updates.
     ///
     /// Learn more by reading the [`RoomInfoNotableUpdate`] type.
+    ///
+    /// If the receiver isn't polled fast enough and falls behind by more than
+    /// the capacity configured with
+    /// [`StoreConfig::room_info_notable_update_capacity`], its next receive
+    /// returns [`RecvError::Lagged`] indicating how many updates were skipped.
+    /// Callers should then refresh the room information they care about
+    /// instead of relying on the updates they missed.
+    ///
+    /// [`RecvError::Lagged`]: broadcast::error::RecvError::Lagged
     pub fn room_info_notable_update_receiver(&self) -> broadcast::Receiver<RoomInfoNotableUpdate> {
         self.room_info_notable_update_sender.subscribe()
     }
@@ -1613,6 +2237,25 @@ impl Default for BaseClient {
     }
 }
 
+/// Get the ID of the event replaced by the given event, if it has an
+/// `m.replace` relation.
+fn replaced_event_id(event: &Raw<AnySyncTimelineEvent>) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct RelatesTo {
+        rel_type: RelationType,
+        event_id: OwnedEventId,
+    }
+
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<RelatesTo>,
+    }
+
+    let relates_to = event.get_field::<Content>("content").ok()??.relates_to?;
+    (relates_to.rel_type == RelationType::Replacement).then_some(relates_to.event_id)
+}
+
 fn handle_room_member_event_for_profiles(
     room_id: &RoomId,
     event: &SyncStateEvent<RoomMemberEventContent>,
@@ -1646,17 +2289,29 @@ fn handle_room_member_event_for_profiles(
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use assert_matches::assert_matches;
     use matrix_sdk_test::{
-        async_test, ruma_response_from_json, sync_timeline_event, InvitedRoomBuilder,
-        LeftRoomBuilder, StateTestEvent, StrippedStateTestEvent, SyncResponseBuilder,
+        async_test, ruma_response_from_json, sync_state_event, sync_timeline_event,
+        InvitedRoomBuilder, LeftRoomBuilder, PresenceTestEvent, StateTestEvent,
+        StrippedStateTestEvent, SyncResponseBuilder,
+    };
+    use ruma::{
+        api::client as api, event_id, events::room::member::MembershipState, room_id, serde::Raw,
+        time::SystemTime, user_id, MilliSecondsSinceUnixEpoch, UserId,
     };
-    use ruma::{api::client as api, room_id, serde::Raw, user_id, UserId};
     use serde_json::{json, value::to_raw_value};
+    use tokio::sync::broadcast::error::RecvError;
 
     use super::BaseClient;
     use crate::{
-        store::StateStoreExt, test_utils::logged_in_base_client, DisplayName, RoomState,
-        SessionMeta,
+        deserialized_responses::SyncTimelineEvent,
+        rooms::normal::RoomInfoNotableUpdateReasons,
+        store::{StateStoreExt, StoreConfig, StoreError},
+        sync::UnreadNotificationsCount,
+        test_utils::logged_in_base_client,
+        DisplayName, Error, RoomInfo, RoomMemberships, RoomState, SessionMeta, StateChanges,
     };
 
     #[async_test]
@@ -1682,25 +2337,468 @@ mod tests {
             })))
             .build_sync_response();
         client.receive_sync_response(response).await.unwrap();
-        assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Left);
+        assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Left);
+
+        let response = sync_builder
+            .add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": {
+                        "displayname": "Alice",
+                        "membership": "invite",
+                    },
+                    "event_id": "$143273582443PhrSn:example.org",
+                    "origin_server_ts": 1432735824653u64,
+                    "sender": "@example:example.org",
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Invited);
+    }
+
+    #[async_test]
+    async fn test_get_own_membership() {
+        let user_id = user_id!("@alice:example.org");
+        let joined_room_id = room_id!("!joined:example.org");
+        let invited_room_id = room_id!("!invited:example.org");
+        let left_room_id = room_id!("!left:example.org");
+        let unknown_room_id = room_id!("!unknown:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let member_event = |membership: &str| {
+            sync_timeline_event!({
+                "content": {
+                    "membership": membership,
+                },
+                "event_id": format!("${membership}:example.org"),
+                "origin_server_ts": 1432135524678u64,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            })
+        };
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(
+                matrix_sdk_test::JoinedRoomBuilder::new(joined_room_id)
+                    .add_timeline_event(member_event("join")),
+            )
+            .add_invited_room(InvitedRoomBuilder::new(invited_room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": {
+                        "membership": "invite",
+                    },
+                    "sender": "@example:example.org",
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .add_left_room(
+                LeftRoomBuilder::new(left_room_id).add_timeline_event(member_event("leave")),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(
+            client.get_own_membership(joined_room_id).await.unwrap(),
+            Some(MembershipState::Join)
+        );
+        assert_eq!(
+            client.get_own_membership(invited_room_id).await.unwrap(),
+            Some(MembershipState::Invite)
+        );
+        assert_eq!(
+            client.get_own_membership(left_room_id).await.unwrap(),
+            Some(MembershipState::Leave)
+        );
+        assert_eq!(client.get_own_membership(unknown_room_id).await.unwrap(), None);
+    }
+
+    #[async_test]
+    async fn test_update_notification_counts() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!counted:example.org");
+        let other_room_id = room_id!("!other:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id))
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(other_room_id))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let mut receiver = client.room_info_notable_update_receiver();
+
+        let counts = UnreadNotificationsCount { highlight_count: 1, notification_count: 3 };
+        client
+            .update_notification_counts(BTreeMap::from([
+                (room_id.to_owned(), counts),
+                (room_id!("!unknown:example.org").to_owned(), counts),
+            ]))
+            .await
+            .unwrap();
+
+        // The loaded room is updated and its update is broadcast.
+        assert_eq!(client.get_room(room_id).unwrap().unread_notification_counts(), counts);
+        assert_eq!(receiver.recv().await.unwrap().room_id, room_id);
+        assert!(receiver.is_empty());
+
+        // The store is updated too.
+        let room_info = client.store().get_room_info(room_id).await.unwrap().unwrap();
+        assert_eq!(room_info.notification_counts, counts);
+
+        // The other rooms are left as they are.
+        assert_eq!(
+            client.get_room(other_room_id).unwrap().unread_notification_counts(),
+            UnreadNotificationsCount::default()
+        );
+        assert!(client.get_room(room_id!("!unknown:example.org")).is_none());
+    }
+
+    #[async_test]
+    async fn test_set_marked_unread() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!marked_unread:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let mut receiver = client.room_info_notable_update_receiver();
+
+        client.set_marked_unread(room_id, true).await.unwrap();
+        assert!(client.get_room(room_id).unwrap().is_marked_unread());
+        assert!(client.store().is_marked_unread(room_id).await.unwrap());
+        let room_info = client.store().get_room_info(room_id).await.unwrap().unwrap();
+        assert!(room_info.base_info.is_marked_unread);
+        let update = receiver.recv().await.unwrap();
+        assert_eq!(update.room_id, room_id);
+        assert!(update.reasons.contains(RoomInfoNotableUpdateReasons::UNREAD_MARKER));
+
+        client.set_marked_unread(room_id, false).await.unwrap();
+        assert!(!client.get_room(room_id).unwrap().is_marked_unread());
+        assert!(!client.store().is_marked_unread(room_id).await.unwrap());
+
+        // The flag can be set for a room the client doesn't know yet.
+        let unknown_room_id = room_id!("!unknown_marked_unread:example.org");
+        client.set_marked_unread(unknown_room_id, true).await.unwrap();
+        assert!(client.store().is_marked_unread(unknown_room_id).await.unwrap());
+        assert!(client.get_room(unknown_room_id).is_none());
+    }
+
+    #[async_test]
+    async fn test_room_info_notable_update_receiver_lags() {
+        let user_id = user_id!("@alice:example.org");
+
+        let client =
+            BaseClient::with_store_config(StoreConfig::new().room_info_notable_update_capacity(1));
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let mut receiver = client.room_info_notable_update_receiver();
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id!("!a:example.org")))
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id!("!b:example.org")))
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id!("!c:example.org")))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // The receiver didn't keep up, so it's told it lagged instead of silently
+        // missing updates.
+        assert_matches!(receiver.recv().await, Err(RecvError::Lagged(_)));
+        // And it then resumes with the most recent update.
+        assert_matches!(receiver.recv().await, Ok(_));
+    }
+
+    #[async_test]
+    async fn test_get_latest_edit() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let original_event_id = event_id!("$original:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let edit_event = |event_id: &str, origin_server_ts: u64, body: &str| {
+            sync_timeline_event!({
+                "content": {
+                    "body": format!("* {body}"),
+                    "msgtype": "m.text",
+                    "m.new_content": {
+                        "body": body,
+                        "msgtype": "m.text",
+                    },
+                    "m.relates_to": {
+                        "rel_type": "m.replace",
+                        "event_id": original_event_id,
+                    },
+                },
+                "event_id": event_id,
+                "origin_server_ts": origin_server_ts,
+                "sender": user_id,
+                "type": "m.room.message",
+            })
+        };
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(
+                matrix_sdk_test::JoinedRoomBuilder::new(room_id)
+                    .add_timeline_event(sync_timeline_event!({
+                        "content": {
+                            "body": "hello",
+                            "msgtype": "m.text",
+                        },
+                        "event_id": original_event_id,
+                        "origin_server_ts": 1,
+                        "sender": user_id,
+                        "type": "m.room.message",
+                    }))
+                    .add_timeline_event(edit_event("$edit1:example.org", 2, "hello there"))
+                    .add_timeline_event(edit_event("$edit2:example.org", 3, "hello world")),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let latest_edit_id = |client: &BaseClient| {
+            let client = client.clone();
+            async move {
+                client
+                    .get_latest_edit(room_id, original_event_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .get_field::<String>("event_id")
+                    .unwrap()
+                    .unwrap()
+            }
+        };
+        assert_eq!(latest_edit_id(&client).await, "$edit2:example.org");
+
+        // An older edit received later doesn't replace the newest one.
+        let response =
+            sync_builder
+                .add_joined_room(
+                    matrix_sdk_test::JoinedRoomBuilder::new(room_id)
+                        .add_timeline_event(edit_event("$edit0:example.org", 1, "hi")),
+                )
+                .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(latest_edit_id(&client).await, "$edit2:example.org");
+
+        // Events that weren't edited have no edit.
+        assert!(client
+            .get_latest_edit(room_id, event_id!("$edit2:example.org"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_test]
+    async fn test_presence_is_not_stored_when_disabled() {
+        let user_id = user_id!("@alice:example.org");
+        // The sender of the canned presence event.
+        let presence_user_id = user_id!("@example:localhost");
+
+        for store_presence in [true, false] {
+            let client =
+                BaseClient::with_store_config(StoreConfig::new().store_presence(store_presence));
+            client
+                .set_session_meta(
+                    SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                    #[cfg(feature = "e2e-encryption")]
+                    None,
+                )
+                .await
+                .unwrap();
+
+            let response = SyncResponseBuilder::new()
+                .add_presence_event(PresenceTestEvent::Presence)
+                .build_sync_response();
+            let sync_response = client.receive_sync_response(response).await.unwrap();
+
+            // The presence event is always part of the sync response…
+            assert_eq!(sync_response.presence.len(), 1);
+            // …but only persisted if enabled.
+            let stored = client.store().get_presence_event(presence_user_id).await.unwrap();
+            assert_eq!(stored.is_some(), store_presence);
+        }
+    }
+
+    #[async_test]
+    async fn test_max_members_per_room_drops_non_joined_members_first() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!big:example.org");
+
+        let client =
+            BaseClient::with_store_config(StoreConfig::new().max_members_per_room(Some(2)));
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let member_event = |user_id: &str, membership: &str| {
+            sync_state_event!({
+                "content": { "membership": membership },
+                "event_id": format!("${}", &user_id[1..]),
+                "origin_server_ts": 1,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            })
+        };
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_state_bulk([
+                member_event("@alice:example.org", "join"),
+                member_event("@bob:example.org", "invite"),
+                member_event("@carol:example.org", "leave"),
+                member_event("@dave:example.org", "join"),
+            ]))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let mut stored =
+            client.store().get_user_ids(room_id, RoomMemberships::empty()).await.unwrap();
+        stored.sort();
+        assert_eq!(
+            stored,
+            [user_id!("@alice:example.org").to_owned(), user_id!("@dave:example.org").to_owned()]
+        );
+    }
+
+    #[async_test]
+    async fn test_max_members_per_room_evicts_least_recently_active_members() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!big:example.org");
+        let max_members = 3;
+
+        let client = BaseClient::with_store_config(
+            StoreConfig::new().max_members_per_room(Some(max_members)),
+        );
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let member_event = |user_id: &str, membership: &str, ts: u64| {
+            sync_state_event!({
+                "content": { "membership": membership },
+                "event_id": format!("${}_{ts}", &user_id[1..]),
+                "origin_server_ts": ts,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            })
+        };
+        let stored_members = || async {
+            let mut stored =
+                client.store().get_user_ids(room_id, RoomMemberships::empty()).await.unwrap();
+            stored.sort();
+            stored
+        };
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_state_bulk([
+                member_event("@alice:example.org", "join", 1),
+                sync_state_event!({
+                    "content": { "displayname": "Bob", "membership": "join" },
+                    "event_id": "$bob_2",
+                    "origin_server_ts": 2,
+                    "sender": "@bob:example.org",
+                    "state_key": "@bob:example.org",
+                    "type": "m.room.member",
+                }),
+                member_event("@carol:example.org", "join", 3),
+            ]))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(stored_members().await.len(), max_members);
+        let bob = user_id!("@bob:example.org");
+        assert!(client.store().get_profile(room_id, bob).await.unwrap().is_some());
+
+        // A new joined member evicts the least recently active one. The user of
+        // the client is older, but is never evicted.
+        let response =
+            SyncResponseBuilder::new()
+                .add_joined_room(
+                    matrix_sdk_test::JoinedRoomBuilder::new(room_id)
+                        .add_state_bulk([member_event("@dave:example.org", "join", 4)]),
+                )
+                .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(
+            stored_members().await,
+            [
+                user_id!("@alice:example.org").to_owned(),
+                user_id!("@carol:example.org").to_owned(),
+                user_id!("@dave:example.org").to_owned(),
+            ]
+        );
+        let evicted = client
+            .store()
+            .get_state_event(room_id, StateEventType::RoomMember, "@bob:example.org")
+            .await
+            .unwrap();
+        assert!(evicted.is_none());
+        // The profile and the display name of the evicted member are gone too.
+        assert!(client.store().get_profile(room_id, bob).await.unwrap().is_none());
+        assert!(client
+            .store()
+            .get_users_with_display_name(room_id, "Bob")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Members that aren't joined are evicted before the joined ones.
+        let response =
+            SyncResponseBuilder::new()
+                .add_joined_room(
+                    matrix_sdk_test::JoinedRoomBuilder::new(room_id)
+                        .add_state_bulk([member_event("@dave:example.org", "leave", 5)]),
+                )
+                .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
 
-        let response = sync_builder
-            .add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
-                StrippedStateTestEvent::Custom(json!({
-                    "content": {
-                        "displayname": "Alice",
-                        "membership": "invite",
-                    },
-                    "event_id": "$143273582443PhrSn:example.org",
-                    "origin_server_ts": 1432735824653u64,
-                    "sender": "@example:example.org",
-                    "state_key": user_id,
-                    "type": "m.room.member",
-                })),
-            ))
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_state_bulk([
+                member_event("@erin:example.org", "join", 6),
+                member_event("@frank:example.org", "join", 7),
+            ]))
             .build_sync_response();
         client.receive_sync_response(response).await.unwrap();
-        assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Invited);
+
+        let stored = stored_members().await;
+        assert!(stored.len() <= max_members);
+        assert_eq!(
+            stored,
+            [
+                user_id!("@alice:example.org").to_owned(),
+                user_id!("@erin:example.org").to_owned(),
+                user_id!("@frank:example.org").to_owned(),
+            ]
+        );
     }
 
     #[async_test]
@@ -1794,10 +2892,6 @@ mod tests {
     #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
     #[async_test]
     async fn test_when_there_are_no_latest_encrypted_events_decrypting_them_does_nothing() {
-        use std::collections::BTreeMap;
-
-        use crate::{rooms::normal::RoomInfoNotableUpdateReasons, StateChanges};
-
         // Given a room
         let user_id = user_id!("@u:u.to");
         let room_id = room_id!("!r:u.to");
@@ -2047,4 +3141,597 @@ mod tests {
         assert_eq!(member.display_name().unwrap(), "Invited Alice");
         assert_eq!(member.avatar_url().unwrap().to_string(), "mxc://localhost/fewjilfewjil42");
     }
+
+    #[async_test]
+    async fn test_rooms_changed_since() {
+        let user_id = user_id!("@alice:example.org");
+        let first_room_id = room_id!("!first:example.org");
+        let second_room_id = room_id!("!second:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+        let mut sync_builder = SyncResponseBuilder::new();
+
+        let response = sync_builder
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(first_room_id))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let (rooms, marker) = client.rooms_changed_since(0);
+        assert_eq!(rooms, vec![first_room_id.to_owned()]);
+
+        // Nothing changed since the last marker.
+        let (rooms, same_marker) = client.rooms_changed_since(marker);
+        assert!(rooms.is_empty());
+        assert_eq!(same_marker, marker);
+
+        let response = sync_builder
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(second_room_id))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // Only the room written after the marker is returned.
+        let (rooms, new_marker) = client.rooms_changed_since(marker);
+        assert_eq!(rooms, vec![second_room_id.to_owned()]);
+        assert!(new_marker > marker);
+
+        let (rooms, _) = client.rooms_changed_since(0);
+        assert_eq!(rooms.len(), 2);
+    }
+
+    #[async_test]
+    async fn test_rooms_changed_since_tracks_direct_store_writes() {
+        let room_id = room_id!("!direct:example.org");
+        let client = logged_in_base_client(None).await;
+        let (_, marker) = client.rooms_changed_since(0);
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        client.store().save_changes(&changes).await.unwrap();
+
+        let (rooms, new_marker) = client.rooms_changed_since(marker);
+        assert_eq!(rooms, vec![room_id.to_owned()]);
+        assert!(new_marker > marker);
+    }
+
+    #[async_test]
+    async fn test_rooms_changed_since_tracks_removed_rooms() {
+        let room_id = room_id!("!removed:example.org");
+        let invited_room_id = room_id!("!invited:example.org");
+        let user_id = user_id!("@bob:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let raw = sync_state_event!({
+            "content": { "membership": "join" },
+            "event_id": "$bob",
+            "origin_server_ts": 1,
+            "sender": user_id,
+            "state_key": user_id,
+            "type": "m.room.member",
+        });
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        changes.add_room(RoomInfo::new(invited_room_id, RoomState::Invited));
+        changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        client.store().save_changes(&changes).await.unwrap();
+        let (_, marker) = client.rooms_changed_since(0);
+
+        // Forgetting a room reports it, and drops the changes of its members.
+        client.store().remove_room(room_id).await.unwrap();
+        let (rooms, new_marker) = client.rooms_changed_since(marker);
+        assert_eq!(rooms, vec![room_id.to_owned()]);
+        assert!(new_marker > marker);
+        let (members, removed, _) = client.members_changed_since(room_id, 0).await.unwrap();
+        assert!(members.is_empty());
+        assert!(removed.is_empty());
+
+        // Rejecting an invite reports the room too.
+        client.store().mark_invite_rejected(invited_room_id).await.unwrap();
+        let (rooms, _) = client.rooms_changed_since(new_marker);
+        assert_eq!(rooms, vec![invited_room_id.to_owned()]);
+    }
+
+    #[async_test]
+    async fn test_members_changed_since_reports_removed_members() {
+        let room_id = room_id!("!members:example.org");
+        let user_id = user_id!("@bob:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let raw = sync_state_event!({
+            "content": { "membership": "join" },
+            "event_id": "$bob",
+            "origin_server_ts": 1,
+            "sender": user_id,
+            "state_key": user_id,
+            "type": "m.room.member",
+        });
+        let mut changes = StateChanges::default();
+        changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        client.store().save_changes(&changes).await.unwrap();
+        let (_, marker) = client.rooms_changed_since(0);
+
+        // The member is evicted, so only its ID is reported.
+        let changes = StateChanges {
+            members_to_delete: [(room_id.to_owned(), vec![user_id.to_owned()])].into(),
+            ..Default::default()
+        };
+        client.store().save_changes(&changes).await.unwrap();
+
+        let (members, removed, new_marker) =
+            client.members_changed_since(room_id, marker).await.unwrap();
+        assert!(members.is_empty());
+        assert_eq!(removed, vec![user_id.to_owned()]);
+        assert!(new_marker > marker);
+    }
+
+    #[async_test]
+    async fn test_members_changed_since() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!members:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+        let mut sync_builder = SyncResponseBuilder::new();
+
+        let member_event = |user_id: &str| {
+            sync_state_event!({
+                "content": { "membership": "join" },
+                "event_id": format!("${}", &user_id[1..]),
+                "origin_server_ts": 1,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            })
+        };
+
+        let response = sync_builder
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_state_bulk([
+                member_event("@alice:example.org"),
+                member_event("@bob:example.org"),
+            ]))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let (members, removed, marker) = client.members_changed_since(room_id, 0).await.unwrap();
+        assert!(removed.is_empty());
+        assert_eq!(members.len(), 2);
+
+        let response = sync_builder
+            .add_joined_room(
+                matrix_sdk_test::JoinedRoomBuilder::new(room_id)
+                    .add_state_bulk([member_event("@carol:example.org")]),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // Only the member written after the marker is returned.
+        let (members, _, new_marker) = client.members_changed_since(room_id, marker).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].user_id(), user_id!("@carol:example.org"));
+        assert!(new_marker > marker);
+
+        let (members, _, _) = client.members_changed_since(room_id, new_marker).await.unwrap();
+        assert!(members.is_empty());
+    }
+
+    #[async_test]
+    async fn test_members_changed_since_tracks_direct_store_writes() {
+        let room_id = room_id!("!direct:example.org");
+        let user_id = user_id!("@bob:example.org");
+        let client = logged_in_base_client(None).await;
+        let (_, marker) = client.rooms_changed_since(0);
+
+        let raw = sync_state_event!({
+            "content": { "membership": "join" },
+            "event_id": "$bob",
+            "origin_server_ts": 1,
+            "sender": user_id,
+            "state_key": user_id,
+            "type": "m.room.member",
+        });
+        let mut changes = StateChanges::default();
+        changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        client.store().save_changes(&changes).await.unwrap();
+
+        let (members, _, new_marker) = client.members_changed_since(room_id, marker).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].user_id(), user_id);
+        assert!(new_marker > marker);
+    }
+
+    #[async_test]
+    async fn test_stripped_member_with_invalid_user_id() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!invited:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let response = SyncResponseBuilder::new()
+            .add_invited_room(
+                InvitedRoomBuilder::new(room_id)
+                    .add_state_event(StrippedStateTestEvent::Custom(json!({
+                        "content": { "membership": "invite" },
+                        "sender": "@example:example.org",
+                        "state_key": user_id,
+                        "type": "m.room.member",
+                    })))
+                    .add_state_event(StrippedStateTestEvent::Custom(json!({
+                        "content": { "membership": "join" },
+                        "sender": "@example:example.org",
+                        "state_key": "not a user id",
+                        "type": "m.room.member",
+                    }))),
+            )
+            .build_sync_response();
+
+        // The malformed member is skipped, the rest of the invite is kept.
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.state(), RoomState::Invited);
+        assert_eq!(
+            client.store().get_user_ids(room_id, RoomMemberships::empty()).await.unwrap(),
+            [user_id.to_owned()]
+        );
+    }
+
+    #[async_test]
+    async fn test_room_senders() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!senders:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_state_bulk([
+                sync_state_event!({
+                    "content": { "membership": "join" },
+                    "event_id": "$alice",
+                    "origin_server_ts": 1,
+                    "sender": "@alice:example.org",
+                    "state_key": "@alice:example.org",
+                    "type": "m.room.member",
+                }),
+                sync_state_event!({
+                    "content": { "topic": "Senders" },
+                    "event_id": "$topic",
+                    "origin_server_ts": 2,
+                    "sender": "@bob:example.org",
+                    "state_key": "",
+                    "type": "m.room.topic",
+                }),
+            ]))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        for (event_id, sender) in
+            [("$first", "@carol:example.org"), ("$second", "@bob:example.org")]
+        {
+            let event = SyncTimelineEvent::new(sync_timeline_event!({
+                "content": { "body": "hello", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": 3,
+                "sender": sender,
+                "type": "m.room.message",
+            }));
+            client.event_cache_store().append_timeline_event(room_id, event).await.unwrap();
+        }
+
+        // The senders of both the state and the timeline events are returned,
+        // once each.
+        assert_eq!(
+            client.get_room_senders(room_id).await.unwrap().into_iter().collect::<Vec<_>>(),
+            [
+                user_id!("@alice:example.org").to_owned(),
+                user_id!("@bob:example.org").to_owned(),
+                user_id!("@carol:example.org").to_owned(),
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn test_rooms_by_activity() {
+        let user_id = user_id!("@alice:example.org");
+        let busy_room_id = room_id!("!busy:example.org");
+        let quiet_room_id = room_id!("!quiet:example.org");
+        let silent_room_id = room_id!("!silent:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let now = MilliSecondsSinceUnixEpoch::now();
+        let two_days_ago = MilliSecondsSinceUnixEpoch::from_system_time(
+            SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60),
+        )
+        .unwrap();
+        let message = |event_id: &str, origin_server_ts: MilliSecondsSinceUnixEpoch| {
+            sync_timeline_event!({
+                "content": { "body": "hello", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": origin_server_ts,
+                "sender": user_id,
+                "type": "m.room.message",
+            })
+        };
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(
+                matrix_sdk_test::JoinedRoomBuilder::new(quiet_room_id)
+                    .add_timeline_event(message("$old:example.org", two_days_ago))
+                    .add_timeline_event(message("$quiet:example.org", now)),
+            )
+            .add_joined_room(
+                matrix_sdk_test::JoinedRoomBuilder::new(busy_room_id)
+                    .add_timeline_event(message("$busy1:example.org", now))
+                    .add_timeline_event(message("$busy2:example.org", now))
+                    .add_timeline_event(message("$busy3:example.org", now)),
+            )
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(silent_room_id))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // Only the messages of the last day count.
+        let rooms = client.store().get_rooms_by_activity(10).await.unwrap();
+        assert_eq!(rooms, vec![(busy_room_id.to_owned(), 3), (quiet_room_id.to_owned(), 1)]);
+
+        let rooms = client.store().get_rooms_by_activity(1).await.unwrap();
+        assert_eq!(rooms, vec![(busy_room_id.to_owned(), 3)]);
+
+        // Messages received again aren't counted twice.
+        let response = sync_builder
+            .add_joined_room(
+                matrix_sdk_test::JoinedRoomBuilder::new(busy_room_id)
+                    .set_timeline_limited()
+                    .add_timeline_event(message("$busy2:example.org", now))
+                    .add_timeline_event(message("$busy3:example.org", now))
+                    .add_timeline_event(message("$busy4:example.org", now)),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let rooms = client.store().get_rooms_by_activity(10).await.unwrap();
+        assert_eq!(rooms, vec![(busy_room_id.to_owned(), 4), (quiet_room_id.to_owned(), 1)]);
+    }
+
+    #[async_test]
+    async fn test_sync_saves_last_sync_time() {
+        let client = logged_in_base_client(None).await;
+        assert_eq!(client.store().get_last_sync_time().await.unwrap(), None);
+
+        let before_sync = SystemTime::now() - Duration::from_secs(1);
+        let response = SyncResponseBuilder::new().build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let last_sync_time = client.store().get_last_sync_time().await.unwrap().unwrap();
+        assert!(last_sync_time >= before_sync);
+    }
+
+    #[async_test]
+    async fn test_imported_credentials_resume_the_sync() {
+        let user_id = user_id!("@alice:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+        let response = SyncResponseBuilder::new().build_sync_response();
+        let next_batch = response.next_batch.clone();
+        client.receive_sync_response(response).await.unwrap();
+
+        let bundle = client.export_credentials(&[]).await.unwrap();
+        assert_eq!(bundle.session_meta.as_ref(), client.session_meta());
+        assert_eq!(bundle.sync_token.as_deref(), Some(next_batch.as_str()));
+
+        // Restore the session with a fresh store.
+        let restored = BaseClient::new();
+        assert_eq!(restored.sync_token().await, None);
+        restored.import_credentials(&bundle).await.unwrap();
+
+        // The session is restored and the next sync continues from the exported
+        // token.
+        assert_eq!(restored.session_meta().map(|meta| &*meta.user_id), Some(user_id));
+        assert_eq!(restored.sync_token().await, Some(next_batch));
+    }
+
+    #[async_test]
+    async fn test_set_session_meta_open_timeout() {
+        let user_id = user_id!("@alice:example.org");
+        let session_meta = SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() };
+
+        let client = BaseClient::with_store_config(
+            StoreConfig::new().open_timeout(Some(Duration::from_millis(10))),
+        );
+
+        // Block the restoration of the sync token, like a store that is too
+        // slow to open.
+        let sync_token = client.store.sync_token.write().await;
+        let result = client
+            .set_session_meta(
+                session_meta.clone(),
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await;
+        assert_matches!(result, Err(Error::StateStore(StoreError::OpenTimeout)));
+        assert!(client.session_meta().is_none());
+
+        // The session can be restored once the store answers.
+        drop(sync_token);
+        client
+            .set_session_meta(
+                session_meta,
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(client.session_meta().map(|meta| &*meta.user_id), Some(user_id));
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_room_keys_received_retries_undecryptable_events() {
+        use matrix_sdk_crypto::{EncryptionSettings, OlmMachine};
+        use ruma::{device_id, events::room::message::RoomMessageEventContent};
+
+        let alice_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!encrypted:example.org");
+
+        let client = logged_in_base_client(None).await;
+
+        // Alice encrypts a message before sharing any room key with us.
+        let alice = OlmMachine::new(alice_id, device_id!("ALICEDEVICE")).await;
+        alice
+            .share_room_key(room_id, std::iter::empty(), EncryptionSettings::default())
+            .await
+            .unwrap();
+        let content = alice
+            .encrypt_room_event(room_id, RoomMessageEventContent::text_plain("Secret"))
+            .await
+            .unwrap();
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_timeline_event(
+                sync_timeline_event!({
+                    "content": content,
+                    "event_id": "$encrypted",
+                    "origin_server_ts": 1,
+                    "sender": alice_id,
+                    "type": "m.room.encrypted",
+                }),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // Without the room key, the event still can't be decrypted.
+        assert!(client.on_room_keys_received(room_id).await.is_empty());
+
+        let keys = alice.store().export_room_keys(|_| true).await.unwrap();
+        client
+            .olm_machine()
+            .await
+            .as_ref()
+            .unwrap()
+            .store()
+            .import_exported_room_keys(keys, |_, _| {})
+            .await
+            .unwrap();
+
+        let decrypted = client.on_room_keys_received(room_id).await;
+        assert_eq!(decrypted.len(), 1);
+        assert!(decrypted[0].encryption_info.is_some());
+
+        // The event isn't queued anymore.
+        assert!(client.on_room_keys_received(room_id).await.is_empty());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_sync_retries_undecryptable_events_with_received_room_keys() {
+        use std::{collections::BTreeMap, iter};
+
+        use matrix_sdk_crypto::{EncryptionSettings, OlmMachine, OutgoingRequests};
+        use ruma::{
+            api::client::keys::{claim_keys, get_keys},
+            device_id,
+            events::room::message::RoomMessageEventContent,
+            to_device::DeviceIdOrAllDevices,
+        };
+
+        let alice_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!encrypted:example.org");
+
+        let client = logged_in_base_client(None).await;
+        let user_id = client.session_meta().unwrap().user_id.clone();
+        let device_id = client.session_meta().unwrap().device_id.clone();
+
+        // Alice learns about our device keys and claims one of our one-time keys.
+        let keys_upload = client
+            .olm_machine()
+            .await
+            .as_ref()
+            .unwrap()
+            .outgoing_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .find_map(|request| match request.request() {
+                OutgoingRequests::KeysUpload(request) => Some(request.clone()),
+                _ => None,
+            })
+            .unwrap();
+
+        let alice = OlmMachine::new(alice_id, device_id!("ALICEDEVICE")).await;
+        alice.update_tracked_users(iter::once(&*user_id)).await.unwrap();
+        let keys_query_id = alice
+            .outgoing_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|request| matches!(request.request(), OutgoingRequests::KeysQuery(_)))
+            .unwrap()
+            .request_id()
+            .to_owned();
+
+        let mut keys_query = get_keys::v3::Response::new();
+        keys_query.device_keys = BTreeMap::from([(
+            user_id.clone(),
+            BTreeMap::from([(device_id.clone(), keys_upload.device_keys.unwrap())]),
+        )]);
+        alice.mark_request_as_sent(&keys_query_id, &keys_query).await.unwrap();
+
+        let (keys_claim_id, _) =
+            alice.get_missing_sessions(iter::once(&*user_id)).await.unwrap().unwrap();
+        let one_time_key = keys_upload.one_time_keys.into_iter().next().unwrap();
+        let keys_claim = claim_keys::v3::Response::new(BTreeMap::from([(
+            user_id.clone(),
+            BTreeMap::from([(device_id.clone(), BTreeMap::from([one_time_key]))]),
+        )]));
+        alice.mark_request_as_sent(&keys_claim_id, &keys_claim).await.unwrap();
+
+        // Alice shares a room key with us and encrypts a message with it.
+        let to_device_requests = alice
+            .share_room_key(room_id, iter::once(&*user_id), EncryptionSettings::default())
+            .await
+            .unwrap();
+        let room_key_content = to_device_requests[0].messages[&user_id]
+            [&DeviceIdOrAllDevices::DeviceId(device_id.clone())]
+            .clone();
+        let content = alice
+            .encrypt_room_event(room_id, RoomMessageEventContent::text_plain("Secret"))
+            .await
+            .unwrap();
+
+        // The message arrives before the room key, so it can't be decrypted yet.
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_timeline_event(
+                sync_timeline_event!({
+                    "content": content,
+                    "event_id": "$encrypted",
+                    "origin_server_ts": 1,
+                    "sender": alice_id,
+                    "type": "m.room.encrypted",
+                }),
+            ))
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+        assert!(sync_response.decrypted_events.is_empty());
+
+        // The room key arrives in the next sync, which decrypts the message.
+        let mut response = sync_builder.build_sync_response();
+        response.to_device.events.push(
+            Raw::new(&json!({
+                "content": room_key_content,
+                "sender": alice_id,
+                "type": "m.room.encrypted",
+            }))
+            .unwrap()
+            .cast(),
+        );
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+
+        let decrypted = &sync_response.decrypted_events[room_id];
+        assert_eq!(decrypted.len(), 1);
+        assert!(decrypted[0].encryption_info.is_some());
+
+        // The message isn't queued anymore.
+        assert!(client.on_room_keys_received(room_id).await.is_empty());
+    }
 }
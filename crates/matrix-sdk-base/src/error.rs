@@ -42,6 +42,10 @@ pub enum Error {
     #[error(transparent)]
     StateStore(#[from] crate::store::StoreError),
 
+    /// An error occurred in the event cache store.
+    #[error(transparent)]
+    EventCacheStore(#[from] crate::event_cache_store::EventCacheStoreError),
+
     /// An error occurred in the crypto store.
     #[cfg(feature = "e2e-encryption")]
     #[error(transparent)]
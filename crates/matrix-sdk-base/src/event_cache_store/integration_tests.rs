@@ -15,8 +15,11 @@
 //! Trait and macro of integration tests for `EventCacheStore` implementations.
 
 use async_trait::async_trait;
+use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
+use matrix_sdk_test::sync_timeline_event;
 use ruma::{
-    api::client::media::get_content_thumbnail::v3::Method, events::room::MediaSource, mxc_uri, uint,
+    api::client::media::get_content_thumbnail::v3::Method, event_id, events::room::MediaSource,
+    mxc_uri, room_id, uint, EventId, MilliSecondsSinceUnixEpoch,
 };
 
 use super::DynEventCacheStore;
@@ -31,6 +34,26 @@ use crate::media::{MediaFormat, MediaRequest, MediaThumbnailSettings};
 pub trait EventCacheStoreIntegrationTests {
     /// Test media content storage.
     async fn test_media_content(&self);
+
+    /// Test timeline events storage.
+    async fn test_timeline(&self);
+
+    /// Test removing the timeline events that were sent before a given time.
+    async fn test_remove_timeline_events_before(&self);
+}
+
+fn timeline_event(event_id: &EventId) -> SyncTimelineEvent {
+    timeline_event_at(event_id, 0)
+}
+
+fn timeline_event_at(event_id: &EventId, origin_server_ts: u64) -> SyncTimelineEvent {
+    SyncTimelineEvent::new(sync_timeline_event!({
+        "content": { "body": "hello", "msgtype": "m.text" },
+        "event_id": event_id,
+        "origin_server_ts": origin_server_ts,
+        "sender": "@alice:localhost",
+        "type": "m.room.message",
+    }))
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -139,6 +162,62 @@ impl EventCacheStoreIntegrationTests for DynEventCacheStore {
             "other media was removed"
         );
     }
+
+    async fn test_timeline(&self) {
+        let room_id = room_id!("!room:localhost");
+        let other_room_id = room_id!("!other_room:localhost");
+        let event_ids =
+            [event_id!("$first"), event_id!("$second"), event_id!("$third"), event_id!("$fourth")];
+
+        assert!(self.get_timeline(room_id, 0, 10).await.unwrap().is_empty());
+
+        for (expected_position, event_id) in event_ids.into_iter().enumerate() {
+            let position = self.append_timeline_event(room_id, timeline_event(event_id)).await;
+            assert_eq!(position.unwrap(), expected_position as u64);
+        }
+
+        // Positions are tracked separately for every room.
+        let position =
+            self.append_timeline_event(other_room_id, timeline_event(event_id!("$other"))).await;
+        assert_eq!(position.unwrap(), 0);
+
+        let window = self.get_timeline(room_id, 1, 2).await.unwrap();
+        let window_ids = window.iter().map(|event| event.event_id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(window_ids, [event_ids[1].to_owned(), event_ids[2].to_owned()]);
+
+        let whole = self.get_timeline(room_id, 0, 10).await.unwrap();
+        let whole_ids = whole.iter().map(|event| event.event_id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(whole_ids, event_ids.map(|event_id| event_id.to_owned()));
+
+        assert!(self.get_timeline(room_id, 4, 10).await.unwrap().is_empty());
+        assert_eq!(self.get_timeline(other_room_id, 0, 10).await.unwrap().len(), 1);
+    }
+
+    async fn test_remove_timeline_events_before(&self) {
+        let room_id = room_id!("!room:localhost");
+        let other_room_id = room_id!("!other_room:localhost");
+
+        for (event_id, ts) in [(event_id!("$old"), 1000), (event_id!("$new"), 3000)] {
+            self.append_timeline_event(room_id, timeline_event_at(event_id, ts)).await.unwrap();
+        }
+        self.append_timeline_event(other_room_id, timeline_event_at(event_id!("$other"), 1000))
+            .await
+            .unwrap();
+
+        self.remove_timeline_events_before(MilliSecondsSinceUnixEpoch(uint!(2000))).await.unwrap();
+
+        let timeline = self.get_timeline(room_id, 0, 10).await.unwrap();
+        let timeline_ids =
+            timeline.iter().map(|event| event.event_id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(timeline_ids, [event_id!("$new").to_owned()]);
+        assert!(self.get_timeline(other_room_id, 0, 10).await.unwrap().is_empty());
+
+        // The remaining events keep their position.
+        assert!(self.get_timeline(room_id, 2, 10).await.unwrap().is_empty());
+        let position =
+            self.append_timeline_event(room_id, timeline_event(event_id!("$newest"))).await;
+        assert_eq!(position.unwrap(), 2);
+    }
 }
 
 /// Macro building to allow your `EventCacheStore` implementation to run the
@@ -184,6 +263,20 @@ macro_rules! event_cache_store_integration_tests {
                     get_event_cache_store().await.unwrap().into_event_cache_store();
                 event_cache_store.test_media_content().await;
             }
+
+            #[async_test]
+            async fn test_timeline() {
+                let event_cache_store =
+                    get_event_cache_store().await.unwrap().into_event_cache_store();
+                event_cache_store.test_timeline().await;
+            }
+
+            #[async_test]
+            async fn test_remove_timeline_events_before() {
+                let event_cache_store =
+                    get_event_cache_store().await.unwrap().into_event_cache_store();
+                event_cache_store.test_remove_timeline_events_before().await;
+            }
         }
     };
 }
@@ -12,11 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{num::NonZeroUsize, sync::RwLock as StdRwLock};
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroUsize,
+    sync::RwLock as StdRwLock,
+};
 
 use async_trait::async_trait;
-use matrix_sdk_common::ring_buffer::RingBuffer;
-use ruma::{MxcUri, OwnedMxcUri};
+use matrix_sdk_common::{deserialized_responses::SyncTimelineEvent, ring_buffer::RingBuffer};
+use ruma::{MilliSecondsSinceUnixEpoch, MxcUri, OwnedMxcUri, OwnedRoomId, RoomId};
 
 use super::{EventCacheStore, EventCacheStoreError, Result};
 use crate::media::{MediaRequest, UniqueKey as _};
@@ -28,6 +32,7 @@ use crate::media::{MediaRequest, UniqueKey as _};
 #[derive(Debug)]
 pub struct MemoryStore {
     media: StdRwLock<RingBuffer<(OwnedMxcUri, String /* unique key */, Vec<u8>)>>,
+    timelines: StdRwLock<HashMap<OwnedRoomId, BTreeMap<u64, SyncTimelineEvent>>>,
 }
 
 // SAFETY: `new_unchecked` is safe because 20 is not zero.
@@ -35,7 +40,10 @@ const NUMBER_OF_MEDIAS: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(20)
 
 impl Default for MemoryStore {
     fn default() -> Self {
-        Self { media: StdRwLock::new(RingBuffer::new(NUMBER_OF_MEDIAS)) }
+        Self {
+            media: StdRwLock::new(RingBuffer::new(NUMBER_OF_MEDIAS)),
+            timelines: Default::default(),
+        }
     }
 }
 
@@ -102,6 +110,49 @@ impl EventCacheStore for MemoryStore {
 
         Ok(())
     }
+
+    async fn append_timeline_event(
+        &self,
+        room_id: &RoomId,
+        event: SyncTimelineEvent,
+    ) -> Result<u64> {
+        let mut timelines = self.timelines.write().unwrap();
+        let timeline = timelines.entry(room_id.to_owned()).or_default();
+        let position = timeline.last_key_value().map_or(0, |(position, _)| position + 1);
+        timeline.insert(position, event);
+
+        Ok(position)
+    }
+
+    async fn get_timeline(
+        &self,
+        room_id: &RoomId,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<SyncTimelineEvent>> {
+        let timelines = self.timelines.read().unwrap();
+        let Some(timeline) = timelines.get(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(timeline.range(from..).take(limit).map(|(_, event)| event.clone()).collect())
+    }
+
+    async fn remove_timeline_events_before(&self, ts: MilliSecondsSinceUnixEpoch) -> Result<()> {
+        let mut timelines = self.timelines.write().unwrap();
+        for timeline in timelines.values_mut() {
+            timeline.retain(|_, event| {
+                let event_ts = event
+                    .event
+                    .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                    .ok()
+                    .flatten();
+                !event_ts.is_some_and(|event_ts| event_ts < ts)
+            });
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -15,8 +15,8 @@
 use std::{fmt, sync::Arc};
 
 use async_trait::async_trait;
-use matrix_sdk_common::AsyncTraitDeps;
-use ruma::MxcUri;
+use matrix_sdk_common::{deserialized_responses::SyncTimelineEvent, AsyncTraitDeps};
+use ruma::{MilliSecondsSinceUnixEpoch, MxcUri, RoomId};
 
 use super::EventCacheStoreError;
 use crate::media::MediaRequest;
@@ -66,6 +66,54 @@ pub trait EventCacheStore: AsyncTraitDeps {
     ///
     /// * `uri` - The `MxcUri` of the media files.
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<(), Self::Error>;
+
+    /// Append an event at the end of the stored timeline of a room.
+    ///
+    /// Returns the position of the event in the timeline. Positions start at
+    /// `0` for every room and grow by one with every appended event.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room the event belongs to.
+    ///
+    /// * `event` - The event to append.
+    async fn append_timeline_event(
+        &self,
+        room_id: &RoomId,
+        event: SyncTimelineEvent,
+    ) -> Result<u64, Self::Error>;
+
+    /// Get a window of the stored timeline of a room, in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room.
+    ///
+    /// * `from` - The position of the first event to return. If there's no
+    ///   event at this position, the window starts at the next one.
+    ///
+    /// * `limit` - The maximum number of events to return.
+    async fn get_timeline(
+        &self,
+        room_id: &RoomId,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<SyncTimelineEvent>, Self::Error>;
+
+    /// Remove the events of the stored timelines of all the rooms that were
+    /// sent before the given time.
+    ///
+    /// Events without an `origin_server_ts` are kept. The positions of the
+    /// remaining events don't change, so the timelines can have gaps
+    /// afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `ts` - The time before which events are removed.
+    async fn remove_timeline_events_before(
+        &self,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error>;
 }
 
 #[repr(transparent)]
@@ -105,6 +153,30 @@ impl<T: EventCacheStore> EventCacheStore for EraseEventCacheStoreError<T> {
     async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<(), Self::Error> {
         self.0.remove_media_content_for_uri(uri).await.map_err(Into::into)
     }
+
+    async fn append_timeline_event(
+        &self,
+        room_id: &RoomId,
+        event: SyncTimelineEvent,
+    ) -> Result<u64, Self::Error> {
+        self.0.append_timeline_event(room_id, event).await.map_err(Into::into)
+    }
+
+    async fn get_timeline(
+        &self,
+        room_id: &RoomId,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<SyncTimelineEvent>, Self::Error> {
+        self.0.get_timeline(room_id, from, limit).await.map_err(Into::into)
+    }
+
+    async fn remove_timeline_events_before(
+        &self,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.0.remove_timeline_events_before(ts).await.map_err(Into::into)
+    }
 }
 
 /// A type-erased [`EventCacheStore`].
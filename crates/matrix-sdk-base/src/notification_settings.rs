@@ -14,7 +14,19 @@
 
 //! Some shared types about notification settings.
 
+use ruma::{
+    events::{push_rules::PushRulesEventContent, GlobalAccountDataEventType},
+    push::{
+        Action, NewConditionalPushRule, NewPushRule, NewSimplePushRule, PushCondition, RuleKind,
+        Ruleset, Tweak,
+    },
+    serde::Raw,
+    RoomId,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::StateChanges;
 
 /// Enum representing the push notification modes for a room.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
@@ -26,3 +38,165 @@ pub enum RoomNotificationMode {
     /// Do not receive any notifications.
     Mute,
 }
+
+/// Get the notification mode the user defined for the given room in
+/// `ruleset`, if any.
+///
+/// Returns `None` if the room uses the default mode.
+pub fn get_user_defined_room_notification_mode(
+    ruleset: &Ruleset,
+    room_id: &RoomId,
+) -> Option<RoomNotificationMode> {
+    // An enabled `Override` rule matching this room, without a `Notify` action,
+    // mutes it. More than one override rule may match on the room ID, so checking
+    // only the rule ID is not sufficient.
+    if ruleset.override_.iter().any(|rule| {
+        rule.enabled
+            && rule.conditions.iter().any(|condition| is_room_condition(condition, room_id))
+            && !rule.actions.iter().any(|action| action.should_notify())
+    }) {
+        return Some(RoomNotificationMode::Mute);
+    }
+
+    // Otherwise, a `Room` rule for this room decides whether all messages notify.
+    if let Some(rule) = ruleset.get(RuleKind::Room, room_id) {
+        if rule.triggers_notification() {
+            return Some(RoomNotificationMode::AllMessages);
+        }
+        return Some(RoomNotificationMode::MentionsAndKeywordsOnly);
+    }
+
+    None
+}
+
+/// Update `ruleset` so that the given room uses the given notification mode.
+///
+/// This replaces all the custom rules for the room with a single rule: an
+/// `Override` rule for [`RoomNotificationMode::Mute`], or a `Room` rule
+/// otherwise.
+pub fn set_user_defined_room_notification_mode(
+    ruleset: &mut Ruleset,
+    room_id: &RoomId,
+    mode: RoomNotificationMode,
+) {
+    let custom_overrides: Vec<_> = ruleset
+        .override_
+        .iter()
+        .filter(|rule| {
+            rule.rule_id == room_id.as_str()
+                || rule.conditions.iter().any(|condition| is_room_condition(condition, room_id))
+        })
+        .map(|rule| rule.rule_id.clone())
+        .collect();
+    for rule_id in custom_overrides {
+        // Server-default rules never match a room ID, so this can't fail.
+        let _ = ruleset.remove(RuleKind::Override, rule_id);
+    }
+    // The rule might not exist, which is fine.
+    let _ = ruleset.remove(RuleKind::Room, room_id);
+
+    let new_rule = match mode {
+        RoomNotificationMode::AllMessages => {
+            NewPushRule::Room(NewSimplePushRule::new(room_id.to_owned(), notify_actions()))
+        }
+        RoomNotificationMode::MentionsAndKeywordsOnly => {
+            NewPushRule::Room(NewSimplePushRule::new(room_id.to_owned(), Vec::new()))
+        }
+        RoomNotificationMode::Mute => NewPushRule::Override(NewConditionalPushRule::new(
+            room_id.to_string(),
+            vec![PushCondition::EventMatch {
+                key: "room_id".to_owned(),
+                pattern: room_id.to_string(),
+            }],
+            Vec::new(),
+        )),
+    };
+
+    ruleset.insert(new_rule, None, None).expect("room rules never use a server-default rule ID");
+}
+
+impl StateChanges {
+    /// Update the `StateChanges` struct with the push rules resulting of
+    /// setting the notification mode of the given room in `ruleset`.
+    ///
+    /// `ruleset` should be the current push rules of the user.
+    pub fn set_room_notification_mode(
+        &mut self,
+        mut ruleset: Ruleset,
+        room_id: &RoomId,
+        mode: RoomNotificationMode,
+    ) {
+        set_user_defined_room_notification_mode(&mut ruleset, room_id, mode);
+
+        let event = json!({
+            "type": GlobalAccountDataEventType::PushRules.to_string(),
+            "content": PushRulesEventContent::new(ruleset),
+        });
+        let raw = Raw::new(&event).expect("push rules can always be serialized").cast();
+        self.account_data.insert(GlobalAccountDataEventType::PushRules, raw);
+    }
+}
+
+fn is_room_condition(condition: &PushCondition, room_id: &RoomId) -> bool {
+    matches!(
+        condition,
+        PushCondition::EventMatch { key, pattern } if key == "room_id" && pattern == room_id
+    )
+}
+
+fn notify_actions() -> Vec<Action> {
+    vec![Action::Notify, Action::SetTweak(Tweak::Sound("default".into()))]
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_test::async_test;
+    use ruma::{push::Ruleset, room_id, user_id};
+
+    use super::{get_user_defined_room_notification_mode, RoomNotificationMode};
+    use crate::{
+        store::{MemoryStore, StateStoreExt},
+        StateChanges, StateStore,
+    };
+
+    #[async_test]
+    async fn test_room_notification_mode_saving() {
+        let room_id = room_id!("!test:localhost");
+        let other_room_id = room_id!("!other:localhost");
+        let store = MemoryStore::new();
+
+        // No push rules in the store yet.
+        assert_eq!(store.get_room_notification_mode(room_id).await.unwrap(), None);
+
+        let ruleset = Ruleset::server_default(user_id!("@example:localhost"));
+        let mut changes = StateChanges::default();
+        changes.set_room_notification_mode(
+            ruleset,
+            room_id,
+            RoomNotificationMode::MentionsAndKeywordsOnly,
+        );
+        store.save_changes(&changes).await.unwrap();
+
+        assert_eq!(
+            store.get_room_notification_mode(room_id).await.unwrap(),
+            Some(RoomNotificationMode::MentionsAndKeywordsOnly)
+        );
+        assert_eq!(store.get_room_notification_mode(other_room_id).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_room_notification_mode_replaces_previous_mode() {
+        let room_id = room_id!("!test:localhost");
+        let mut ruleset = Ruleset::server_default(user_id!("@example:localhost"));
+
+        for mode in [
+            RoomNotificationMode::Mute,
+            RoomNotificationMode::AllMessages,
+            RoomNotificationMode::MentionsAndKeywordsOnly,
+            RoomNotificationMode::Mute,
+        ] {
+            super::set_user_defined_room_notification_mode(&mut ruleset, room_id, mode);
+            assert_eq!(get_user_defined_room_notification_mode(&ruleset, room_id), Some(mode));
+        }
+    }
+}
@@ -197,6 +197,12 @@ fn new_nonempty_ring_buffer() -> RingBuffer<OwnedEventId> {
 }
 
 impl RoomReadReceipts {
+    /// Whether the latest read receipt of the user was found in the timeline,
+    /// in which case the counts are those of the events after it.
+    pub(crate) fn has_latest_active(&self) -> bool {
+        self.latest_active.is_some()
+    }
+
     /// Update the [`RoomReadReceipts`] unread counts according to the new
     /// event.
     ///
@@ -12,8 +12,8 @@ use std::{
 use bitflags::bitflags;
 pub use members::RoomMember;
 pub use normal::{
-    Room, RoomHero, RoomInfo, RoomInfoNotableUpdate, RoomInfoNotableUpdateReasons, RoomState,
-    RoomStateFilter,
+    LazyRoomInfo, Room, RoomHero, RoomInfo, RoomInfoNotableUpdate, RoomInfoNotableUpdateReasons,
+    RoomState, RoomStateFilter,
 };
 use ruma::{
     assign,
@@ -521,6 +521,13 @@ bitflags! {
 
 impl RoomMemberships {
     /// Whether the given membership matches this `RoomMemberships`.
+    ///
+    /// Every known membership matches only its own flag. Unknown memberships,
+    /// which can come from a future version of the spec, never match a
+    /// non-empty filter, so they are only returned by the stores when the
+    /// members are not filtered at all. The member events are always stored
+    /// with their actual membership, so no data is lost when a new state is
+    /// added here.
     pub fn matches(&self, membership: &MembershipState) -> bool {
         if self.is_empty() {
             return true;
@@ -12,18 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
-use std::sync::RwLock as SyncRwLock;
 use std::{
     collections::{BTreeMap, HashSet},
     mem,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{atomic::AtomicBool, Arc, RwLock as SyncRwLock},
 };
 
 use bitflags::bitflags;
 use eyeball::{SharedObservable, Subscriber};
 use futures_util::{Stream, StreamExt};
-#[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
 use matrix_sdk_common::ring_buffer::RingBuffer;
 #[cfg(feature = "experimental-sliding-sync")]
 use ruma::events::AnySyncTimelineEvent;
@@ -49,8 +46,8 @@ use ruma::{
     },
     room::RoomType,
     serde::Raw,
-    EventId, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId,
-    RoomAliasId, RoomId, RoomVersionId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId,
+    OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, RoomVersionId, UserId,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
@@ -63,7 +60,7 @@ use super::{
 #[cfg(feature = "experimental-sliding-sync")]
 use crate::latest_event::LatestEvent;
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, RawSyncOrStrippedState},
     notification_settings::RoomNotificationMode,
     read_receipts::RoomReadReceipts,
     store::{DynStateStore, Result as StoreResult, StateStoreExt},
@@ -125,6 +122,8 @@ pub struct Room {
     inner: SharedObservable<RoomInfo>,
     room_info_notable_update_sender: broadcast::Sender<RoomInfoNotableUpdate>,
     store: Arc<DynStateStore>,
+    /// The number of threads used to deserialize the members of the room.
+    member_decode_parallelism: usize,
 
     /// The most recent few encrypted events. When the keys come through to
     /// decrypt these, the most recent relevant one will replace
@@ -137,6 +136,14 @@ pub struct Room {
     /// to disk but held in memory.
     #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
     pub latest_encrypted_events: Arc<SyncRwLock<RingBuffer<Raw<AnySyncTimelineEvent>>>>,
+
+    /// The IDs of the last messages counted in the
+    /// [`RoomInfo::recent_messages`], to not count a message twice when it's
+    /// received again, for example after a limited sync.
+    ///
+    /// These are held in memory rather than in the `RoomInfo`, to not
+    /// serialize them again every time the room is saved.
+    recent_message_ids: Arc<SyncRwLock<RingBuffer<OwnedEventId>>>,
 }
 
 /// The room summary containing member counts and members that should be used to
@@ -177,7 +184,7 @@ impl RoomSummary {
 }
 
 /// Enum keeping track in which state the room is, e.g. if our own user is
-/// joined, invited, or has left the room.
+/// joined, invited, knocked, or has left the room.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum RoomState {
     /// The room is in a joined state.
@@ -186,23 +193,69 @@ pub enum RoomState {
     Left,
     /// The room is in a invited state.
     Invited,
+    /// The room is in a knocked state, i.e. we asked to join it and are
+    /// waiting for the request to be approved.
+    Knocked,
 }
 
 impl From<&MembershipState> for RoomState {
     fn from(membership_state: &MembershipState) -> Self {
-        // We consider Ban, Knock and Leave to be Left, because they all mean we are not
-        // in the room.
+        // We consider Ban and Leave to be Left, because they both mean we are not in
+        // the room.
         match membership_state {
             MembershipState::Ban => Self::Left,
             MembershipState::Invite => Self::Invited,
             MembershipState::Join => Self::Joined,
-            MembershipState::Knock => Self::Left,
+            MembershipState::Knock => Self::Knocked,
             MembershipState::Leave => Self::Left,
             _ => panic!("Unexpected MembershipState: {}", membership_state),
         }
     }
 }
 
+/// The minimum number of member events deserialized by each blocking task,
+/// below which spawning a task costs more than it saves.
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_MEMBER_EVENTS_PER_TASK: usize = 500;
+
+/// Deserialize the given member events, splitting the work between up to
+/// `parallelism` tasks on the blocking thread pool of the runtime.
+async fn deserialize_member_events(
+    raw_events: Vec<RawSyncOrStrippedState<RoomMemberEventContent>>,
+    parallelism: usize,
+) -> serde_json::Result<Vec<MemberEvent>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if parallelism > 1 && raw_events.len() > MIN_MEMBER_EVENTS_PER_TASK {
+        let chunk_size = raw_events.len().div_ceil(parallelism).max(MIN_MEMBER_EVENTS_PER_TASK);
+        let event_count = raw_events.len();
+
+        let mut raw_events = raw_events.into_iter();
+        let mut workers = Vec::new();
+        loop {
+            let chunk: Vec<_> = raw_events.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+
+            workers.push(tokio::task::spawn_blocking(move || {
+                chunk.iter().map(|raw| raw.deserialize()).collect::<Result<Vec<_>, _>>()
+            }));
+        }
+
+        let mut member_events = Vec::with_capacity(event_count);
+        for worker in workers {
+            member_events.extend(worker.await.expect("member deserialization panicked")?);
+        }
+
+        return Ok(member_events);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    let _ = parallelism;
+
+    raw_events.iter().map(|raw| raw.deserialize()).collect()
+}
+
 /// The number of heroes chosen to compute a room's name, if the room didn't
 /// have a name set by the users themselves.
 ///
@@ -218,6 +271,11 @@ impl Room {
     const MAX_ENCRYPTED_EVENTS: std::num::NonZeroUsize =
         unsafe { std::num::NonZeroUsize::new_unchecked(10) };
 
+    /// The size of the recent_message_ids RingBuffer
+    // SAFETY: `new_unchecked` is safe because 50 is not zero.
+    const MAX_RECENT_MESSAGE_IDS: std::num::NonZeroUsize =
+        unsafe { std::num::NonZeroUsize::new_unchecked(50) };
+
     pub(crate) fn new(
         own_user_id: &UserId,
         store: Arc<DynStateStore>,
@@ -239,15 +297,28 @@ impl Room {
             own_user_id: own_user_id.into(),
             room_id: room_info.room_id.clone(),
             store,
+            member_decode_parallelism: 1,
             inner: SharedObservable::new(room_info),
             #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
             latest_encrypted_events: Arc::new(SyncRwLock::new(RingBuffer::new(
                 Self::MAX_ENCRYPTED_EVENTS,
             ))),
+            recent_message_ids: Arc::new(SyncRwLock::new(RingBuffer::new(
+                Self::MAX_RECENT_MESSAGE_IDS,
+            ))),
             room_info_notable_update_sender,
         }
     }
 
+    /// Set the number of threads used to deserialize the members of this
+    /// room, see [`StoreConfig::member_decode_parallelism`].
+    ///
+    /// [`StoreConfig::member_decode_parallelism`]: crate::store::StoreConfig::member_decode_parallelism
+    pub(crate) fn with_member_decode_parallelism(mut self, parallelism: usize) -> Self {
+        self.member_decode_parallelism = parallelism;
+        self
+    }
+
     /// Get the unique room id of the room.
     pub fn room_id(&self) -> &RoomId {
         &self.room_id
@@ -391,7 +462,7 @@ impl Room {
     #[instrument(skip_all, fields(room_id = ?self.room_id))]
     pub async fn is_direct(&self) -> StoreResult<bool> {
         match self.state() {
-            RoomState::Joined | RoomState::Left => {
+            RoomState::Joined | RoomState::Left | RoomState::Knocked => {
                 Ok(!self.inner.read().base_info.dm_targets.is_empty())
             }
 
@@ -519,7 +590,9 @@ impl Room {
     /// or otherwise calculate it, taking into account its name, aliases and
     /// members.
     ///
-    /// The display name is calculated according to [this algorithm][spec].
+    /// The display name is calculated according to [this algorithm][spec],
+    /// unless the room has a local nickname set with
+    /// [`StateStoreExt::set_room_nickname`].
     ///
     /// This is automatically recomputed on every successful sync, and the
     /// cached result can be retrieved in
@@ -539,6 +612,10 @@ impl Room {
             new_val
         };
 
+        if let Some(nickname) = self.store.get_room_nickname(self.room_id()).await? {
+            return Ok(update_cache(DisplayName::Named(nickname)));
+        }
+
         let summary = {
             let inner = self.inner.read();
 
@@ -693,6 +770,21 @@ impl Room {
         self.inner.read().latest_event.as_deref().cloned()
     }
 
+    /// Remember that the message with the given ID was counted in the
+    /// [`RoomInfo::recent_messages`].
+    ///
+    /// Returns `false` if it was already counted.
+    pub(crate) fn mark_recent_message(&self, event_id: &EventId) -> bool {
+        let mut recent_message_ids = self.recent_message_ids.write().unwrap();
+
+        if recent_message_ids.iter().any(|id| id == event_id) {
+            return false;
+        }
+
+        recent_message_ids.push(event_id.to_owned());
+        true
+    }
+
     /// Return the most recent few encrypted events. When the keys come through
     /// to decrypt these, the most recent relevant one will replace
     /// latest_event. (We can't tell which one is relevant until
@@ -750,16 +842,15 @@ impl Room {
             return Ok(Vec::new());
         }
 
-        let member_events = self
+        let raw_member_events = self
             .store
             .get_state_events_for_keys_static::<RoomMemberEventContent, _, _>(
                 self.room_id(),
                 &user_ids,
             )
-            .await?
-            .into_iter()
-            .map(|raw_event| raw_event.deserialize())
-            .collect::<Result<Vec<_>, _>>()?;
+            .await?;
+        let member_events =
+            deserialize_member_events(raw_member_events, self.member_decode_parallelism).await?;
 
         let mut profiles = self.store.get_profiles(self.room_id(), &user_ids).await?;
 
@@ -1079,6 +1170,54 @@ pub struct RoomInfo {
     #[cfg(feature = "experimental-sliding-sync")]
     #[serde(default)]
     pub(crate) recency_stamp: Option<u64>,
+
+    /// The messages recently received in the timeline of this room through
+    /// sync.
+    #[serde(default, skip_serializing_if = "ruma::serde::is_default")]
+    pub(crate) recent_messages: RecentMessages,
+}
+
+/// The number of hours during which a message counts towards the activity of
+/// a room.
+const RECENT_MESSAGES_WINDOW_HOURS: u64 = 24;
+
+const HOUR_IN_MILLIS: u64 = 60 * 60 * 1000;
+
+/// The number of messages a room received during the last
+/// [`RECENT_MESSAGES_WINDOW_HOURS`], per hour.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct RecentMessages {
+    /// The number of messages sent during each hour, keyed by the number of
+    /// hours since the unix epoch.
+    counts: BTreeMap<u64, u64>,
+}
+
+impl RecentMessages {
+    /// Count a message, unless it is older than the window.
+    pub(crate) fn record(
+        &mut self,
+        origin_server_ts: MilliSecondsSinceUnixEpoch,
+        now: MilliSecondsSinceUnixEpoch,
+    ) {
+        let first_hour = Self::first_hour(now);
+        let hour = u64::from(origin_server_ts.get()) / HOUR_IN_MILLIS;
+
+        if hour < first_hour {
+            return;
+        }
+
+        *self.counts.entry(hour).or_default() += 1;
+        self.counts.retain(|hour, _| *hour >= first_hour);
+    }
+
+    /// The number of messages sent during the window ending at `now`.
+    pub(crate) fn count(&self, now: MilliSecondsSinceUnixEpoch) -> u64 {
+        self.counts.range(Self::first_hour(now)..).map(|(_, count)| count).sum()
+    }
+
+    fn first_hour(now: MilliSecondsSinceUnixEpoch) -> u64 {
+        (u64::from(now.get()) / HOUR_IN_MILLIS).saturating_sub(RECENT_MESSAGES_WINDOW_HOURS - 1)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -1119,6 +1258,7 @@ impl RoomInfo {
             cached_user_defined_notification_mode: None,
             #[cfg(feature = "experimental-sliding-sync")]
             recency_stamp: None,
+            recent_messages: Default::default(),
         }
     }
 
@@ -1137,6 +1277,11 @@ impl RoomInfo {
         self.room_state = RoomState::Invited;
     }
 
+    /// Mark this Room as knocked.
+    pub fn mark_as_knocked(&mut self) {
+        self.room_state = RoomState::Knocked;
+    }
+
     /// Set the membership RoomState of this Room
     pub fn set_state(&mut self, room_state: RoomState) {
         self.room_state = room_state;
@@ -1551,6 +1696,74 @@ impl RoomInfo {
     }
 }
 
+/// A [`RoomInfo`] loaded from a store, that is only deserialized when it's
+/// accessed.
+///
+/// Only the ID of the room is read upfront, which makes it cheap to list all
+/// the rooms of a store and to only pay for the ones that are actually used.
+///
+/// See [`StateStore::get_lazy_room_infos`][crate::store::StateStore::get_lazy_room_infos].
+#[derive(Clone, Debug)]
+pub struct LazyRoomInfo {
+    room_id: OwnedRoomId,
+    inner: LazyRoomInfoInner,
+}
+
+#[derive(Clone, Debug)]
+enum LazyRoomInfoInner {
+    Raw(Raw<RoomInfo>),
+    Deserialized(Box<RoomInfo>),
+}
+
+impl LazyRoomInfo {
+    /// Create a `LazyRoomInfo` from a serialized [`RoomInfo`].
+    ///
+    /// Returns an error if the ID of the room can't be read from it.
+    pub fn from_raw(raw: Raw<RoomInfo>) -> serde_json::Result<Self> {
+        let room_id = raw
+            .get_field("room_id")?
+            .ok_or_else(|| <serde_json::Error as serde::de::Error>::missing_field("room_id"))?;
+
+        Ok(Self { room_id, inner: LazyRoomInfoInner::Raw(raw) })
+    }
+
+    /// The ID of the room.
+    pub fn room_id(&self) -> &RoomId {
+        &self.room_id
+    }
+
+    /// The state of the room.
+    ///
+    /// Only this field is deserialized if the [`RoomInfo`] hasn't been yet.
+    pub fn state(&self) -> serde_json::Result<RoomState> {
+        match &self.inner {
+            LazyRoomInfoInner::Raw(raw) => raw.get_field("room_state")?.ok_or_else(|| {
+                <serde_json::Error as serde::de::Error>::missing_field("room_state")
+            }),
+            LazyRoomInfoInner::Deserialized(info) => Ok(info.state()),
+        }
+    }
+
+    /// Whether the [`RoomInfo`] has already been deserialized.
+    pub fn is_deserialized(&self) -> bool {
+        matches!(self.inner, LazyRoomInfoInner::Deserialized(_))
+    }
+
+    /// Get the [`RoomInfo`], deserializing it if necessary.
+    pub fn deserialize(self) -> serde_json::Result<RoomInfo> {
+        match self.inner {
+            LazyRoomInfoInner::Raw(raw) => raw.deserialize(),
+            LazyRoomInfoInner::Deserialized(info) => Ok(*info),
+        }
+    }
+}
+
+impl From<RoomInfo> for LazyRoomInfo {
+    fn from(info: RoomInfo) -> Self {
+        Self { room_id: info.room_id.clone(), inner: LazyRoomInfoInner::Deserialized(info.into()) }
+    }
+}
+
 #[cfg(feature = "experimental-sliding-sync")]
 fn apply_redaction(
     event: &Raw<AnySyncTimelineEvent>,
@@ -1599,6 +1812,8 @@ bitflags! {
         const INVITED  = 0b00000010;
         /// The room is in a left state.
         const LEFT     = 0b00000100;
+        /// The room is in a knocked state.
+        const KNOCKED  = 0b00001000;
     }
 }
 
@@ -1613,6 +1828,7 @@ impl RoomStateFilter {
             RoomState::Joined => Self::JOINED,
             RoomState::Left => Self::LEFT,
             RoomState::Invited => Self::INVITED,
+            RoomState::Knocked => Self::KNOCKED,
         };
 
         self.contains(bit_state)
@@ -1631,6 +1847,9 @@ impl RoomStateFilter {
         if self.contains(Self::INVITED) {
             states.push(RoomState::Invited);
         }
+        if self.contains(Self::KNOCKED) {
+            states.push(RoomState::Knocked);
+        }
 
         states
     }
@@ -1673,6 +1892,7 @@ fn compute_display_name_from_heroes(num_joined_invited: u64, mut heroes: Vec<&st
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::BTreeSet,
         ops::{Not, Sub},
         str::FromStr,
         sync::Arc,
@@ -1706,18 +1926,23 @@ mod tests {
         owned_event_id, room_alias_id, room_id,
         serde::Raw,
         time::SystemTime,
-        user_id, EventEncryptionAlgorithm, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId,
-        UserId,
+        uint, user_id, EventEncryptionAlgorithm, MilliSecondsSinceUnixEpoch, OwnedEventId,
+        OwnedUserId, UserId,
     };
     use serde_json::json;
     use stream_assert::{assert_pending, assert_ready};
 
-    use super::{compute_display_name_from_heroes, Room, RoomHero, RoomInfo, RoomState, SyncInfo};
+    use super::{
+        compute_display_name_from_heroes, LazyRoomInfo, RecentMessages, Room, RoomHero, RoomInfo,
+        RoomState, SyncInfo,
+    };
     #[cfg(any(feature = "experimental-sliding-sync", feature = "e2e-encryption"))]
     use crate::latest_event::LatestEvent;
     use crate::{
-        store::{MemoryStore, StateChanges, StateStore},
-        BaseClient, DisplayName, MinimalStateEvent, OriginalMinimalStateEvent, SessionMeta,
+        rooms::RoomMember,
+        store::{MemoryStore, StateChanges, StateStore, StateStoreExt},
+        BaseClient, DisplayName, MinimalStateEvent, OriginalMinimalStateEvent, RoomMemberships,
+        SessionMeta,
     };
 
     #[test]
@@ -1762,6 +1987,7 @@ mod tests {
             cached_display_name: None,
             cached_user_defined_notification_mode: None,
             recency_stamp: Some(42),
+            recent_messages: Default::default(),
         };
 
         let info_json = json!({
@@ -1905,6 +2131,29 @@ mod tests {
         assert!(info.base_info.topic.is_none());
     }
 
+    #[test]
+    fn test_lazy_room_info_defers_deserialization() {
+        // Only the room ID is valid, the rest is only read when deserializing.
+        let raw = Raw::new(&json!({
+            "room_id": "!lazy:localhost",
+            "room_state": "NotAState",
+        }))
+        .unwrap()
+        .cast();
+        let lazy = LazyRoomInfo::from_raw(raw).unwrap();
+        assert_eq!(lazy.room_id(), room_id!("!lazy:localhost"));
+        assert!(!lazy.is_deserialized());
+        lazy.deserialize().unwrap_err();
+
+        let info = RoomInfo::new(room_id!("!room:localhost"), RoomState::Joined);
+        let lazy = LazyRoomInfo::from_raw(Raw::new(&info).unwrap()).unwrap();
+        assert!(!lazy.is_deserialized());
+        assert_eq!(lazy.deserialize().unwrap().room_id(), room_id!("!room:localhost"));
+
+        // A room info that is already deserialized is kept as is.
+        assert!(LazyRoomInfo::from(info).is_deserialized());
+    }
+
     #[test]
     #[cfg(feature = "experimental-sliding-sync")]
     fn test_room_info_deserialization() {
@@ -2223,6 +2472,26 @@ mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_display_name_for_joined_room_prefers_nickname_over_name() {
+        let (store, room) = make_room_test_helper(RoomState::Joined);
+        room.inner.update(|info| info.base_info.name = Some(make_name_event()));
+
+        store.set_room_nickname(room.room_id(), Some("My Room")).await.unwrap();
+        assert_eq!(
+            room.compute_display_name().await.unwrap(),
+            DisplayName::Named("My Room".to_owned())
+        );
+
+        // Removing the nickname brings back the name of the room.
+        store.set_room_nickname(room.room_id(), None).await.unwrap();
+        assert_eq!(store.get_room_nickname(room.room_id()).await.unwrap(), None);
+        assert_eq!(
+            room.compute_display_name().await.unwrap(),
+            DisplayName::Named("Test Room".to_owned())
+        );
+    }
+
     #[async_test]
     async fn test_display_name_for_invited_room_is_empty_if_no_info() {
         let (_, room) = make_room_test_helper(RoomState::Invited);
@@ -2523,6 +2792,45 @@ mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_members_deserialized_in_parallel_match_serial() {
+        let (store, room) = make_room_test_helper(RoomState::Joined);
+        let room_id = room_id!("!test:localhost");
+        let mut changes = StateChanges::new("".to_owned());
+
+        let members = changes
+            .state
+            .entry(room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default();
+        // Enough members to be split between several tasks.
+        for i in 0..1200 {
+            let user_id = UserId::parse(format!("@user_{i}:example.org")).unwrap();
+            members.insert(user_id.to_string(), make_member_event(&user_id, &i.to_string()).cast());
+        }
+        store.save_changes(&changes).await.unwrap();
+
+        let member_names = |members: Vec<RoomMember>| {
+            members
+                .into_iter()
+                .map(|member| (member.user_id().to_owned(), member.name().to_owned()))
+                .collect::<BTreeSet<_>>()
+        };
+
+        let serial = member_names(room.members(RoomMemberships::JOIN).await.unwrap());
+        let parallel = member_names(
+            room.clone()
+                .with_member_decode_parallelism(4)
+                .members(RoomMemberships::JOIN)
+                .await
+                .unwrap(),
+        );
+
+        assert_eq!(serial.len(), 1200);
+        assert_eq!(parallel, serial);
+    }
+
     #[async_test]
     #[cfg(feature = "experimental-sliding-sync")]
     async fn test_setting_the_latest_event_doesnt_cause_a_room_info_notable_update() {
@@ -2879,4 +3187,20 @@ mod tests {
         assert!(room.is_encryption_state_synced());
         assert!(room.is_encrypted());
     }
+
+    #[test]
+    fn test_recent_messages_decay() {
+        let mut recent_messages = RecentMessages::default();
+        let now = timestamp(0);
+
+        recent_messages.record(timestamp(30), now);
+        recent_messages.record(timestamp(0), now);
+        // Older than the window.
+        recent_messages.record(timestamp(25 * 60), now);
+        assert_eq!(recent_messages.count(now), 2);
+
+        // The messages stop counting once they're out of the window.
+        let tomorrow = MilliSecondsSinceUnixEpoch(now.0 + uint!(24 * 60 * 60 * 1000));
+        assert_eq!(recent_messages.count(tomorrow), 0);
+    }
 }
@@ -28,15 +28,13 @@ use ruma::{
     api::client::sync::sync_events::v3::{self, InvitedRoom},
     events::{AnyRoomAccountDataEvent, AnySyncStateEvent, AnySyncTimelineEvent},
     serde::Raw,
-    JsOption, OwnedRoomId, RoomId, UInt,
+    JsOption, MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId, UInt,
 };
 use tracing::{debug, error, instrument, trace, warn};
 
 use super::BaseClient;
 #[cfg(feature = "e2e-encryption")]
 use crate::latest_event::{is_suitable_for_latest_event, LatestEvent, PossibleLatestEvent};
-#[cfg(feature = "e2e-encryption")]
-use crate::RoomMemberships;
 use crate::{
     error::Result,
     read_receipts::{compute_unread_counts, PreviousEventsProvider},
@@ -48,6 +46,8 @@ use crate::{
     sync::{JoinedRoomUpdate, LeftRoomUpdate, Notification, RoomUpdates, SyncResponse},
     Room, RoomInfo,
 };
+#[cfg(feature = "e2e-encryption")]
+use crate::{sync::DecryptedEvents, RoomMemberships};
 
 impl BaseClient {
     #[cfg(feature = "e2e-encryption")]
@@ -59,7 +59,7 @@ impl BaseClient {
     pub async fn process_sliding_sync_e2ee(
         &self,
         extensions: &http::response::Extensions,
-    ) -> Result<Vec<Raw<AnyToDeviceEvent>>> {
+    ) -> Result<(Vec<Raw<AnyToDeviceEvent>>, DecryptedEvents)> {
         if extensions.is_empty() {
             return Ok(Default::default());
         }
@@ -87,7 +87,7 @@ impl BaseClient {
         // Passing in the default empty maps and vecs for this is completely fine, since
         // the `OlmMachine` assumes empty maps/vecs mean no change in the one-time key
         // counts.
-        let to_device = self
+        let (to_device, decrypted_events) = self
             .preprocess_to_device_events(
                 matrix_sdk_crypto::EncryptionSyncChanges {
                     to_device_events,
@@ -108,7 +108,7 @@ impl BaseClient {
         self.apply_changes(&changes, room_info_notable_updates);
         trace!("applied changes");
 
-        Ok(to_device)
+        Ok((to_device, decrypted_events))
     }
 
     /// Process a response from a sliding sync call.
@@ -262,7 +262,7 @@ impl BaseClient {
                         .or_insert_with(LeftRoomUpdate::default)
                         .account_data
                         .append(&mut raw.to_vec()),
-                    RoomState::Invited => {}
+                    RoomState::Invited | RoomState::Knocked => {}
                 }
             }
         }
@@ -320,6 +320,9 @@ impl BaseClient {
 
         changes.ambiguity_maps = ambiguity_cache.cache;
 
+        self.enforce_max_members_per_room(&mut changes).await?;
+        changes.last_sync_time = Some(MilliSecondsSinceUnixEpoch::now());
+
         trace!("ready to submit changes to store");
         store.save_changes(&changes).await?;
         self.apply_changes(&changes, room_info_notable_updates);
@@ -339,6 +342,7 @@ impl BaseClient {
             presence: Default::default(),
             account_data: account_data.global.clone(),
             to_device: Default::default(),
+            decrypted_events: Default::default(),
         })
     }
 
@@ -535,7 +539,7 @@ impl BaseClient {
                 None,
             )),
 
-            RoomState::Invited => Ok((room_info, None, None, invited_room)),
+            RoomState::Invited | RoomState::Knocked => Ok((room_info, None, None, invited_room)),
         }
     }
 
@@ -891,7 +895,7 @@ mod tests {
         rooms::normal::{RoomHero, RoomInfoNotableUpdateReasons},
         store::MemoryStore,
         test_utils::logged_in_base_client,
-        BaseClient, Room, RoomInfoNotableUpdate, RoomState,
+        BaseClient, Room, RoomInfoNotableUpdate, RoomState, RoomStateFilter,
     };
 
     #[async_test]
@@ -1106,6 +1110,33 @@ mod tests {
         assert!(!sync_resp.rooms.invite.contains_key(room_id));
     }
 
+    #[async_test]
+    async fn test_knocked_a_room_from_required_state_event() {
+        // Given a logged-in client
+        let client = logged_in_base_client(None).await;
+        let room_id = room_id!("!r:e.uk");
+        let user_id = user_id!("@u:e.uk");
+
+        // When I knock on a room…
+        let mut room = http::response::Room::new();
+        room.required_state.push(make_membership_event(user_id, MembershipState::Knock));
+        let response = response_with_room(room_id, room);
+        let sync_resp = client
+            .process_sliding_sync(&response, &(), true)
+            .await
+            .expect("Failed to process sync");
+
+        // The room is knocked, not left.
+        assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Knocked);
+        assert_eq!(client.rooms_filtered(RoomStateFilter::KNOCKED).len(), 1);
+        assert!(client.rooms_filtered(RoomStateFilter::LEFT).is_empty());
+
+        // And it isn't added to any of the joined, left or invited lists.
+        assert!(!sync_resp.rooms.join.contains_key(room_id));
+        assert!(!sync_resp.rooms.leave.contains_key(room_id));
+        assert!(!sync_resp.rooms.invite.contains_key(room_id));
+    }
+
     #[async_test]
     async fn test_kick_or_ban_updates_room_to_left() {
         for membership in [MembershipState::Leave, MembershipState::Ban] {
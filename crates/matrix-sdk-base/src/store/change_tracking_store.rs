@@ -0,0 +1,579 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, RwLock as StdRwLock},
+};
+
+use async_trait::async_trait;
+use ruma::{
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncTimelineEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomAliasId, OwnedRoomId,
+    OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UserId,
+};
+
+use super::{
+    ChildTransactionId, CustomValueUpdate, DependentQueuedEvent, DependentQueuedEventKind,
+    DynStateStore, QueuedEvent, Result, SerializableEventContent, StateChanges, StateStore,
+    StoreError,
+};
+use crate::{
+    deserialized_responses::RawAnySyncOrStrippedState, LazyRoomInfo, MinimalRoomMemberEvent,
+    RoomInfo, RoomMemberships, StateStoreDataKey, StateStoreDataValue,
+};
+
+/// A [`StateStore`] that records the rooms and members touched by the writes
+/// made through it: the saved changes, and the rooms, invites, stale state
+/// and members that are removed.
+///
+/// The `Store` wraps its inner store with it, so that the changes saved
+/// through [`BaseClient::store`] or by the rooms directly are tracked too, not
+/// only the ones of the sync.
+///
+/// [`BaseClient::store`]: crate::BaseClient::store
+#[derive(Debug)]
+pub(super) struct ChangeTrackingStore {
+    inner: Arc<DynStateStore>,
+    room_changes: Arc<StdRwLock<RoomChanges>>,
+}
+
+impl ChangeTrackingStore {
+    /// Wrap `inner`, recording the changes saved through it in
+    /// `room_changes`.
+    pub(super) fn new(
+        inner: Arc<DynStateStore>,
+        room_changes: Arc<StdRwLock<RoomChanges>>,
+    ) -> Self {
+        Self { inner, room_changes }
+    }
+}
+
+/// Keeps track of which rooms and members were touched by the saved
+/// changes.
+///
+/// This lives in memory only: the sequence numbers start again from `0`
+/// every time the client is created.
+#[derive(Debug, Default)]
+pub(super) struct RoomChanges {
+    /// The sequence number of the last saved changes.
+    pub(super) seq: u64,
+    /// The sequence number of the last change of each room.
+    pub(super) rooms: BTreeMap<OwnedRoomId, u64>,
+    /// The sequence number of the last change of each member, by room.
+    pub(super) members: BTreeMap<OwnedRoomId, BTreeMap<OwnedUserId, u64>>,
+}
+
+impl RoomChanges {
+    /// Get the sequence number of a new change.
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    /// Mark the rooms and the members touched by `changes` as changed, with
+    /// a new sequence number.
+    fn record(&mut self, changes: &StateChanges) {
+        let changed_rooms = changes.changed_rooms();
+        if changed_rooms.is_empty() {
+            return;
+        }
+
+        let seq = self.next_seq();
+        for room_id in changed_rooms {
+            self.rooms.insert(room_id.to_owned(), seq);
+        }
+
+        let member_state_keys = changes
+            .state
+            .iter()
+            .filter_map(|(room_id, state)| {
+                let events = state.get(&StateEventType::RoomMember)?;
+                Some((room_id, events.keys().collect::<Vec<_>>()))
+            })
+            .chain(changes.stripped_state.iter().filter_map(|(room_id, state)| {
+                let events = state.get(&StateEventType::RoomMember)?;
+                Some((room_id, events.keys().collect::<Vec<_>>()))
+            }));
+        for (room_id, state_keys) in member_state_keys {
+            let members = self.members.entry(room_id.clone()).or_default();
+            for user_id in state_keys.into_iter().filter_map(|key| UserId::parse(key).ok()) {
+                members.insert(user_id, seq);
+            }
+        }
+
+        for (room_id, user_ids) in &changes.members_to_delete {
+            let members = self.members.entry(room_id.clone()).or_default();
+            for user_id in user_ids {
+                members.insert(user_id.clone(), seq);
+            }
+        }
+    }
+
+    /// Mark the given room and the given members of it as changed, with a new
+    /// sequence number.
+    fn record_members(&mut self, room_id: &RoomId, user_ids: Vec<OwnedUserId>) {
+        let seq = self.next_seq();
+        self.rooms.insert(room_id.to_owned(), seq);
+
+        let members = self.members.entry(room_id.to_owned()).or_default();
+        for user_id in user_ids {
+            members.insert(user_id, seq);
+        }
+    }
+
+    /// Mark the given rooms as changed, with a new sequence number, and forget
+    /// the changes of their members, which were removed along with them.
+    fn record_removed_rooms(&mut self, room_ids: &[&RoomId]) {
+        if room_ids.is_empty() {
+            return;
+        }
+
+        let seq = self.next_seq();
+        for room_id in room_ids {
+            self.rooms.insert((*room_id).to_owned(), seq);
+            self.members.remove(*room_id);
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StateStore for ChangeTrackingStore {
+    type Error = StoreError;
+
+    async fn get_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+    ) -> Result<Option<StateStoreDataValue>, Self::Error> {
+        self.inner.get_kv_data(key).await
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_kv_data(key, value).await
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<(), Self::Error> {
+        self.inner.remove_kv_data(key).await
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<(), Self::Error> {
+        self.inner.save_changes(changes).await?;
+        self.room_changes.write().unwrap().record(changes);
+        Ok(())
+    }
+
+    async fn get_presence_event(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<Raw<PresenceEvent>>, Self::Error> {
+        self.inner.get_presence_event(user_id).await
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>, Self::Error> {
+        self.inner.get_presence_events(user_ids).await
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_event(room_id, event_type, state_key).await
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_events(room_id, event_type).await
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_events_for_keys(room_id, event_type, state_keys).await
+    }
+
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize, Self::Error> {
+        self.inner.count_room_events(room_id).await
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_all_state_events(room_id).await
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        self.inner.get_all_rooms_state_events(room_ids).await
+    }
+
+    async fn get_state_event_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedEventId>, Self::Error> {
+        self.inner.get_state_event_ids(room_id).await
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>, Self::Error> {
+        self.inner.get_profile(room_id, user_id).await
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>, Self::Error> {
+        self.inner.get_profiles(room_id, user_ids).await
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_user_ids(room_id, memberships).await
+    }
+
+    async fn get_invited_user_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_invited_user_ids(room_id).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_joined_user_ids(room_id).await
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.inner.get_room_infos().await
+    }
+
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>, Self::Error> {
+        self.inner.get_room_info(room_id).await
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>, Self::Error> {
+        self.inner.get_lazy_room_infos().await
+    }
+
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.inner.get_stripped_room_infos().await
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>, Self::Error> {
+        self.inner.get_users_with_display_name(room_id, display_name).await
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>, Self::Error> {
+        self.inner.get_users_with_display_names(room_id, display_names).await
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>, Self::Error> {
+        self.inner.get_account_data_event(event_type).await
+    }
+
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>, Self::Error> {
+        self.inner.get_account_data_types().await
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.inner.get_room_account_data_event(room_id, event_type).await
+    }
+
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.inner.get_room_account_data_events(room_id).await
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>, Self::Error> {
+        self.inner.get_user_room_receipt_event(room_id, receipt_type, thread, user_id).await
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error> {
+        self.inner.get_event_room_receipt_events(room_id, receipt_type, thread, event_id).await
+    }
+
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>, Self::Error> {
+        self.inner.get_latest_edit(room_id, event_id).await
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>, Self::Error> {
+        self.inner.get_alias_history_entries(room_id).await
+    }
+
+    async fn remove_events_before(
+        &self,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.inner.remove_events_before(ts).await
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.get_custom_value(key).await
+    }
+
+    async fn set_custom_value(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.set_custom_value(key, value).await
+    }
+
+    async fn set_custom_value_no_read(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_custom_value_no_read(key, value).await
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.remove_custom_value(key).await
+    }
+
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.update_custom_value(key, update).await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.inner.remove_room(room_id).await?;
+        self.room_changes.write().unwrap().record_removed_rooms(&[room_id]);
+        Ok(())
+    }
+
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<(), Self::Error> {
+        self.inner.remove_rooms(room_ids).await?;
+        self.room_changes.write().unwrap().record_removed_rooms(room_ids);
+        Ok(())
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        // The stripped members are removed, so they are the changed members.
+        let user_ids = self.inner.get_user_ids(room_id, RoomMemberships::empty()).await?;
+        self.inner.mark_invite_rejected(room_id).await?;
+        self.room_changes.write().unwrap().record_members(room_id, user_ids);
+        Ok(())
+    }
+
+    async fn clear_ephemeral(&self) -> Result<(), Self::Error> {
+        self.inner.clear_ephemeral().await
+    }
+
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>, Self::Error> {
+        self.inner.find_orphaned_members().await
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<(), Self::Error> {
+        let orphaned_members = self.inner.find_orphaned_members().await?;
+        self.inner.prune_orphaned_members().await?;
+
+        // The rooms of the orphaned members aren't known anymore, so the
+        // changes of their members are dropped instead of being reported.
+        let mut room_changes = self.room_changes.write().unwrap();
+        for (room_id, _) in orphaned_members {
+            room_changes.members.remove(&room_id);
+        }
+
+        Ok(())
+    }
+
+    async fn compact_if_needed(&self, threshold: f64) -> Result<bool, Self::Error> {
+        self.inner.compact_if_needed(threshold).await
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<(), Self::Error> {
+        let user_ids = self.inner.get_user_ids(room_id, RoomMemberships::empty()).await?;
+        self.inner.prune_stale_state(room_id, current_keys).await?;
+
+        // Only the members whose member event isn't part of the current state
+        // are removed.
+        let removed_user_ids = user_ids
+            .into_iter()
+            .filter(|user_id| {
+                !current_keys.contains(&(StateEventType::RoomMember, user_id.to_string()))
+            })
+            .collect();
+        self.room_changes.write().unwrap().record_members(room_id, removed_user_ids);
+
+        Ok(())
+    }
+
+    async fn save_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: OwnedTransactionId,
+        content: SerializableEventContent,
+    ) -> Result<(), Self::Error> {
+        self.inner.save_send_queue_event(room_id, transaction_id, content).await
+    }
+
+    async fn update_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        content: SerializableEventContent,
+    ) -> Result<bool, Self::Error> {
+        self.inner.update_send_queue_event(room_id, transaction_id, content).await
+    }
+
+    async fn remove_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+    ) -> Result<bool, Self::Error> {
+        self.inner.remove_send_queue_event(room_id, transaction_id).await
+    }
+
+    async fn load_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.inner.load_send_queue_events(room_id).await
+    }
+
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.inner.drain_send_queue_events(room_id, limit).await
+    }
+
+    async fn update_send_queue_event_status(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        wedged: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.update_send_queue_event_status(room_id, transaction_id, wedged).await
+    }
+
+    async fn load_rooms_with_unsent_events(&self) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        self.inner.load_rooms_with_unsent_events().await
+    }
+
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error> {
+        self.inner.next_send_sequence(room_id).await
+    }
+
+    async fn save_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        own_txn_id: ChildTransactionId,
+        content: DependentQueuedEventKind,
+    ) -> Result<(), Self::Error> {
+        self.inner
+            .save_dependent_send_queue_event(room_id, parent_txn_id, own_txn_id, content)
+            .await
+    }
+
+    async fn update_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        event_id: OwnedEventId,
+    ) -> Result<usize, Self::Error> {
+        self.inner.update_dependent_send_queue_event(room_id, parent_txn_id, event_id).await
+    }
+
+    async fn remove_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        own_txn_id: &ChildTransactionId,
+    ) -> Result<bool, Self::Error> {
+        self.inner.remove_dependent_send_queue_event(room_id, own_txn_id).await
+    }
+
+    async fn list_dependent_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<DependentQueuedEvent>, Self::Error> {
+        self.inner.list_dependent_send_queue_events(room_id).await
+    }
+}
@@ -1,19 +1,32 @@
 //! Trait and macro of integration tests for StateStore implementations.
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use async_trait::async_trait;
+use futures_util::future::join_all;
 use growable_bloom_filter::GrowableBloomBuilder;
 use matrix_sdk_test::test_json;
 use ruma::{
-    api::MatrixVersion,
-    event_id,
+    api::{
+        client::{
+            device::Device,
+            filter::{FilterDefinition, LazyLoadOptions},
+            sync::sync_events::v3::RoomSummary as RumaSummary,
+        },
+        MatrixVersion,
+    },
+    device_id, event_id,
     events::{
         presence::PresenceEvent,
         receipt::{ReceiptThread, ReceiptType},
         room::{
+            history_visibility::HistoryVisibility,
             member::{
                 MembershipState, RoomMemberEventContent, StrippedRoomMemberEvent,
                 SyncRoomMemberEvent,
@@ -22,22 +35,36 @@ use ruma::{
             power_levels::RoomPowerLevelsEventContent,
             topic::RoomTopicEventContent,
         },
+        tag::TagName,
         AnyEphemeralRoomEventContent, AnyGlobalAccountDataEvent, AnyMessageLikeEventContent,
         AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncEphemeralRoomEvent,
-        AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
-        SyncStateEvent,
+        AnySyncStateEvent, AnySyncTimelineEvent, GlobalAccountDataEventType,
+        RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
-    owned_event_id, owned_mxc_uri, room_id,
+    owned_event_id, owned_mxc_uri, owned_room_alias_id,
+    presence::PresenceState,
+    room_id,
     serde::Raw,
-    uint, user_id, EventId, OwnedEventId, OwnedUserId, RoomId, TransactionId, UserId,
+    server_name,
+    time::SystemTime,
+    uint, user_id, EventId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedEventId, OwnedRoomId,
+    OwnedUserId, RoomId, TransactionId, UInt, UserId,
 };
 use serde_json::{json, value::Value as JsonValue};
 
-use super::{DependentQueuedEventKind, DynStateStore, ServerCapabilities};
+use super::{
+    ComposerDraft, ComposerDraftType, CredentialBundle, DependentQueuedEventKind, DiscoveryInfo,
+    DynStateStore, FailedSend, Features, IntoStateStore, MemoryStore, RoomKind, RotationPolicy,
+    ServerCapabilities, CUSTOM_VALUE_KEY_PREFIX,
+};
 use crate::{
-    deserialized_responses::MemberEvent,
-    store::{traits::ChildTransactionId, Result, SerializableEventContent, StateStoreExt},
-    RoomInfo, RoomMemberships, RoomState, StateChanges, StateStoreDataKey, StateStoreDataValue,
+    deserialized_responses::{MemberEvent, RawAnySyncOrStrippedState},
+    store::{
+        traits::ChildTransactionId, Result, SerializableEventContent, StateMap, StateStoreExt,
+    },
+    sync::UnreadNotificationsCount,
+    DisplayName, RoomInfo, RoomMemberships, RoomState, StateChanges, StateStoreDataKey,
+    StateStoreDataValue,
 };
 
 /// `StateStore` integration tests.
@@ -87,8 +114,172 @@ pub trait StateStoreIntegrationTests {
     async fn test_send_queue(&self);
     /// Test operations related to send queue dependents.
     async fn test_send_queue_dependents(&self);
+    /// Test draining events from the send queue.
+    async fn test_send_queue_drain(&self);
     /// Test saving/restoring server capabilities.
     async fn test_server_capabilities_saving(&self);
+    /// Test copying the content of a `MemoryStore` into this store.
+    async fn test_persist_from_memory_store(&self) -> Result<()>;
+    /// Test marking items as acknowledged.
+    async fn test_acknowledged_saving(&self) -> Result<()>;
+    /// Test reading the history visibility of rooms.
+    async fn test_history_visibility(&self) -> Result<()>;
+    /// Test counting the state events of rooms.
+    async fn test_count_room_events(&self) -> Result<()>;
+    /// Test finding the direct message room with a user.
+    async fn test_get_dm_room(&self) -> Result<()>;
+    /// Test loading room infos lazily.
+    async fn test_get_lazy_room_infos(&self) -> Result<()>;
+    /// Test saving the bookkeeping of outbound group sessions.
+    async fn test_group_session_meta_saving(&self) -> Result<()>;
+    /// Test tracking the rooms whose keys need to be backed up.
+    async fn test_rooms_needing_backup(&self) -> Result<()>;
+    /// Test saving many presence events at once.
+    async fn test_save_presence_in_bulk(&self) -> Result<()>;
+    /// Test listing the event ids of the state events of rooms.
+    async fn test_get_state_event_ids(&self) -> Result<()>;
+    /// Test remembering the sends that failed.
+    async fn test_failed_sends(&self) -> Result<()>;
+    /// Test getting the memberships of all the members of a room.
+    async fn test_get_room_memberships(&self) -> Result<()>;
+    /// Test setting the read marker of a room locally.
+    async fn test_local_read_marker(&self) -> Result<()>;
+    /// Test counting the rooms the user is invited to.
+    async fn test_invited_rooms_count(&self) -> Result<()>;
+    /// Test getting the avatar of rooms.
+    async fn test_get_room_avatar(&self) -> Result<()>;
+    /// Test getting the rooms with a tag, sorted by order.
+    async fn test_get_rooms_with_tag(&self) -> Result<()>;
+    /// Test that clearing the ephemeral data keeps the rest of the store.
+    async fn test_clear_ephemeral(&self) -> Result<()>;
+    /// Test finding and pruning the members of rooms without a room info.
+    async fn test_orphaned_members(&self) -> Result<()>;
+    /// Test getting the rooms in which a user is banned.
+    async fn test_get_banned_rooms_for_user(&self) -> Result<()>;
+    /// Test saving and loading a filter definition.
+    async fn test_filter_definition(&self) -> Result<()>;
+    /// Test getting the stripped members of an invited room.
+    async fn test_get_stripped_members(&self) -> Result<()>;
+
+    /// Test getting the whole state of a room as a map.
+    async fn test_get_room_state(&self) -> Result<()>;
+
+    /// Test that members are filtered by each of their membership states.
+    async fn test_user_ids_by_membership(&self) -> Result<()>;
+
+    /// Test saving and loading the information about the devices of the user.
+    async fn test_device_info(&self) -> Result<()>;
+
+    /// Test getting the other participant of a direct message room.
+    async fn test_get_dm_peer(&self) -> Result<()>;
+
+    /// Test listing the types of the global account data events.
+    async fn test_get_account_data_types(&self) -> Result<()>;
+
+    /// Test that send-order sequence numbers increase for every room.
+    async fn test_next_send_sequence(&self) -> Result<()>;
+
+    /// Test estimating the storage used by every room.
+    async fn test_room_storage_breakdown(&self) -> Result<()>;
+
+    /// Test hiding invites locally.
+    async fn test_hidden_invites(&self) -> Result<()>;
+
+    /// Test getting the pending third-party invites of a room.
+    async fn test_get_third_party_invites(&self) -> Result<()>;
+
+    /// Test checking whether a room is a space.
+    async fn test_is_space(&self) -> Result<()>;
+
+    /// Test sorting the invites by the time they were received.
+    async fn test_get_invites_sorted(&self) -> Result<()>;
+
+    /// Test caching the discovery info.
+    async fn test_discovery_saving(&self) -> Result<()>;
+
+    /// Test finding the rooms missing a state event type.
+    async fn test_rooms_missing_state(&self) -> Result<()>;
+
+    /// Test getting the display names of a user across rooms.
+    async fn test_get_user_display_names(&self) -> Result<()>;
+
+    /// Test saving the feature flags of the client.
+    async fn test_features_saving(&self) -> Result<()>;
+
+    /// Test rejecting an invite.
+    async fn test_mark_invite_rejected(&self) -> Result<()>;
+
+    /// Test remembering the reported events.
+    async fn test_reported_events(&self) -> Result<()>;
+
+    /// Test reading the rotation policy of encrypted rooms.
+    async fn test_get_rotation_policy(&self) -> Result<()>;
+
+    /// Test telling apart the summary and the loaded member counts.
+    async fn test_summary_member_count(&self) -> Result<()>;
+
+    /// Test saving and removing the state of verification flows.
+    async fn test_verification_state(&self) -> Result<()>;
+
+    /// Test finding the rooms where a user has a minimum power level.
+    async fn test_get_rooms_where_user_has_power(&self) -> Result<()>;
+
+    /// Test classifying the rooms into DMs, group rooms and spaces.
+    async fn test_classify_rooms(&self) -> Result<()>;
+
+    /// Test listing the members waiting for an answer to their knock.
+    async fn test_get_knocking_members(&self) -> Result<()>;
+
+    /// Test getting all the room account data of a room at once.
+    async fn test_get_room_account_data_events(&self) -> Result<()>;
+
+    /// Test saving the time of the last sync.
+    async fn test_last_sync_time_saving(&self) -> Result<()>;
+
+    /// Test exporting and importing the credentials of the store.
+    async fn test_credentials_export_import(&self) -> Result<()>;
+
+    /// Test reading the "marked unread" flag of a room.
+    async fn test_marked_unread(&self) -> Result<()>;
+
+    /// Test removing several rooms at once.
+    async fn test_rooms_removal(&self) -> Result<()>;
+
+    /// Test that the changes are visible right after they are saved.
+    async fn test_read_your_writes(&self) -> Result<()>;
+
+    /// Test ranking the emoji the user reacted with.
+    async fn test_recent_reactions(&self) -> Result<()>;
+
+    /// Test saving the sync tokens of several sync connections.
+    async fn test_sync_token_per_connection(&self) -> Result<()>;
+
+    /// Test removing the state events that aren't in the current state.
+    async fn test_prune_stale_state(&self) -> Result<()>;
+    /// Test fetching the state of several rooms at once.
+    async fn test_get_rooms_state(&self) -> Result<()>;
+    /// Test blocking and unblocking servers.
+    async fn test_blocked_servers(&self) -> Result<()>;
+    /// Test that a room list entry matches the values of the separate getters.
+    async fn test_room_list_entry(&self) -> Result<()>;
+    /// Test saving, loading and clearing the composer draft of a room.
+    async fn test_composer_draft(&self) -> Result<()>;
+    /// Test summing the unread notification counts of the rooms of a space.
+    async fn test_space_unread_summary(&self) -> Result<()>;
+    /// Test that saving the info of a knocked room again keeps its stripped state.
+    async fn test_knocked_room_info_resave(&self) -> Result<()>;
+    /// Test saving the latest edits and removing them.
+    async fn test_latest_edit_saving(&self) -> Result<()>;
+    /// Test removing the receipts and presence events older than a given time.
+    async fn test_remove_events_before(&self) -> Result<()>;
+    /// Test saving the alias history and removing it with its room.
+    async fn test_alias_history_saving(&self) -> Result<()>;
+    /// Test updating a custom value atomically.
+    async fn test_update_custom_value(&self) -> Result<()>;
+    /// Test deleting members along with their member event.
+    async fn test_members_to_delete(&self) -> Result<()>;
+    /// Test setting the local nickname of a room and removing it with its room.
+    async fn test_room_nickname(&self) -> Result<()>;
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -259,6 +450,12 @@ impl StateStoreIntegrationTests for DynStateStore {
         assert!(self.get_kv_data(StateStoreDataKey::SyncToken).await?.is_some());
         assert!(self.get_presence_event(user_id).await?.is_some());
         assert_eq!(self.get_room_infos().await?.len(), 2, "Expected to find 2 room infos");
+        assert_eq!(
+            self.get_room_info(room_id).await?.map(|info| info.room_id),
+            Some(room_id.to_owned()),
+            "Expected to find the room info of the room"
+        );
+        assert!(self.get_room_info(room_id!("!unknown:localhost")).await?.is_none());
         #[allow(deprecated)]
         let stripped_rooms = self.get_stripped_room_infos().await?;
         assert_eq!(stripped_rooms.len(), 1, "Expected to find 1 stripped room info");
@@ -1044,6 +1241,7 @@ impl StateStoreIntegrationTests for DynStateStore {
         self.remove_room(stripped_room_id).await?;
 
         assert!(self.get_room_infos().await?.is_empty(), "still room info found");
+        assert!(self.get_room_info(room_id).await?.is_none(), "still room info found");
         #[allow(deprecated)]
         let stripped_rooms = self.get_stripped_room_infos().await?;
         assert!(stripped_rooms.is_empty(), "still stripped room info found");
@@ -1473,163 +1671,3040 @@ impl StateStoreIntegrationTests for DynStateStore {
         let dependents = self.list_dependent_send_queue_events(room_id).await.unwrap();
         assert_eq!(dependents.len(), 2);
     }
-}
 
-/// Macro building to allow your StateStore implementation to run the entire
-/// tests suite locally.
-///
-/// You need to provide a `async fn get_store() -> StoreResult<impl StateStore>`
-/// providing a fresh store on the same level you invoke the macro.
-///
-/// ## Usage Example:
-/// ```no_run
-/// # use matrix_sdk_base::store::{
-/// #    StateStore,
-/// #    MemoryStore as MyStore,
-/// #    Result as StoreResult,
-/// # };
-///
-/// #[cfg(test)]
-/// mod tests {
-///     use super::{MyStore, StateStore, StoreResult};
-///
-///     async fn get_store() -> StoreResult<impl StateStore> {
-///         Ok(MyStore::new())
-///     }
-///
-///     statestore_integration_tests!();
-/// }
-/// ```
-#[allow(unused_macros, unused_extern_crates)]
-#[macro_export]
-macro_rules! statestore_integration_tests {
-    () => {
-        mod statestore_integration_tests {
-            use matrix_sdk_test::async_test;
-            use $crate::store::{
-                IntoStateStore, Result as StoreResult, StateStoreIntegrationTests,
-            };
+    async fn test_send_queue_drain(&self) {
+        let room_id = room_id!("!test_send_queue_drain:localhost");
+        let other_room_id = room_id!("!test_send_queue_drain_other:localhost");
 
-            use super::get_store;
+        // Draining an empty queue works.
+        assert!(self.drain_send_queue_events(room_id, 2).await.unwrap().is_empty());
 
-            #[async_test]
-            async fn test_topic_redaction() -> StoreResult<()> {
-                let store = get_store().await?.into_state_store();
-                store.test_topic_redaction().await
-            }
+        let mut txns = Vec::new();
+        for i in 0..3 {
+            let txn = TransactionId::new();
+            let event = SerializableEventContent::new(
+                &RoomMessageEventContent::text_plain(format!("msg{i}")).into(),
+            )
+            .unwrap();
+            self.save_send_queue_event(room_id, txn.clone(), event).await.unwrap();
+            txns.push(txn);
+        }
 
-            #[async_test]
-            async fn test_populate_store() -> StoreResult<()> {
-                let store = get_store().await?.into_state_store();
-                store.test_populate_store().await
-            }
+        let event =
+            SerializableEventContent::new(&RoomMessageEventContent::text_plain("other").into())
+                .unwrap();
+        self.save_send_queue_event(other_room_id, TransactionId::new(), event).await.unwrap();
 
-            #[async_test]
-            async fn test_member_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_member_saving().await
-            }
+        // The two oldest events are drained, in order.
+        let drained = self.drain_send_queue_events(room_id, 2).await.unwrap();
+        assert_eq!(drained.len(), 2);
+        assert_eq!(drained[0].transaction_id, txns[0]);
+        assert_eq!(drained[1].transaction_id, txns[1]);
 
-            #[async_test]
-            async fn test_filter_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_filter_saving().await
-            }
+        // And they're not in the store anymore.
+        let pending = self.load_send_queue_events(room_id).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].transaction_id, txns[2]);
 
-            #[async_test]
-            async fn test_user_avatar_url_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_user_avatar_url_saving().await
-            }
+        // Draining more than what's left only returns what's left.
+        let drained = self.drain_send_queue_events(room_id, 10).await.unwrap();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].transaction_id, txns[2]);
+        assert!(self.load_send_queue_events(room_id).await.unwrap().is_empty());
 
-            #[async_test]
-            async fn test_server_capabilities_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_server_capabilities_saving().await
-            }
+        // The other room's queue wasn't touched.
+        assert_eq!(self.load_send_queue_events(other_room_id).await.unwrap().len(), 1);
+        let outstanding_rooms = self.load_rooms_with_unsent_events().await.unwrap();
+        assert_eq!(outstanding_rooms, vec![other_room_id.to_owned()]);
+    }
 
-            #[async_test]
-            async fn test_sync_token_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_sync_token_saving().await
-            }
+    async fn test_persist_from_memory_store(&self) -> Result<()> {
+        let room_id = room_id();
+        let user_id = user_id();
+        let stripped_room_id = stripped_room_id();
 
-            #[async_test]
-            async fn test_utd_hook_manager_data_saving() {
-                let store = get_store().await.expect("creating store failed").into_state_store();
-                store.test_utd_hook_manager_data_saving().await;
-            }
+        let memory_store = Arc::new(MemoryStore::new());
+        let source = memory_store.clone().into_state_store();
+        source.populate().await?;
 
-            #[async_test]
-            async fn test_stripped_member_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_stripped_member_saving().await
-            }
+        source
+            .set_kv_data(
+                StateStoreDataKey::Filter("filter_name"),
+                StateStoreDataValue::Filter("filter_id".to_owned()),
+            )
+            .await?;
+        source.set_custom_value(b"my_key", b"my_value".to_vec()).await?;
 
-            #[async_test]
-            async fn test_power_level_saving() {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_power_level_saving().await
-            }
+        let txn = TransactionId::new();
+        let event =
+            SerializableEventContent::new(&RoomMessageEventContent::text_plain("msg").into())
+                .unwrap();
+        source.save_send_queue_event(room_id, txn.clone(), event).await?;
 
-            #[async_test]
-            async fn test_receipts_saving() {
-                let store = get_store().await.expect("creating store failed").into_state_store();
-                store.test_receipts_saving().await;
-            }
+        memory_store.persist_into(self).await?;
 
-            #[async_test]
-            async fn test_custom_storage() -> StoreResult<()> {
-                let store = get_store().await?.into_state_store();
-                store.test_custom_storage().await
-            }
+        assert_let!(
+            Some(StateStoreDataValue::SyncToken(sync_token)) =
+                self.get_kv_data(StateStoreDataKey::SyncToken).await?
+        );
+        assert_eq!(sync_token, "t392-516_47314_0_7_1_1_1_11444_1");
+        assert_let!(
+            Some(StateStoreDataValue::Filter(filter_id)) =
+                self.get_kv_data(StateStoreDataKey::Filter("filter_name")).await?
+        );
+        assert_eq!(filter_id, "filter_id");
+        assert_eq!(self.get_custom_value(b"my_key").await?.as_deref(), Some(&b"my_value"[..]));
 
-            #[async_test]
-            async fn test_persist_invited_room() -> StoreResult<()> {
-                let store = get_store().await?.into_state_store();
-                store.test_persist_invited_room().await
-            }
+        assert_eq!(self.get_room_infos().await?.len(), 2);
+        assert!(self.get_presence_event(user_id).await?.is_some());
+        assert!(self
+            .get_account_data_event(GlobalAccountDataEventType::PushRules)
+            .await?
+            .is_some());
+        assert!(self
+            .get_room_account_data_event(room_id, RoomAccountDataEventType::Tag)
+            .await?
+            .is_some());
+        assert!(self.get_state_event(room_id, StateEventType::RoomName, "").await?.is_some());
+        assert!(self.get_profile(room_id, user_id).await?.is_some());
+        assert_eq!(self.get_user_ids(room_id, RoomMemberships::empty()).await?.len(), 2);
+        assert_eq!(self.get_users_with_display_name(room_id, "example").await?.len(), 2);
+        assert!(self
+            .get_state_event(stripped_room_id, StateEventType::RoomName, "")
+            .await?
+            .is_some());
+        assert!(self.get_member_event(stripped_room_id, user_id).await?.is_some());
+        assert_eq!(
+            self.get_event_room_receipt_events(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                first_receipt_event_id()
+            )
+            .await?
+            .len(),
+            1
+        );
 
-            #[async_test]
-            async fn test_stripped_non_stripped() -> StoreResult<()> {
-                let store = get_store().await.unwrap().into_state_store();
-                store.test_stripped_non_stripped().await
-            }
+        let pending = self.load_send_queue_events(room_id).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].transaction_id, txn);
 
-            #[async_test]
-            async fn test_room_removal() -> StoreResult<()> {
-                let store = get_store().await?.into_state_store();
-                store.test_room_removal().await
-            }
+        Ok(())
+    }
 
-            #[async_test]
-            async fn test_profile_removal() -> StoreResult<()> {
-                let store = get_store().await?.into_state_store();
-                store.test_profile_removal().await
-            }
+    async fn test_acknowledged_saving(&self) -> Result<()> {
+        let notice = "m.server_notice.terms:2024-06";
+        let other_notice = "m.server_notice.terms:2024-09";
 
-            #[async_test]
-            async fn test_presence_saving() {
-                let store = get_store().await.expect("creating store failed").into_state_store();
-                store.test_presence_saving().await;
-            }
+        assert!(!self.is_acknowledged(notice).await?);
+        assert!(!self.is_acknowledged(other_notice).await?);
 
-            #[async_test]
-            async fn test_display_names_saving() {
-                let store = get_store().await.expect("creating store failed").into_state_store();
-                store.test_display_names_saving().await;
-            }
+        self.mark_acknowledged(notice).await?;
+        assert!(self.is_acknowledged(notice).await?);
+        assert!(!self.is_acknowledged(other_notice).await?);
 
-            #[async_test]
-            async fn test_send_queue() {
-                let store = get_store().await.expect("creating store failed").into_state_store();
-                store.test_send_queue().await;
-            }
+        // Marking it twice is fine.
+        self.mark_acknowledged(notice).await?;
+        assert!(self.is_acknowledged(notice).await?);
 
-            #[async_test]
-            async fn test_send_queue_dependents() {
-                let store = get_store().await.expect("creating store failed").into_state_store();
-                store.test_send_queue_dependents().await;
+        Ok(())
+    }
+
+    async fn test_history_visibility(&self) -> Result<()> {
+        let world_readable_room_id = room_id!("!world_readable:localhost");
+        let shared_room_id = room_id!("!shared:localhost");
+        let unknown_room_id = room_id!("!unknown:localhost");
+
+        let history_visibility_event = |visibility: &str| {
+            serde_json::from_value::<Raw<AnySyncStateEvent>>(json!({
+                "content": { "history_visibility": visibility },
+                "event_id": "$h",
+                "origin_server_ts": 0,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.history_visibility",
+            }))
+            .unwrap()
+        };
+
+        let mut changes = StateChanges::default();
+        for (room_id, visibility) in
+            [(world_readable_room_id, "world_readable"), (shared_room_id, "shared")]
+        {
+            let raw = history_visibility_event(visibility);
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        }
+        self.save_changes(&changes).await?;
+
+        assert_eq!(
+            self.get_history_visibility(world_readable_room_id).await?,
+            Some(HistoryVisibility::WorldReadable)
+        );
+        assert_eq!(
+            self.get_history_visibility(shared_room_id).await?,
+            Some(HistoryVisibility::Shared)
+        );
+        assert_eq!(self.get_history_visibility(unknown_room_id).await?, None);
+
+        Ok(())
+    }
+
+    async fn test_count_room_events(&self) -> Result<()> {
+        let room_id = room_id!("!counted:localhost");
+        let stripped_room_id = room_id!("!counted_stripped:localhost");
+        let unknown_room_id = room_id!("!unknown:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        let mut changes = StateChanges::default();
+
+        for json in [&*test_json::NAME, &*test_json::TOPIC] {
+            let raw = serde_json::from_value::<Raw<AnySyncStateEvent>>(json.clone())?;
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        for (user_id, event_id) in
+            [(alice, owned_event_id!("$alice")), (bob, owned_event_id!("$bob"))]
+        {
+            let raw: Raw<AnySyncStateEvent> = custom_membership_event(user_id, &event_id).cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+
+        changes
+            .stripped_state
+            .entry(stripped_room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default()
+            .insert(alice.into(), custom_stripped_membership_event(alice).cast());
+
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.count_room_events(room_id).await?, 4);
+        assert_eq!(self.count_room_events(stripped_room_id).await?, 1);
+        assert_eq!(self.count_room_events(unknown_room_id).await?, 0);
+
+        Ok(())
+    }
+
+    async fn test_get_dm_room(&self) -> Result<()> {
+        let left_room_id = room_id!("!left_dm:localhost");
+        let dm_room_id = room_id!("!dm:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        let mut changes = StateChanges::default();
+
+        let direct_raw = serde_json::from_value::<Raw<AnyGlobalAccountDataEvent>>(json!({
+            "content": { alice.as_str(): [left_room_id, dm_room_id] },
+            "type": "m.direct",
+        }))?;
+        changes.add_account_data(direct_raw.deserialize()?, direct_raw);
+
+        let mut left_room = RoomInfo::new(left_room_id, RoomState::Joined);
+        left_room.mark_as_left();
+        changes.add_room(left_room);
+        changes.add_room(RoomInfo::new(dm_room_id, RoomState::Joined));
+
+        self.save_changes(&changes).await?;
+
+        // The left room is skipped, even if it comes first.
+        assert_eq!(self.get_dm_room(alice).await?.as_deref(), Some(dm_room_id));
+        assert_eq!(self.get_dm_room(bob).await?, None);
+
+        Ok(())
+    }
+
+    async fn test_get_lazy_room_infos(&self) -> Result<()> {
+        let joined_room_id = room_id!("!lazy_joined:localhost");
+        let invited_room_id = room_id!("!lazy_invited:localhost");
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(joined_room_id, RoomState::Joined));
+        changes.add_room(RoomInfo::new(invited_room_id, RoomState::Invited));
+        self.save_changes(&changes).await?;
+
+        let mut lazy_infos = self.get_lazy_room_infos().await?;
+        lazy_infos.sort_by(|a, b| a.room_id().cmp(b.room_id()));
+        assert_eq!(lazy_infos.len(), 2);
+        assert_eq!(lazy_infos[0].room_id(), invited_room_id);
+        assert_eq!(lazy_infos[1].room_id(), joined_room_id);
+
+        let infos =
+            lazy_infos.into_iter().map(|lazy| lazy.deserialize()).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(infos[0].state(), RoomState::Invited);
+        assert_eq!(infos[1].state(), RoomState::Joined);
+
+        Ok(())
+    }
+
+    async fn test_group_session_meta_saving(&self) -> Result<()> {
+        let room_id = room_id!("!encrypted:localhost");
+        let other_room_id = room_id!("!other_encrypted:localhost");
+        let key = StateStoreDataKey::GroupSessionMeta(room_id);
+
+        assert!(self.get_kv_data(key).await?.is_none());
+
+        // Count a few messages sent in the room.
+        for _ in 0..3 {
+            let mut meta = self
+                .get_kv_data(key)
+                .await?
+                .and_then(StateStoreDataValue::into_group_session_meta)
+                .unwrap_or_default();
+            meta.messages_sent += 1;
+            self.set_kv_data(key, StateStoreDataValue::GroupSessionMeta(meta)).await?;
+        }
+
+        let rotation_ts = MilliSecondsSinceUnixEpoch(uint!(1_700_000_000_000));
+        let mut meta = self.get_kv_data(key).await?.unwrap().into_group_session_meta().unwrap();
+        assert_eq!(meta.messages_sent, 3);
+        assert_eq!(meta.last_rotation_ts, None);
+
+        // Record a rotation.
+        meta.messages_sent = 0;
+        meta.last_rotation_ts = Some(rotation_ts);
+        self.set_kv_data(key, StateStoreDataValue::GroupSessionMeta(meta.clone())).await?;
+
+        assert_let!(
+            Some(StateStoreDataValue::GroupSessionMeta(stored_meta)) =
+                self.get_kv_data(key).await?
+        );
+        assert_eq!(stored_meta, meta);
+
+        // The bookkeeping is per room.
+        assert!(self
+            .get_kv_data(StateStoreDataKey::GroupSessionMeta(other_room_id))
+            .await?
+            .is_none());
+
+        self.remove_kv_data(key).await?;
+        assert!(self.get_kv_data(key).await?.is_none());
+
+        Ok(())
+    }
+
+    async fn test_rooms_needing_backup(&self) -> Result<()> {
+        let first_room_id = room_id!("!first_backup:localhost");
+        let second_room_id = room_id!("!second_backup:localhost");
+
+        assert!(self.rooms_needing_backup().await?.is_empty());
+
+        self.mark_needs_backup(first_room_id).await?;
+        self.mark_needs_backup(second_room_id).await?;
+        // Marking a room twice is a no-op.
+        self.mark_needs_backup(first_room_id).await?;
+        assert_eq!(
+            self.rooms_needing_backup().await?,
+            [first_room_id.to_owned(), second_room_id.to_owned()].into()
+        );
+
+        self.clear_needs_backup(first_room_id).await?;
+        assert_eq!(self.rooms_needing_backup().await?, [second_room_id.to_owned()].into());
+
+        // Clearing a room that doesn't need a backup is a no-op.
+        self.clear_needs_backup(first_room_id).await?;
+        self.clear_needs_backup(second_room_id).await?;
+        assert!(self.rooms_needing_backup().await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_save_presence_in_bulk(&self) -> Result<()> {
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+        let carol = user_id!("@carol:localhost");
+
+        let presence_event = |user_id: &UserId, presence: &str, last_active_ago: Option<u64>| {
+            Raw::new(&json!({
+                "content": { "presence": presence, "last_active_ago": last_active_ago },
+                "sender": user_id,
+                "type": "m.presence",
+            }))
+            .unwrap()
+            .cast::<PresenceEvent>()
+        };
+
+        self.save_presence(vec![
+            presence_event(alice, "online", Some(10)),
+            presence_event(bob, "unavailable", Some(60_000)),
+            // Alice was active more recently than this event says, so it's ignored.
+            presence_event(alice, "offline", Some(5_000)),
+            // Without `last_active_ago`, the last event wins.
+            presence_event(carol, "online", None),
+            presence_event(carol, "offline", None),
+        ])
+        .await?;
+
+        for (user_id, expected_presence) in [
+            (alice, PresenceState::Online),
+            (bob, PresenceState::Unavailable),
+            (carol, PresenceState::Offline),
+        ] {
+            let event = self.get_presence_event(user_id).await?.unwrap().deserialize()?;
+            assert_eq!(event.content.presence, expected_presence, "wrong presence for {user_id}");
+        }
+
+        Ok(())
+    }
+
+    async fn test_get_state_event_ids(&self) -> Result<()> {
+        let room_id = room_id!("!audited:localhost");
+        let other_room_id = room_id!("!other_audited:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        assert!(self.get_state_event_ids(room_id).await?.is_empty());
+
+        let mut changes = StateChanges::default();
+        for (room_id, user_id, event_id) in [
+            (room_id, alice, owned_event_id!("$alice_member")),
+            (room_id, bob, owned_event_id!("$bob_member")),
+            (other_room_id, alice, owned_event_id!("$other_alice_member")),
+        ] {
+            let raw: Raw<AnySyncStateEvent> = custom_membership_event(user_id, &event_id).cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let mut event_ids = self.get_state_event_ids(room_id).await?;
+        event_ids.sort();
+        assert_eq!(event_ids, [owned_event_id!("$alice_member"), owned_event_id!("$bob_member")]);
+
+        // Replacing a state event replaces its event id.
+        let mut changes = StateChanges::default();
+        let raw: Raw<AnySyncStateEvent> =
+            custom_membership_event(bob, event_id!("$bob_member_2")).cast();
+        changes.add_state_event(room_id, raw.deserialize()?, raw);
+        self.save_changes(&changes).await?;
+
+        let mut event_ids = self.get_state_event_ids(room_id).await?;
+        event_ids.sort();
+        assert_eq!(event_ids, [owned_event_id!("$alice_member"), owned_event_id!("$bob_member_2")]);
+
+        Ok(())
+    }
+
+    async fn test_failed_sends(&self) -> Result<()> {
+        let room_id = room_id!("!failed_sends:localhost");
+        let other_room_id = room_id!("!other_failed_sends:localhost");
+
+        assert!(self.get_failed_sends(room_id).await?.is_empty());
+
+        let failed_send = |room_id: &RoomId, body: &str| FailedSend {
+            room_id: room_id.to_owned(),
+            transaction_id: TransactionId::new(),
+            event: SerializableEventContent::new(&RoomMessageEventContent::text_plain(body).into())
+                .unwrap(),
+            error: "M_TOO_LARGE: the event is too large".to_owned(),
+        };
+
+        let failed = failed_send(room_id, "too big");
+        let txn = failed.transaction_id.clone();
+        self.save_failed_send(failed).await?;
+        self.save_failed_send(failed_send(other_room_id, "elsewhere")).await?;
+
+        let failed_sends = self.get_failed_sends(room_id).await?;
+        assert_eq!(failed_sends.len(), 1);
+        assert_eq!(failed_sends[0].transaction_id, txn);
+        assert_eq!(failed_sends[0].error, "M_TOO_LARGE: the event is too large");
+        assert_let!(
+            AnyMessageLikeEventContent::RoomMessage(content) =
+                failed_sends[0].event.deserialize().unwrap()
+        );
+        assert_eq!(content.body(), "too big");
+
+        // The failed send is only removed from its own room.
+        assert!(!self.remove_failed_send(other_room_id, &txn).await?);
+        assert!(self.remove_failed_send(room_id, &txn).await?);
+        assert!(!self.remove_failed_send(room_id, &txn).await?);
+        assert!(self.get_failed_sends(room_id).await?.is_empty());
+        assert_eq!(self.get_failed_sends(other_room_id).await?.len(), 1);
+
+        // The failed sends are removed with their room.
+        self.remove_room(other_room_id).await?;
+        assert!(self.get_failed_sends(other_room_id).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_get_room_memberships(&self) -> Result<()> {
+        let room_id = room_id!("!memberships:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+        let carol = user_id!("@carol:localhost");
+        let dave = user_id!("@dave:localhost");
+
+        assert!(self.get_room_memberships(room_id).await?.is_empty());
+
+        let mut changes = StateChanges::default();
+        for (user_id, membership) in [
+            (alice, MembershipState::Join),
+            (bob, MembershipState::Invite),
+            (carol, MembershipState::Leave),
+            (dave, MembershipState::Ban),
+        ] {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "type": "m.room.member",
+                "content": RoomMemberEventContent::new(membership),
+                "event_id": format!("$membership_{}", user_id.localpart()),
+                "origin_server_ts": 0,
+                "sender": user_id,
+                "state_key": user_id,
+            }))?
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let memberships = self.get_room_memberships(room_id).await?;
+        assert_eq!(memberships.len(), 4);
+        assert_eq!(memberships[alice], MembershipState::Join);
+        assert_eq!(memberships[bob], MembershipState::Invite);
+        assert_eq!(memberships[carol], MembershipState::Leave);
+        assert_eq!(memberships[dave], MembershipState::Ban);
+
+        Ok(())
+    }
+
+    async fn test_local_read_marker(&self) -> Result<()> {
+        let room_id = room_id!("!read_marker:localhost");
+
+        let server_marker_changes = |event_id: &str| {
+            let raw = serde_json::from_value::<Raw<AnyRoomAccountDataEvent>>(json!({
+                "type": "m.fully_read",
+                "content": { "event_id": event_id },
+            }))
+            .unwrap();
+            let mut changes = StateChanges::default();
+            changes.add_room_account_data(room_id, raw.deserialize().unwrap(), raw);
+            changes
+        };
+
+        assert_eq!(self.get_read_marker(room_id).await?, None);
+
+        self.save_changes(&server_marker_changes("$server_1")).await?;
+        assert_eq!(self.get_read_marker(room_id).await?, Some(owned_event_id!("$server_1")));
+
+        // The local marker takes precedence over the one from the server.
+        self.set_local_read_marker(room_id, event_id!("$local")).await?;
+        assert_eq!(self.get_read_marker(room_id).await?, Some(owned_event_id!("$local")));
+        assert_eq!(self.get_server_read_marker(room_id).await?, Some(owned_event_id!("$server_1")));
+
+        // Until a new marker is received from the server.
+        self.save_changes(&server_marker_changes("$server_2")).await?;
+        assert_eq!(self.get_read_marker(room_id).await?, Some(owned_event_id!("$server_2")));
+
+        // The local marker is removed with the room.
+        self.set_local_read_marker(room_id, event_id!("$local")).await?;
+        self.remove_room(room_id).await?;
+        assert_eq!(self.get_read_marker(room_id).await?, None);
+        let key = format!("{CUSTOM_VALUE_KEY_PREFIX}local_read_marker:{room_id}");
+        assert!(self.get_custom_value(key.as_bytes()).await?.is_none());
+
+        Ok(())
+    }
+
+    async fn test_invited_rooms_count(&self) -> Result<()> {
+        let first_invite_id = room_id!("!first_invite:localhost");
+        let second_invite_id = room_id!("!second_invite:localhost");
+
+        assert_eq!(self.invited_rooms_count().await?, 0);
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id!("!joined:localhost"), RoomState::Joined));
+        changes.add_room(RoomInfo::new(first_invite_id, RoomState::Invited));
+        changes.add_room(RoomInfo::new(second_invite_id, RoomState::Invited));
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.invited_rooms_count().await?, 2);
+
+        // Accepting an invite removes it from the count.
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(first_invite_id, RoomState::Joined));
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.invited_rooms_count().await?, 1);
+
+        Ok(())
+    }
+
+    async fn test_get_room_avatar(&self) -> Result<()> {
+        let room_id = room_id!("!avatar:localhost");
+        let dm_room_id = room_id!("!avatar_dm:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        assert_eq!(self.get_room_avatar(room_id).await?, None);
+
+        let mut changes = StateChanges::default();
+
+        let avatar_raw = serde_json::from_value::<Raw<AnySyncStateEvent>>(json!({
+            "content": { "url": "mxc://localhost/room_avatar" },
+            "event_id": "$room_avatar",
+            "origin_server_ts": 0,
+            "sender": alice,
+            "state_key": "",
+            "type": "m.room.avatar",
+        }))?;
+        changes.add_state_event(room_id, avatar_raw.deserialize()?, avatar_raw);
+
+        let direct_raw = serde_json::from_value::<Raw<AnyGlobalAccountDataEvent>>(json!({
+            "content": { bob.as_str(): [dm_room_id] },
+            "type": "m.direct",
+        }))?;
+        changes.add_account_data(direct_raw.deserialize()?, direct_raw);
+
+        let member_raw = serde_json::from_value::<Raw<AnySyncStateEvent>>(json!({
+            "content": { "avatar_url": "mxc://localhost/bob_avatar", "membership": "join" },
+            "event_id": "$bob_member",
+            "origin_server_ts": 0,
+            "sender": bob,
+            "state_key": bob,
+            "type": "m.room.member",
+        }))?;
+        changes.add_state_event(dm_room_id, member_raw.deserialize()?, member_raw);
+
+        self.save_changes(&changes).await?;
+
+        assert_eq!(
+            self.get_room_avatar(room_id).await?,
+            Some(owned_mxc_uri!("mxc://localhost/room_avatar"))
+        );
+        // The DM room has no avatar of its own, so the peer's is used.
+        assert_eq!(
+            self.get_room_avatar(dm_room_id).await?,
+            Some(owned_mxc_uri!("mxc://localhost/bob_avatar"))
+        );
+
+        Ok(())
+    }
+
+    async fn test_get_rooms_with_tag(&self) -> Result<()> {
+        let first_id = room_id!("!tag_first:localhost");
+        let second_id = room_id!("!tag_second:localhost");
+        let unordered_id = room_id!("!tag_unordered:localhost");
+        let low_priority_id = room_id!("!tag_low_priority:localhost");
+
+        let mut changes = StateChanges::default();
+        for (room_id, tags) in [
+            (second_id, json!({ "m.favourite": { "order": 0.5 } })),
+            (unordered_id, json!({ "m.favourite": {} })),
+            (first_id, json!({ "m.favourite": { "order": 0.1 }, "u.work": { "order": 0.9 } })),
+            (low_priority_id, json!({ "m.lowpriority": { "order": 0.2 } })),
+        ] {
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+
+            let raw = serde_json::from_value::<Raw<AnyRoomAccountDataEvent>>(json!({
+                "content": { "tags": tags },
+                "type": "m.tag",
+            }))?;
+            changes.add_room_account_data(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let favourites = self.get_rooms_with_tag(&TagName::Favorite).await?;
+        assert_eq!(
+            favourites,
+            [
+                (first_id.to_owned(), Some(0.1)),
+                (second_id.to_owned(), Some(0.5)),
+                (unordered_id.to_owned(), None),
+            ]
+        );
+
+        let low_priority = self.get_rooms_with_tag(&TagName::LowPriority).await?;
+        assert_eq!(low_priority, [(low_priority_id.to_owned(), Some(0.2))]);
+
+        assert!(self.get_rooms_with_tag(&TagName::ServerNotice).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_clear_ephemeral(&self) -> Result<()> {
+        let room_id = room_id();
+        let user_id = user_id();
+        self.populate().await?;
+
+        self.clear_ephemeral().await?;
+
+        // The presence and the receipts are gone.
+        assert!(self.get_presence_event(user_id).await?.is_none());
+        assert!(self
+            .get_user_room_receipt_event(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                user_id
+            )
+            .await?
+            .is_none());
+        assert!(self
+            .get_event_room_receipt_events(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                first_receipt_event_id()
+            )
+            .await?
+            .is_empty());
+
+        // The rooms, their members and the session are kept.
+        assert_eq!(self.get_room_infos().await?.len(), 2);
+        assert!(self.get_member_event(room_id, user_id).await?.is_some());
+        assert!(self.get_state_event(room_id, StateEventType::RoomName, "").await?.is_some());
+        assert!(self.get_kv_data(StateStoreDataKey::SyncToken).await?.is_some());
+
+        Ok(())
+    }
+
+    async fn test_orphaned_members(&self) -> Result<()> {
+        let room_id = room_id!("!orphaned_members:localhost");
+        let orphan_room_id = room_id!("!orphaned_members_deleted:localhost");
+        let user_id = user_id();
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        // The room info of the second room is missing, like after a partial
+        // removal of the room.
+        for (room_id, event_id) in
+            [(room_id, event_id!("$member")), (orphan_room_id, event_id!("$orphan_member"))]
+        {
+            let raw: Raw<AnySyncStateEvent> = custom_membership_event(user_id, event_id).cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        // Stores that hash their keys can't report the orphaned members.
+        let orphans = self.find_orphaned_members().await?;
+        assert!(
+            orphans.is_empty() || orphans == [(orphan_room_id.to_owned(), user_id.to_owned())],
+            "unexpected orphaned members: {orphans:?}"
+        );
+
+        self.prune_orphaned_members().await?;
+
+        assert!(self.find_orphaned_members().await?.is_empty());
+        assert!(self.get_user_ids(orphan_room_id, RoomMemberships::empty()).await?.is_empty());
+        assert_eq!(
+            self.get_user_ids(room_id, RoomMemberships::empty()).await?,
+            [user_id.to_owned()]
+        );
+
+        Ok(())
+    }
+
+    async fn test_get_banned_rooms_for_user(&self) -> Result<()> {
+        let first_room_id = room_id!("!banned_first:localhost");
+        let second_room_id = room_id!("!banned_second:localhost");
+        let joined_room_id = room_id!("!banned_joined:localhost");
+        let moderator = user_id!("@moderator:localhost");
+        let user_id = user_id!("@banned:localhost");
+
+        assert!(self.get_banned_rooms_for_user(user_id).await?.is_empty());
+
+        let mut changes = StateChanges::default();
+        for (room_id, membership) in [
+            (first_room_id, MembershipState::Ban),
+            (second_room_id, MembershipState::Ban),
+            (joined_room_id, MembershipState::Join),
+        ] {
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "type": "m.room.member",
+                "content": RoomMemberEventContent::new(membership),
+                "event_id": format!("$ban_{}", room_id.localpart()),
+                "origin_server_ts": 0,
+                "sender": moderator,
+                "state_key": user_id,
+            }))?
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let mut banned_rooms = self.get_banned_rooms_for_user(user_id).await?;
+        banned_rooms.sort();
+        assert_eq!(banned_rooms, [first_room_id.to_owned(), second_room_id.to_owned()]);
+        assert!(self.get_banned_rooms_for_user(moderator).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_filter_definition(&self) -> Result<()> {
+        let filter_name = "filter_definition";
+        assert!(self.get_filter_definition(filter_name).await?.is_none());
+
+        let mut definition = FilterDefinition::default();
+        definition.room.state.lazy_load_options =
+            LazyLoadOptions::Enabled { include_redundant_members: false };
+        self.save_filter_definition(filter_name, &definition).await?;
+
+        let saved = self.get_filter_definition(filter_name).await?.unwrap();
+        assert_eq!(serde_json::to_value(saved)?, serde_json::to_value(&definition)?);
+        assert!(self.get_filter_definition("other_filter").await?.is_none());
+
+        Ok(())
+    }
+
+    async fn test_get_stripped_members(&self) -> Result<()> {
+        let invited_room_id = room_id!("!stripped_members:localhost");
+        let joined_room_id = room_id!("!stripped_members_joined:localhost");
+        let user_ids = [
+            user_id!("@alice:localhost"),
+            user_id!("@bob:localhost"),
+            user_id!("@carol:localhost"),
+        ];
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(invited_room_id, RoomState::Invited));
+        let stripped_members = changes
+            .stripped_state
+            .entry(invited_room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default();
+        for user_id in user_ids {
+            stripped_members
+                .insert(user_id.into(), custom_stripped_membership_event(user_id).cast());
+        }
+
+        changes.add_room(RoomInfo::new(joined_room_id, RoomState::Joined));
+        let raw: Raw<AnySyncStateEvent> =
+            custom_membership_event(user_ids[0], event_id!("$stripped_members_join")).cast();
+        changes.add_state_event(joined_room_id, raw.deserialize()?, raw);
+
+        self.save_changes(&changes).await?;
+
+        let mut members = self
+            .get_stripped_members(invited_room_id)
+            .await?
+            .iter()
+            .map(|raw| raw.deserialize().map(|event| event.state_key))
+            .collect::<Result<Vec<_>, _>>()?;
+        members.sort();
+        assert_eq!(members, user_ids);
+
+        assert!(self.get_stripped_members(joined_room_id).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_get_room_state(&self) -> Result<()> {
+        let room_id = room_id!("!room_state:localhost");
+        let other_user_id = user_id!("@other:localhost");
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        let events: [Raw<AnySyncStateEvent>; 4] = [
+            Raw::new(&json!({
+                "content": { "name": "Inspected room" },
+                "event_id": "$room_state_name",
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.name",
+            }))?
+            .cast(),
+            Raw::new(&json!({
+                "content": { "topic": "Everything at once" },
+                "event_id": "$room_state_topic",
+                "origin_server_ts": 2,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.topic",
+            }))?
+            .cast(),
+            custom_membership_event(user_id(), event_id!("$room_state_member")).cast(),
+            custom_membership_event(other_user_id, event_id!("$room_state_other_member")).cast(),
+        ];
+        for raw in events {
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let state = self.get_room_state(room_id).await?;
+        assert_eq!(
+            state.keys().cloned().collect::<Vec<_>>(),
+            [
+                (StateEventType::RoomMember, other_user_id.to_string()),
+                (StateEventType::RoomMember, user_id().to_string()),
+                (StateEventType::RoomName, String::new()),
+                (StateEventType::RoomTopic, String::new()),
+            ]
+        );
+        assert_let!(
+            Some(RawAnySyncOrStrippedState::Sync(name)) =
+                state.get(&(StateEventType::RoomName, String::new()))
+        );
+        assert_eq!(
+            name.get_field::<OwnedEventId>("event_id")?.as_deref(),
+            Some(event_id!("$room_state_name"))
+        );
+
+        assert!(self.get_room_state(room_id!("!unknown:localhost")).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_user_ids_by_membership(&self) -> Result<()> {
+        let room_id = room_id!("!memberships:localhost");
+        let members = [
+            (user_id!("@joined:localhost"), "join", RoomMemberships::JOIN),
+            (user_id!("@invited:localhost"), "invite", RoomMemberships::INVITE),
+            (user_id!("@knocking:localhost"), "knock", RoomMemberships::KNOCK),
+            (user_id!("@left:localhost"), "leave", RoomMemberships::LEAVE),
+            (user_id!("@banned:localhost"), "ban", RoomMemberships::BAN),
+        ];
+        let unknown_user_id = user_id!("@unknown:localhost");
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        let memberships = members
+            .iter()
+            .map(|(user_id, membership, _)| (*user_id, *membership))
+            .chain([(unknown_user_id, "org.example.custom")]);
+        for (user_id, membership) in memberships {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "content": { "membership": membership },
+                "event_id": format!("$membership_{}", user_id.localpart()),
+                "origin_server_ts": 1,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))?
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        // Every known membership is only returned by its own filter.
+        for (user_id, _, filter) in members {
+            assert_eq!(self.get_user_ids(room_id, filter).await?, [user_id.to_owned()]);
+        }
+
+        let mut active = self.get_user_ids(room_id, RoomMemberships::ACTIVE).await?;
+        active.sort();
+        assert_eq!(
+            active,
+            [user_id!("@invited:localhost").to_owned(), user_id!("@joined:localhost").to_owned()]
+        );
+
+        // Unknown memberships are only returned when the members aren't filtered.
+        let all = self.get_user_ids(room_id, RoomMemberships::all()).await?;
+        assert_eq!(all.len(), members.len());
+        assert!(!all.contains(&unknown_user_id.to_owned()));
+
+        let unfiltered = self.get_user_ids(room_id, RoomMemberships::empty()).await?;
+        assert_eq!(unfiltered.len(), members.len() + 1);
+        assert!(unfiltered.contains(&unknown_user_id.to_owned()));
+
+        Ok(())
+    }
+
+    async fn test_device_info(&self) -> Result<()> {
+        let phone_id = device_id!("PHONE");
+        let laptop_id = device_id!("LAPTOP");
+
+        assert!(self.get_device_info(phone_id).await?.is_none());
+        assert!(self.get_all_device_info().await?.is_empty());
+
+        let mut phone = Device::new(phone_id.to_owned());
+        phone.display_name = Some("Phone".to_owned());
+        phone.last_seen_ip = Some("10.0.0.1".to_owned());
+        phone.last_seen_ts = Some(MilliSecondsSinceUnixEpoch(uint!(1000)));
+        self.save_device_info(phone).await?;
+
+        let mut laptop = Device::new(laptop_id.to_owned());
+        laptop.display_name = Some("Laptop".to_owned());
+        self.save_device_info(laptop).await?;
+
+        let phone = self.get_device_info(phone_id).await?.expect("the phone info was saved");
+        assert_eq!(phone.display_name.as_deref(), Some("Phone"));
+        assert_eq!(phone.last_seen_ip.as_deref(), Some("10.0.0.1"));
+        assert_eq!(phone.last_seen_ts, Some(MilliSecondsSinceUnixEpoch(uint!(1000))));
+
+        // Saving a device again replaces its information.
+        let mut laptop = Device::new(laptop_id.to_owned());
+        laptop.display_name = Some("Work laptop".to_owned());
+        laptop.last_seen_ts = Some(MilliSecondsSinceUnixEpoch(uint!(2000)));
+        self.save_device_info(laptop).await?;
+
+        let devices = self.get_all_device_info().await?;
+        assert_eq!(
+            devices.iter().map(|device| device.device_id.as_str()).collect::<Vec<_>>(),
+            ["LAPTOP", "PHONE"]
+        );
+        assert_eq!(devices[0].display_name.as_deref(), Some("Work laptop"));
+        assert_eq!(devices[0].last_seen_ts, Some(MilliSecondsSinceUnixEpoch(uint!(2000))));
+        assert!(devices[0].last_seen_ip.is_none());
+
+        // Devices saved concurrently are all kept.
+        let tablets = ["TABLET_1", "TABLET_2", "TABLET_3"]
+            .map(|device_id| Device::new(OwnedDeviceId::from(device_id)));
+        join_all(tablets.into_iter().map(|tablet| self.save_device_info(tablet)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(self.get_all_device_info().await?.len(), 5);
+
+        Ok(())
+    }
+
+    async fn test_get_dm_peer(&self) -> Result<()> {
+        let dm_room_id = room_id!("!dm_peer:localhost");
+        let group_room_id = room_id!("!dm_peer_group:localhost");
+        let other_room_id = room_id!("!dm_peer_other:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+        let carol = user_id!("@carol:localhost");
+
+        let mut changes = StateChanges::default();
+
+        let direct_raw = serde_json::from_value::<Raw<AnyGlobalAccountDataEvent>>(json!({
+            "content": { alice.as_str(): [dm_room_id], bob.as_str(): [group_room_id] },
+            "type": "m.direct",
+        }))?;
+        changes.add_account_data(direct_raw.deserialize()?, direct_raw);
+
+        let rooms = [
+            (dm_room_id, vec![user_id(), alice]),
+            (group_room_id, vec![user_id(), bob, carol]),
+            (other_room_id, vec![user_id(), carol]),
+        ];
+        for (room_id, members) in rooms {
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+            for member in members {
+                let event_id = OwnedEventId::try_from(format!("$dm_peer_{}", member.localpart()))?;
+                let raw: Raw<AnySyncStateEvent> = custom_membership_event(member, &event_id).cast();
+                changes.add_state_event(room_id, raw.deserialize()?, raw);
+            }
+        }
+
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.get_dm_peer(dm_room_id).await?.as_deref(), Some(alice));
+        // The DM room was joined by another user, it's a group room now.
+        assert_eq!(self.get_dm_peer(group_room_id).await?, None);
+        // The room isn't a DM room at all.
+        assert_eq!(self.get_dm_peer(other_room_id).await?, None);
+
+        Ok(())
+    }
+
+    async fn test_get_account_data_types(&self) -> Result<()> {
+        assert!(self.get_account_data_types().await?.is_empty());
+
+        let mut changes = StateChanges::default();
+        for raw in [
+            json!({ "content": {}, "type": "m.direct" }),
+            json!({ "content": { "ignored_users": {} }, "type": "m.ignored_user_list" }),
+            json!({ "content": { "theme": "dark" }, "type": "org.example.settings" }),
+        ] {
+            let raw = serde_json::from_value::<Raw<AnyGlobalAccountDataEvent>>(raw)?;
+            changes.add_account_data(raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let mut types = self
+            .get_account_data_types()
+            .await?
+            .into_iter()
+            .map(|event_type| event_type.to_string())
+            .collect::<Vec<_>>();
+        types.sort();
+        assert_eq!(types, ["m.direct", "m.ignored_user_list", "org.example.settings"]);
+
+        Ok(())
+    }
+
+    async fn test_next_send_sequence(&self) -> Result<()> {
+        let room_id = room_id();
+        let other_room_id = room_id!("!other_room:localhost");
+
+        assert_eq!(self.next_send_sequence(room_id).await?, 0);
+        assert_eq!(self.next_send_sequence(room_id).await?, 1);
+        assert_eq!(self.next_send_sequence(room_id).await?, 2);
+
+        // Every room has its own sequence.
+        assert_eq!(self.next_send_sequence(other_room_id).await?, 0);
+        assert_eq!(self.next_send_sequence(room_id).await?, 3);
+
+        Ok(())
+    }
+
+    async fn test_room_storage_breakdown(&self) -> Result<()> {
+        let small_room_id = room_id!("!small_room:localhost");
+        let large_room_id = room_id!("!large_room:localhost");
+
+        let long_topic = "Long".repeat(500);
+
+        let mut changes = StateChanges::default();
+        for (room_id, event_id, topic) in [
+            (small_room_id, "$small_room_topic", "Short"),
+            (large_room_id, "$large_room_topic", long_topic.as_str()),
+        ] {
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "content": { "topic": topic },
+                "event_id": event_id,
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.topic",
+            }))?
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+
+        let member: Raw<AnySyncStateEvent> =
+            custom_membership_event(user_id(), event_id!("$large_room_member")).cast();
+        changes.add_state_event(large_room_id, member.deserialize()?, member);
+        self.save_changes(&changes).await?;
+
+        let breakdown = self.room_storage_breakdown().await?;
+        assert!(breakdown[small_room_id] > 0);
+        assert!(breakdown[large_room_id] > breakdown[small_room_id]);
+
+        Ok(())
+    }
+
+    async fn test_hidden_invites(&self) -> Result<()> {
+        let hidden_room_id = room_id!("!hidden_invite:localhost");
+        let visible_room_id = room_id!("!visible_invite:localhost");
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(hidden_room_id, RoomState::Invited));
+        changes.add_room(RoomInfo::new(visible_room_id, RoomState::Invited));
+        changes.add_room(RoomInfo::new(room_id(), RoomState::Joined));
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.get_visible_invites().await?.len(), 2);
+        assert!(!self.is_invite_hidden(hidden_room_id).await?);
+
+        self.mark_invite_hidden(hidden_room_id).await?;
+        assert!(self.is_invite_hidden(hidden_room_id).await?);
+        assert!(!self.is_invite_hidden(visible_room_id).await?);
+
+        let invites = self.get_visible_invites().await?;
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].room_id, visible_room_id);
+
+        // The hidden invite is still in the store.
+        let room_infos = self.get_room_infos().await?;
+        assert!(room_infos.iter().any(|info| info.room_id == hidden_room_id));
+        assert_eq!(self.hidden_invites().await?, [hidden_room_id.to_owned()].into());
+
+        // The invite isn't hidden anymore once the room was removed.
+        self.remove_room(hidden_room_id).await?;
+        assert!(!self.is_invite_hidden(hidden_room_id).await?);
+        assert!(self.hidden_invites().await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_get_third_party_invites(&self) -> Result<()> {
+        let room_id = room_id!("!third_party_invites:localhost");
+        let claimed_user_id = user_id!("@bob:localhost");
+
+        // A third-party invite event, redacted when it has no display name.
+        let third_party_invite =
+            |token: &str, display_name: Option<&str>| -> Result<Raw<AnySyncStateEvent>> {
+                let mut event = json!({
+                    "content": {},
+                    "event_id": format!("${token}"),
+                    "origin_server_ts": 1,
+                    "sender": user_id(),
+                    "state_key": token,
+                    "type": "m.room.third_party_invite",
+                });
+                if let Some(display_name) = display_name {
+                    event["content"] = json!({
+                        "display_name": display_name,
+                        "key_validity_url": "https://localhost/isvalid",
+                        "public_key": "a2V5",
+                    });
+                } else {
+                    event["unsigned"] = json!({
+                        "redacted_because": {
+                            "content": {},
+                            "event_id": "$redaction",
+                            "origin_server_ts": 2,
+                            "redacts": format!("${token}"),
+                            "sender": user_id(),
+                            "type": "m.room.redaction",
+                        },
+                    });
+                }
+                Ok(Raw::new(&event)?.cast())
+            };
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        let events = [
+            third_party_invite("pending_token", Some("a...@example.com"))?,
+            third_party_invite("revoked_token", None)?,
+            third_party_invite("claimed_token", Some("b...@example.com"))?,
+            Raw::new(&json!({
+                "content": {
+                    "membership": "join",
+                    "third_party_invite": {
+                        "display_name": "b...@example.com",
+                        "signed": {
+                            "mxid": claimed_user_id,
+                            "signatures": { "localhost": { "ed25519:0": "signature" } },
+                            "token": "claimed_token",
+                        },
+                    },
+                },
+                "event_id": "$claimed_member",
+                "origin_server_ts": 2,
+                "sender": claimed_user_id,
+                "state_key": claimed_user_id,
+                "type": "m.room.member",
+            }))?
+            .cast(),
+        ];
+        for raw in events {
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let invites = self.get_third_party_invites(room_id).await?;
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].token, "pending_token");
+        assert_eq!(invites[0].display_name, "a...@example.com");
+        assert_eq!(invites[0].sender, user_id());
+
+        assert!(self.get_third_party_invites(room_id!("!no_invites:localhost")).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_is_space(&self) -> Result<()> {
+        let space_id = room_id!("!space:localhost");
+        let regular_room_id = room_id!("!regular_room:localhost");
+
+        let create_event = |room_type: Option<&str>| -> Result<Raw<AnySyncStateEvent>> {
+            let mut content = json!({ "creator": user_id(), "room_version": "11" });
+            if let Some(room_type) = room_type {
+                content["type"] = room_type.into();
+            }
+
+            Ok(Raw::new(&json!({
+                "content": content,
+                "event_id": "$create",
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.create",
+            }))?
+            .cast())
+        };
+
+        let mut changes = StateChanges::default();
+        for (room_id, room_type) in [(space_id, Some("m.space")), (regular_room_id, None)] {
+            let raw = create_event(room_type)?;
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        }
+        self.save_changes(&changes).await?;
+
+        assert!(self.is_space(space_id).await?);
+        assert!(!self.is_space(regular_room_id).await?);
+        assert!(!self.is_space(room_id!("!unknown:localhost")).await?);
+
+        Ok(())
+    }
+
+    async fn test_get_invites_sorted(&self) -> Result<()> {
+        let old_invite_id = room_id!("!old_invite:localhost");
+        let new_invite_id = room_id!("!new_invite:localhost");
+        let undated_invite_id = room_id!("!undated_invite:localhost");
+
+        let mut changes = StateChanges::default();
+        for (room_id, ts) in
+            [(old_invite_id, Some(1000)), (undated_invite_id, None), (new_invite_id, Some(2000))]
+        {
+            let mut event = json!({
+                "content": { "membership": "invite" },
+                "sender": invited_user_id(),
+                "state_key": user_id(),
+                "type": "m.room.member",
+            });
+            if let Some(ts) = ts {
+                event["origin_server_ts"] = ts.into();
+            }
+            let raw = Raw::<AnyStrippedStateEvent>::new(&event)?.cast();
+
+            changes
+                .stripped_state
+                .entry(room_id.to_owned())
+                .or_default()
+                .entry(StateEventType::RoomMember)
+                .or_default()
+                .insert(user_id().to_string(), raw);
+            changes.add_room(RoomInfo::new(room_id, RoomState::Invited));
+        }
+        changes.add_room(RoomInfo::new(room_id(), RoomState::Joined));
+        self.save_changes(&changes).await?;
+
+        let invites = self.get_invites_sorted(user_id()).await?;
+        assert_eq!(
+            invites,
+            [
+                (new_invite_id.to_owned(), Some(MilliSecondsSinceUnixEpoch(uint!(2000)))),
+                (old_invite_id.to_owned(), Some(MilliSecondsSinceUnixEpoch(uint!(1000)))),
+                (undated_invite_id.to_owned(), None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    async fn test_discovery_saving(&self) -> Result<()> {
+        assert_eq!(self.get_discovery(DiscoveryInfo::DEFAULT_TTL).await?, None);
+
+        self.save_discovery(
+            "https://matrix.localhost".to_owned(),
+            Some("https://identity.localhost".to_owned()),
+        )
+        .await?;
+
+        let info = self
+            .get_discovery(DiscoveryInfo::DEFAULT_TTL)
+            .await?
+            .expect("discovery info should be cached");
+        assert_eq!(info.homeserver_url, "https://matrix.localhost");
+        assert_eq!(info.identity_server_url.as_deref(), Some("https://identity.localhost"));
+
+        // Even fresh discovery info is stale without a TTL.
+        assert_eq!(self.get_discovery(Duration::ZERO).await?, None);
+
+        // Discovery info resolved a long time ago is stale.
+        let stale: DiscoveryInfo = serde_json::from_value(json!({
+            "homeserver_url": "https://matrix.localhost",
+            "identity_server_url": null,
+            "last_fetch_ts": 0.0,
+        }))?;
+        self.set_kv_data(StateStoreDataKey::Discovery, StateStoreDataValue::Discovery(stale))
+            .await?;
+        assert_eq!(self.get_discovery(DiscoveryInfo::DEFAULT_TTL).await?, None);
+
+        self.remove_kv_data(StateStoreDataKey::Discovery).await?;
+        assert_matches!(self.get_kv_data(StateStoreDataKey::Discovery).await, Ok(None));
+
+        Ok(())
+    }
+
+    async fn test_rooms_missing_state(&self) -> Result<()> {
+        let complete_room_id = room_id!("!complete:localhost");
+        let gappy_room_id = room_id!("!gappy:localhost");
+
+        let create_event = Raw::<AnySyncStateEvent>::new(&json!({
+            "content": { "creator": user_id(), "room_version": "11" },
+            "event_id": "$create",
+            "origin_server_ts": 1,
+            "sender": user_id(),
+            "state_key": "",
+            "type": "m.room.create",
+        }))?
+        .cast();
+
+        let mut changes = StateChanges::default();
+        changes.add_state_event(complete_room_id, create_event.deserialize()?, create_event);
+        changes.add_room(RoomInfo::new(complete_room_id, RoomState::Joined));
+        changes.add_room(RoomInfo::new(gappy_room_id, RoomState::Joined));
+        self.save_changes(&changes).await?;
+
+        assert_eq!(
+            self.rooms_missing_state(StateEventType::RoomCreate).await?,
+            [gappy_room_id.to_owned()]
+        );
+
+        Ok(())
+    }
+
+    async fn test_get_user_display_names(&self) -> Result<()> {
+        let work_room_id = room_id!("!work:localhost");
+        let friends_room_id = room_id!("!friends:localhost");
+        let nameless_room_id = room_id!("!nameless:localhost");
+
+        let mut changes = StateChanges::default();
+        for (room_id, display_name) in [
+            (work_room_id, Some("Alice (Work)")),
+            (friends_room_id, Some("Ally")),
+            (nameless_room_id, None),
+        ] {
+            let mut content = json!({ "membership": "join" });
+            if let Some(display_name) = display_name {
+                content["displayname"] = display_name.into();
+            }
+            let raw = Raw::<AnySyncStateEvent>::new(&json!({
+                "content": content,
+                "event_id": "$member",
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": user_id(),
+                "type": "m.room.member",
+            }))?
+            .cast();
+
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        }
+        self.save_changes(&changes).await?;
+
+        let display_names = self.get_user_display_names(user_id()).await?;
+        assert_eq!(display_names.len(), 2);
+        assert_eq!(display_names[work_room_id], "Alice (Work)");
+        assert_eq!(display_names[friends_room_id], "Ally");
+
+        assert!(self.get_user_display_names(invited_user_id()).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_features_saving(&self) -> Result<()> {
+        assert_eq!(self.get_features().await?, None);
+
+        let features = Features {
+            can_change_password: true,
+            can_set_displayname: false,
+            can_set_avatar_url: true,
+            supported_login_types: vec!["m.login.password".to_owned(), "m.login.sso".to_owned()],
+        };
+        self.save_features(&features).await?;
+        assert_eq!(self.get_features().await?, Some(features));
+
+        let features = Features::default();
+        self.save_features(&features).await?;
+        assert_eq!(self.get_features().await?, Some(features));
+
+        Ok(())
+    }
+
+    async fn test_mark_invite_rejected(&self) -> Result<()> {
+        let room_id = stripped_room_id();
+
+        let mut changes = StateChanges::default();
+        for event in [
+            json!({
+                "content": { "membership": "invite" },
+                "sender": invited_user_id(),
+                "state_key": user_id(),
+                "type": "m.room.member",
+            }),
+            json!({
+                "content": { "name": "Invitation" },
+                "sender": invited_user_id(),
+                "state_key": "",
+                "type": "m.room.name",
+            }),
+        ] {
+            let raw = Raw::<AnyStrippedStateEvent>::new(&event)?.cast();
+            let event = raw.deserialize()?;
+            changes
+                .stripped_state
+                .entry(room_id.to_owned())
+                .or_default()
+                .entry(event.event_type())
+                .or_default()
+                .insert(event.state_key().to_owned(), raw);
+        }
+        changes.add_room(RoomInfo::new(room_id, RoomState::Invited));
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.get_all_state_events(room_id).await?.len(), 2);
+        assert!(!self.get_user_ids(room_id, RoomMemberships::empty()).await?.is_empty());
+
+        self.mark_invite_rejected(room_id).await?;
+
+        assert!(self.get_all_state_events(room_id).await?.is_empty());
+        assert!(self.get_user_ids(room_id, RoomMemberships::empty()).await?.is_empty());
+        let room_infos = self.get_room_infos().await?;
+        assert_eq!(room_infos.len(), 1);
+        assert_eq!(room_infos[0].state(), RoomState::Left);
+
+        Ok(())
+    }
+
+    async fn test_reported_events(&self) -> Result<()> {
+        let reported = event_id!("$reported");
+        let other = event_id!("$other");
+
+        assert!(!self.is_event_reported(reported).await?);
+
+        self.mark_event_reported(reported).await?;
+        assert!(self.is_event_reported(reported).await?);
+        assert!(!self.is_event_reported(other).await?);
+
+        // Reporting it again is harmless.
+        self.mark_event_reported(reported).await?;
+        assert!(self.is_event_reported(reported).await?);
+
+        Ok(())
+    }
+
+    async fn test_get_rotation_policy(&self) -> Result<()> {
+        let custom_room_id = room_id!("!custom:localhost");
+        let default_room_id = room_id!("!default:localhost");
+
+        let mut changes = StateChanges::default();
+        for (room_id, content) in [
+            (
+                custom_room_id,
+                json!({
+                    "algorithm": "m.megolm.v1.aes-sha2",
+                    "rotation_period_ms": 3_600_000,
+                    "rotation_period_msgs": 50,
+                }),
+            ),
+            (default_room_id, json!({ "algorithm": "m.megolm.v1.aes-sha2" })),
+        ] {
+            let raw = Raw::<AnySyncStateEvent>::new(&json!({
+                "content": content,
+                "event_id": "$encryption",
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.encryption",
+            }))?
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        assert_eq!(
+            self.get_rotation_policy(custom_room_id).await?,
+            Some(RotationPolicy {
+                rotation_period: Some(Duration::from_secs(3600)),
+                rotation_period_msgs: Some(50),
+            })
+        );
+        assert_eq!(
+            self.get_rotation_policy(default_room_id).await?,
+            Some(RotationPolicy::default())
+        );
+        assert_eq!(self.get_rotation_policy(room_id()).await?, None);
+
+        Ok(())
+    }
+
+    async fn test_summary_member_count(&self) -> Result<()> {
+        let room_id = room_id();
+
+        let mut room_info = RoomInfo::new(room_id, RoomState::Joined);
+        let mut summary = RumaSummary::new();
+        summary.joined_member_count = Some(uint!(42));
+        room_info.update_from_ruma_summary(&summary);
+
+        // Only our own member event was loaded.
+        let mut changes = StateChanges::default();
+        changes
+            .state
+            .entry(room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default()
+            .insert(user_id().into(), membership_event().cast());
+        changes.add_room(room_info);
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.get_summary_member_count(room_id).await?, Some(42));
+        assert_eq!(self.get_loaded_joined_members_count(room_id).await?, 1);
+
+        assert_eq!(self.get_summary_member_count(stripped_room_id()).await?, None);
+
+        Ok(())
+    }
+
+    async fn test_verification_state(&self) -> Result<()> {
+        let flow_id = "AQWJSDNKQW";
+        let other_flow_id = "$request_event";
+
+        assert_eq!(self.get_verification_state(flow_id).await?, None);
+
+        let state = json!({ "state": "ready", "methods": ["m.sas.v1"] });
+        self.save_verification_state(flow_id, &state).await?;
+        self.save_verification_state(other_flow_id, &json!({ "state": "requested" })).await?;
+        assert_eq!(self.get_verification_state(flow_id).await?, Some(state));
+
+        // The state is replaced as the flow goes on.
+        let state = json!({ "state": "started", "method": "m.sas.v1" });
+        self.save_verification_state(flow_id, &state).await?;
+        assert_eq!(self.get_verification_state(flow_id).await?, Some(state));
+
+        // Once done, the flow is forgotten, but not the other one.
+        self.remove_verification_state(flow_id).await?;
+        assert_eq!(self.get_verification_state(flow_id).await?, None);
+        assert!(self.get_verification_state(other_flow_id).await?.is_some());
+
+        Ok(())
+    }
+
+    async fn test_get_rooms_where_user_has_power(&self) -> Result<()> {
+        let admin_room_id = room_id!("!admin:localhost");
+        let member_room_id = room_id!("!member:localhost");
+
+        let mut changes = StateChanges::default();
+        for (room_id, users) in [
+            (admin_room_id, json!({ user_id().as_str(): 100 })),
+            (member_room_id, json!({ invited_user_id().as_str(): 100 })),
+        ] {
+            let raw = Raw::<AnySyncStateEvent>::new(&json!({
+                "content": { "users": users },
+                "event_id": "$power_levels",
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": "",
+                "type": "m.room.power_levels",
+            }))?
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+            changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        }
+        self.save_changes(&changes).await?;
+
+        let admin_rooms = self.get_rooms_where_user_has_power(user_id(), 100).await?;
+        assert_eq!(admin_rooms, vec![admin_room_id.to_owned()]);
+
+        let mut rooms = self.get_rooms_where_user_has_power(user_id(), 0).await?;
+        rooms.sort();
+        assert_eq!(rooms, vec![admin_room_id.to_owned(), member_room_id.to_owned()]);
+
+        Ok(())
+    }
+
+    async fn test_classify_rooms(&self) -> Result<()> {
+        let dm_room_id = room_id!("!classify_dm:localhost");
+        let crowded_dm_room_id = room_id!("!classify_crowded_dm:localhost");
+        let group_room_id = room_id!("!classify_group:localhost");
+        let space_id = room_id!("!classify_space:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        let mut changes = StateChanges::default();
+
+        let direct_raw = serde_json::from_value::<Raw<AnyGlobalAccountDataEvent>>(json!({
+            "content": { alice.as_str(): [dm_room_id], bob.as_str(): [crowded_dm_room_id] },
+            "type": "m.direct",
+        }))?;
+        changes.add_account_data(direct_raw.deserialize()?, direct_raw);
+
+        let rooms = [
+            (dm_room_id, vec![user_id(), alice]),
+            (crowded_dm_room_id, vec![user_id(), alice, bob]),
+            (group_room_id, vec![user_id(), alice]),
+            (space_id, vec![user_id()]),
+        ];
+        for (room_id, members) in rooms {
+            let mut room_info = RoomInfo::new(room_id, RoomState::Joined);
+
+            if room_id == space_id {
+                let create_raw = Raw::<AnySyncStateEvent>::new(&json!({
+                    "content": { "creator": user_id(), "room_version": "11", "type": "m.space" },
+                    "event_id": "$create",
+                    "origin_server_ts": 1,
+                    "sender": user_id(),
+                    "state_key": "",
+                    "type": "m.room.create",
+                }))?
+                .cast();
+                let create_event = create_raw.deserialize()?;
+                room_info.handle_state_event(&create_event);
+                changes.add_state_event(room_id, create_event, create_raw);
+            }
+
+            for member in members {
+                let event_id = OwnedEventId::try_from(format!("$classify_{}", member.localpart()))?;
+                let raw: Raw<AnySyncStateEvent> = custom_membership_event(member, &event_id).cast();
+                changes.add_state_event(room_id, raw.deserialize()?, raw);
+            }
+
+            changes.add_room(room_info);
+        }
+        self.save_changes(&changes).await?;
+
+        let kinds = self.classify_rooms().await?;
+        assert_eq!(kinds.get(dm_room_id), Some(&RoomKind::Dm));
+        // The DM room was joined by another user, it's a group room now.
+        assert_eq!(kinds.get(crowded_dm_room_id), Some(&RoomKind::Group));
+        assert_eq!(kinds.get(group_room_id), Some(&RoomKind::Group));
+        assert_eq!(kinds.get(space_id), Some(&RoomKind::Space));
+
+        Ok(())
+    }
+
+    async fn test_get_knocking_members(&self) -> Result<()> {
+        let room_id = room_id!("!knocks:localhost");
+        let knocking = user_id!("@knocking:localhost");
+        let let_in = user_id!("@let_in:localhost");
+
+        let member_event = |user_id: &UserId, membership: &str, ts: u64| {
+            Raw::<AnySyncStateEvent>::new(&json!({
+                "content": { "membership": membership },
+                "event_id": format!("$knock_{}_{membership}", user_id.localpart()),
+                "origin_server_ts": ts,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))
+        };
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        for raw in [member_event(knocking, "knock", 1)?, member_event(let_in, "knock", 1)?] {
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+
+        let mut knocking_members = self.get_knocking_members(room_id).await?;
+        knocking_members.sort();
+        assert_eq!(knocking_members, [knocking.to_owned(), let_in.to_owned()]);
+
+        // Once let in, the user isn't waiting anymore.
+        let mut changes = StateChanges::default();
+        let raw = member_event(let_in, "join", 2)?;
+        changes.add_state_event(room_id, raw.deserialize()?, raw);
+        self.save_changes(&changes).await?;
+
+        assert_eq!(self.get_knocking_members(room_id).await?, [knocking.to_owned()]);
+
+        Ok(())
+    }
+
+    async fn test_get_room_account_data_events(&self) -> Result<()> {
+        let room_id = room_id!("!account_data:localhost");
+        let other_room_id = room_id!("!other_account_data:localhost");
+
+        assert!(self.get_room_account_data_events(room_id).await?.is_empty());
+
+        let mut changes = StateChanges::default();
+        for raw in [
+            json!({ "content": { "tags": { "m.favourite": {} } }, "type": "m.tag" }),
+            json!({ "content": { "event_id": "$read" }, "type": "m.fully_read" }),
+            json!({ "content": { "muted": true }, "type": "org.example.notifications" }),
+        ] {
+            let raw = serde_json::from_value::<Raw<AnyRoomAccountDataEvent>>(raw)?;
+            changes.add_room_account_data(room_id, raw.deserialize()?, raw);
+        }
+        let raw = serde_json::from_value::<Raw<AnyRoomAccountDataEvent>>(
+            json!({ "content": { "tags": {} }, "type": "m.tag" }),
+        )?;
+        changes.add_room_account_data(other_room_id, raw.deserialize()?, raw);
+        self.save_changes(&changes).await?;
+
+        let events = self.get_room_account_data_events(room_id).await?;
+        let mut types = events.keys().map(ToString::to_string).collect::<Vec<_>>();
+        types.sort();
+        assert_eq!(types, ["m.fully_read", "m.tag", "org.example.notifications"]);
+        let fully_read = &events[&RoomAccountDataEventType::FullyRead];
+        assert_eq!(fully_read.get_field::<String>("type")?.as_deref(), Some("m.fully_read"));
+
+        assert_eq!(self.get_room_account_data_events(other_room_id).await?.len(), 1);
+
+        Ok(())
+    }
+
+    async fn test_last_sync_time_saving(&self) -> Result<()> {
+        assert_eq!(self.get_last_sync_time().await?, None);
+
+        let first_sync = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        self.save_last_sync_time(first_sync).await?;
+        assert_eq!(self.get_last_sync_time().await?, Some(first_sync));
+
+        // The time is replaced by the next sync.
+        let second_sync = first_sync + Duration::from_secs(120);
+        self.save_last_sync_time(second_sync).await?;
+        assert_eq!(self.get_last_sync_time().await?, Some(second_sync));
+
+        // The time is saved along with the rest of the changes of a sync.
+        let third_sync = second_sync + Duration::from_secs(120);
+        let changes = StateChanges {
+            sync_token: Some("t_last_sync_time".to_owned()),
+            last_sync_time: MilliSecondsSinceUnixEpoch::from_system_time(third_sync),
+            ..Default::default()
+        };
+        self.save_changes(&changes).await?;
+        assert_eq!(self.get_last_sync_time().await?, Some(third_sync));
+
+        // Changes without a time keep the previous one.
+        self.save_changes(&StateChanges::default()).await?;
+        assert_eq!(self.get_last_sync_time().await?, Some(third_sync));
+
+        Ok(())
+    }
+
+    async fn test_credentials_export_import(&self) -> Result<()> {
+        let bundle = self.export_credentials(&["main"]).await?;
+        assert_eq!(bundle, CredentialBundle::default());
+
+        let bundle = CredentialBundle {
+            session_meta: None,
+            sync_token: Some("s42_t1".to_owned()),
+            filters: [("main".to_owned(), "filter_1".to_owned())].into(),
+        };
+        self.import_credentials(&bundle).await?;
+
+        assert_eq!(
+            self.get_kv_data(StateStoreDataKey::SyncToken)
+                .await?
+                .and_then(StateStoreDataValue::into_sync_token)
+                .as_deref(),
+            Some("s42_t1")
+        );
+        assert_eq!(self.export_credentials(&["main"]).await?, bundle);
+
+        // Unknown filters aren't part of the bundle.
+        let exported = self.export_credentials(&["main", "other"]).await?;
+        assert_eq!(exported.filters.len(), 1);
+
+        Ok(())
+    }
+
+    async fn test_marked_unread(&self) -> Result<()> {
+        let room_id = room_id!("!marked_unread:localhost");
+
+        assert!(!self.is_marked_unread(room_id).await?);
+
+        for unread in [true, false] {
+            let raw: Raw<AnyRoomAccountDataEvent> = Raw::new(&json!({
+                "type": "com.famedly.marked_unread",
+                "content": { "unread": unread },
+            }))?
+            .cast();
+            let mut changes = StateChanges::default();
+            changes.add_room_account_data(room_id, raw.deserialize()?, raw);
+            self.save_changes(&changes).await?;
+
+            assert_eq!(self.is_marked_unread(room_id).await?, unread);
+        }
+
+        Ok(())
+    }
+
+    async fn test_rooms_removal(&self) -> Result<()> {
+        let room_id = room_id();
+        let stripped_room_id = stripped_room_id();
+        let kept_room_id = room_id!("!kept:localhost");
+        let user_id = user_id();
+
+        self.populate().await?;
+
+        let mut changes = StateChanges::default();
+        changes
+            .state
+            .entry(kept_room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default()
+            .insert(user_id.into(), membership_event().cast());
+        changes.add_room(RoomInfo::new(kept_room_id, RoomState::Joined));
+        self.save_changes(&changes).await?;
+
+        self.remove_rooms(&[room_id, stripped_room_id]).await?;
+
+        let room_infos = self.get_room_infos().await?;
+        assert_eq!(room_infos.len(), 1);
+        assert_eq!(room_infos[0].room_id, kept_room_id);
+
+        assert!(self.get_member_event(room_id, user_id).await?.is_none());
+        assert!(self.get_state_event(room_id, StateEventType::RoomName, "").await?.is_none());
+        assert!(self
+            .get_room_account_data_event(room_id, RoomAccountDataEventType::Tag)
+            .await?
+            .is_none());
+        assert!(self.get_user_ids(stripped_room_id, RoomMemberships::empty()).await?.is_empty());
+
+        // The other room is untouched.
+        assert!(self.get_member_event(kept_room_id, user_id).await?.is_some());
+
+        Ok(())
+    }
+
+    async fn test_read_your_writes(&self) -> Result<()> {
+        let room_id = room_id!("!read_your_writes:localhost");
+        let user_id = user_id();
+
+        for i in 0..10u64 {
+            let mut changes = StateChanges::default();
+            let sync_token = format!("s{i}");
+            changes.sync_token = Some(sync_token.clone());
+
+            let mut room_info = RoomInfo::new(room_id, RoomState::Joined);
+            room_info.update_notification_count(UnreadNotificationsCount {
+                highlight_count: i,
+                notification_count: i,
+            });
+            changes.add_room(room_info);
+
+            let event_id = OwnedEventId::try_from(format!("$member_{i}"))?;
+            changes
+                .state
+                .entry(room_id.to_owned())
+                .or_default()
+                .entry(StateEventType::RoomMember)
+                .or_default()
+                .insert(user_id.into(), custom_membership_event(user_id, &event_id).cast());
+
+            self.save_changes(&changes).await?;
+
+            // Every getter sees the saved values, without waiting for anything else.
+            assert_eq!(
+                self.get_kv_data(StateStoreDataKey::SyncToken)
+                    .await?
+                    .and_then(StateStoreDataValue::into_sync_token),
+                Some(sync_token)
+            );
+            let room_info = self
+                .get_room_infos()
+                .await?
+                .into_iter()
+                .find(|info| info.room_id == room_id)
+                .unwrap();
+            assert_eq!(room_info.notification_counts.notification_count, i);
+            let member = self.get_member_event(room_id, user_id).await?.unwrap();
+            assert_eq!(member.deserialize()?.event_id(), Some(&*event_id));
+        }
+
+        Ok(())
+    }
+
+    async fn test_recent_reactions(&self) -> Result<()> {
+        assert!(self.get_recent_reactions(5).await?.is_empty());
+
+        for emoji in ["👍", "🎉", "👍", "❤️", "👍", "🎉"] {
+            self.record_reaction(emoji).await?;
+        }
+
+        // The most used come first.
+        assert_eq!(self.get_recent_reactions(5).await?, ["👍", "🎉", "❤️"]);
+        assert_eq!(self.get_recent_reactions(2).await?, ["👍", "🎉"]);
+
+        // Between emoji used as many times, the most recent comes first.
+        self.record_reaction("😂").await?;
+        assert_eq!(self.get_recent_reactions(5).await?, ["👍", "🎉", "😂", "❤️"]);
+
+        // The least used emoji are forgotten when there are too many of them.
+        for i in 0..60 {
+            self.record_reaction(&format!("emoji_{i}")).await?;
+        }
+        let reactions = self.get_recent_reactions(100).await?;
+        assert_eq!(reactions.len(), 50);
+        assert_eq!(reactions[..2], ["👍", "🎉"]);
+        assert_eq!(reactions[2], "emoji_59");
+        assert!(!reactions.iter().any(|emoji| emoji == "❤️" || emoji == "😂"));
+
+        // Reactions recorded concurrently are all counted.
+        join_all((0..5).map(|_| self.record_reaction("🚀")))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(self.get_recent_reactions(2).await?, ["🚀", "👍"]);
+
+        Ok(())
+    }
+
+    async fn test_sync_token_per_connection(&self) -> Result<()> {
+        assert_eq!(self.get_sync_token("foreground").await?, None);
+
+        self.save_sync_token("foreground", "s1_foreground").await?;
+        self.save_sync_token("notifications", "s1_notifications").await?;
+        assert_eq!(self.get_sync_token("foreground").await?.as_deref(), Some("s1_foreground"));
+        assert_eq!(
+            self.get_sync_token("notifications").await?.as_deref(),
+            Some("s1_notifications")
+        );
+
+        // Each connection moves forward independently.
+        self.save_sync_token("notifications", "s2_notifications").await?;
+        assert_eq!(self.get_sync_token("foreground").await?.as_deref(), Some("s1_foreground"));
+        assert_eq!(
+            self.get_sync_token("notifications").await?.as_deref(),
+            Some("s2_notifications")
+        );
+
+        // The main sync token is separate.
+        assert!(self.get_kv_data(StateStoreDataKey::SyncToken).await?.is_none());
+
+        Ok(())
+    }
+
+    async fn test_prune_stale_state(&self) -> Result<()> {
+        let room_id = room_id();
+        let invited_user_id = invited_user_id();
+
+        self.populate().await?;
+
+        let mut current_keys: BTreeSet<_> =
+            self.get_room_state(room_id).await?.into_keys().collect();
+        let topic_key = (StateEventType::RoomTopic, String::new());
+        let invited_member_key = (StateEventType::RoomMember, invited_user_id.to_string());
+        assert!(current_keys.remove(&topic_key));
+        assert!(current_keys.remove(&invited_member_key));
+        assert!(self
+            .get_user_ids(room_id, RoomMemberships::INVITE)
+            .await?
+            .contains(&invited_user_id.to_owned()));
+
+        self.prune_stale_state(room_id, &current_keys).await?;
+
+        // Only the events that aren't in the current state are removed.
+        let state_keys: BTreeSet<_> = self.get_room_state(room_id).await?.into_keys().collect();
+        assert_eq!(state_keys, current_keys);
+        assert!(self.get_state_event(room_id, StateEventType::RoomTopic, "").await?.is_none());
+        assert!(self.get_member_event(room_id, invited_user_id).await?.is_none());
+        assert!(self.get_user_ids(room_id, RoomMemberships::INVITE).await?.is_empty());
+        assert!(self.get_member_event(room_id, user_id()).await?.is_some());
+
+        Ok(())
+    }
+
+    async fn test_get_rooms_state(&self) -> Result<()> {
+        let room_id = room_id();
+        let stripped_room_id = stripped_room_id();
+        let third_room_id = room_id!("!test_get_rooms_state:localhost");
+        let empty_room_id = room_id!("!test_get_rooms_state_empty:localhost");
+
+        self.populate().await?;
+
+        let mut changes = StateChanges::default();
+        let name_raw =
+            serde_json::from_value::<Raw<AnySyncStateEvent>>(test_json::NAME.clone()).unwrap();
+        let name_event = name_raw.deserialize().unwrap();
+        changes.add_state_event(third_room_id, name_event, name_raw);
+        self.save_changes(&changes).await?;
+
+        let rooms_state = self
+            .get_rooms_state(&[room_id, stripped_room_id, third_room_id, empty_room_id])
+            .await?;
+
+        // Rooms without state are left out.
+        assert_eq!(rooms_state.len(), 3);
+        assert!(!rooms_state.contains_key(empty_room_id));
+
+        // Every room gets the same complete state as when fetched on its own.
+        let serialize = |state: &StateMap| -> BTreeMap<(StateEventType, String), String> {
+            state
+                .iter()
+                .map(|(key, ev)| (key.clone(), serde_json::to_string(ev).unwrap()))
+                .collect()
+        };
+        for room_id in [room_id, stripped_room_id, third_room_id] {
+            let expected = self.get_room_state(room_id).await?;
+            assert!(!expected.is_empty());
+            assert_eq!(serialize(&rooms_state[room_id]), serialize(&expected));
+        }
+        assert!(rooms_state[stripped_room_id]
+            .values()
+            .all(|ev| matches!(ev, RawAnySyncOrStrippedState::Stripped(_))));
+        assert_eq!(
+            rooms_state[third_room_id].keys().collect::<Vec<_>>(),
+            [&(StateEventType::RoomName, String::new())]
+        );
+
+        Ok(())
+    }
+
+    async fn test_blocked_servers(&self) -> Result<()> {
+        let server_name = server_name!("spam.localhost");
+        let other_server_name = server_name!("other.localhost");
+
+        assert!(self.blocked_servers().await?.is_empty());
+
+        self.add_blocked_server(server_name).await?;
+        self.add_blocked_server(other_server_name).await?;
+        // Blocking a server twice doesn't duplicate it.
+        self.add_blocked_server(server_name).await?;
+        assert_eq!(
+            self.blocked_servers().await?,
+            BTreeSet::from([server_name.to_owned(), other_server_name.to_owned()])
+        );
+
+        self.remove_blocked_server(server_name).await?;
+        assert_eq!(self.blocked_servers().await?, BTreeSet::from([other_server_name.to_owned()]));
+
+        Ok(())
+    }
+
+    async fn test_room_list_entry(&self) -> Result<()> {
+        let room_id = room_id();
+
+        assert!(self.get_room_list_entry(room_id!("!unknown:localhost")).await?.is_none());
+
+        self.populate().await?;
+        self.set_room_nickname(room_id, Some("My Room")).await?;
+        let marked_unread: Raw<AnyRoomAccountDataEvent> = Raw::new(&json!({
+            "type": "com.famedly.marked_unread",
+            "content": { "unread": true },
+        }))?
+        .cast();
+        let mut info = self.get_room_info(room_id).await?.unwrap();
+        info.base_info.is_marked_unread = true;
+        info.update_notification_count(UnreadNotificationsCount {
+            highlight_count: 1,
+            notification_count: 3,
+        });
+        let mut changes = StateChanges::default();
+        changes.add_room_account_data(room_id, marked_unread.deserialize()?, marked_unread);
+        changes.add_room(info);
+        self.save_changes(&changes).await?;
+
+        let entry = self.get_room_list_entry(room_id).await?.unwrap();
+        let info = self.get_room_infos().await?.into_iter().find(|info| info.room_id == room_id);
+
+        assert_eq!(
+            entry.name,
+            Some(DisplayName::Named(self.get_room_nickname(room_id).await?.unwrap()))
+        );
+        assert_eq!(entry.avatar_url, self.get_room_avatar(room_id).await?);
+        assert_eq!(entry.unread_notification_counts, info.unwrap().notification_counts);
+        assert_eq!(entry.is_marked_unread, self.is_marked_unread(room_id).await?);
+        assert!(entry.is_marked_unread);
+        #[cfg(feature = "experimental-sliding-sync")]
+        assert_eq!(
+            entry.latest_event.and_then(|ev| ev.event_id()),
+            self.get_latest_events(&[room_id.to_owned()]).await?[0]
+                .as_ref()
+                .and_then(|ev| ev.event_id())
+        );
+
+        Ok(())
+    }
+
+    async fn test_composer_draft(&self) -> Result<()> {
+        let room_id = room_id();
+        let other_room_id = room_id!("!test_composer_draft:localhost");
+
+        assert!(self.get_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await?.is_none());
+
+        let draft = ComposerDraft {
+            plain_text: "Hello".to_owned(),
+            html_text: Some("<b>Hello</b>".to_owned()),
+            draft_type: ComposerDraftType::Reply { event_id: owned_event_id!("$replied") },
+        };
+        self.set_kv_data(
+            StateStoreDataKey::ComposerDraft(room_id),
+            StateStoreDataValue::ComposerDraft(draft.clone()),
+        )
+        .await?;
+
+        let stored = self
+            .get_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+            .await?
+            .and_then(StateStoreDataValue::into_composer_draft);
+        assert_eq!(stored, Some(draft));
+        // Drafts are kept per room.
+        assert!(self.get_kv_data(StateStoreDataKey::ComposerDraft(other_room_id)).await?.is_none());
+
+        self.remove_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await?;
+        assert!(self.get_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await?.is_none());
+
+        Ok(())
+    }
+
+    async fn test_space_unread_summary(&self) -> Result<()> {
+        let space_id = room_id!("!space:localhost");
+        let subspace_id = room_id!("!subspace:localhost");
+        let first_child_id = room_id!("!first_child:localhost");
+        let second_child_id = room_id!("!second_child:localhost");
+        let left_child_id = room_id!("!left_child:localhost");
+        let removed_child_id = room_id!("!removed_child:localhost");
+
+        let mut changes = StateChanges::default();
+        for (i, (parent_id, child_id, via)) in [
+            (space_id, first_child_id, json!(["localhost"])),
+            (space_id, subspace_id, json!(["localhost"])),
+            (space_id, left_child_id, json!(["localhost"])),
+            (space_id, removed_child_id, json!([])),
+            (subspace_id, second_child_id, json!(["localhost"])),
+            // A loop back to the space doesn't count its rooms twice.
+            (subspace_id, space_id, json!(["localhost"])),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "content": { "via": via },
+                "event_id": format!("$space_child{i}"),
+                "origin_server_ts": 1,
+                "sender": user_id(),
+                "state_key": child_id,
+                "type": "m.space.child",
+            }))
+            .unwrap()
+            .cast();
+            changes.add_state_event(parent_id, raw.deserialize().unwrap(), raw);
+        }
+
+        for (room_id, room_state, highlight_count, notification_count) in [
+            (space_id, RoomState::Joined, 0, 0),
+            (subspace_id, RoomState::Joined, 0, 0),
+            (first_child_id, RoomState::Joined, 1, 2),
+            (second_child_id, RoomState::Joined, 2, 5),
+            (left_child_id, RoomState::Left, 4, 4),
+            (removed_child_id, RoomState::Joined, 8, 8),
+        ] {
+            let mut info = RoomInfo::new(room_id, room_state);
+            info.update_notification_count(UnreadNotificationsCount {
+                highlight_count,
+                notification_count,
+            });
+            changes.add_room(info);
+        }
+        self.save_changes(&changes).await?;
+
+        assert_eq!(
+            self.space_unread_summary(space_id).await?,
+            UnreadNotificationsCount { highlight_count: 3, notification_count: 7 }
+        );
+        assert_eq!(
+            self.space_unread_summary(subspace_id).await?,
+            UnreadNotificationsCount { highlight_count: 3, notification_count: 7 }
+        );
+        assert_eq!(
+            self.space_unread_summary(first_child_id).await?,
+            UnreadNotificationsCount::default()
+        );
+
+        Ok(())
+    }
+
+    async fn test_knocked_room_info_resave(&self) -> Result<()> {
+        let room_id = room_id!("!test_knocked_room_info_resave:localhost");
+        let user_id = user_id();
+
+        let mut changes = StateChanges::default();
+        changes.add_stripped_member(room_id, user_id, custom_stripped_membership_event(user_id));
+        changes.add_room(RoomInfo::new(room_id, RoomState::Knocked));
+        self.save_changes(&changes).await?;
+
+        // Saving the room info again, without any state, keeps its stripped state.
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Knocked));
+        self.save_changes(&changes).await?;
+
+        let member_event = self.get_member_event(room_id, user_id).await?.unwrap().deserialize()?;
+        assert!(matches!(member_event, MemberEvent::Stripped(_)));
+        #[allow(deprecated)]
+        let stripped_rooms = self.get_stripped_room_infos().await?;
+        assert_eq!(stripped_rooms.len(), 1);
+
+        Ok(())
+    }
+
+    async fn test_latest_edit_saving(&self) -> Result<()> {
+        let room_id = room_id!("!test_latest_edit_saving:localhost");
+        let original_event_id = event_id!("$original");
+        let edit = |event_id: &str, ts: u64| -> Raw<AnySyncTimelineEvent> {
+            Raw::new(&json!({
+                "content": {
+                    "body": "* edited",
+                    "msgtype": "m.text",
+                    "m.new_content": { "body": "edited", "msgtype": "m.text" },
+                    "m.relates_to": { "rel_type": "m.replace", "event_id": original_event_id },
+                },
+                "event_id": event_id,
+                "origin_server_ts": ts,
+                "sender": user_id(),
+                "type": "m.room.message",
+            }))
+            .unwrap()
+            .cast()
+        };
+        let edit_id = |edit: Option<Raw<AnySyncTimelineEvent>>| {
+            edit.and_then(|edit| edit.get_field::<String>("event_id").unwrap())
+        };
+
+        assert!(self.get_latest_edit(room_id, original_event_id).await?.is_none());
+
+        let mut changes = StateChanges::default();
+        changes.add_edit(room_id, original_event_id, edit("$edit2", 2000));
+        self.save_changes(&changes).await?;
+        assert_eq!(
+            edit_id(self.get_latest_edit(room_id, original_event_id).await?).as_deref(),
+            Some("$edit2")
+        );
+
+        // An older edit saved later doesn't replace the newest one.
+        let mut changes = StateChanges::default();
+        changes.add_edit(room_id, original_event_id, edit("$edit1", 1000));
+        self.save_changes(&changes).await?;
+        assert_eq!(
+            edit_id(self.get_latest_edit(room_id, original_event_id).await?).as_deref(),
+            Some("$edit2")
+        );
+
+        // A newer one does.
+        let mut changes = StateChanges::default();
+        changes.add_edit(room_id, original_event_id, edit("$edit3", 3000));
+        self.save_changes(&changes).await?;
+        assert_eq!(
+            edit_id(self.get_latest_edit(room_id, original_event_id).await?).as_deref(),
+            Some("$edit3")
+        );
+
+        // Edits that are older than the given time are removed.
+        let other_event_id = event_id!("$other");
+        let mut changes = StateChanges::default();
+        changes.add_edit(room_id, other_event_id, edit("$other_edit", 5000));
+        self.save_changes(&changes).await?;
+        self.remove_events_before(MilliSecondsSinceUnixEpoch(uint!(4000))).await?;
+        assert!(self.get_latest_edit(room_id, original_event_id).await?.is_none());
+        assert!(self.get_latest_edit(room_id, other_event_id).await?.is_some());
+
+        // The edits of a room are removed with it.
+        self.remove_room(room_id).await?;
+        assert!(self.get_latest_edit(room_id, other_event_id).await?.is_none());
+
+        Ok(())
+    }
+
+    async fn test_remove_events_before(&self) -> Result<()> {
+        let room_id = room_id!("!test_remove_events_before:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+        let carol = user_id!("@carol:localhost");
+        let now = MilliSecondsSinceUnixEpoch::now().get();
+        let hour = UInt::from(60 * 60 * 1000u32);
+        let before = MilliSecondsSinceUnixEpoch(now - hour);
+
+        let receipts = serde_json::from_value(json!({
+            "$old": { "m.read": { alice: { "ts": now - hour - hour } } },
+            "$new": { "m.read": { bob: { "ts": now } } },
+        }))?;
+        let mut changes = StateChanges::default();
+        changes.add_receipts(room_id, receipts);
+        self.save_changes(&changes).await?;
+
+        let presence_event = |user_id: &UserId, last_active_ago: Option<UInt>| {
+            Raw::new(&json!({
+                "content": { "presence": "online", "last_active_ago": last_active_ago },
+                "sender": user_id,
+                "type": "m.presence",
+            }))
+            .unwrap()
+            .cast::<PresenceEvent>()
+        };
+        self.save_presence(vec![
+            presence_event(alice, Some(hour + hour)),
+            presence_event(bob, Some(uint!(10))),
+            presence_event(carol, None),
+        ])
+        .await?;
+
+        self.remove_events_before(before).await?;
+
+        let receipt = |user_id: &'static UserId| {
+            self.get_user_room_receipt_event(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                user_id,
+            )
+        };
+        assert!(receipt(alice).await?.is_none(), "the old receipt was kept");
+        assert_eq!(receipt(bob).await?.unwrap().0, event_id!("$new"));
+        assert!(self
+            .get_event_room_receipt_events(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                event_id!("$old")
+            )
+            .await?
+            .is_empty());
+
+        // The presence of the users is kept, even when they were inactive since
+        // before.
+        assert!(self.get_presence_event(alice).await?.is_some());
+        assert!(self.get_presence_event(bob).await?.is_some());
+        assert!(self.get_presence_event(carol).await?.is_some());
+
+        Ok(())
+    }
+
+    async fn test_alias_history_saving(&self) -> Result<()> {
+        let room_id = room_id!("!test_alias_history_saving:localhost");
+        let first = owned_room_alias_id!("#first:localhost");
+        let second = owned_room_alias_id!("#second:localhost");
+        let ts = |ts: u32| MilliSecondsSinceUnixEpoch(ts.into());
+
+        assert!(self.get_alias_history_entries(room_id).await?.is_empty());
+
+        let mut changes = StateChanges::default();
+        changes
+            .alias_history
+            .insert(room_id.to_owned(), vec![(first.clone(), ts(1000)), (first.clone(), ts(1500))]);
+        self.save_changes(&changes).await?;
+
+        // The latest alias isn't appended again.
+        changes.alias_history.insert(
+            room_id.to_owned(),
+            vec![(first.clone(), ts(1800)), (second.clone(), ts(2000))],
+        );
+        self.save_changes(&changes).await?;
+
+        assert_eq!(
+            self.get_alias_history_entries(room_id).await?,
+            [(first, ts(1000)), (second, ts(2000))]
+        );
+
+        self.remove_room(room_id).await?;
+        assert!(self.get_alias_history_entries(room_id).await?.is_empty());
+
+        Ok(())
+    }
+
+    async fn test_update_custom_value(&self) -> Result<()> {
+        let key = b"update_custom_value";
+
+        // Updating a missing value sees `None`.
+        let value = self
+            .update_custom_value(
+                key,
+                Box::new(|value| {
+                    assert_eq!(value, None);
+                    Ok(Some(b"first".to_vec()))
+                }),
+            )
+            .await?;
+        assert_eq!(value.as_deref(), Some(&b"first"[..]));
+        assert_eq!(self.get_custom_value(key).await?.as_deref(), Some(&b"first"[..]));
+
+        // Updating an existing value sees the current one.
+        self.update_custom_value(
+            key,
+            Box::new(|value| {
+                assert_eq!(value, Some(&b"first"[..]));
+                Ok(Some(b"second".to_vec()))
+            }),
+        )
+        .await?;
+        assert_eq!(self.get_custom_value(key).await?.as_deref(), Some(&b"second"[..]));
+
+        // Returning `None` removes the value.
+        let value = self.update_custom_value(key, Box::new(|_| Ok(None))).await?;
+        assert_eq!(value, None);
+        assert_eq!(self.get_custom_value(key).await?, None);
+
+        // Concurrent updates don't overwrite each other.
+        let room_ids: Vec<OwnedRoomId> = (0..10)
+            .map(|i| OwnedRoomId::try_from(format!("!concurrent_backup_{i}:localhost")).unwrap())
+            .collect();
+        join_all(room_ids.iter().map(|room_id| self.mark_needs_backup(room_id)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(self.rooms_needing_backup().await?, room_ids.iter().cloned().collect());
+
+        Ok(())
+    }
+
+    async fn test_members_to_delete(&self) -> Result<()> {
+        let room_id = room_id!("!members_to_delete:localhost");
+        let alice = user_id!("@alice:localhost");
+        let bob = user_id!("@bob:localhost");
+
+        let mut changes = StateChanges::default();
+        for user_id in [alice, bob] {
+            let raw: Raw<AnySyncStateEvent> = serde_json::from_value(json!({
+                "content": { "membership": "join" },
+                "event_id": format!("$join_{}", user_id.localpart()),
+                "origin_server_ts": 1000,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))?;
+            changes.add_state_event(room_id, raw.deserialize()?, raw);
+        }
+        self.save_changes(&changes).await?;
+        assert_eq!(self.get_user_ids(room_id, RoomMemberships::JOIN).await?.len(), 2);
+
+        let changes = StateChanges {
+            members_to_delete: [(room_id.to_owned(), vec![bob.to_owned()])].into(),
+            ..Default::default()
+        };
+        self.save_changes(&changes).await?;
+
+        assert!(self.get_member_event(room_id, alice).await?.is_some());
+        assert!(self.get_member_event(room_id, bob).await?.is_none());
+        assert_eq!(self.get_user_ids(room_id, RoomMemberships::JOIN).await?, [alice.to_owned()]);
+
+        Ok(())
+    }
+
+    async fn test_room_nickname(&self) -> Result<()> {
+        let room_id = room_id!("!nickname:localhost");
+
+        assert_eq!(self.get_room_nickname(room_id).await?, None);
+
+        self.set_room_nickname(room_id, Some("My Room")).await?;
+        assert_eq!(self.get_room_nickname(room_id).await?.as_deref(), Some("My Room"));
+
+        // The nickname is removed with the room.
+        self.remove_room(room_id).await?;
+        assert_eq!(self.get_room_nickname(room_id).await?, None);
+
+        Ok(())
+    }
+}
+
+/// Macro building to allow your StateStore implementation to run the entire
+/// tests suite locally.
+///
+/// You need to provide a `async fn get_store() -> StoreResult<impl StateStore>`
+/// providing a fresh store on the same level you invoke the macro.
+///
+/// ## Usage Example:
+/// ```no_run
+/// # use matrix_sdk_base::store::{
+/// #    StateStore,
+/// #    MemoryStore as MyStore,
+/// #    Result as StoreResult,
+/// # };
+///
+/// #[cfg(test)]
+/// mod tests {
+///     use super::{MyStore, StateStore, StoreResult};
+///
+///     async fn get_store() -> StoreResult<impl StateStore> {
+///         Ok(MyStore::new())
+///     }
+///
+///     statestore_integration_tests!();
+/// }
+/// ```
+#[allow(unused_macros, unused_extern_crates)]
+#[macro_export]
+macro_rules! statestore_integration_tests {
+    () => {
+        mod statestore_integration_tests {
+            use matrix_sdk_test::async_test;
+            use $crate::store::{
+                IntoStateStore, Result as StoreResult, StateStoreIntegrationTests,
+            };
+
+            use super::get_store;
+
+            #[async_test]
+            async fn test_topic_redaction() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_topic_redaction().await
+            }
+
+            #[async_test]
+            async fn test_populate_store() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_populate_store().await
+            }
+
+            #[async_test]
+            async fn test_member_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_member_saving().await
+            }
+
+            #[async_test]
+            async fn test_filter_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_filter_saving().await
+            }
+
+            #[async_test]
+            async fn test_user_avatar_url_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_user_avatar_url_saving().await
+            }
+
+            #[async_test]
+            async fn test_server_capabilities_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_server_capabilities_saving().await
+            }
+
+            #[async_test]
+            async fn test_sync_token_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_sync_token_saving().await
+            }
+
+            #[async_test]
+            async fn test_utd_hook_manager_data_saving() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_utd_hook_manager_data_saving().await;
+            }
+
+            #[async_test]
+            async fn test_stripped_member_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_stripped_member_saving().await
+            }
+
+            #[async_test]
+            async fn test_power_level_saving() {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_power_level_saving().await
+            }
+
+            #[async_test]
+            async fn test_receipts_saving() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_receipts_saving().await;
+            }
+
+            #[async_test]
+            async fn test_custom_storage() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_custom_storage().await
+            }
+
+            #[async_test]
+            async fn test_persist_invited_room() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_persist_invited_room().await
+            }
+
+            #[async_test]
+            async fn test_stripped_non_stripped() -> StoreResult<()> {
+                let store = get_store().await.unwrap().into_state_store();
+                store.test_stripped_non_stripped().await
+            }
+
+            #[async_test]
+            async fn test_room_removal() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_room_removal().await
+            }
+
+            #[async_test]
+            async fn test_profile_removal() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_profile_removal().await
+            }
+
+            #[async_test]
+            async fn test_presence_saving() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_presence_saving().await;
+            }
+
+            #[async_test]
+            async fn test_display_names_saving() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_display_names_saving().await;
+            }
+
+            #[async_test]
+            async fn test_send_queue() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_send_queue().await;
+            }
+
+            #[async_test]
+            async fn test_send_queue_dependents() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_send_queue_dependents().await;
+            }
+
+            #[async_test]
+            async fn test_send_queue_drain() {
+                let store = get_store().await.expect("creating store failed").into_state_store();
+                store.test_send_queue_drain().await;
+            }
+
+            #[async_test]
+            async fn test_persist_from_memory_store() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_persist_from_memory_store().await
+            }
+
+            #[async_test]
+            async fn test_acknowledged_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_acknowledged_saving().await
+            }
+
+            #[async_test]
+            async fn test_history_visibility() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_history_visibility().await
+            }
+
+            #[async_test]
+            async fn test_count_room_events() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_count_room_events().await
+            }
+
+            #[async_test]
+            async fn test_get_dm_room() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_dm_room().await
+            }
+
+            #[async_test]
+            async fn test_get_lazy_room_infos() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_lazy_room_infos().await
+            }
+
+            #[async_test]
+            async fn test_group_session_meta_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_group_session_meta_saving().await
+            }
+
+            #[async_test]
+            async fn test_rooms_needing_backup() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_rooms_needing_backup().await
+            }
+
+            #[async_test]
+            async fn test_save_presence_in_bulk() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_save_presence_in_bulk().await
+            }
+
+            #[async_test]
+            async fn test_get_state_event_ids() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_state_event_ids().await
+            }
+
+            #[async_test]
+            async fn test_failed_sends() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_failed_sends().await
+            }
+
+            #[async_test]
+            async fn test_get_room_memberships() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_room_memberships().await
+            }
+
+            #[async_test]
+            async fn test_local_read_marker() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_local_read_marker().await
+            }
+
+            #[async_test]
+            async fn test_invited_rooms_count() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_invited_rooms_count().await
+            }
+
+            #[async_test]
+            async fn test_get_room_avatar() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_room_avatar().await
+            }
+
+            #[async_test]
+            async fn test_get_rooms_with_tag() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_rooms_with_tag().await
+            }
+
+            #[async_test]
+            async fn test_clear_ephemeral() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_clear_ephemeral().await
+            }
+
+            #[async_test]
+            async fn test_orphaned_members() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_orphaned_members().await
+            }
+
+            #[async_test]
+            async fn test_get_banned_rooms_for_user() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_banned_rooms_for_user().await
+            }
+
+            #[async_test]
+            async fn test_filter_definition() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_filter_definition().await
+            }
+
+            #[async_test]
+            async fn test_get_stripped_members() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_stripped_members().await
+            }
+
+            #[async_test]
+            async fn test_get_room_state() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_room_state().await
+            }
+
+            #[async_test]
+            async fn test_user_ids_by_membership() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_user_ids_by_membership().await
+            }
+
+            #[async_test]
+            async fn test_device_info() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_device_info().await
+            }
+
+            #[async_test]
+            async fn test_get_dm_peer() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_dm_peer().await
+            }
+
+            #[async_test]
+            async fn test_get_account_data_types() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_account_data_types().await
+            }
+
+            #[async_test]
+            async fn test_next_send_sequence() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_next_send_sequence().await
+            }
+
+            #[async_test]
+            async fn test_room_storage_breakdown() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_room_storage_breakdown().await
+            }
+
+            #[async_test]
+            async fn test_hidden_invites() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_hidden_invites().await
+            }
+
+            #[async_test]
+            async fn test_get_third_party_invites() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_third_party_invites().await
+            }
+
+            #[async_test]
+            async fn test_is_space() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_is_space().await
+            }
+
+            #[async_test]
+            async fn test_get_invites_sorted() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_invites_sorted().await
+            }
+
+            #[async_test]
+            async fn test_discovery_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_discovery_saving().await
+            }
+
+            #[async_test]
+            async fn test_rooms_missing_state() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_rooms_missing_state().await
+            }
+
+            #[async_test]
+            async fn test_get_user_display_names() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_user_display_names().await
+            }
+
+            #[async_test]
+            async fn test_features_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_features_saving().await
+            }
+
+            #[async_test]
+            async fn test_mark_invite_rejected() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_mark_invite_rejected().await
+            }
+
+            #[async_test]
+            async fn test_reported_events() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_reported_events().await
+            }
+
+            #[async_test]
+            async fn test_get_rotation_policy() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_rotation_policy().await
+            }
+
+            #[async_test]
+            async fn test_summary_member_count() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_summary_member_count().await
+            }
+
+            #[async_test]
+            async fn test_verification_state() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_verification_state().await
+            }
+
+            #[async_test]
+            async fn test_get_rooms_where_user_has_power() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_rooms_where_user_has_power().await
+            }
+
+            #[async_test]
+            async fn test_classify_rooms() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_classify_rooms().await
+            }
+
+            #[async_test]
+            async fn test_get_knocking_members() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_knocking_members().await
+            }
+
+            #[async_test]
+            async fn test_get_room_account_data_events() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_room_account_data_events().await
+            }
+
+            #[async_test]
+            async fn test_last_sync_time_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_last_sync_time_saving().await
+            }
+
+            #[async_test]
+            async fn test_credentials_export_import() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_credentials_export_import().await
+            }
+
+            #[async_test]
+            async fn test_marked_unread() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_marked_unread().await
+            }
+
+            #[async_test]
+            async fn test_rooms_removal() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_rooms_removal().await
+            }
+
+            #[async_test]
+            async fn test_read_your_writes() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_read_your_writes().await
+            }
+
+            #[async_test]
+            async fn test_recent_reactions() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_recent_reactions().await
+            }
+
+            #[async_test]
+            async fn test_sync_token_per_connection() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_sync_token_per_connection().await
+            }
+
+            #[async_test]
+            async fn test_prune_stale_state() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_prune_stale_state().await
+            }
+
+            #[async_test]
+            async fn test_get_rooms_state() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_get_rooms_state().await
+            }
+
+            #[async_test]
+            async fn test_blocked_servers() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_blocked_servers().await
+            }
+
+            #[async_test]
+            async fn test_room_list_entry() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_room_list_entry().await
+            }
+
+            #[async_test]
+            async fn test_composer_draft() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_composer_draft().await
+            }
+
+            #[async_test]
+            async fn test_space_unread_summary() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_space_unread_summary().await
+            }
+
+            #[async_test]
+            async fn test_knocked_room_info_resave() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_knocked_room_info_resave().await
+            }
+
+            #[async_test]
+            async fn test_latest_edit_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_latest_edit_saving().await
+            }
+
+            #[async_test]
+            async fn test_remove_events_before() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_remove_events_before().await
+            }
+
+            #[async_test]
+            async fn test_alias_history_saving() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_alias_history_saving().await
+            }
+
+            #[async_test]
+            async fn test_update_custom_value() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_update_custom_value().await
+            }
+
+            #[async_test]
+            async fn test_members_to_delete() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_members_to_delete().await
+            }
+
+            #[async_test]
+            async fn test_room_nickname() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_room_nickname().await
             }
         }
     };
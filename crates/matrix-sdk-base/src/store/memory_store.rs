@@ -23,42 +23,52 @@ use ruma::{
     canonical_json::{redact, RedactedBecause},
     events::{
         presence::PresenceEvent,
-        receipt::{Receipt, ReceiptThread, ReceiptType},
+        receipt::{Receipt, ReceiptEventContent, ReceiptThread, ReceiptType},
         room::member::{MembershipState, StrippedRoomMemberEvent, SyncRoomMemberEvent},
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
-        AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+        AnySyncStateEvent, AnySyncTimelineEvent, GlobalAccountDataEventType,
+        RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
     time::Instant,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedTransactionId,
-    OwnedUserId, RoomId, RoomVersionId, TransactionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedMxcUri,
+    OwnedRoomAliasId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId, RoomVersionId,
+    TransactionId, UserId,
 };
 use tracing::{debug, instrument, trace, warn};
 
 use super::{
+    is_older_edit, is_older_than, room_custom_value_keys,
     traits::{
-        ChildTransactionId, ComposerDraft, QueuedEvent, SerializableEventContent,
-        ServerCapabilities,
+        ChildTransactionId, ComposerDraft, CustomValueUpdate, DiscoveryInfo, GroupSessionMeta,
+        QueuedEvent, SerializableEventContent, ServerCapabilities,
     },
-    DependentQueuedEvent, DependentQueuedEventKind, Result, RoomInfo, StateChanges, StateStore,
-    StoreError,
+    DependentQueuedEvent, DependentQueuedEventKind, DynStateStore, Result, RoomInfo, StateChanges,
+    StateStore, StoreError,
 };
 use crate::{
-    deserialized_responses::RawAnySyncOrStrippedState, MinimalRoomMemberEvent, RoomMemberships,
-    RoomState, StateStoreDataKey, StateStoreDataValue,
+    deserialized_responses::RawAnySyncOrStrippedState, LazyRoomInfo, MinimalRoomMemberEvent,
+    RoomMemberships, RoomState, StateStoreDataKey, StateStoreDataValue,
 };
 
 /// In-memory, non-persistent implementation of the `StateStore`.
 ///
 /// Default if no other is configured at startup.
+///
+/// The methods that hold several locks at once take them in this order, to not
+/// deadlock with each other: `room_info`, `room_state`, `stripped_room_state`,
+/// `members`, `stripped_members`, then the other fields.
 #[allow(clippy::type_complexity)]
 #[derive(Debug, Default)]
 pub struct MemoryStore {
     recently_visited_rooms: StdRwLock<HashMap<OwnedUserId, Vec<OwnedRoomId>>>,
     composer_drafts: StdRwLock<HashMap<OwnedRoomId, ComposerDraft>>,
+    group_session_meta: StdRwLock<HashMap<OwnedRoomId, GroupSessionMeta>>,
     user_avatar_url: StdRwLock<HashMap<OwnedUserId, OwnedMxcUri>>,
     sync_token: StdRwLock<Option<String>>,
+    last_sync_time: StdRwLock<Option<MilliSecondsSinceUnixEpoch>>,
     server_capabilities: StdRwLock<Option<ServerCapabilities>>,
+    discovery: StdRwLock<Option<DiscoveryInfo>>,
     filters: StdRwLock<HashMap<String, String>>,
     utd_hook_manager_data: StdRwLock<Option<GrowableBloom>>,
     account_data: StdRwLock<HashMap<GlobalAccountDataEventType, Raw<AnyGlobalAccountDataEvent>>>,
@@ -89,9 +99,13 @@ pub struct MemoryStore {
             HashMap<(String, Option<String>), HashMap<OwnedEventId, HashMap<OwnedUserId, Receipt>>>,
         >,
     >,
+    edits: StdRwLock<HashMap<OwnedRoomId, HashMap<OwnedEventId, Raw<AnySyncTimelineEvent>>>>,
+    alias_history:
+        StdRwLock<HashMap<OwnedRoomId, Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>>>,
     custom: StdRwLock<HashMap<Vec<u8>, Vec<u8>>>,
     send_queue_events: StdRwLock<BTreeMap<OwnedRoomId, Vec<QueuedEvent>>>,
     dependent_send_queue_events: StdRwLock<BTreeMap<OwnedRoomId, Vec<DependentQueuedEvent>>>,
+    send_sequences: StdRwLock<BTreeMap<OwnedRoomId, u64>>,
 }
 
 impl MemoryStore {
@@ -100,6 +114,239 @@ impl MemoryStore {
         Self::default()
     }
 
+    /// Copy the whole content of this store into `target`.
+    ///
+    /// This is meant for clients that start with an in-memory store to get
+    /// going quickly, and want to keep what they have once they decide to
+    /// persist their data, e.g. after a successful login.
+    ///
+    /// All the room state, account data, receipts, room infos and the sync
+    /// token are written with a single [`StateStore::save_changes`] call. The
+    /// other key-value data, the custom values and the send queues can't be
+    /// part of it, so they are written one by one beforehand. If any of the
+    /// writes fails, the ones that were already made are undone, restoring the
+    /// previous values of `target`, and the state isn't written. Undoing is
+    /// best effort: a write that fails to be undone is only logged. Data that
+    /// already exists in `target` for the same keys is overwritten.
+    pub async fn persist_into(&self, target: &DynStateStore) -> Result<()> {
+        let mut undo = Vec::new();
+
+        let result = async {
+            self.persist_non_state_into(target, &mut undo).await?;
+            target.save_changes(&self.to_state_changes()).await
+        }
+        .await;
+
+        if let Err(error) = result {
+            for step in undo.into_iter().rev() {
+                if let Err(undo_error) = step.run(target).await {
+                    warn!("Failed to undo a write of MemoryStore::persist_into: {undo_error}");
+                }
+            }
+
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Copy the data of this store that isn't part of its
+    /// [`StateChanges`] into `target`, recording how to undo every write in
+    /// `undo`.
+    async fn persist_non_state_into(
+        &self,
+        target: &DynStateStore,
+        undo: &mut Vec<PersistUndo>,
+    ) -> Result<()> {
+        for (key, value) in self.kv_entries() {
+            let previous = target.get_kv_data(key.as_key()).await?;
+            target.set_kv_data(key.as_key(), value).await?;
+            undo.push(PersistUndo::KvData(key, previous));
+        }
+
+        let custom = self.custom.read().unwrap().clone();
+        for (key, value) in custom {
+            let previous = target.set_custom_value(&key, value).await?;
+            undo.push(PersistUndo::CustomValue(key, previous));
+        }
+
+        let send_queue_events = self.send_queue_events.read().unwrap().clone();
+        for (room_id, events) in send_queue_events {
+            for event in events {
+                target
+                    .save_send_queue_event(&room_id, event.transaction_id.clone(), event.event)
+                    .await?;
+                undo.push(PersistUndo::SendQueueEvent(
+                    room_id.clone(),
+                    event.transaction_id.clone(),
+                ));
+
+                if event.is_wedged {
+                    target
+                        .update_send_queue_event_status(&room_id, &event.transaction_id, true)
+                        .await?;
+                }
+            }
+        }
+
+        let dependent_events = self.dependent_send_queue_events.read().unwrap().clone();
+        for (room_id, events) in dependent_events {
+            for event in events {
+                target
+                    .save_dependent_send_queue_event(
+                        &room_id,
+                        &event.parent_transaction_id,
+                        event.own_transaction_id.clone(),
+                        event.kind,
+                    )
+                    .await?;
+                undo.push(PersistUndo::DependentSendQueueEvent(
+                    room_id.clone(),
+                    event.own_transaction_id,
+                ));
+
+                if let Some(event_id) = event.event_id {
+                    target
+                        .update_dependent_send_queue_event(
+                            &room_id,
+                            &event.parent_transaction_id,
+                            event_id,
+                        )
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get all the key-value data of this store, except the sync token and the
+    /// time of the last sync which are part of its [`StateChanges`].
+    fn kv_entries(&self) -> Vec<(OwnedKvKey, StateStoreDataValue)> {
+        let mut entries = Vec::new();
+
+        if let Some(caps) = self.server_capabilities.read().unwrap().clone() {
+            entries.push((
+                OwnedKvKey::ServerCapabilities,
+                StateStoreDataValue::ServerCapabilities(caps),
+            ));
+        }
+        if let Some(info) = self.discovery.read().unwrap().clone() {
+            entries.push((OwnedKvKey::Discovery, StateStoreDataValue::Discovery(info)));
+        }
+        if let Some(data) = self.utd_hook_manager_data.read().unwrap().clone() {
+            entries.push((
+                OwnedKvKey::UtdHookManagerData,
+                StateStoreDataValue::UtdHookManagerData(data),
+            ));
+        }
+        for (name, filter_id) in self.filters.read().unwrap().iter() {
+            entries.push((
+                OwnedKvKey::Filter(name.clone()),
+                StateStoreDataValue::Filter(filter_id.clone()),
+            ));
+        }
+        for (user_id, url) in self.user_avatar_url.read().unwrap().iter() {
+            entries.push((
+                OwnedKvKey::UserAvatarUrl(user_id.clone()),
+                StateStoreDataValue::UserAvatarUrl(url.clone()),
+            ));
+        }
+        for (user_id, rooms) in self.recently_visited_rooms.read().unwrap().iter() {
+            entries.push((
+                OwnedKvKey::RecentlyVisitedRooms(user_id.clone()),
+                StateStoreDataValue::RecentlyVisitedRooms(rooms.clone()),
+            ));
+        }
+        for (room_id, draft) in self.composer_drafts.read().unwrap().iter() {
+            entries.push((
+                OwnedKvKey::ComposerDraft(room_id.clone()),
+                StateStoreDataValue::ComposerDraft(draft.clone()),
+            ));
+        }
+        for (room_id, meta) in self.group_session_meta.read().unwrap().iter() {
+            entries.push((
+                OwnedKvKey::GroupSessionMeta(room_id.clone()),
+                StateStoreDataValue::GroupSessionMeta(meta.clone()),
+            ));
+        }
+
+        entries
+    }
+
+    /// Build a [`StateChanges`] containing all the state held by this store.
+    fn to_state_changes(&self) -> StateChanges {
+        fn to_btree<K: Ord + Clone, V: Clone>(map: &HashMap<K, V>) -> BTreeMap<K, V> {
+            map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        }
+
+        let mut changes = StateChanges {
+            sync_token: self.sync_token.read().unwrap().clone(),
+            last_sync_time: *self.last_sync_time.read().unwrap(),
+            account_data: to_btree(&self.account_data.read().unwrap()),
+            presence: to_btree(&self.presence.read().unwrap()),
+            room_infos: to_btree(&self.room_info.read().unwrap()),
+            ..Default::default()
+        };
+
+        for (room_id, profiles) in self.profiles.read().unwrap().iter() {
+            changes.profiles.insert(room_id.clone(), to_btree(profiles));
+        }
+
+        for (room_id, map) in self.display_names.read().unwrap().iter() {
+            changes.ambiguity_maps.insert(room_id.clone(), to_btree(map));
+        }
+
+        for (room_id, events) in self.room_account_data.read().unwrap().iter() {
+            changes.room_account_data.insert(room_id.clone(), to_btree(events));
+        }
+
+        for (room_id, event_types) in self.room_state.read().unwrap().iter() {
+            let room_state = changes.state.entry(room_id.clone()).or_default();
+            for (event_type, events) in event_types {
+                room_state.insert(event_type.clone(), to_btree(events));
+            }
+        }
+
+        for (room_id, event_types) in self.stripped_room_state.read().unwrap().iter() {
+            let room_state = changes.stripped_state.entry(room_id.clone()).or_default();
+            for (event_type, events) in event_types {
+                room_state.insert(event_type.clone(), to_btree(events));
+            }
+        }
+
+        for (room_id, edits) in self.edits.read().unwrap().iter() {
+            changes.edits.insert(room_id.clone(), to_btree(edits));
+        }
+
+        for (room_id, history) in self.alias_history.read().unwrap().iter() {
+            changes.alias_history.insert(room_id.clone(), history.clone());
+        }
+
+        // Only the latest receipt of each user is needed, the event receipts are
+        // derived from them when saving them. The thread is part of the receipt
+        // itself, so it doesn't need to be kept separately.
+        for (room_id, receipts) in self.room_user_receipts.read().unwrap().iter() {
+            let content = changes
+                .receipts
+                .entry(room_id.clone())
+                .or_insert_with(|| ReceiptEventContent(BTreeMap::new()));
+            for ((receipt_type, _), user_receipts) in receipts {
+                for (user_id, (event_id, receipt)) in user_receipts {
+                    content
+                        .0
+                        .entry(event_id.clone())
+                        .or_default()
+                        .entry(ReceiptType::from(receipt_type.as_str()))
+                        .or_default()
+                        .insert(user_id.clone(), receipt.clone());
+                }
+            }
+        }
+
+        changes
+    }
+
     fn get_user_room_receipt_event_impl(
         &self,
         room_id: &RoomId,
@@ -137,6 +384,67 @@ impl MemoryStore {
     }
 }
 
+/// An owned [`StateStoreDataKey`], for the key-value data copied by
+/// [`MemoryStore::persist_into`].
+enum OwnedKvKey {
+    ServerCapabilities,
+    Discovery,
+    UtdHookManagerData,
+    Filter(String),
+    UserAvatarUrl(OwnedUserId),
+    RecentlyVisitedRooms(OwnedUserId),
+    ComposerDraft(OwnedRoomId),
+    GroupSessionMeta(OwnedRoomId),
+}
+
+impl OwnedKvKey {
+    fn as_key(&self) -> StateStoreDataKey<'_> {
+        match self {
+            Self::ServerCapabilities => StateStoreDataKey::ServerCapabilities,
+            Self::Discovery => StateStoreDataKey::Discovery,
+            Self::UtdHookManagerData => StateStoreDataKey::UtdHookManagerData,
+            Self::Filter(name) => StateStoreDataKey::Filter(name),
+            Self::UserAvatarUrl(user_id) => StateStoreDataKey::UserAvatarUrl(user_id),
+            Self::RecentlyVisitedRooms(user_id) => StateStoreDataKey::RecentlyVisitedRooms(user_id),
+            Self::ComposerDraft(room_id) => StateStoreDataKey::ComposerDraft(room_id),
+            Self::GroupSessionMeta(room_id) => StateStoreDataKey::GroupSessionMeta(room_id),
+        }
+    }
+}
+
+/// A write made by [`MemoryStore::persist_into`], to undo if a later one
+/// fails.
+enum PersistUndo {
+    /// Restore the previous value of the key-value data, or remove it.
+    KvData(OwnedKvKey, Option<StateStoreDataValue>),
+    /// Restore the previous custom value, or remove it.
+    CustomValue(Vec<u8>, Option<Vec<u8>>),
+    /// Remove the copied send queue event.
+    SendQueueEvent(OwnedRoomId, OwnedTransactionId),
+    /// Remove the copied dependent send queue event.
+    DependentSendQueueEvent(OwnedRoomId, ChildTransactionId),
+}
+
+impl PersistUndo {
+    async fn run(self, target: &DynStateStore) -> Result<()> {
+        match self {
+            Self::KvData(key, Some(value)) => target.set_kv_data(key.as_key(), value).await,
+            Self::KvData(key, None) => target.remove_kv_data(key.as_key()).await,
+            Self::CustomValue(key, Some(value)) => {
+                target.set_custom_value_no_read(&key, value).await
+            }
+            Self::CustomValue(key, None) => target.remove_custom_value(&key).await.map(|_| ()),
+            Self::SendQueueEvent(room_id, transaction_id) => {
+                target.remove_send_queue_event(&room_id, &transaction_id).await.map(|_| ())
+            }
+            Self::DependentSendQueueEvent(room_id, transaction_id) => target
+                .remove_dependent_send_queue_event(&room_id, &transaction_id)
+                .await
+                .map(|_| ()),
+        }
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl StateStore for MemoryStore {
@@ -187,6 +495,19 @@ impl StateStore for MemoryStore {
                 .get(room_id)
                 .cloned()
                 .map(StateStoreDataValue::ComposerDraft),
+            StateStoreDataKey::GroupSessionMeta(room_id) => self
+                .group_session_meta
+                .read()
+                .unwrap()
+                .get(room_id)
+                .cloned()
+                .map(StateStoreDataValue::GroupSessionMeta),
+            StateStoreDataKey::Discovery => {
+                self.discovery.read().unwrap().clone().map(StateStoreDataValue::Discovery)
+            }
+            StateStoreDataKey::LastSyncTime => {
+                self.last_sync_time.read().unwrap().map(StateStoreDataValue::LastSyncTime)
+            }
         })
     }
 
@@ -233,6 +554,14 @@ impl StateStore for MemoryStore {
                     value.into_composer_draft().expect("Session data not a composer draft"),
                 );
             }
+            StateStoreDataKey::GroupSessionMeta(room_id) => {
+                self.group_session_meta.write().unwrap().insert(
+                    room_id.to_owned(),
+                    value
+                        .into_group_session_meta()
+                        .expect("Session data not a group session bookkeeping"),
+                );
+            }
             StateStoreDataKey::ServerCapabilities => {
                 *self.server_capabilities.write().unwrap() = Some(
                     value
@@ -240,6 +569,14 @@ impl StateStore for MemoryStore {
                         .expect("Session data not containing server capabilities"),
                 );
             }
+            StateStoreDataKey::Discovery => {
+                *self.discovery.write().unwrap() =
+                    Some(value.into_discovery().expect("Session data not a discovery info"));
+            }
+            StateStoreDataKey::LastSyncTime => {
+                *self.last_sync_time.write().unwrap() =
+                    Some(value.into_last_sync_time().expect("Session data not a sync time"));
+            }
         }
 
         Ok(())
@@ -266,6 +603,11 @@ impl StateStore for MemoryStore {
             StateStoreDataKey::ComposerDraft(room_id) => {
                 self.composer_drafts.write().unwrap().remove(room_id);
             }
+            StateStoreDataKey::GroupSessionMeta(room_id) => {
+                self.group_session_meta.write().unwrap().remove(room_id);
+            }
+            StateStoreDataKey::Discovery => *self.discovery.write().unwrap() = None,
+            StateStoreDataKey::LastSyncTime => *self.last_sync_time.write().unwrap() = None,
         }
         Ok(())
     }
@@ -281,6 +623,10 @@ impl StateStore for MemoryStore {
             trace!("assigned sync token");
         }
 
+        if let Some(ts) = changes.last_sync_time {
+            *self.last_sync_time.write().unwrap() = Some(ts);
+        }
+
         trace!("profiles");
         {
             let mut profiles = self.profiles.write().unwrap();
@@ -348,6 +694,20 @@ impl StateStore for MemoryStore {
             let mut stripped_members = self.stripped_members.write().unwrap();
             trace!("room state: got stripped_members lock");
 
+            for (room, user_ids) in &changes.members_to_delete {
+                for user_id in user_ids {
+                    if let Some(events) = room_state
+                        .get_mut(room)
+                        .and_then(|state| state.get_mut(&StateEventType::RoomMember))
+                    {
+                        events.remove(user_id.as_str());
+                    }
+                    if let Some(members) = members.get_mut(room) {
+                        members.remove(user_id);
+                    }
+                }
+            }
+
             for (room, event_types) in &changes.state {
                 for (event_type, events) in event_types {
                     for (state_key, raw_event) in events {
@@ -522,6 +882,38 @@ impl StateStore for MemoryStore {
             }
         }
 
+        trace!("edits");
+        {
+            let mut edits = self.edits.write().unwrap();
+
+            for (room_id, room_edits) in &changes.edits {
+                let known_edits = edits.entry(room_id.clone()).or_default();
+                for (event_id, edit) in room_edits {
+                    match known_edits.get(event_id) {
+                        Some(existing) if is_older_edit(edit, existing) => {}
+                        _ => {
+                            known_edits.insert(event_id.clone(), edit.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        trace!("alias history");
+        {
+            let mut alias_history = self.alias_history.write().unwrap();
+
+            for (room_id, entries) in &changes.alias_history {
+                let history = alias_history.entry(room_id.clone()).or_default();
+                for (alias, set_at) in entries {
+                    if history.last().is_some_and(|(latest, _)| latest == alias) {
+                        continue;
+                    }
+                    history.push((alias.clone(), *set_at));
+                }
+            }
+        }
+
         debug!("Saved changes in {:?}", now.elapsed());
 
         Ok(())
@@ -624,6 +1016,101 @@ impl StateStore for MemoryStore {
         )
     }
 
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize> {
+        fn count<T>(
+            state_map: &HashMap<OwnedRoomId, HashMap<StateEventType, HashMap<String, Raw<T>>>>,
+            room_id: &RoomId,
+        ) -> usize {
+            state_map.get(room_id).map_or(0, |events| events.values().map(HashMap::len).sum())
+        }
+
+        Ok(count(&self.room_state.read().unwrap(), room_id)
+            + count(&self.stripped_room_state.read().unwrap(), room_id))
+    }
+
+    async fn get_state_event_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedEventId>> {
+        Ok(self
+            .room_state
+            .read()
+            .unwrap()
+            .get(room_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .flat_map(HashMap::values)
+            .filter_map(|raw| raw.get_field::<OwnedEventId>("event_id").ok().flatten())
+            .collect())
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let stripped_events = self
+            .stripped_room_state
+            .read()
+            .unwrap()
+            .get(room_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .flat_map(HashMap::values)
+            .cloned()
+            .map(RawAnySyncOrStrippedState::Stripped)
+            .collect::<Vec<_>>();
+
+        if !stripped_events.is_empty() {
+            return Ok(stripped_events);
+        }
+
+        Ok(self
+            .room_state
+            .read()
+            .unwrap()
+            .get(room_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .flat_map(HashMap::values)
+            .cloned()
+            .map(RawAnySyncOrStrippedState::Sync)
+            .collect())
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>> {
+        let room_state = self.room_state.read().unwrap();
+        let stripped_room_state = self.stripped_room_state.read().unwrap();
+
+        Ok(room_ids
+            .iter()
+            .filter_map(|&room_id| {
+                let stripped_events = stripped_room_state
+                    .get(room_id)
+                    .into_iter()
+                    .flat_map(HashMap::values)
+                    .flat_map(HashMap::values)
+                    .cloned()
+                    .map(RawAnySyncOrStrippedState::Stripped)
+                    .collect::<Vec<_>>();
+
+                let events = if stripped_events.is_empty() {
+                    room_state
+                        .get(room_id)
+                        .into_iter()
+                        .flat_map(HashMap::values)
+                        .flat_map(HashMap::values)
+                        .cloned()
+                        .map(RawAnySyncOrStrippedState::Sync)
+                        .collect()
+                } else {
+                    stripped_events
+                };
+
+                (!events.is_empty()).then(|| (room_id.to_owned(), events))
+            })
+            .collect())
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -708,13 +1195,22 @@ impl StateStore for MemoryStore {
         Ok(self.room_info.read().unwrap().values().cloned().collect())
     }
 
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>> {
+        Ok(self.room_info.read().unwrap().get(room_id).cloned())
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>> {
+        // Room infos are kept deserialized in memory, there's nothing to defer.
+        Ok(self.room_info.read().unwrap().values().cloned().map(Into::into).collect())
+    }
+
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
         Ok(self
             .room_info
             .read()
             .unwrap()
             .values()
-            .filter(|r| matches!(r.state(), RoomState::Invited))
+            .filter(|r| matches!(r.state(), RoomState::Invited | RoomState::Knocked))
             .cloned()
             .collect())
     }
@@ -760,6 +1256,10 @@ impl StateStore for MemoryStore {
         Ok(self.account_data.read().unwrap().get(&event_type).cloned())
     }
 
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>> {
+        Ok(self.account_data.read().unwrap().keys().cloned().collect())
+    }
+
     async fn get_room_account_data_event(
         &self,
         room_id: &RoomId,
@@ -774,6 +1274,19 @@ impl StateStore for MemoryStore {
             .cloned())
     }
 
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>> {
+        Ok(self
+            .room_account_data
+            .read()
+            .unwrap()
+            .get(room_id)
+            .map(|events| events.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+
     async fn get_user_room_receipt_event(
         &self,
         room_id: &RoomId,
@@ -796,6 +1309,51 @@ impl StateStore for MemoryStore {
             .unwrap_or_default())
     }
 
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>> {
+        Ok(self.edits.read().unwrap().get(room_id).and_then(|edits| edits.get(event_id)).cloned())
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>> {
+        Ok(self.alias_history.read().unwrap().get(room_id).cloned().unwrap_or_default())
+    }
+
+    async fn remove_events_before(&self, ts: MilliSecondsSinceUnixEpoch) -> Result<()> {
+        let is_older_receipt =
+            |receipt: &Receipt| receipt.ts.is_some_and(|receipt_ts| receipt_ts < ts);
+
+        {
+            let mut edits = self.edits.write().unwrap();
+            for room_edits in edits.values_mut() {
+                room_edits.retain(|_, edit| !is_older_than(edit, ts));
+            }
+            edits.retain(|_, room_edits| !room_edits.is_empty());
+        }
+
+        {
+            let mut room_user_receipts = self.room_user_receipts.write().unwrap();
+            for receipts in room_user_receipts.values_mut().flat_map(HashMap::values_mut) {
+                receipts.retain(|_, (_, receipt)| !is_older_receipt(receipt));
+            }
+
+            let mut room_event_receipts = self.room_event_receipts.write().unwrap();
+            for receipts in room_event_receipts.values_mut().flat_map(HashMap::values_mut) {
+                for event_receipts in receipts.values_mut() {
+                    event_receipts.retain(|_, receipt| !is_older_receipt(receipt));
+                }
+                receipts.retain(|_, event_receipts| !event_receipts.is_empty());
+            }
+        }
+
+        Ok(())
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         Ok(self.custom.read().unwrap().get(key).cloned())
     }
@@ -808,17 +1366,148 @@ impl StateStore for MemoryStore {
         Ok(self.custom.write().unwrap().remove(key))
     }
 
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut custom = self.custom.write().unwrap();
+        let value = update(custom.get(key).map(Vec::as_slice))?;
+
+        match &value {
+            Some(value) => custom.insert(key.to_vec(), value.clone()),
+            None => custom.remove(key),
+        };
+
+        Ok(value)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
-        self.profiles.write().unwrap().remove(room_id);
-        self.display_names.write().unwrap().remove(room_id);
-        self.members.write().unwrap().remove(room_id);
-        self.room_info.write().unwrap().remove(room_id);
-        self.room_state.write().unwrap().remove(room_id);
-        self.room_account_data.write().unwrap().remove(room_id);
-        self.stripped_room_state.write().unwrap().remove(room_id);
-        self.stripped_members.write().unwrap().remove(room_id);
-        self.room_user_receipts.write().unwrap().remove(room_id);
-        self.room_event_receipts.write().unwrap().remove(room_id);
+        self.remove_rooms(&[room_id]).await
+    }
+
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<()> {
+        // Hold all the locks so no reader sees a partially removed room. They
+        // are taken in the same order as in `save_changes`, to not deadlock
+        // with a concurrent sync.
+        let mut room_info = self.room_info.write().unwrap();
+        let mut room_state = self.room_state.write().unwrap();
+        let mut stripped_room_state = self.stripped_room_state.write().unwrap();
+        let mut members = self.members.write().unwrap();
+        let mut stripped_members = self.stripped_members.write().unwrap();
+        let mut profiles = self.profiles.write().unwrap();
+        let mut display_names = self.display_names.write().unwrap();
+        let mut room_account_data = self.room_account_data.write().unwrap();
+        let mut room_user_receipts = self.room_user_receipts.write().unwrap();
+        let mut room_event_receipts = self.room_event_receipts.write().unwrap();
+        let mut edits = self.edits.write().unwrap();
+        let mut alias_history = self.alias_history.write().unwrap();
+        let mut custom = self.custom.write().unwrap();
+
+        for &room_id in room_ids {
+            profiles.remove(room_id);
+            display_names.remove(room_id);
+            members.remove(room_id);
+            room_info.remove(room_id);
+            room_state.remove(room_id);
+            room_account_data.remove(room_id);
+            stripped_room_state.remove(room_id);
+            stripped_members.remove(room_id);
+            room_user_receipts.remove(room_id);
+            room_event_receipts.remove(room_id);
+            edits.remove(room_id);
+            alias_history.remove(room_id);
+            for key in room_custom_value_keys(room_id) {
+                custom.remove(key.as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<()> {
+        let mut room_info = self.room_info.write().unwrap();
+        let mut stripped_room_state = self.stripped_room_state.write().unwrap();
+        let mut stripped_members = self.stripped_members.write().unwrap();
+
+        stripped_room_state.remove(room_id);
+        stripped_members.remove(room_id);
+        if let Some(info) = room_info.get_mut(room_id) {
+            info.mark_as_left();
+        }
+
+        Ok(())
+    }
+
+    async fn clear_ephemeral(&self) -> Result<()> {
+        self.presence.write().unwrap().clear();
+        self.room_user_receipts.write().unwrap().clear();
+        self.room_event_receipts.write().unwrap().clear();
+
+        Ok(())
+    }
+
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>> {
+        let room_info = self.room_info.read().unwrap();
+        let mut orphans = BTreeSet::new();
+
+        for members in [&self.members, &self.stripped_members] {
+            for (room_id, members) in members.read().unwrap().iter() {
+                if !room_info.contains_key(room_id) {
+                    orphans
+                        .extend(members.keys().map(|user_id| (room_id.clone(), user_id.clone())));
+                }
+            }
+        }
+
+        Ok(orphans.into_iter().collect())
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<()> {
+        let room_info = self.room_info.read().unwrap();
+
+        for members in [&self.members, &self.stripped_members] {
+            members.write().unwrap().retain(|room_id, _| room_info.contains_key(room_id));
+        }
+
+        Ok(())
+    }
+
+    async fn compact_if_needed(&self, _threshold: f64) -> Result<bool> {
+        // The maps release the memory of the removed data right away.
+        Ok(false)
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<()> {
+        let mut room_state = self.room_state.write().unwrap();
+        let mut members = self.members.write().unwrap();
+
+        let Some(state) = room_state.get_mut(room_id) else {
+            return Ok(());
+        };
+
+        for (event_type, events) in state.iter_mut() {
+            events.retain(|state_key, _| {
+                if current_keys.contains(&(event_type.clone(), state_key.clone())) {
+                    return true;
+                }
+
+                if *event_type == StateEventType::RoomMember {
+                    if let (Some(members), Ok(user_id)) =
+                        (members.get_mut(room_id), UserId::parse(state_key))
+                    {
+                        members.remove(&user_id);
+                    }
+                }
+
+                false
+            });
+        }
+        state.retain(|_, events| !events.is_empty());
 
         Ok(())
     }
@@ -891,6 +1580,24 @@ impl StateStore for MemoryStore {
         Ok(self.send_queue_events.write().unwrap().entry(room_id.to_owned()).or_default().clone())
     }
 
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        let mut send_queue_events = self.send_queue_events.write().unwrap();
+        let Some(events) = send_queue_events.get_mut(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let drained: Vec<_> = events.drain(..limit.min(events.len())).collect();
+        if events.is_empty() {
+            send_queue_events.remove(room_id);
+        }
+
+        Ok(drained)
+    }
+
     async fn update_send_queue_event_status(
         &self,
         room_id: &RoomId,
@@ -915,6 +1622,14 @@ impl StateStore for MemoryStore {
         Ok(self.send_queue_events.read().unwrap().keys().cloned().collect())
     }
 
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error> {
+        let mut send_sequences = self.send_sequences.write().unwrap();
+        let next = send_sequences.entry(room_id.to_owned()).or_default();
+        let sequence = *next;
+        *next += 1;
+        Ok(sequence)
+    }
+
     async fn save_dependent_send_queue_event(
         &self,
         room: &RoomId,
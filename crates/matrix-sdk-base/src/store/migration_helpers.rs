@@ -128,6 +128,7 @@ impl RoomInfoV1 {
             cached_user_defined_notification_mode: None,
             #[cfg(feature = "experimental-sliding-sync")]
             recency_stamp: None,
+            recent_messages: Default::default(),
         }
     }
 }
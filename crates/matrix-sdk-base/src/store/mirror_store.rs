@@ -0,0 +1,641 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use ruma::{
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncTimelineEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomAliasId, OwnedRoomId,
+    OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UserId,
+};
+use tracing::warn;
+
+use super::{
+    ChildTransactionId, CustomValueUpdate, DependentQueuedEvent, DependentQueuedEventKind,
+    DynStateStore, IntoStateStore, QueuedEvent, Result, SerializableEventContent, StateChanges,
+    StateStore, StoreError,
+};
+use crate::{
+    deserialized_responses::RawAnySyncOrStrippedState, LazyRoomInfo, MinimalRoomMemberEvent,
+    RoomInfo, RoomMemberships, StateStoreDataKey, StateStoreDataValue,
+};
+
+/// A [`StateStore`] that writes to two stores, and reads from the first one.
+///
+/// Every write goes to the primary store first, then to the secondary store.
+/// If the primary store fails, the secondary store isn't written to. Reads are
+/// only served by the primary store.
+///
+/// The secondary store only receives the writes made through the
+/// `MirrorStore`, nothing that already is in the primary store is copied to
+/// it. To migrate the data to a new backend without stopping the client, the
+/// existing data must first be copied to the new backend, e.g. with
+/// [`MemoryStore::persist_into`] if the primary store is a [`MemoryStore`].
+/// The new backend is then used as the secondary store, so it doesn't miss
+/// the writes that happen during the migration, and as the primary store once
+/// the client is restarted.
+///
+/// [`MemoryStore`]: super::MemoryStore
+/// [`MemoryStore::persist_into`]: super::MemoryStore::persist_into
+///
+/// # Examples
+///
+/// ```
+/// # use matrix_sdk_base::store::{MemoryStore, MirrorStore, StoreConfig};
+/// let store = MirrorStore::new(MemoryStore::new(), MemoryStore::new())
+///     .fail_on_secondary_error(true);
+/// let store_config = StoreConfig::new().state_store(store);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MirrorStore {
+    primary: Arc<DynStateStore>,
+    secondary: Arc<DynStateStore>,
+    fail_on_secondary_error: bool,
+}
+
+impl MirrorStore {
+    /// Create a new `MirrorStore` reading from `primary` and writing to both
+    /// `primary` and `secondary`.
+    pub fn new(primary: impl IntoStateStore, secondary: impl IntoStateStore) -> Self {
+        Self {
+            primary: primary.into_state_store(),
+            secondary: secondary.into_state_store(),
+            fail_on_secondary_error: false,
+        }
+    }
+
+    /// Set whether a failed write to the secondary store makes the whole write
+    /// fail.
+    ///
+    /// The primary store was already written to at this point. Defaults to
+    /// `false`, which means that the errors of the secondary store are only
+    /// logged.
+    pub fn fail_on_secondary_error(mut self, fail_on_secondary_error: bool) -> Self {
+        self.fail_on_secondary_error = fail_on_secondary_error;
+        self
+    }
+
+    /// Handle the `result` of the write named `operation` to the secondary
+    /// store.
+    fn mirror<T>(&self, operation: &str, result: Result<T>) -> Result<()> {
+        match result {
+            Ok(_) => Ok(()),
+            Err(error) if self.fail_on_secondary_error => Err(error),
+            Err(error) => {
+                warn!(operation, "Failed to write to the secondary state store: {error}");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StateStore for MirrorStore {
+    type Error = StoreError;
+
+    async fn get_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+    ) -> Result<Option<StateStoreDataValue>, Self::Error> {
+        self.primary.get_kv_data(key).await
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<(), Self::Error> {
+        self.primary.set_kv_data(key, value.clone()).await?;
+        self.mirror("set_kv_data", self.secondary.set_kv_data(key, value).await)
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<(), Self::Error> {
+        self.primary.remove_kv_data(key).await?;
+        self.mirror("remove_kv_data", self.secondary.remove_kv_data(key).await)
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<(), Self::Error> {
+        self.primary.save_changes(changes).await?;
+        self.mirror("save_changes", self.secondary.save_changes(changes).await)
+    }
+
+    async fn get_presence_event(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<Raw<PresenceEvent>>, Self::Error> {
+        self.primary.get_presence_event(user_id).await
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>, Self::Error> {
+        self.primary.get_presence_events(user_ids).await
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>, Self::Error> {
+        self.primary.get_state_event(room_id, event_type, state_key).await
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.primary.get_state_events(room_id, event_type).await
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.primary.get_state_events_for_keys(room_id, event_type, state_keys).await
+    }
+
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize, Self::Error> {
+        self.primary.count_room_events(room_id).await
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.primary.get_all_state_events(room_id).await
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        self.primary.get_all_rooms_state_events(room_ids).await
+    }
+
+    async fn get_state_event_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedEventId>, Self::Error> {
+        self.primary.get_state_event_ids(room_id).await
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>, Self::Error> {
+        self.primary.get_profile(room_id, user_id).await
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>, Self::Error> {
+        self.primary.get_profiles(room_id, user_ids).await
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.primary.get_user_ids(room_id, memberships).await
+    }
+
+    async fn get_invited_user_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.primary.get_user_ids(room_id, RoomMemberships::INVITE).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.primary.get_user_ids(room_id, RoomMemberships::JOIN).await
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.primary.get_room_infos().await
+    }
+
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>, Self::Error> {
+        self.primary.get_room_info(room_id).await
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>, Self::Error> {
+        self.primary.get_lazy_room_infos().await
+    }
+
+    #[allow(deprecated)]
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.primary.get_stripped_room_infos().await
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>, Self::Error> {
+        self.primary.get_users_with_display_name(room_id, display_name).await
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>, Self::Error> {
+        self.primary.get_users_with_display_names(room_id, display_names).await
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>, Self::Error> {
+        self.primary.get_account_data_event(event_type).await
+    }
+
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>, Self::Error> {
+        self.primary.get_account_data_types().await
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.primary.get_room_account_data_event(room_id, event_type).await
+    }
+
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.primary.get_room_account_data_events(room_id).await
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>, Self::Error> {
+        self.primary.get_user_room_receipt_event(room_id, receipt_type, thread, user_id).await
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error> {
+        self.primary.get_event_room_receipt_events(room_id, receipt_type, thread, event_id).await
+    }
+
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>, Self::Error> {
+        self.primary.get_latest_edit(room_id, event_id).await
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>, Self::Error> {
+        self.primary.get_alias_history_entries(room_id).await
+    }
+
+    async fn remove_events_before(
+        &self,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.primary.remove_events_before(ts).await?;
+        self.mirror("remove_events_before", self.secondary.remove_events_before(ts).await)
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.primary.get_custom_value(key).await
+    }
+
+    async fn set_custom_value(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let result = self.primary.set_custom_value(key, value.clone()).await?;
+        self.mirror("set_custom_value", self.secondary.set_custom_value(key, value).await)?;
+        Ok(result)
+    }
+
+    async fn set_custom_value_no_read(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        self.primary.set_custom_value_no_read(key, value.clone()).await?;
+        self.mirror(
+            "set_custom_value_no_read",
+            self.secondary.set_custom_value_no_read(key, value).await,
+        )
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        let result = self.primary.remove_custom_value(key).await?;
+        self.mirror("remove_custom_value", self.secondary.remove_custom_value(key).await)?;
+        Ok(result)
+    }
+
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let value = self.primary.update_custom_value(key, update).await?;
+        let result = match &value {
+            Some(value) => self.secondary.set_custom_value_no_read(key, value.clone()).await,
+            None => self.secondary.remove_custom_value(key).await.map(|_| ()),
+        };
+        self.mirror("update_custom_value", result)?;
+        Ok(value)
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.primary.remove_room(room_id).await?;
+        self.mirror("remove_room", self.secondary.remove_room(room_id).await)
+    }
+
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<(), Self::Error> {
+        self.primary.remove_rooms(room_ids).await?;
+        self.mirror("remove_rooms", self.secondary.remove_rooms(room_ids).await)
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.primary.mark_invite_rejected(room_id).await?;
+        self.mirror("mark_invite_rejected", self.secondary.mark_invite_rejected(room_id).await)
+    }
+
+    async fn clear_ephemeral(&self) -> Result<(), Self::Error> {
+        self.primary.clear_ephemeral().await?;
+        self.mirror("clear_ephemeral", self.secondary.clear_ephemeral().await)
+    }
+
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>, Self::Error> {
+        self.primary.find_orphaned_members().await
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<(), Self::Error> {
+        self.primary.prune_orphaned_members().await?;
+        self.mirror("prune_orphaned_members", self.secondary.prune_orphaned_members().await)
+    }
+
+    async fn compact_if_needed(&self, threshold: f64) -> Result<bool, Self::Error> {
+        let compacted = self.primary.compact_if_needed(threshold).await?;
+        self.mirror("compact_if_needed", self.secondary.compact_if_needed(threshold).await)?;
+
+        Ok(compacted)
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<(), Self::Error> {
+        self.primary.prune_stale_state(room_id, current_keys).await?;
+        self.mirror(
+            "prune_stale_state",
+            self.secondary.prune_stale_state(room_id, current_keys).await,
+        )
+    }
+
+    async fn save_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: OwnedTransactionId,
+        content: SerializableEventContent,
+    ) -> Result<(), Self::Error> {
+        self.primary
+            .save_send_queue_event(room_id, transaction_id.clone(), content.clone())
+            .await?;
+        self.mirror(
+            "save_send_queue_event",
+            self.secondary.save_send_queue_event(room_id, transaction_id, content).await,
+        )
+    }
+
+    async fn update_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        content: SerializableEventContent,
+    ) -> Result<bool, Self::Error> {
+        let result =
+            self.primary.update_send_queue_event(room_id, transaction_id, content.clone()).await?;
+        self.mirror(
+            "update_send_queue_event",
+            self.secondary.update_send_queue_event(room_id, transaction_id, content).await,
+        )?;
+        Ok(result)
+    }
+
+    async fn remove_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+    ) -> Result<bool, Self::Error> {
+        let result = self.primary.remove_send_queue_event(room_id, transaction_id).await?;
+        self.mirror(
+            "remove_send_queue_event",
+            self.secondary.remove_send_queue_event(room_id, transaction_id).await,
+        )?;
+        Ok(result)
+    }
+
+    async fn load_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.primary.load_send_queue_events(room_id).await
+    }
+
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        let result = self.primary.drain_send_queue_events(room_id, limit).await?;
+        self.mirror(
+            "drain_send_queue_events",
+            self.secondary.drain_send_queue_events(room_id, limit).await,
+        )?;
+        Ok(result)
+    }
+
+    async fn update_send_queue_event_status(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        wedged: bool,
+    ) -> Result<(), Self::Error> {
+        self.primary.update_send_queue_event_status(room_id, transaction_id, wedged).await?;
+        self.mirror(
+            "update_send_queue_event_status",
+            self.secondary.update_send_queue_event_status(room_id, transaction_id, wedged).await,
+        )
+    }
+
+    async fn load_rooms_with_unsent_events(&self) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        self.primary.load_rooms_with_unsent_events().await
+    }
+
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error> {
+        let result = self.primary.next_send_sequence(room_id).await?;
+        self.mirror("next_send_sequence", self.secondary.next_send_sequence(room_id).await)?;
+        Ok(result)
+    }
+
+    async fn save_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        own_txn_id: ChildTransactionId,
+        content: DependentQueuedEventKind,
+    ) -> Result<(), Self::Error> {
+        self.primary
+            .save_dependent_send_queue_event(
+                room_id,
+                parent_txn_id,
+                own_txn_id.clone(),
+                content.clone(),
+            )
+            .await?;
+        self.mirror(
+            "save_dependent_send_queue_event",
+            self.secondary
+                .save_dependent_send_queue_event(room_id, parent_txn_id, own_txn_id, content)
+                .await,
+        )
+    }
+
+    async fn update_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        event_id: OwnedEventId,
+    ) -> Result<usize, Self::Error> {
+        let result = self
+            .primary
+            .update_dependent_send_queue_event(room_id, parent_txn_id, event_id.clone())
+            .await?;
+        self.mirror(
+            "update_dependent_send_queue_event",
+            self.secondary
+                .update_dependent_send_queue_event(room_id, parent_txn_id, event_id)
+                .await,
+        )?;
+        Ok(result)
+    }
+
+    async fn remove_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        own_txn_id: &ChildTransactionId,
+    ) -> Result<bool, Self::Error> {
+        let result = self.primary.remove_dependent_send_queue_event(room_id, own_txn_id).await?;
+        self.mirror(
+            "remove_dependent_send_queue_event",
+            self.secondary.remove_dependent_send_queue_event(room_id, own_txn_id).await,
+        )?;
+        Ok(result)
+    }
+
+    async fn list_dependent_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<DependentQueuedEvent>, Self::Error> {
+        self.primary.list_dependent_send_queue_events(room_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use matrix_sdk_test::async_test;
+    use ruma::room_id;
+
+    use super::{MirrorStore, Result, StateStore};
+    use crate::{store::MemoryStore, RoomInfo, RoomState, StateChanges};
+
+    async fn get_store() -> Result<impl StateStore> {
+        Ok(MirrorStore::new(MemoryStore::new(), MemoryStore::new()))
+    }
+
+    statestore_integration_tests!();
+
+    #[async_test]
+    async fn test_writes_are_mirrored() {
+        let primary = Arc::new(MemoryStore::new());
+        let secondary = Arc::new(MemoryStore::new());
+        let store = MirrorStore::new(primary.clone(), secondary.clone());
+        let room_id = room_id!("!mirrored:localhost");
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        store.save_changes(&changes).await.unwrap();
+        store.set_custom_value(b"key", b"value".to_vec()).await.unwrap();
+
+        for backend in [&primary, &secondary] {
+            let room_infos = backend.get_room_infos().await.unwrap();
+            assert_eq!(room_infos.len(), 1);
+            assert_eq!(room_infos[0].room_id, room_id);
+            assert_eq!(backend.get_custom_value(b"key").await.unwrap().unwrap(), b"value");
+        }
+
+        store.remove_custom_value(b"key").await.unwrap();
+        assert_eq!(primary.get_custom_value(b"key").await.unwrap(), None);
+        assert_eq!(secondary.get_custom_value(b"key").await.unwrap(), None);
+    }
+
+    #[async_test]
+    async fn test_reads_come_from_the_primary_store() {
+        let primary = Arc::new(MemoryStore::new());
+        let secondary = Arc::new(MemoryStore::new());
+        let store = MirrorStore::new(primary.clone(), secondary.clone());
+
+        secondary.set_custom_value(b"secondary", b"value".to_vec()).await.unwrap();
+        primary.set_custom_value(b"primary", b"value".to_vec()).await.unwrap();
+
+        assert_eq!(store.get_custom_value(b"secondary").await.unwrap(), None);
+        assert_eq!(store.get_custom_value(b"primary").await.unwrap().unwrap(), b"value");
+    }
+}
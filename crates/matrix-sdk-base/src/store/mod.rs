@@ -23,10 +23,15 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt,
+    future::Future,
     ops::Deref,
     result::Result as StdResult,
     str::Utf8Error,
-    sync::{Arc, RwLock as StdRwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex, RwLock as StdRwLock,
+    },
+    time::Duration,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -41,42 +46,64 @@ pub mod integration_tests;
 mod observable_map;
 mod traits;
 
+use change_tracking_store::{ChangeTrackingStore, RoomChanges};
+use matrix_sdk_common::{
+    executor::{spawn, JoinHandle},
+    timeout::timeout,
+    SendOutsideWasm,
+};
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::store::{DynCryptoStore, IntoCryptoStore};
 pub use matrix_sdk_store_encryption::Error as StoreEncryptionError;
 use observable_map::ObservableMap;
+use processing_store::ProcessingStore;
 use ruma::{
     events::{
         presence::PresenceEvent,
         receipt::ReceiptEventContent,
         room::{member::StrippedRoomMemberEvent, redaction::SyncRoomRedactionEvent},
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
-        AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+        AnySyncStateEvent, AnySyncTimelineEvent, GlobalAccountDataEventType,
+        RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
     serde::Raw,
-    EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    time::{Instant, SystemTime},
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomAliasId, OwnedRoomId, OwnedUserId,
+    RoomId, UserId,
 };
-use tokio::sync::{broadcast, Mutex, RwLock};
+use serde::Serialize;
+use serde_json::value::RawValue as RawJsonValue;
+use tokio::sync::{broadcast, Mutex, RwLock, Semaphore};
+use tracing::warn;
 
 use crate::{
+    deserialized_responses::MemberEvent,
     event_cache_store::{DynEventCacheStore, IntoEventCacheStore},
     rooms::{normal::RoomInfoNotableUpdate, RoomInfo, RoomState},
-    MinimalRoomMemberEvent, Room, RoomStateFilter, SessionMeta,
+    MinimalRoomMemberEvent, Room, RoomMemberships, RoomStateFilter, SessionMeta,
 };
 
 pub(crate) mod ambiguity_map;
+mod change_tracking_store;
 mod memory_store;
 pub mod migration_helpers;
+mod mirror_store;
+mod processing_store;
+mod room_bundle;
 
 #[cfg(any(test, feature = "testing"))]
 pub use self::integration_tests::StateStoreIntegrationTests;
 pub use self::{
     memory_store::MemoryStore,
+    mirror_store::MirrorStore,
+    room_bundle::RoomBundle,
     traits::{
-        ChildTransactionId, ComposerDraft, ComposerDraftType, DependentQueuedEvent,
-        DependentQueuedEventKind, DynStateStore, IntoStateStore, QueuedEvent,
-        SerializableEventContent, ServerCapabilities, StateStore, StateStoreDataKey,
-        StateStoreDataValue, StateStoreExt,
+        room_custom_value_keys, ChildTransactionId, ComposerDraft, ComposerDraftType,
+        CredentialBundle, CustomValueUpdate, DependentQueuedEvent, DependentQueuedEventKind,
+        DiscoveryInfo, DynStateStore, FailedSend, Features, GroupSessionMeta, IntoStateStore,
+        QueuedEvent, RoomKind, RoomListEntry, RotationPolicy, SerializableEventContent,
+        ServerCapabilities, StateMap, StateStore, StateStoreDataKey, StateStoreDataValue,
+        StateStoreExt, ThirdPartyInvite, CUSTOM_VALUE_KEY_PREFIX,
     },
 };
 
@@ -119,6 +146,17 @@ pub enum StoreError {
     /// This should never happen.
     #[error("Redaction failed: {0}")]
     Redaction(#[source] ruma::canonical_json::RedactionError),
+    /// Restoring the session from the store took longer than the timeout set
+    /// with [`StoreConfig::open_timeout`].
+    #[error("Restoring the session from the store timed out")]
+    OpenTimeout,
+    /// A [`RoomBundle`] was created with a version of the format that isn't
+    /// supported.
+    #[error(
+        "The room bundle format is not supported, bundle version: {0}, \
+        supported version: {1}"
+    )]
+    UnsupportedBundleVersion(u32, u32),
 }
 
 impl StoreError {
@@ -143,7 +181,13 @@ pub type Result<T, E = StoreError> = std::result::Result<T, E>;
 /// `StateStore` implementation.
 #[derive(Clone)]
 pub(crate) struct Store {
+    /// The wrapped `StateStore`, behind a `ProcessingStore` and a
+    /// `ChangeTrackingStore`.
     pub(super) inner: Arc<DynStateStore>,
+    /// The wrapped `StateStore` behind the `ChangeTrackingStore` only, that
+    /// the `ProcessingStore` of `inner` is rebuilt around when its
+    /// configuration changes.
+    tracked_inner: Arc<DynStateStore>,
     session_meta: Arc<OnceCell<SessionMeta>>,
     /// The current sync token that should be used for the next sync call.
     pub(super) sync_token: Arc<RwLock<Option<String>>>,
@@ -152,18 +196,438 @@ pub(crate) struct Store {
     /// A lock to synchronize access to the store, such that data by the sync is
     /// never overwritten.
     sync_lock: Arc<Mutex<()>>,
+    /// The sequence numbers of the last change of every room and member,
+    /// recorded by the inner `ChangeTrackingStore`.
+    room_changes: Arc<StdRwLock<RoomChanges>>,
+    /// The handlers to call with the saved events, by event type.
+    event_type_handlers: Arc<StdRwLock<BTreeMap<String, Vec<EventTypeHandler>>>>,
+    /// How long timeline events are kept, if they expire.
+    pub(super) event_ttl: Option<Duration>,
+    /// The event cache store whose timelines expire along with the events of
+    /// this store, if any.
+    event_cache_store: Option<Arc<DynEventCacheStore>>,
+    /// The number of threads used to deserialize the members of a room.
+    pub(super) member_decode_parallelism: usize,
+    /// The verifier that the room events must pass to be saved, if any.
+    pub(super) event_verifier: Option<EventVerifier>,
+    /// How often the store maintenance runs, if it's enabled.
+    pub(super) maintenance_interval: Option<Duration>,
+    /// When the store maintenance last ran.
+    last_maintenance: Arc<StdMutex<Option<Instant>>>,
+    /// Whether the store maintenance is running in the background.
+    maintenance_running: Arc<AtomicBool>,
+    /// The ratio of free space above which the maintenance compacts the
+    /// store, if it does.
+    pub(super) compaction_threshold: Option<f64>,
+    /// How much of the store is checked when the session is restored.
+    pub(super) open_check: OpenCheck,
+    /// How long restoring the session can take, if it's limited.
+    pub(super) open_timeout: Option<Duration>,
+    /// Whether the member indexes are checked after every save.
+    pub(super) check_member_indexes: bool,
+    /// The permits of the background tasks of this store and its clones, if
+    /// their number is limited.
+    background_tasks: Option<Arc<Semaphore>>,
+}
+
+/// A handler registered with [`Store::add_event_type_handler`], called with
+/// the ID of the event's room, if any, and the raw event.
+pub(crate) type EventTypeHandler = Arc<dyn Fn(Option<&RoomId>, &RawJsonValue) + Send + Sync>;
+
+/// Marks the store maintenance as running for as long as it's alive, even if
+/// the maintenance panics.
+struct MaintenanceGuard(Arc<AtomicBool>);
+
+impl MaintenanceGuard {
+    /// Mark the maintenance as running, unless it already is.
+    fn acquire(running: &Arc<AtomicBool>) -> Option<Self> {
+        (!running.swap(true, Ordering::AcqRel)).then(|| Self(running.clone()))
+    }
+}
+
+impl Drop for MaintenanceGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// A function checking the signatures and hashes of a room event, set with
+/// [`StoreConfig::event_verifier`].
+///
+/// It's called with the ID of the room of the event and the raw event, and
+/// returns whether the event is valid.
+pub type EventVerifier = Arc<dyn Fn(&RoomId, &RawJsonValue) -> bool + Send + Sync>;
+
+/// How much of the state store is checked for corrupted data when the session
+/// is restored, set with [`StoreConfig::open_check`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OpenCheck {
+    /// Nothing is checked.
+    None,
+    /// The state events of at most [`OpenCheck::QUICK_ROOM_LIMIT`] rooms are
+    /// checked.
+    #[default]
+    Quick,
+    /// The state events of every room are checked.
+    Full,
+}
+
+impl OpenCheck {
+    /// The number of rooms whose state is checked with [`OpenCheck::Quick`].
+    pub const QUICK_ROOM_LIMIT: usize = 20;
+}
+
+/// The outcome of [`Store::check_consistency`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenCheckReport {
+    /// The number of rooms whose state was checked.
+    pub rooms_checked: usize,
+    /// The number of state events that were checked.
+    pub events_checked: usize,
+    /// The rooms that have state events that can't be deserialized.
+    pub corrupted_rooms: BTreeSet<OwnedRoomId>,
 }
 
 impl Store {
     /// Create a new store, wrapping the given `StateStore`
     pub fn new(inner: Arc<DynStateStore>) -> Self {
+        let room_changes = Arc::<StdRwLock<RoomChanges>>::default();
+        let tracked_inner =
+            ChangeTrackingStore::new(inner, room_changes.clone()).into_state_store();
+        let event_type_handlers = Arc::<StdRwLock<BTreeMap<_, _>>>::default();
+        let inner = ProcessingStore::new(tracked_inner.clone(), event_type_handlers.clone())
+            .into_state_store();
+
         Self {
             inner,
+            tracked_inner,
             session_meta: Default::default(),
             sync_token: Default::default(),
             rooms: Arc::new(StdRwLock::new(ObservableMap::new())),
             sync_lock: Default::default(),
+            room_changes,
+            event_type_handlers,
+            event_ttl: None,
+            event_cache_store: None,
+            member_decode_parallelism: 1,
+            event_verifier: None,
+            maintenance_interval: None,
+            last_maintenance: Default::default(),
+            maintenance_running: Default::default(),
+            compaction_threshold: None,
+            open_check: OpenCheck::default(),
+            open_timeout: None,
+            check_member_indexes: false,
+            background_tasks: None,
+        }
+    }
+
+    /// Set how many background tasks of this store can run at the same time,
+    /// see [`StoreConfig::max_background_tasks`].
+    pub fn with_max_background_tasks(mut self, max_background_tasks: Option<usize>) -> Self {
+        self.background_tasks =
+            max_background_tasks.map(|max| Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+
+    /// Spawn a background task of this store.
+    ///
+    /// If [`StoreConfig::max_background_tasks`] is set, the task waits for
+    /// one of the permits shared by the tasks of this store before running
+    /// `future`, and holds it until `future` is done.
+    pub(crate) fn spawn_background_task<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + SendOutsideWasm + 'static,
+        F::Output: SendOutsideWasm + 'static,
+    {
+        let background_tasks = self.background_tasks.clone();
+
+        spawn(async move {
+            let _permit = match background_tasks {
+                Some(semaphore) => {
+                    Some(semaphore.acquire_owned().await.expect("the semaphore is never closed"))
+                }
+                None => None,
+            };
+
+            future.await
+        })
+    }
+
+    /// Set whether the member indexes of this store are checked after every
+    /// save, see [`StoreConfig::check_member_indexes`].
+    pub fn with_check_member_indexes(mut self, check_member_indexes: bool) -> Self {
+        self.check_member_indexes = check_member_indexes;
+        self.rebuild_processing_store();
+        self
+    }
+
+    /// Set how much of this store is checked when the session is restored,
+    /// see [`StoreConfig::open_check`].
+    pub fn with_open_check(mut self, open_check: OpenCheck) -> Self {
+        self.open_check = open_check;
+        self
+    }
+
+    /// Set how long restoring the session from this store can take, see
+    /// [`StoreConfig::open_timeout`].
+    pub fn with_open_timeout(mut self, open_timeout: Option<Duration>) -> Self {
+        self.open_timeout = open_timeout;
+        self
+    }
+
+    /// Set how often the maintenance of this store runs, see
+    /// [`StoreConfig::maintenance_interval`].
+    pub fn with_maintenance_interval(mut self, maintenance_interval: Option<Duration>) -> Self {
+        self.maintenance_interval = maintenance_interval;
+        self
+    }
+
+    /// Set the ratio of free space above which the maintenance compacts this
+    /// store, see [`StoreConfig::compaction_threshold`].
+    pub fn with_compaction_threshold(mut self, compaction_threshold: Option<f64>) -> Self {
+        self.compaction_threshold = compaction_threshold;
+        self
+    }
+
+    /// Set the verifier that the room events must pass to be saved by this
+    /// store, see [`StoreConfig::event_verifier`].
+    pub fn with_event_verifier(mut self, event_verifier: Option<EventVerifier>) -> Self {
+        self.event_verifier = event_verifier;
+        self.rebuild_processing_store();
+        self
+    }
+
+    /// Set the number of threads used to deserialize the members of the rooms
+    /// of this store, see [`StoreConfig::member_decode_parallelism`].
+    pub fn with_member_decode_parallelism(mut self, parallelism: usize) -> Self {
+        self.member_decode_parallelism = parallelism;
+        self
+    }
+
+    /// Set how long the timeline events kept by this store live, see
+    /// [`StoreConfig::event_ttl`].
+    pub fn with_event_ttl(mut self, event_ttl: Option<Duration>) -> Self {
+        self.event_ttl = event_ttl;
+        self.rebuild_processing_store();
+        self
+    }
+
+    /// Set the event cache store from which the events that outlived
+    /// [`StoreConfig::event_ttl`] are also removed when this store prunes its
+    /// expired events.
+    pub fn with_event_cache_store(mut self, event_cache_store: Arc<DynEventCacheStore>) -> Self {
+        self.event_cache_store = Some(event_cache_store);
+        self
+    }
+
+    /// Wrap the inner `StateStore` in a new `ProcessingStore` with the
+    /// current configuration of this store.
+    fn rebuild_processing_store(&mut self) {
+        self.inner =
+            ProcessingStore::new(self.tracked_inner.clone(), self.event_type_handlers.clone())
+                .with_event_verifier(self.event_verifier.clone())
+                .with_event_ttl(self.event_ttl)
+                .with_check_member_indexes(self.check_member_indexes)
+                .into_state_store();
+    }
+
+    /// Save the given changes in the inner `StateStore`.
+    ///
+    /// The changes are processed by the inner `ProcessingStore`, like the ones
+    /// saved through the `DynStateStore` this store derefs to. If the
+    /// configured [`StoreConfig::maintenance_interval`] elapsed, the
+    /// maintenance of the store is also spawned in the background once the
+    /// changes are saved.
+    pub async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
+        self.inner.save_changes(changes).await?;
+        self.spawn_maintenance_if_due();
+
+        Ok(())
+    }
+
+    /// Spawn the maintenance of this store in the background if the
+    /// configured [`StoreConfig::maintenance_interval`] elapsed since it last
+    /// ran, and it isn't running already.
+    ///
+    /// The errors of the maintenance are only logged, because the changes
+    /// that triggered it are already saved.
+    fn spawn_maintenance_if_due(&self) {
+        let Some(interval) = self.maintenance_interval else {
+            return;
+        };
+
+        let mut last_maintenance = self.last_maintenance.lock().unwrap();
+        if last_maintenance.is_some_and(|last| last.elapsed() < interval) {
+            return;
+        }
+
+        let Some(running) = MaintenanceGuard::acquire(&self.maintenance_running) else {
+            return;
+        };
+        *last_maintenance = Some(Instant::now());
+
+        let store = self.clone();
+        self.spawn_background_task(async move {
+            let _running = running;
+            if let Err(error) = store.run_maintenance().await {
+                warn!("The maintenance of the state store failed: {error}");
+            }
+        });
+    }
+
+    /// Run all the cleanup steps of this store: drop the expired timeline
+    /// events, if [`StoreConfig::event_ttl`] is set, remove the members of
+    /// rooms that the store doesn't know anymore, and compact the store if
+    /// [`StoreConfig::compaction_threshold`] is set and exceeded.
+    ///
+    /// Compacting can rewrite the whole store, so this only runs in the
+    /// background tasks spawned by [`Store::spawn_maintenance`] and
+    /// [`Store::save_changes`].
+    async fn run_maintenance(&self) -> Result<()> {
+        self.prune_expired_events().await?;
+
+        {
+            // A room saved by a sync could otherwise be seen with its members
+            // but without its room info, and lose its members.
+            let _sync_lock = self.sync_lock.lock().await;
+            self.inner.prune_orphaned_members().await?;
+        }
+
+        if let Some(threshold) = self.compaction_threshold {
+            self.inner.compact_if_needed(threshold).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Spawn the maintenance of this store in a background task now.
+    ///
+    /// Returns `None` if the maintenance is already running, either spawned
+    /// by this method or after [`Store::save_changes`]. Otherwise the returned
+    /// handle resolves with the result of the maintenance once it's done.
+    pub fn spawn_maintenance(&self) -> Option<JoinHandle<Result<()>>> {
+        let running = MaintenanceGuard::acquire(&self.maintenance_running)?;
+        *self.last_maintenance.lock().unwrap() = Some(Instant::now());
+
+        let store = self.clone();
+        Some(self.spawn_background_task(async move {
+            let _running = running;
+            store.run_maintenance().await
+        }))
+    }
+
+    /// Remove the timeline events that are older than the configured
+    /// [`StoreConfig::event_ttl`] from this store: the edits and the receipts,
+    /// and the timelines of the event cache store, if one was set with
+    /// [`Store::with_event_cache_store`].
+    ///
+    /// This is done by the store maintenance, see
+    /// [`StoreConfig::maintenance_interval`], but can also be called
+    /// periodically by clients that don't enable it. Expired edits are never
+    /// returned by [`Store::latest_edit`], even before they are removed.
+    pub async fn prune_expired_events(&self) -> Result<()> {
+        match self.expired_before() {
+            Some(ts) => self.prune_events_older_than(ts).await,
+            None => Ok(()),
+        }
+    }
+
+    /// The timestamp before which timeline events are expired, if they expire.
+    fn expired_before(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        expired_before(self.event_ttl)
+    }
+
+    async fn prune_events_older_than(&self, ts: MilliSecondsSinceUnixEpoch) -> Result<()> {
+        self.inner.remove_events_before(ts).await?;
+
+        if let Some(event_cache_store) = &self.event_cache_store {
+            event_cache_store
+                .remove_timeline_events_before(ts)
+                .await
+                .map_err(StoreError::backend)?;
+        }
+
+        Ok(())
+    }
+
+    /// Register a handler that is called with every event of the given type
+    /// saved in this store, including through the `DynStateStore` it derefs
+    /// to.
+    pub fn add_event_type_handler(&self, event_type: String, handler: EventTypeHandler) {
+        self.event_type_handlers.write().unwrap().entry(event_type).or_default().push(handler);
+    }
+
+    /// Get the rooms that were changed in the inner `StateStore` after the
+    /// given marker, along with the marker to use for the next call.
+    ///
+    /// Every change saved through this store is tracked, including the ones
+    /// saved through the `DynStateStore` it derefs to. The markers only live
+    /// in memory: they start again from `0` when a new `Store` is created,
+    /// e.g. after a restart, so markers kept from an earlier run must be
+    /// dropped and all the rooms considered changed. Passing `0` returns all
+    /// the rooms changed since this `Store` was created.
+    pub fn rooms_changed_since(&self, seq: u64) -> (Vec<OwnedRoomId>, u64) {
+        let room_changes = self.room_changes.read().unwrap();
+        let rooms = room_changes
+            .rooms
+            .iter()
+            .filter(|(_, room_seq)| **room_seq > seq)
+            .map(|(room_id, _)| room_id.clone())
+            .collect();
+        (rooms, room_changes.seq)
+    }
+
+    /// Get the members of the given room that were changed in the inner
+    /// `StateStore` after the given marker, along with the marker to use for
+    /// the next call.
+    ///
+    /// The member events of the changed members that are still stored are
+    /// returned first, followed by the IDs of the changed members that were
+    /// removed from the store, e.g. after they were evicted. The markers are
+    /// the same as for [`Store::rooms_changed_since`].
+    pub async fn members_changed_since(
+        &self,
+        room_id: &RoomId,
+        seq: u64,
+    ) -> Result<(Vec<MemberEvent>, Vec<OwnedUserId>, u64)> {
+        let (user_ids, next_seq) = {
+            let room_changes = self.room_changes.read().unwrap();
+            let user_ids: Vec<_> = room_changes
+                .members
+                .get(room_id)
+                .into_iter()
+                .flatten()
+                .filter(|(_, member_seq)| **member_seq > seq)
+                .map(|(user_id, _)| user_id.clone())
+                .collect();
+            (user_ids, room_changes.seq)
+        };
+
+        let mut members = Vec::with_capacity(user_ids.len());
+        let mut removed_members = Vec::new();
+        for user_id in user_ids {
+            match self.inner.get_member_event(room_id, &user_id).await? {
+                Some(raw) => members.push(raw.deserialize()?),
+                None => removed_members.push(user_id),
+            }
         }
+
+        Ok((members, removed_members, next_seq))
+    }
+
+    /// Get the latest event replacing the given event, if it has been edited.
+    ///
+    /// Edits that outlived the configured [`StoreConfig::event_ttl`] but
+    /// weren't pruned yet aren't returned.
+    pub async fn latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>> {
+        let edit = self.inner.get_latest_edit(room_id, event_id).await?;
+        let expired_before = self.expired_before();
+        Ok(edit.filter(|edit| !expired_before.is_some_and(|ts| is_older_than(edit, ts))))
     }
 
     /// Get access to the syncing lock.
@@ -176,11 +640,29 @@ impl Store {
     /// Restores the state of this `Store` from the given `SessionMeta` and the
     /// inner `StateStore`.
     ///
-    /// This method panics if it is called twice.
+    /// This method panics if it is called twice. It fails with
+    /// [`StoreError::OpenTimeout`] if the restoration takes longer than the
+    /// [`StoreConfig::open_timeout`], in which case the session meta isn't
+    /// set.
     pub async fn set_session_meta(
         &self,
         session_meta: SessionMeta,
         room_info_notable_update_sender: &broadcast::Sender<RoomInfoNotableUpdate>,
+    ) -> Result<()> {
+        let restore = self.restore(&session_meta.user_id, room_info_notable_update_sender);
+        with_open_timeout(self.open_timeout, restore).await?;
+
+        self.session_meta.set(session_meta).expect("Session Meta was already set");
+
+        Ok(())
+    }
+
+    /// Load the rooms and the sync token of the inner `StateStore`, for
+    /// [`Store::set_session_meta`].
+    async fn restore(
+        &self,
+        user_id: &UserId,
+        room_info_notable_update_sender: &broadcast::Sender<RoomInfoNotableUpdate>,
     ) -> Result<()> {
         {
             let room_infos = self.inner.get_room_infos().await?;
@@ -189,26 +671,65 @@ impl Store {
 
             for room_info in room_infos {
                 let new_room = Room::restore(
-                    &session_meta.user_id,
+                    user_id,
                     self.inner.clone(),
                     room_info,
                     room_info_notable_update_sender.clone(),
-                );
+                )
+                .with_member_decode_parallelism(self.member_decode_parallelism);
                 let new_room_id = new_room.room_id().to_owned();
 
                 rooms.insert(new_room_id, new_room);
             }
         }
 
+        let report = self.check_consistency(self.open_check).await?;
+        if !report.corrupted_rooms.is_empty() {
+            warn!(
+                rooms_checked = report.rooms_checked,
+                corrupted_rooms = ?report.corrupted_rooms,
+                "Found rooms with corrupted state in the store"
+            );
+        }
+
         let token =
             self.get_kv_data(StateStoreDataKey::SyncToken).await?.and_then(|s| s.into_sync_token());
         *self.sync_token.write().await = token;
 
-        self.session_meta.set(session_meta).expect("Session Meta was already set");
-
         Ok(())
     }
 
+    /// Check that the state events of the rooms of the inner `StateStore` can
+    /// be deserialized.
+    ///
+    /// With [`OpenCheck::Quick`], only the first
+    /// [`OpenCheck::QUICK_ROOM_LIMIT`] rooms are checked, which keeps the cost
+    /// bounded for large stores. This is done when the session is restored,
+    /// with the configured [`StoreConfig::open_check`].
+    pub async fn check_consistency(&self, check: OpenCheck) -> Result<OpenCheckReport> {
+        let limit = match check {
+            OpenCheck::None => return Ok(OpenCheckReport::default()),
+            OpenCheck::Quick => OpenCheck::QUICK_ROOM_LIMIT,
+            OpenCheck::Full => usize::MAX,
+        };
+
+        let mut report = OpenCheckReport::default();
+
+        for room_info in self.inner.get_lazy_room_infos().await?.into_iter().take(limit) {
+            let room_id = room_info.room_id();
+            let events = self.inner.get_all_state_events(room_id).await?;
+
+            report.rooms_checked += 1;
+            report.events_checked += events.len();
+
+            if events.iter().any(|raw| raw.deserialize().is_err()) {
+                report.corrupted_rooms.insert(room_id.to_owned());
+            }
+        }
+
+        Ok(report)
+    }
+
     /// The current [`SessionMeta`] containing our user ID and device ID.
     pub fn session_meta(&self) -> Option<&SessionMeta> {
         self.session_meta.get()
@@ -270,6 +791,7 @@ impl Store {
                     room_type,
                     room_info_notable_update_sender,
                 )
+                .with_member_decode_parallelism(self.member_decode_parallelism)
             })
             .clone()
     }
@@ -283,6 +805,7 @@ impl fmt::Debug for Store {
             .field("session_meta", &self.session_meta)
             .field("sync_token", &self.sync_token)
             .field("rooms", &self.rooms)
+            .field("room_changes", &self.room_changes)
             .finish_non_exhaustive()
     }
 }
@@ -300,6 +823,9 @@ impl Deref for Store {
 pub struct StateChanges {
     /// The sync token that relates to this update.
     pub sync_token: Option<String>,
+    /// The time of the sync that relates to this update, saved as the time of
+    /// the last successful sync.
+    pub last_sync_time: Option<MilliSecondsSinceUnixEpoch>,
     /// A mapping of event type string to `AnyBasicEvent`.
     pub account_data: BTreeMap<GlobalAccountDataEventType, Raw<AnyGlobalAccountDataEvent>>,
     /// A mapping of `UserId` to `PresenceEvent`.
@@ -314,6 +840,12 @@ pub struct StateChanges {
     /// These are deleted *before* other room profiles are inserted.
     pub profiles_to_delete: BTreeMap<OwnedRoomId, Vec<OwnedUserId>>,
 
+    /// A mapping of room members to delete, along with their member event.
+    ///
+    /// These are deleted *before* the state events of [`StateChanges::state`]
+    /// are inserted.
+    pub members_to_delete: BTreeMap<OwnedRoomId, Vec<OwnedUserId>>,
+
     /// A mapping of `RoomId` to a map of event type string to a state key and
     /// `AnySyncStateEvent`.
     pub state:
@@ -342,6 +874,22 @@ pub struct StateChanges {
     /// A map from room id to a map of a display name and a set of user ids that
     /// share that display name in the given room.
     pub ambiguity_maps: BTreeMap<OwnedRoomId, BTreeMap<String, BTreeSet<OwnedUserId>>>,
+
+    /// A mapping of `RoomId` to a map of event IDs to the latest event that
+    /// replaces them with an `m.replace` relation.
+    ///
+    /// For every edited event, the `StateStore` only keeps the edit with the
+    /// greatest `origin_server_ts` it was given.
+    pub edits: BTreeMap<OwnedRoomId, BTreeMap<OwnedEventId, Raw<AnySyncTimelineEvent>>>,
+
+    /// A mapping of `RoomId` to the canonical aliases to append to the alias
+    /// history of the room, oldest first, with the time they were set.
+    ///
+    /// The `StateStore` doesn't append an alias that is already the latest
+    /// one of the history. The client fills it from the
+    /// `m.room.canonical_alias` events of [`StateChanges::state`] when the
+    /// changes are saved.
+    pub alias_history: BTreeMap<OwnedRoomId, Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>>,
 }
 
 impl StateChanges {
@@ -401,18 +949,33 @@ impl StateChanges {
 
     /// Update the `StateChanges` struct with the given room with a new
     /// `AnySyncStateEvent`.
+    ///
+    /// If an event with the same type and state key was already added, the
+    /// one with the greater `origin_server_ts` is kept. On a tie, the event
+    /// added last wins.
     pub fn add_state_event(
         &mut self,
         room_id: &RoomId,
         event: AnySyncStateEvent,
         raw_event: Raw<AnySyncStateEvent>,
     ) {
-        self.state
+        let events = self
+            .state
             .entry(room_id.to_owned())
             .or_default()
             .entry(event.event_type())
-            .or_default()
-            .insert(event.state_key().to_owned(), raw_event);
+            .or_default();
+
+        if let Some(existing) = events.get(event.state_key()) {
+            let existing_ts =
+                existing.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts").ok().flatten();
+
+            if existing_ts.is_some_and(|ts| ts > event.origin_server_ts()) {
+                return;
+            }
+        }
+
+        events.insert(event.state_key().to_owned(), raw_event);
     }
 
     /// Redact an event in the room
@@ -428,11 +991,252 @@ impl StateChanges {
             .insert(redacted_event_id.to_owned(), redaction);
     }
 
+    /// Record that `original_event_id` is replaced by `edit`.
+    ///
+    /// If an edit of the same event was already added, the one with the
+    /// greater `origin_server_ts` is kept.
+    pub fn add_edit(
+        &mut self,
+        room_id: &RoomId,
+        original_event_id: &EventId,
+        edit: Raw<AnySyncTimelineEvent>,
+    ) {
+        let edits = self.edits.entry(room_id.to_owned()).or_default();
+
+        if let Some(existing) = edits.get(original_event_id) {
+            if is_older_edit(&edit, existing) {
+                return;
+            }
+        }
+
+        edits.insert(original_event_id.to_owned(), edit);
+    }
+
     /// Update the `StateChanges` struct with the given room with a new
     /// `Receipts`.
     pub fn add_receipts(&mut self, room_id: &RoomId, event: ReceiptEventContent) {
         self.receipts.insert(room_id.to_owned(), event);
     }
+
+    /// Get the IDs of all the rooms that are touched by these changes.
+    pub fn changed_rooms(&self) -> BTreeSet<&RoomId> {
+        self.profiles
+            .keys()
+            .chain(self.profiles_to_delete.keys())
+            .chain(self.members_to_delete.keys())
+            .chain(self.state.keys())
+            .chain(self.room_account_data.keys())
+            .chain(self.room_infos.keys())
+            .chain(self.receipts.keys())
+            .chain(self.redactions.keys())
+            .chain(self.stripped_state.keys())
+            .chain(self.ambiguity_maps.keys())
+            .chain(self.edits.keys())
+            .chain(self.alias_history.keys())
+            .map(|room_id| &**room_id)
+            .collect()
+    }
+
+    /// Get the canonical aliases set by the `m.room.canonical_alias` events of
+    /// these changes, along with the time they were set, by room.
+    fn canonical_aliases(
+        &self,
+    ) -> Vec<(OwnedRoomId, (OwnedRoomAliasId, MilliSecondsSinceUnixEpoch))> {
+        self.state
+            .iter()
+            .filter_map(|(room_id, state)| {
+                let raw = state.get(&StateEventType::RoomCanonicalAlias)?.get("")?;
+                let Ok(AnySyncStateEvent::RoomCanonicalAlias(SyncStateEvent::Original(event))) =
+                    raw.deserialize()
+                else {
+                    return None;
+                };
+
+                Some((room_id.clone(), (event.content.alias?, event.origin_server_ts)))
+            })
+            .collect()
+    }
+
+    /// Get a copy of these changes without the room events that fail the
+    /// given verifier.
+    ///
+    /// The profiles of the members whose member event is dropped are dropped
+    /// too.
+    fn with_verified_events(
+        &self,
+        verifier: &(dyn Fn(&RoomId, &RawJsonValue) -> bool + Send + Sync),
+    ) -> Self {
+        let mut changes = self.clone();
+
+        for (room_id, state) in &mut changes.state {
+            for (event_type, raws) in state.iter_mut() {
+                raws.retain(|state_key, raw| {
+                    let is_valid = verifier(&**room_id, raw.json());
+                    if !is_valid {
+                        warn!(%room_id, %event_type, %state_key, "Dropping invalid state event");
+
+                        if *event_type == StateEventType::RoomMember {
+                            if let Some(profiles) = changes.profiles.get_mut(room_id) {
+                                profiles.retain(|user_id, _| user_id.as_str() != state_key);
+                            }
+                        }
+                    }
+                    is_valid
+                });
+            }
+        }
+        for (room_id, redactions) in &mut changes.redactions {
+            redactions.retain(|event_id, raw| {
+                let is_valid = verifier(&**room_id, raw.json());
+                if !is_valid {
+                    warn!(%room_id, %event_id, "Dropping invalid redaction");
+                }
+                is_valid
+            });
+        }
+        for (room_id, edits) in &mut changes.edits {
+            edits.retain(|event_id, raw| {
+                let is_valid = verifier(&**room_id, raw.json());
+                if !is_valid {
+                    warn!(%room_id, %event_id, "Dropping invalid edit");
+                }
+                is_valid
+            });
+        }
+
+        changes
+    }
+
+    /// Get all the events contained in these changes, with their type and the
+    /// ID of their room, if any.
+    fn raw_events(&self) -> Vec<(String, Option<&RoomId>, &RawJsonValue)> {
+        let mut events = Vec::new();
+
+        for (event_type, raw) in &self.account_data {
+            events.push((event_type.to_string(), None, raw.json()));
+        }
+        for raw in self.presence.values() {
+            events.push(("m.presence".to_owned(), None, raw.json()));
+        }
+        for (room_id, state) in &self.state {
+            for (event_type, raws) in state {
+                for raw in raws.values() {
+                    events.push((event_type.to_string(), Some(&**room_id), raw.json()));
+                }
+            }
+        }
+        for (room_id, state) in &self.stripped_state {
+            for (event_type, raws) in state {
+                for raw in raws.values() {
+                    events.push((event_type.to_string(), Some(&**room_id), raw.json()));
+                }
+            }
+        }
+        for (room_id, account_data) in &self.room_account_data {
+            for (event_type, raw) in account_data {
+                events.push((event_type.to_string(), Some(&**room_id), raw.json()));
+            }
+        }
+        for (room_id, redactions) in &self.redactions {
+            for raw in redactions.values() {
+                events.push(("m.room.redaction".to_owned(), Some(&**room_id), raw.json()));
+            }
+        }
+        for (room_id, edits) in &self.edits {
+            for raw in edits.values() {
+                if let Ok(Some(event_type)) = raw.get_field::<String>("type") {
+                    events.push((event_type, Some(&**room_id), raw.json()));
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Get an estimate of the size of these changes, in bytes.
+    ///
+    /// This is the sum of the lengths of the serialized events and values, so
+    /// it's only an approximation of the memory they use. It's meant to be
+    /// compared to a budget, for example to decide when buffered changes
+    /// should be saved.
+    pub fn estimated_size(&self) -> usize {
+        fn raw_len<T>(raw: &Raw<T>) -> usize {
+            raw.json().get().len()
+        }
+
+        fn serialized_len(value: &impl Serialize) -> usize {
+            serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
+        }
+
+        let state = self.state.values().flat_map(BTreeMap::values).flat_map(BTreeMap::values);
+        let stripped_state =
+            self.stripped_state.values().flat_map(BTreeMap::values).flat_map(BTreeMap::values);
+        let ambiguity_maps = self.ambiguity_maps.values().flatten().map(|(name, user_ids)| {
+            name.len() + user_ids.iter().map(|user_id| user_id.as_str().len()).sum::<usize>()
+        });
+        let alias_history =
+            self.alias_history.values().flatten().map(|(alias, _)| alias.as_str().len() + 8);
+
+        self.sync_token.as_ref().map_or(0, String::len)
+            + self.last_sync_time.map_or(0, |_| 8)
+            + self.account_data.values().map(raw_len).sum::<usize>()
+            + self.presence.values().map(raw_len).sum::<usize>()
+            + self.profiles.values().flat_map(BTreeMap::values).map(serialized_len).sum::<usize>()
+            + self.profiles_to_delete.values().flatten().map(|u| u.as_str().len()).sum::<usize>()
+            + self.members_to_delete.values().flatten().map(|u| u.as_str().len()).sum::<usize>()
+            + state.map(raw_len).sum::<usize>()
+            + self.room_account_data.values().flat_map(BTreeMap::values).map(raw_len).sum::<usize>()
+            + self.room_infos.values().map(serialized_len).sum::<usize>()
+            + self.receipts.values().map(serialized_len).sum::<usize>()
+            + self.redactions.values().flat_map(BTreeMap::values).map(raw_len).sum::<usize>()
+            + stripped_state.map(raw_len).sum::<usize>()
+            + ambiguity_maps.sum::<usize>()
+            + self.edits.values().flat_map(BTreeMap::values).map(raw_len).sum::<usize>()
+            + alias_history.sum::<usize>()
+    }
+}
+
+/// Whether `edit` is older than `existing`, according to their
+/// `origin_server_ts`. On a tie, `edit` is considered newer.
+fn is_older_edit(edit: &Raw<AnySyncTimelineEvent>, existing: &Raw<AnySyncTimelineEvent>) -> bool {
+    let ts = |event: &Raw<AnySyncTimelineEvent>| {
+        event.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts").ok().flatten()
+    };
+
+    match (ts(edit), ts(existing)) {
+        (Some(edit_ts), Some(existing_ts)) => edit_ts < existing_ts,
+        _ => false,
+    }
+}
+
+/// The timestamp before which timeline events are expired with the given
+/// time to live, if they expire.
+fn expired_before(event_ttl: Option<Duration>) -> Option<MilliSecondsSinceUnixEpoch> {
+    let expired_before = SystemTime::now().checked_sub(event_ttl?)?;
+    MilliSecondsSinceUnixEpoch::from_system_time(expired_before)
+}
+
+/// Whether the given event was sent before `ts`.
+fn is_older_than(event: &Raw<AnySyncTimelineEvent>, ts: MilliSecondsSinceUnixEpoch) -> bool {
+    event
+        .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+        .ok()
+        .flatten()
+        .is_some_and(|event_ts| event_ts < ts)
+}
+
+/// Run `future`, failing with [`StoreError::OpenTimeout`] if it doesn't finish
+/// within `open_timeout`.
+async fn with_open_timeout<T>(
+    open_timeout: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match open_timeout {
+        Some(duration) => {
+            timeout(Box::pin(future), duration).await.map_err(|_| StoreError::OpenTimeout)?
+        }
+        None => future.await,
+    }
 }
 
 /// Configuration for the various stores.
@@ -454,6 +1258,18 @@ pub struct StoreConfig {
     pub(crate) crypto_store: Arc<DynCryptoStore>,
     pub(crate) state_store: Arc<DynStateStore>,
     pub(crate) event_cache_store: Arc<DynEventCacheStore>,
+    pub(crate) room_info_notable_update_capacity: usize,
+    pub(crate) store_presence: bool,
+    pub(crate) max_members_per_room: Option<usize>,
+    pub(crate) event_ttl: Option<Duration>,
+    pub(crate) member_decode_parallelism: usize,
+    pub(crate) event_verifier: Option<EventVerifier>,
+    pub(crate) maintenance_interval: Option<Duration>,
+    pub(crate) compaction_threshold: Option<f64>,
+    pub(crate) open_check: OpenCheck,
+    pub(crate) open_timeout: Option<Duration>,
+    pub(crate) check_member_indexes: bool,
+    pub(crate) max_background_tasks: Option<usize>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -473,6 +1289,18 @@ impl StoreConfig {
             state_store: Arc::new(MemoryStore::new()),
             event_cache_store: crate::event_cache_store::MemoryStore::new()
                 .into_event_cache_store(),
+            room_info_notable_update_capacity: 100,
+            store_presence: true,
+            max_members_per_room: None,
+            event_ttl: None,
+            member_decode_parallelism: 1,
+            event_verifier: None,
+            maintenance_interval: None,
+            compaction_threshold: None,
+            open_check: OpenCheck::default(),
+            open_timeout: None,
+            check_member_indexes: false,
+            max_background_tasks: None,
         }
     }
 
@@ -496,6 +1324,179 @@ impl StoreConfig {
         self.event_cache_store = event_cache_store.into_event_cache_store();
         self
     }
+
+    /// Set whether presence events received during sync should be persisted
+    /// in the state store.
+    ///
+    /// Presence changes very often, so clients that don't display it can
+    /// disable this to save a lot of writes. Defaults to `true`.
+    pub fn store_presence(mut self, store_presence: bool) -> Self {
+        self.store_presence = store_presence;
+        self
+    }
+
+    /// Set the maximum number of members whose member event is stored for a
+    /// room.
+    ///
+    /// Once a room reaches this number, the member events of new members are
+    /// only stored if they are joined, so invites, leaves and bans are the
+    /// first to be dropped in very large rooms. When new joined members still
+    /// exceed the limit, stored members are evicted to make room for them: the
+    /// ones that aren't joined first, then the least recently active ones. The
+    /// member events of members that are already stored are always updated,
+    /// and the member event of the user of the client is never evicted.
+    /// Defaults to `None`, which means there is no limit.
+    pub fn max_members_per_room(mut self, max_members_per_room: Option<usize>) -> Self {
+        self.max_members_per_room = max_members_per_room;
+        self
+    }
+
+    /// Set how long the timeline events kept by the client live.
+    ///
+    /// Events older than this, based on their `origin_server_ts`, aren't saved
+    /// anymore, and are removed from the state store and the event cache store
+    /// by the store maintenance or with [`BaseClient::prune_expired_events`].
+    /// This covers the edits, the receipts and the cached timelines. The
+    /// session, the current state of the rooms and the presence of the users
+    /// never expire. Defaults to `None`, which means that events never expire.
+    ///
+    /// [`BaseClient::prune_expired_events`]: crate::BaseClient::prune_expired_events
+    pub fn event_ttl(mut self, event_ttl: Option<Duration>) -> Self {
+        self.event_ttl = event_ttl;
+        self
+    }
+
+    /// Set the number of threads used to deserialize the member events when
+    /// the members of a room are loaded from the state store.
+    ///
+    /// Deserializing the members of very large rooms one by one can be slow,
+    /// so this splits the work between up to this many tasks on the blocking
+    /// thread pool of the Tokio runtime, on platforms that support it. Rooms
+    /// with a few hundred members are still deserialized in one go, since
+    /// spawning the tasks would cost more than it saves. Defaults to `1`,
+    /// which deserializes them on the calling task.
+    pub fn member_decode_parallelism(mut self, parallelism: usize) -> Self {
+        self.member_decode_parallelism = parallelism;
+        self
+    }
+
+    /// Set the verifier that the room events must pass to be saved in the
+    /// state store.
+    ///
+    /// When it's set, the state events, redactions and edits received by the
+    /// client are passed to the verifier before they are saved, and the ones
+    /// it rejects are dropped. A verifier would typically check the server
+    /// signatures and the content hash of the events. Defaults to `None`,
+    /// which means that the events are not verified.
+    pub fn event_verifier(mut self, event_verifier: Option<EventVerifier>) -> Self {
+        self.event_verifier = event_verifier;
+        self
+    }
+
+    /// Set how often the maintenance of the state store runs.
+    ///
+    /// The maintenance groups the cleanup steps of the store: it drops the
+    /// timeline events that outlived [`StoreConfig::event_ttl`], removes the
+    /// members of rooms that aren't known anymore and compacts the store
+    /// according to [`StoreConfig::compaction_threshold`]. It's spawned in a
+    /// background task when changes are saved and the interval elapsed since
+    /// the last run, so it never delays a save and its errors are only logged.
+    /// It can also be spawned on demand with
+    /// [`BaseClient::spawn_store_maintenance`]. Only one maintenance runs at
+    /// a time. Defaults to `None`, which means that it only runs on demand.
+    ///
+    /// [`BaseClient::spawn_store_maintenance`]: crate::BaseClient::spawn_store_maintenance
+    pub fn maintenance_interval(mut self, maintenance_interval: Option<Duration>) -> Self {
+        self.maintenance_interval = maintenance_interval;
+        self
+    }
+
+    /// Set the ratio of free space above which the maintenance compacts the
+    /// state store.
+    ///
+    /// Some stores, like the SQLite one, keep the space of the deleted data in
+    /// their files to reuse it later. After many deletions, the maintenance
+    /// gives it back to the file system once the free space makes up more
+    /// than `threshold` of the store, between `0.0` and `1.0`. Compacting
+    /// rewrites the whole store, so it shouldn't be too low, and it's only
+    /// done by the maintenance, which runs in a background task and never
+    /// when changes are saved. Defaults to `None`, which means that the store
+    /// is never compacted.
+    pub fn compaction_threshold(mut self, compaction_threshold: Option<f64>) -> Self {
+        self.compaction_threshold = compaction_threshold;
+        self
+    }
+
+    /// Set how much of the state store is checked for corrupted data when the
+    /// session is restored.
+    ///
+    /// The check deserializes the stored state events of the rooms and logs
+    /// the rooms where some of them can't be. A full check reads all the state
+    /// of every room, which can slow down the startup of clients with large
+    /// stores, so the default is [`OpenCheck::Quick`], which only looks at a
+    /// bounded number of rooms.
+    pub fn open_check(mut self, open_check: OpenCheck) -> Self {
+        self.open_check = open_check;
+        self
+    }
+
+    /// Set how long restoring the session from the state store can take.
+    ///
+    /// When the store is on slow or networked storage, loading the rooms can
+    /// hang. After this timeout, restoring the session fails with
+    /// [`StoreError::OpenTimeout`] instead of blocking the startup of the
+    /// client forever. Defaults to `None`, which means that there is no
+    /// timeout.
+    pub fn open_timeout(mut self, open_timeout: Option<Duration>) -> Self {
+        self.open_timeout = open_timeout;
+        self
+    }
+
+    /// Set whether the member indexes of the state store are checked after
+    /// changes are saved.
+    ///
+    /// The stores keep the joined and invited users of the rooms apart from
+    /// their member events. With this check, saving changes logs an error
+    /// when one of the saved members isn't among the joined or invited users
+    /// that match the membership of their member event, to catch the store
+    /// bugs early. It reads the member events of the saved members and the
+    /// joined and invited users of their rooms, in debug and release builds
+    /// alike. Defaults to `false`.
+    pub fn check_member_indexes(mut self, check_member_indexes: bool) -> Self {
+        self.check_member_indexes = check_member_indexes;
+        self
+    }
+
+    /// Set how many background tasks of the state store can run at the same
+    /// time.
+    ///
+    /// The store runs its maintenance, see
+    /// [`StoreConfig::maintenance_interval`], in background tasks. On
+    /// constrained devices, this caps how many of them are active at once: the
+    /// other ones wait for one of the running tasks to finish before they
+    /// start. A limit of `0` is treated as `1`. Defaults to `None`, which
+    /// means that there is no limit.
+    pub fn max_background_tasks(mut self, max_background_tasks: Option<usize>) -> Self {
+        self.max_background_tasks = max_background_tasks;
+        self
+    }
+
+    /// Set the capacity of the channel broadcasting room info notable updates.
+    ///
+    /// A receiver that falls more than `capacity` updates behind will get a
+    /// [`RecvError::Lagged`] error on its next receive, instead of the updates
+    /// it missed. The default capacity is 100.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    ///
+    /// [`RecvError::Lagged`]: tokio::sync::broadcast::error::RecvError::Lagged
+    pub fn room_info_notable_update_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "the room info notable update capacity must be greater than 0");
+        self.room_info_notable_update_capacity = capacity;
+        self
+    }
 }
 
 impl Default for StoreConfig {
@@ -503,3 +1504,646 @@ impl Default for StoreConfig {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::async_test;
+    use ruma::{
+        event_id,
+        events::{AnySyncStateEvent, AnySyncTimelineEvent, StateEventType},
+        room_id,
+        serde::Raw,
+        user_id, MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId, UInt,
+    };
+    use serde_json::{json, value::RawValue as RawJsonValue};
+
+    use super::{
+        processing_store::member_index_mismatches, with_open_timeout, DynStateStore, EventVerifier,
+        IntoStateStore, MemoryStore, OpenCheck, Result, StateChanges, StateStore, StateStoreExt,
+        Store, StoreError,
+    };
+    use crate::{
+        deserialized_responses::{RawAnySyncOrStrippedState, SyncTimelineEvent},
+        event_cache_store::{
+            EventCacheStore, IntoEventCacheStore, MemoryStore as EventCacheMemoryStore,
+        },
+        RoomInfo, RoomMemberships, RoomState,
+    };
+
+    fn topic_event(
+        event_id: &str,
+        topic: &str,
+        ts: u64,
+    ) -> (AnySyncStateEvent, Raw<AnySyncStateEvent>) {
+        let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "content": { "topic": topic },
+            "event_id": event_id,
+            "origin_server_ts": ts,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.topic",
+        }))
+        .unwrap()
+        .cast();
+        (raw.deserialize().unwrap(), raw)
+    }
+
+    fn stored_topic(changes: &StateChanges) -> String {
+        let room_id = room_id!("!test:localhost");
+        let raw = &changes.state[room_id][&StateEventType::RoomTopic][""];
+        raw.get_field::<serde_json::Value>("content").unwrap().unwrap()["topic"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_add_state_event_keeps_newest() {
+        let room_id = room_id!("!test:localhost");
+
+        // The newer event is kept even if it's added first.
+        let mut changes = StateChanges::default();
+        let (event, raw) = topic_event("$new", "new", 2000);
+        changes.add_state_event(room_id, event, raw);
+        let (event, raw) = topic_event("$old", "old", 1000);
+        changes.add_state_event(room_id, event, raw);
+        assert_eq!(stored_topic(&changes), "new");
+
+        // And it replaces an older event.
+        let mut changes = StateChanges::default();
+        let (event, raw) = topic_event("$old", "old", 1000);
+        changes.add_state_event(room_id, event, raw);
+        let (event, raw) = topic_event("$new", "new", 2000);
+        changes.add_state_event(room_id, event, raw);
+        assert_eq!(stored_topic(&changes), "new");
+
+        // On a tie, the last one wins.
+        let (event, raw) = topic_event("$newer", "newer", 2000);
+        changes.add_state_event(room_id, event, raw);
+        assert_eq!(stored_topic(&changes), "newer");
+    }
+
+    #[test]
+    fn test_estimated_size_grows_with_changes() {
+        let mut changes = StateChanges::default();
+        assert_eq!(changes.estimated_size(), 0);
+
+        let (event, raw) = topic_event("$topic", "A topic", 1000);
+        changes.add_state_event(room_id!("!first:localhost"), event, raw);
+        let small_size = changes.estimated_size();
+        assert!(small_size > 0);
+
+        let (event, raw) = topic_event("$other_topic", "Another topic", 1000);
+        changes.add_state_event(room_id!("!second:localhost"), event, raw);
+        let larger_size = changes.estimated_size();
+        assert!(larger_size > small_size);
+
+        // Replacing an event with a much longer one makes the estimate grow too.
+        let (event, raw) = topic_event("$long_topic", &"A very long topic. ".repeat(100), 2000);
+        changes.add_state_event(room_id!("!second:localhost"), event, raw);
+        assert!(changes.estimated_size() > larger_size);
+    }
+
+    #[async_test]
+    async fn test_event_type_handler_only_gets_matching_events() {
+        let room_id = room_id!("!test:localhost");
+        let store = Store::new(MemoryStore::new().into_state_store());
+
+        let seen = Arc::new(Mutex::new(Vec::<(Option<OwnedRoomId>, String)>::new()));
+        store.add_event_type_handler("m.room.message".to_owned(), {
+            let seen = seen.clone();
+            Arc::new(move |room_id: Option<&RoomId>, event: &RawJsonValue| {
+                seen.lock().unwrap().push((room_id.map(ToOwned::to_owned), event.get().to_owned()));
+            })
+        });
+
+        let mut changes = StateChanges::default();
+        let (event, raw) = topic_event("$topic", "Not a message", 1000);
+        changes.add_state_event(room_id, event, raw);
+        let edit = Raw::new(&json!({
+            "content": {
+                "body": "* edited",
+                "msgtype": "m.text",
+                "m.new_content": { "body": "edited", "msgtype": "m.text" },
+                "m.relates_to": { "rel_type": "m.replace", "event_id": "$original" },
+            },
+            "event_id": "$edit",
+            "origin_server_ts": 2000,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+        }))
+        .unwrap()
+        .cast();
+        changes.add_edit(room_id, event_id!("$original"), edit);
+
+        store.save_changes(&changes).await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].0.as_deref(), Some(room_id));
+        assert!(seen[0].1.contains("$edit"));
+    }
+
+    #[async_test]
+    async fn test_alias_history_records_every_alias() {
+        let room_id = room_id!("!test:localhost");
+        let store = Store::new(MemoryStore::new().into_state_store());
+
+        assert!(store.get_alias_history(room_id).await.unwrap().is_empty());
+
+        for (event_id, alias, ts) in
+            [("$first", "#first:localhost", 1000), ("$second", "#second:localhost", 2000)]
+        {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "content": { "alias": alias },
+                "event_id": event_id,
+                "origin_server_ts": ts,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.canonical_alias",
+            }))
+            .unwrap()
+            .cast();
+
+            let mut changes = StateChanges::default();
+            changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+            store.save_changes(&changes).await.unwrap();
+            // Saving the same alias again doesn't add it to the history twice.
+            store.save_changes(&changes).await.unwrap();
+        }
+
+        let history = store.get_alias_history(room_id).await.unwrap();
+        let aliases: Vec<_> = history.iter().map(|(alias, _)| alias.as_str()).collect();
+        assert_eq!(aliases, ["#first:localhost", "#second:localhost"]);
+        assert!(history[0].1 < history[1].1);
+    }
+
+    #[async_test]
+    async fn test_expired_events_are_pruned() {
+        let room_id = room_id!("!test:localhost");
+        let event_cache_store = EventCacheMemoryStore::new().into_event_cache_store();
+        let store = Store::new(MemoryStore::new().into_state_store())
+            .with_event_ttl(Some(Duration::from_secs(60 * 60)))
+            .with_event_cache_store(event_cache_store.clone());
+
+        let now = MilliSecondsSinceUnixEpoch::now();
+        let two_hours_ago = now.get() - UInt::from(2 * 60 * 60 * 1000u32);
+        let edit = |event_id: &str, ts: UInt| -> Raw<AnySyncTimelineEvent> {
+            Raw::new(&json!({
+                "content": {
+                    "body": "* edited",
+                    "msgtype": "m.text",
+                    "m.new_content": { "body": "edited", "msgtype": "m.text" },
+                    "m.relates_to": { "rel_type": "m.replace", "event_id": "$original" },
+                },
+                "event_id": event_id,
+                "origin_server_ts": ts,
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+            }))
+            .unwrap()
+            .cast()
+        };
+
+        let mut changes = StateChanges::default();
+        changes.add_edit(room_id, event_id!("$old"), edit("$old_edit", two_hours_ago));
+        changes.add_edit(room_id, event_id!("$recent"), edit("$recent_edit", now.get()));
+        store.save_changes(&changes).await.unwrap();
+
+        // The edit that is already expired isn't kept.
+        assert!(store.inner.get_latest_edit(room_id, event_id!("$old")).await.unwrap().is_none());
+        assert!(store.latest_edit(room_id, event_id!("$recent")).await.unwrap().is_some());
+
+        for (event_id, ts) in [("$old_event", two_hours_ago), ("$recent_event", now.get())] {
+            let event = SyncTimelineEvent::new(edit(event_id, ts));
+            event_cache_store.append_timeline_event(room_id, event).await.unwrap();
+        }
+
+        // The expired events are removed from the event cache store.
+        store.prune_expired_events().await.unwrap();
+        let timeline = event_cache_store.get_timeline(room_id, 0, 10).await.unwrap();
+        let timeline_ids =
+            timeline.iter().map(|event| event.event_id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(timeline_ids, [event_id!("$recent_event").to_owned()]);
+
+        // Once the TTL has elapsed, a sweep removes the other events from the
+        // persisted stores.
+        let later = MilliSecondsSinceUnixEpoch(now.get() + UInt::from(2 * 60 * 60 * 1000u32));
+        store.prune_events_older_than(later).await.unwrap();
+        let recent_edit = store.inner.get_latest_edit(room_id, event_id!("$recent")).await.unwrap();
+        assert!(recent_edit.is_none());
+        assert!(event_cache_store.get_timeline(room_id, 0, 10).await.unwrap().is_empty());
+    }
+
+    #[async_test]
+    async fn test_events_failing_the_verifier_are_not_stored() {
+        let room_id = room_id!("!test:localhost");
+        let verifier: EventVerifier =
+            Arc::new(|_, event: &RawJsonValue| !event.get().contains("forged"));
+        let store =
+            Store::new(MemoryStore::new().into_state_store()).with_event_verifier(Some(verifier));
+
+        let mut changes = StateChanges::default();
+        let (event, raw) = topic_event("$forged", "forged", 1000);
+        changes.add_state_event(room_id, event, raw);
+        store.save_changes(&changes).await.unwrap();
+
+        assert!(store
+            .get_state_event(room_id, StateEventType::RoomTopic, "")
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut changes = StateChanges::default();
+        let (event, raw) = topic_event("$valid", "valid", 2000);
+        changes.add_state_event(room_id, event, raw);
+        store.save_changes(&changes).await.unwrap();
+
+        assert!(store
+            .get_state_event(room_id, StateEventType::RoomTopic, "")
+            .await
+            .unwrap()
+            .is_some());
+
+        // The events saved through the `DynStateStore` are verified too.
+        let dyn_store: &DynStateStore = &store;
+        let mut changes = StateChanges::default();
+        let (event, raw) = topic_event("$forged_again", "forged", 3000);
+        changes.add_state_event(room_id, event, raw);
+        dyn_store.save_changes(&changes).await.unwrap();
+
+        let stored = store.get_state_event(room_id, StateEventType::RoomTopic, "").await.unwrap();
+        assert_matches!(stored, Some(RawAnySyncOrStrippedState::Sync(raw)) => {
+            assert_eq!(raw.get_field::<String>("event_id").unwrap().as_deref(), Some("$valid"));
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[async_test]
+    async fn test_maintenance_prunes_orphaned_members() {
+        let room_id = room_id!("!test:localhost");
+        let orphan_room_id = room_id!("!orphan:localhost");
+
+        // The room info of the second room is missing, so its members are orphans.
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        for (room_id, event_id, user_id) in [
+            (room_id, "$member", "@alice:localhost"),
+            (orphan_room_id, "$orphan_first", "@bob:localhost"),
+            (orphan_room_id, "$orphan_second", "@carol:localhost"),
+        ] {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+                "content": { "membership": "join" },
+                "event_id": event_id,
+                "origin_server_ts": 1000,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))
+            .unwrap()
+            .cast();
+            changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        }
+
+        // Without maintenance, the orphaned members stay in the store.
+        let store = Store::new(MemoryStore::new().into_state_store());
+        store.save_changes(&changes).await.unwrap();
+        assert_eq!(
+            store.get_user_ids(orphan_room_id, RoomMemberships::empty()).await.unwrap().len(),
+            2
+        );
+
+        // With maintenance, saving the changes also prunes them in the background.
+        let store = Store::new(MemoryStore::new().into_state_store())
+            .with_maintenance_interval(Some(Duration::ZERO));
+        store.save_changes(&changes).await.unwrap();
+        // The maintenance is still running, so it can't be spawned again.
+        assert!(store.spawn_maintenance().is_none());
+        while store.maintenance_running.load(Ordering::Acquire) {
+            tokio::task::yield_now().await;
+        }
+        assert!(store
+            .get_user_ids(orphan_room_id, RoomMemberships::empty())
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.get_user_ids(room_id, RoomMemberships::empty()).await.unwrap(),
+            [user_id!("@alice:localhost").to_owned()]
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[async_test]
+    async fn test_max_background_tasks() {
+        // Spawn a few background tasks from clones of the store that each stay
+        // active for a while, and return the highest number of them that were
+        // active at once.
+        async fn peak_background_tasks(store: &Store) -> usize {
+            let active = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+
+            let handles: Vec<_> = (0..6)
+                .map(|_| {
+                    let active = active.clone();
+                    let peak = peak.clone();
+
+                    store.clone().spawn_background_task(async move {
+                        let now_active = active.fetch_add(1, Ordering::AcqRel) + 1;
+                        peak.fetch_max(now_active, Ordering::AcqRel);
+                        for _ in 0..10 {
+                            tokio::task::yield_now().await;
+                        }
+                        active.fetch_sub(1, Ordering::AcqRel);
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                handle.await.unwrap();
+            }
+
+            peak.load(Ordering::Acquire)
+        }
+
+        // Without a limit, all the tasks run at the same time.
+        let store = Store::new(MemoryStore::new().into_state_store());
+        assert_eq!(peak_background_tasks(&store).await, 6);
+
+        // With a limit, the other tasks wait for a running one to finish.
+        let store =
+            Store::new(MemoryStore::new().into_state_store()).with_max_background_tasks(Some(2));
+        assert_eq!(peak_background_tasks(&store).await, 2);
+    }
+
+    #[async_test]
+    async fn test_member_indexes_check() {
+        let room_id = room_id!("!test:localhost");
+        let member_event = |user_id: &str, membership: &str| -> Raw<AnySyncStateEvent> {
+            Raw::new(&json!({
+                "content": { "membership": membership },
+                "event_id": format!("${membership}"),
+                "origin_server_ts": 1000,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))
+            .unwrap()
+            .cast()
+        };
+
+        let store =
+            Store::new(MemoryStore::new().into_state_store()).with_check_member_indexes(true);
+
+        // A normal save keeps the indexes in sync with the member events.
+        let mut changes = StateChanges::default();
+        for (user_id, membership) in [
+            ("@alice:localhost", "join"),
+            ("@bob:localhost", "invite"),
+            ("@carol:localhost", "leave"),
+        ] {
+            let raw = member_event(user_id, membership);
+            changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        }
+        store.save_changes(&changes).await.unwrap();
+        assert!(member_index_mismatches(&*store.inner, &changes).await.unwrap().is_empty());
+
+        // A member event saved under the state key of another user indexes a
+        // joined user without a member event.
+        let mut changes = StateChanges::default();
+        changes
+            .state
+            .entry(room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default()
+            .insert("@dan:localhost".to_owned(), member_event("@erin:localhost", "join"));
+        // The mismatches are logged, saving the changes still succeeds.
+        store.save_changes(&changes).await.unwrap();
+        assert_eq!(
+            member_index_mismatches(&*store.inner, &changes).await.unwrap(),
+            [
+                (room_id.to_owned(), user_id!("@dan:localhost").to_owned()),
+                (room_id.to_owned(), user_id!("@erin:localhost").to_owned()),
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn test_check_consistency() {
+        let room_count = OpenCheck::QUICK_ROOM_LIMIT + 5;
+        let corrupted_room_id = room_id!("!corrupted:localhost");
+
+        let mut changes = StateChanges::default();
+        for i in 0..room_count {
+            let room_id = RoomId::parse(format!("!room{i}:localhost")).unwrap();
+            let (event, raw) = topic_event(&format!("$topic{i}"), "topic", 1);
+            changes.add_state_event(&room_id, event, raw);
+            changes.add_room(RoomInfo::new(&room_id, RoomState::Joined));
+        }
+
+        // A topic that isn't a string can't be deserialized.
+        let corrupted: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "content": { "topic": 42 },
+            "event_id": "$corrupted",
+            "origin_server_ts": 1,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.topic",
+        }))
+        .unwrap()
+        .cast();
+        changes
+            .state
+            .entry(corrupted_room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomTopic)
+            .or_default()
+            .insert(String::new(), corrupted);
+        changes.add_room(RoomInfo::new(corrupted_room_id, RoomState::Joined));
+
+        let store = Store::new(MemoryStore::new().into_state_store());
+        store.save_changes(&changes).await.unwrap();
+
+        let report = store.check_consistency(OpenCheck::None).await.unwrap();
+        assert_eq!(report.rooms_checked, 0);
+
+        let report = store.check_consistency(OpenCheck::Quick).await.unwrap();
+        assert_eq!(report.rooms_checked, OpenCheck::QUICK_ROOM_LIMIT);
+        assert_eq!(report.events_checked, OpenCheck::QUICK_ROOM_LIMIT);
+
+        let report = store.check_consistency(OpenCheck::Full).await.unwrap();
+        assert_eq!(report.rooms_checked, room_count + 1);
+        assert_eq!(report.events_checked, room_count + 1);
+        assert_eq!(
+            report.corrupted_rooms.into_iter().collect::<Vec<_>>(),
+            [corrupted_room_id.to_owned()]
+        );
+    }
+
+    #[cfg(feature = "experimental-sliding-sync")]
+    #[async_test]
+    async fn test_get_latest_events() {
+        use matrix_sdk_test::sync_timeline_event;
+
+        use crate::{deserialized_responses::SyncTimelineEvent, latest_event::LatestEvent};
+
+        let room_with_message = room_id!("!with_message:localhost");
+        let room_without_message = room_id!("!without_message:localhost");
+        let unknown_room = room_id!("!unknown:localhost");
+
+        let event = SyncTimelineEvent::new(sync_timeline_event!({
+            "content": { "body": "Preview me", "msgtype": "m.text" },
+            "event_id": "$latest",
+            "origin_server_ts": 1,
+            "sender": "@alice:localhost",
+            "type": "m.room.message",
+        }));
+        let mut room_info = RoomInfo::new(room_with_message, RoomState::Joined);
+        room_info.latest_event = Some(Box::new(LatestEvent::new(event)));
+
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info);
+        changes.add_room(RoomInfo::new(room_without_message, RoomState::Joined));
+
+        let store = MemoryStore::new().into_state_store();
+        store.save_changes(&changes).await.unwrap();
+
+        let latest_events = store
+            .get_latest_events(&[
+                room_without_message.to_owned(),
+                room_with_message.to_owned(),
+                unknown_room.to_owned(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(latest_events.len(), 3);
+        assert!(latest_events[0].is_none());
+        assert_eq!(latest_events[1].as_ref().unwrap().event_id().unwrap(), event_id!("$latest"));
+        assert!(latest_events[2].is_none());
+    }
+
+    #[cfg(feature = "experimental-sliding-sync")]
+    #[async_test]
+    async fn test_is_room_unread() {
+        use matrix_sdk_test::sync_timeline_event;
+
+        use crate::{deserialized_responses::SyncTimelineEvent, latest_event::LatestEvent};
+
+        let user_id = user_id!("@me:localhost");
+        let room_id = room_id!("!unread:localhost");
+
+        let latest_event = |event_id: &str, sender: &str, ts: u64| {
+            LatestEvent::new(SyncTimelineEvent::new(sync_timeline_event!({
+                "content": { "body": "Hello", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": ts,
+                "sender": sender,
+                "type": "m.room.message",
+            })))
+        };
+        let receipt = |event_id: &str, ts: u64, thread: Option<&str>| {
+            let mut receipt = json!({ "ts": ts });
+            if let Some(thread) = thread {
+                receipt["thread_id"] = thread.into();
+            }
+            serde_json::from_value(json!({
+                event_id: { "m.read": { user_id.as_str(): receipt } }
+            }))
+            .unwrap()
+        };
+
+        let store = MemoryStore::new().into_state_store();
+
+        // Without a latest event, the room is read.
+        let mut room_info = RoomInfo::new(room_id, RoomState::Joined);
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        store.save_changes(&changes).await.unwrap();
+        assert!(!store.is_room_unread(room_id, user_id).await.unwrap());
+
+        // The receipt is behind the latest event.
+        room_info.latest_event = Some(Box::new(latest_event("$second", "@alice:localhost", 20)));
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        changes.add_receipts(room_id, receipt("$first", 10, None));
+        store.save_changes(&changes).await.unwrap();
+        assert!(store.is_room_unread(room_id, user_id).await.unwrap());
+
+        // The main-threaded receipt is on the latest event.
+        let mut changes = StateChanges::default();
+        changes.add_receipts(room_id, receipt("$second", 30, Some("main")));
+        store.save_changes(&changes).await.unwrap();
+        assert!(!store.is_room_unread(room_id, user_id).await.unwrap());
+
+        // The receipt is on an event after the latest event, that isn't
+        // suitable to be the latest event.
+        room_info.latest_event = Some(Box::new(latest_event("$third", "@alice:localhost", 40)));
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        changes.add_receipts(room_id, receipt("$reaction", 50, None));
+        store.save_changes(&changes).await.unwrap();
+        assert!(!store.is_room_unread(room_id, user_id).await.unwrap());
+
+        // A receipt in a thread doesn't read the main timeline.
+        room_info.latest_event = Some(Box::new(latest_event("$fourth", "@alice:localhost", 60)));
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        changes.add_receipts(room_id, receipt("$in-thread", 70, Some("$thread")));
+        store.save_changes(&changes).await.unwrap();
+        assert!(store.is_room_unread(room_id, user_id).await.unwrap());
+
+        // Once the receipt was found in the timeline, the events after it
+        // decide.
+        room_info.read_receipts = serde_json::from_value(json!({
+            "num_unread": 0,
+            "num_notifications": 0,
+            "num_mentions": 0,
+            "latest_active": { "event_id": "$after-fourth" },
+        }))
+        .unwrap();
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        store.save_changes(&changes).await.unwrap();
+        assert!(!store.is_room_unread(room_id, user_id).await.unwrap());
+
+        room_info.read_receipts.num_unread = 1;
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        store.save_changes(&changes).await.unwrap();
+        assert!(store.is_room_unread(room_id, user_id).await.unwrap());
+
+        // The user's own latest event is read, even without a receipt.
+        room_info.latest_event = Some(Box::new(latest_event("$fifth", user_id.as_str(), 80)));
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info);
+        store.save_changes(&changes).await.unwrap();
+        assert!(!store.is_room_unread(room_id, user_id).await.unwrap());
+    }
+
+    #[async_test]
+    async fn test_open_timeout() {
+        // A store that never answers.
+        let result = with_open_timeout(
+            Some(Duration::from_millis(10)),
+            std::future::pending::<Result<()>>(),
+        )
+        .await;
+        assert_matches!(result, Err(StoreError::OpenTimeout));
+
+        let result = with_open_timeout(Some(Duration::from_secs(10)), async { Ok(42) }).await;
+        assert_eq!(result.unwrap(), 42);
+
+        let result = with_open_timeout(None, async { Ok(42) }).await;
+        assert_eq!(result.unwrap(), 42);
+    }
+}
@@ -0,0 +1,639 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{Arc, RwLock as StdRwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ruma::{
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::member::MembershipState,
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncTimelineEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomAliasId, OwnedRoomId,
+    OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UserId,
+};
+use tracing::error;
+
+use super::{
+    expired_before, is_older_than, ChildTransactionId, CustomValueUpdate, DependentQueuedEvent,
+    DependentQueuedEventKind, DynStateStore, EventTypeHandler, EventVerifier, QueuedEvent, Result,
+    SerializableEventContent, StateChanges, StateStore, StateStoreExt, StoreError,
+};
+use crate::{
+    deserialized_responses::RawAnySyncOrStrippedState, LazyRoomInfo, MinimalRoomMemberEvent,
+    RoomInfo, RoomMemberships, StateStoreDataKey, StateStoreDataValue,
+};
+
+/// A [`StateStore`] that filters and completes the changes saved through it,
+/// according to the configuration of the `Store`.
+///
+/// Before the changes are saved, the events that fail the
+/// [`StoreConfig::event_verifier`] and the edits that outlived the
+/// [`StoreConfig::event_ttl`] are left out, and the canonical aliases are
+/// added to the alias history of their room. Once they are saved, the member
+/// indexes are checked if [`StoreConfig::check_member_indexes`] is set, and
+/// the saved events are handed to the handlers registered for their type.
+///
+/// The `Store` wraps its inner store with it, so that the changes saved
+/// through [`BaseClient::store`] or by the rooms directly are processed too,
+/// not only the ones of the sync.
+///
+/// [`StoreConfig::event_verifier`]: super::StoreConfig::event_verifier
+/// [`StoreConfig::event_ttl`]: super::StoreConfig::event_ttl
+/// [`StoreConfig::check_member_indexes`]: super::StoreConfig::check_member_indexes
+/// [`BaseClient::store`]: crate::BaseClient::store
+pub(super) struct ProcessingStore {
+    inner: Arc<DynStateStore>,
+    event_type_handlers: Arc<StdRwLock<BTreeMap<String, Vec<EventTypeHandler>>>>,
+    event_verifier: Option<EventVerifier>,
+    event_ttl: Option<Duration>,
+    check_member_indexes: bool,
+}
+
+impl ProcessingStore {
+    /// Wrap `inner`, handing the events saved through it to the
+    /// `event_type_handlers`.
+    pub(super) fn new(
+        inner: Arc<DynStateStore>,
+        event_type_handlers: Arc<StdRwLock<BTreeMap<String, Vec<EventTypeHandler>>>>,
+    ) -> Self {
+        Self {
+            inner,
+            event_type_handlers,
+            event_verifier: None,
+            event_ttl: None,
+            check_member_indexes: false,
+        }
+    }
+
+    /// Set the verifier that the room events must pass to be saved.
+    pub(super) fn with_event_verifier(mut self, event_verifier: Option<EventVerifier>) -> Self {
+        self.event_verifier = event_verifier;
+        self
+    }
+
+    /// Set how long the timeline events live.
+    pub(super) fn with_event_ttl(mut self, event_ttl: Option<Duration>) -> Self {
+        self.event_ttl = event_ttl;
+        self
+    }
+
+    /// Set whether the member indexes are checked after every save.
+    pub(super) fn with_check_member_indexes(mut self, check_member_indexes: bool) -> Self {
+        self.check_member_indexes = check_member_indexes;
+        self
+    }
+
+    /// Get the changes to save instead of `changes`, if some of them must be
+    /// left out or completed.
+    fn process(&self, changes: &StateChanges) -> Option<StateChanges> {
+        let mut processed_changes =
+            self.event_verifier.as_ref().map(|verifier| changes.with_verified_events(&**verifier));
+
+        if let Some(ts) = expired_before(self.event_ttl) {
+            let has_expired_edits = changes
+                .edits
+                .values()
+                .flat_map(BTreeMap::values)
+                .any(|edit| is_older_than(edit, ts));
+
+            if has_expired_edits {
+                let changes = processed_changes.get_or_insert_with(|| changes.clone());
+                for edits in changes.edits.values_mut() {
+                    edits.retain(|_, edit| !is_older_than(edit, ts));
+                }
+            }
+        }
+
+        let canonical_aliases = processed_changes.as_ref().unwrap_or(changes).canonical_aliases();
+        if !canonical_aliases.is_empty() {
+            let changes = processed_changes.get_or_insert_with(|| changes.clone());
+            for (room_id, entry) in canonical_aliases {
+                changes.alias_history.entry(room_id).or_default().push(entry);
+            }
+        }
+
+        processed_changes
+    }
+
+    /// Hand the events of the given, already saved, changes to the handlers
+    /// registered for their type.
+    ///
+    /// The matching events are collected first, so that the handlers are
+    /// called without holding the lock on the registered handlers.
+    fn dispatch_to_event_type_handlers(&self, changes: &StateChanges) {
+        let to_dispatch: Vec<_> = {
+            let handlers = self.event_type_handlers.read().unwrap();
+            if handlers.is_empty() {
+                return;
+            }
+
+            changes
+                .raw_events()
+                .into_iter()
+                .filter_map(|(event_type, room_id, event)| {
+                    Some((handlers.get(&event_type)?.clone(), room_id, event))
+                })
+                .collect()
+        };
+
+        for (handlers, room_id, event) in to_dispatch {
+            for handler in handlers {
+                handler(room_id, event);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ProcessingStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessingStore")
+            .field("inner", &self.inner)
+            .field("event_ttl", &self.event_ttl)
+            .field("check_member_indexes", &self.check_member_indexes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Get the users with a member event in `changes` whose place in the
+/// joined and invited users of their room doesn't match the membership of
+/// their stored member event.
+///
+/// Only the members in `changes` are checked, so this reads the member
+/// events of those members and the joined and invited users of their
+/// rooms.
+pub(super) async fn member_index_mismatches(
+    store: &DynStateStore,
+    changes: &StateChanges,
+) -> Result<Vec<(OwnedRoomId, OwnedUserId)>> {
+    // The members are both those of the state keys the events are saved
+    // under and those of the events themselves, in case they differ.
+    let mut changed_members = BTreeMap::<&OwnedRoomId, BTreeSet<OwnedUserId>>::new();
+    for (room_id, state) in &changes.state {
+        for (state_key, raw) in state.get(&StateEventType::RoomMember).into_iter().flatten() {
+            let members = changed_members.entry(room_id).or_default();
+            members.extend(UserId::parse(state_key.as_str()).ok());
+            members.extend(raw.get_field::<OwnedUserId>("state_key").ok().flatten());
+        }
+    }
+    for (room_id, state) in &changes.stripped_state {
+        for (state_key, raw) in state.get(&StateEventType::RoomMember).into_iter().flatten() {
+            let members = changed_members.entry(room_id).or_default();
+            members.extend(UserId::parse(state_key.as_str()).ok());
+            members.extend(raw.get_field::<OwnedUserId>("state_key").ok().flatten());
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for (room_id, user_ids) in changed_members {
+        let joined: BTreeSet<_> =
+            store.get_user_ids(room_id, RoomMemberships::JOIN).await?.into_iter().collect();
+        let invited: BTreeSet<_> =
+            store.get_user_ids(room_id, RoomMemberships::INVITE).await?.into_iter().collect();
+
+        for user_id in user_ids {
+            let membership = store
+                .get_member_event(room_id, &user_id)
+                .await?
+                .and_then(|event| event.deserialize().ok())
+                .map(|event| event.membership().clone());
+
+            let indexed = (joined.contains(&user_id), invited.contains(&user_id));
+            let expected = (
+                membership == Some(MembershipState::Join),
+                membership == Some(MembershipState::Invite),
+            );
+
+            if indexed != expected {
+                mismatches.push((room_id.clone(), user_id));
+            }
+        }
+    }
+
+    Ok(mismatches)
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StateStore for ProcessingStore {
+    type Error = StoreError;
+
+    async fn get_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+    ) -> Result<Option<StateStoreDataValue>, Self::Error> {
+        self.inner.get_kv_data(key).await
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_kv_data(key, value).await
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<(), Self::Error> {
+        self.inner.remove_kv_data(key).await
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<(), Self::Error> {
+        let processed_changes = self.process(changes);
+        let changes = processed_changes.as_ref().unwrap_or(changes);
+
+        self.inner.save_changes(changes).await?;
+
+        if self.check_member_indexes {
+            let mismatches = member_index_mismatches(&*self.inner, changes).await?;
+            if !mismatches.is_empty() {
+                error!(?mismatches, "The member indexes are out of sync with the member events");
+            }
+        }
+
+        self.dispatch_to_event_type_handlers(changes);
+
+        Ok(())
+    }
+
+    async fn get_presence_event(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<Raw<PresenceEvent>>, Self::Error> {
+        self.inner.get_presence_event(user_id).await
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>, Self::Error> {
+        self.inner.get_presence_events(user_ids).await
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_event(room_id, event_type, state_key).await
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_events(room_id, event_type).await
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_events_for_keys(room_id, event_type, state_keys).await
+    }
+
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize, Self::Error> {
+        self.inner.count_room_events(room_id).await
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_all_state_events(room_id).await
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        self.inner.get_all_rooms_state_events(room_ids).await
+    }
+
+    async fn get_state_event_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedEventId>, Self::Error> {
+        self.inner.get_state_event_ids(room_id).await
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>, Self::Error> {
+        self.inner.get_profile(room_id, user_id).await
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>, Self::Error> {
+        self.inner.get_profiles(room_id, user_ids).await
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_user_ids(room_id, memberships).await
+    }
+
+    async fn get_invited_user_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_invited_user_ids(room_id).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_joined_user_ids(room_id).await
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.inner.get_room_infos().await
+    }
+
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>, Self::Error> {
+        self.inner.get_room_info(room_id).await
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>, Self::Error> {
+        self.inner.get_lazy_room_infos().await
+    }
+
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.inner.get_stripped_room_infos().await
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>, Self::Error> {
+        self.inner.get_users_with_display_name(room_id, display_name).await
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>, Self::Error> {
+        self.inner.get_users_with_display_names(room_id, display_names).await
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>, Self::Error> {
+        self.inner.get_account_data_event(event_type).await
+    }
+
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>, Self::Error> {
+        self.inner.get_account_data_types().await
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.inner.get_room_account_data_event(room_id, event_type).await
+    }
+
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.inner.get_room_account_data_events(room_id).await
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>, Self::Error> {
+        self.inner.get_user_room_receipt_event(room_id, receipt_type, thread, user_id).await
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error> {
+        self.inner.get_event_room_receipt_events(room_id, receipt_type, thread, event_id).await
+    }
+
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>, Self::Error> {
+        self.inner.get_latest_edit(room_id, event_id).await
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>, Self::Error> {
+        self.inner.get_alias_history_entries(room_id).await
+    }
+
+    async fn remove_events_before(
+        &self,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.inner.remove_events_before(ts).await
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.get_custom_value(key).await
+    }
+
+    async fn set_custom_value(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.set_custom_value(key, value).await
+    }
+
+    async fn set_custom_value_no_read(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_custom_value_no_read(key, value).await
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.remove_custom_value(key).await
+    }
+
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.update_custom_value(key, update).await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.inner.remove_room(room_id).await
+    }
+
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<(), Self::Error> {
+        self.inner.remove_rooms(room_ids).await
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.inner.mark_invite_rejected(room_id).await
+    }
+
+    async fn clear_ephemeral(&self) -> Result<(), Self::Error> {
+        self.inner.clear_ephemeral().await
+    }
+
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>, Self::Error> {
+        self.inner.find_orphaned_members().await
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<(), Self::Error> {
+        self.inner.prune_orphaned_members().await
+    }
+
+    async fn compact_if_needed(&self, threshold: f64) -> Result<bool, Self::Error> {
+        self.inner.compact_if_needed(threshold).await
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<(), Self::Error> {
+        self.inner.prune_stale_state(room_id, current_keys).await
+    }
+
+    async fn save_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: OwnedTransactionId,
+        content: SerializableEventContent,
+    ) -> Result<(), Self::Error> {
+        self.inner.save_send_queue_event(room_id, transaction_id, content).await
+    }
+
+    async fn update_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        content: SerializableEventContent,
+    ) -> Result<bool, Self::Error> {
+        self.inner.update_send_queue_event(room_id, transaction_id, content).await
+    }
+
+    async fn remove_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+    ) -> Result<bool, Self::Error> {
+        self.inner.remove_send_queue_event(room_id, transaction_id).await
+    }
+
+    async fn load_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.inner.load_send_queue_events(room_id).await
+    }
+
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.inner.drain_send_queue_events(room_id, limit).await
+    }
+
+    async fn update_send_queue_event_status(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        wedged: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.update_send_queue_event_status(room_id, transaction_id, wedged).await
+    }
+
+    async fn load_rooms_with_unsent_events(&self) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        self.inner.load_rooms_with_unsent_events().await
+    }
+
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error> {
+        self.inner.next_send_sequence(room_id).await
+    }
+
+    async fn save_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        own_txn_id: ChildTransactionId,
+        content: DependentQueuedEventKind,
+    ) -> Result<(), Self::Error> {
+        self.inner
+            .save_dependent_send_queue_event(room_id, parent_txn_id, own_txn_id, content)
+            .await
+    }
+
+    async fn update_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        parent_txn_id: &TransactionId,
+        event_id: OwnedEventId,
+    ) -> Result<usize, Self::Error> {
+        self.inner.update_dependent_send_queue_event(room_id, parent_txn_id, event_id).await
+    }
+
+    async fn remove_dependent_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        own_txn_id: &ChildTransactionId,
+    ) -> Result<bool, Self::Error> {
+        self.inner.remove_dependent_send_queue_event(room_id, own_txn_id).await
+    }
+
+    async fn list_dependent_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<DependentQueuedEvent>, Self::Error> {
+        self.inner.list_dependent_send_queue_events(room_id).await
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A portable, self-contained snapshot of a single room, used to share a
+//! room's state for debugging purposes.
+
+use std::collections::BTreeMap;
+
+use ruma::{
+    events::{AnyStrippedStateEvent, AnySyncStateEvent, StateEventType},
+    serde::Raw,
+    OwnedUserId,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{DynStateStore, Result, StateChanges, StoreError};
+use crate::{
+    deserialized_responses::RawAnySyncOrStrippedState, rooms::RoomInfo, MinimalRoomMemberEvent,
+    RoomMemberships,
+};
+
+/// The state event types that are exported in a [`RoomBundle`].
+const EXPORTED_STATE_EVENT_TYPES: &[StateEventType] = &[
+    StateEventType::RoomAvatar,
+    StateEventType::RoomCanonicalAlias,
+    StateEventType::RoomCreate,
+    StateEventType::RoomEncryption,
+    StateEventType::RoomGuestAccess,
+    StateEventType::RoomHistoryVisibility,
+    StateEventType::RoomJoinRules,
+    StateEventType::RoomMember,
+    StateEventType::RoomName,
+    StateEventType::RoomPinnedEvents,
+    StateEventType::RoomPowerLevels,
+    StateEventType::RoomServerAcl,
+    StateEventType::RoomTombstone,
+    StateEventType::RoomTopic,
+    StateEventType::SpaceChild,
+    StateEventType::SpaceParent,
+];
+
+/// A serializable snapshot of a single room: its [`RoomInfo`], its state
+/// events and the profiles of its members.
+///
+/// The bundle doesn't contain any information about the session of the user
+/// who exported it, nor any account data, so it can be shared with a third
+/// party and imported into another store. The parts of the [`RoomInfo`] that
+/// are specific to the user, like the latest event, the read receipts and the
+/// unread counts, are cleared when the bundle is created.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomBundle {
+    /// The version of the bundle format.
+    pub version: u32,
+    /// The information about the room.
+    pub room_info: RoomInfo,
+    /// The state events of the room, for joined and left rooms.
+    pub state: Vec<Raw<AnySyncStateEvent>>,
+    /// The stripped state events of the room, for invited and knocked rooms.
+    pub stripped_state: Vec<Raw<AnyStrippedStateEvent>>,
+    /// The profiles of the members of the room.
+    pub profiles: BTreeMap<OwnedUserId, MinimalRoomMemberEvent>,
+}
+
+impl RoomBundle {
+    /// The current version of the bundle format.
+    pub const VERSION: u32 = 1;
+
+    /// Collect the bundle of the room described by `room_info` from the given
+    /// store.
+    pub async fn from_store(store: &DynStateStore, mut room_info: RoomInfo) -> Result<Self> {
+        clear_user_data(&mut room_info);
+
+        let room_id = room_info.room_id.clone();
+        let mut state = Vec::new();
+        let mut stripped_state = Vec::new();
+
+        for event_type in EXPORTED_STATE_EVENT_TYPES {
+            for event in store.get_state_events(&room_id, event_type.clone()).await? {
+                match event {
+                    RawAnySyncOrStrippedState::Sync(raw) => state.push(raw),
+                    RawAnySyncOrStrippedState::Stripped(raw) => stripped_state.push(raw),
+                }
+            }
+        }
+
+        let user_ids = store.get_user_ids(&room_id, RoomMemberships::empty()).await?;
+        let profiles = store
+            .get_profiles(&room_id, &user_ids)
+            .await?
+            .into_iter()
+            .map(|(user_id, profile)| (user_id.to_owned(), profile))
+            .collect();
+
+        Ok(Self { version: Self::VERSION, room_info, state, stripped_state, profiles })
+    }
+
+    /// Convert this bundle into the [`StateChanges`] needed to recreate the
+    /// room in a store.
+    ///
+    /// Returns an error if the bundle was created with an unsupported version
+    /// of the format.
+    pub fn into_state_changes(self) -> Result<StateChanges> {
+        if self.version != Self::VERSION {
+            return Err(StoreError::UnsupportedBundleVersion(self.version, Self::VERSION));
+        }
+
+        let room_id = self.room_info.room_id.clone();
+        let mut changes = StateChanges::default();
+
+        for raw in self.state {
+            let event = raw.deserialize()?;
+            changes.add_state_event(&room_id, event, raw);
+        }
+
+        for raw in self.stripped_state {
+            let event = raw.deserialize()?;
+            changes
+                .stripped_state
+                .entry(room_id.clone())
+                .or_default()
+                .entry(event.event_type())
+                .or_default()
+                .insert(event.state_key().to_owned(), raw);
+        }
+
+        changes.profiles.insert(room_id, self.profiles);
+        changes.add_room(self.room_info);
+
+        Ok(changes)
+    }
+}
+
+/// Clear the data of `room_info` that is specific to the user who exports it:
+/// the latest event, which can be a decrypted message, the read receipts, the
+/// unread counts and markers, the tags, the DM targets and the notification
+/// mode.
+fn clear_user_data(room_info: &mut RoomInfo) {
+    #[cfg(feature = "experimental-sliding-sync")]
+    {
+        room_info.latest_event = None;
+    }
+    room_info.read_receipts = Default::default();
+    room_info.notification_counts = Default::default();
+    room_info.last_prev_batch = None;
+    room_info.recent_messages = Default::default();
+    room_info.cached_user_defined_notification_mode = None;
+    room_info.base_info.is_marked_unread = false;
+    room_info.base_info.notable_tags = Default::default();
+    room_info.base_info.dm_targets.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use matrix_sdk_test::{async_test, sync_state_event};
+    use ruma::{
+        api::client::sync::sync_events::UnreadNotificationsCount, events::StateEventType, room_id,
+        user_id,
+    };
+
+    use super::RoomBundle;
+    use crate::{
+        store::{IntoStateStore, MemoryStore, StateChanges, StateStoreExt, StoreError},
+        RoomInfo, RoomMemberships, RoomState,
+    };
+
+    #[async_test]
+    async fn test_export_and_import_room_bundle() {
+        let room_id = room_id!("!room:localhost");
+        let user_id = user_id!("@alice:localhost");
+
+        let store = MemoryStore::new().into_state_store();
+
+        let mut changes = StateChanges::default();
+        let mut room_info = RoomInfo::new(room_id, RoomState::Joined);
+
+        let name_event = sync_state_event!({
+            "content": { "name": "Debug me" },
+            "event_id": "$name",
+            "origin_server_ts": 1,
+            "sender": user_id,
+            "state_key": "",
+            "type": "m.room.name",
+        });
+        room_info.handle_state_event(&name_event.deserialize().unwrap());
+        changes.add_state_event(room_id, name_event.deserialize().unwrap(), name_event);
+
+        let member_event = sync_state_event!({
+            "content": { "displayname": "Alice", "membership": "join" },
+            "event_id": "$member",
+            "origin_server_ts": 2,
+            "sender": user_id,
+            "state_key": user_id,
+            "type": "m.room.member",
+        });
+        changes.add_state_event(room_id, member_event.deserialize().unwrap(), member_event);
+
+        changes.add_room(room_info.clone());
+        store.save_changes(&changes).await.unwrap();
+
+        // Export the room and serialize the bundle, as it would be written to a file.
+        let bundle = RoomBundle::from_store(&*store, room_info).await.unwrap();
+        let serialized = serde_json::to_vec(&bundle).unwrap();
+
+        // Import it into a fresh store.
+        let bundle: RoomBundle = serde_json::from_slice(&serialized).unwrap();
+        let other_store = MemoryStore::new().into_state_store();
+        other_store.save_changes(&bundle.into_state_changes().unwrap()).await.unwrap();
+
+        let room_infos = other_store.get_room_infos().await.unwrap();
+        assert_eq!(room_infos.len(), 1);
+        assert_eq!(room_infos[0].room_id, room_id);
+        assert_eq!(room_infos[0].name(), Some("Debug me"));
+
+        assert!(other_store
+            .get_state_event(room_id, StateEventType::RoomName, "")
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            other_store.get_user_ids(room_id, RoomMemberships::JOIN).await.unwrap(),
+            vec![user_id.to_owned()]
+        );
+        assert!(other_store.get_member_event(room_id, user_id).await.unwrap().is_some());
+    }
+
+    #[async_test]
+    async fn test_import_room_bundle_with_unknown_version_fails() {
+        let room_id = room_id!("!room:localhost");
+        let store = MemoryStore::new().into_state_store();
+
+        let mut bundle = RoomBundle::from_store(&*store, RoomInfo::new(room_id, RoomState::Joined))
+            .await
+            .unwrap();
+        bundle.version = RoomBundle::VERSION + 1;
+
+        assert_matches!(
+            bundle.into_state_changes(),
+            Err(StoreError::UnsupportedBundleVersion(2, 1))
+        );
+    }
+
+    #[async_test]
+    async fn test_room_bundle_excludes_user_data() {
+        let room_id = room_id!("!room:localhost");
+        let store = MemoryStore::new().into_state_store();
+
+        let mut room_info = RoomInfo::new(room_id, RoomState::Joined);
+        room_info.update_notification_count(UnreadNotificationsCount {
+            highlight_count: 1,
+            notification_count: 2,
+        });
+        room_info.read_receipts.num_unread = 3;
+        room_info.base_info.is_marked_unread = true;
+        #[cfg(feature = "experimental-sliding-sync")]
+        {
+            use crate::{deserialized_responses::SyncTimelineEvent, latest_event::LatestEvent};
+
+            let event = matrix_sdk_test::sync_timeline_event!({
+                "content": { "body": "secret", "msgtype": "m.text" },
+                "event_id": "$message",
+                "origin_server_ts": 1,
+                "sender": "@alice:localhost",
+                "type": "m.room.message",
+            });
+            room_info.latest_event =
+                Some(Box::new(LatestEvent::new(SyncTimelineEvent::new(event))));
+        }
+
+        let bundle = RoomBundle::from_store(&*store, room_info).await.unwrap();
+
+        assert_eq!(bundle.room_info.notification_counts, UnreadNotificationsCount::default());
+        assert_eq!(bundle.room_info.read_receipts.num_unread, 0);
+        assert!(!bundle.room_info.base_info.is_marked_unread);
+        #[cfg(feature = "experimental-sliding-sync")]
+        assert!(bundle.room_info.latest_event.is_none());
+
+        let serialized = serde_json::to_string(&bundle).unwrap();
+        assert!(!serialized.contains("secret"));
+    }
+}
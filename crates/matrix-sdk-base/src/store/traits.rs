@@ -14,10 +14,15 @@
 
 use std::{
     borrow::Borrow,
+    cmp::Ordering,
     collections::{BTreeMap, BTreeSet},
     fmt,
     ops::Deref,
-    sync::Arc,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    time::Duration,
 };
 
 use as_variant::as_variant;
@@ -25,27 +30,55 @@ use async_trait::async_trait;
 use growable_bloom_filter::GrowableBloom;
 use matrix_sdk_common::AsyncTraitDeps;
 use ruma::{
-    api::MatrixVersion,
+    api::{
+        client::{device::Device, filter::FilterDefinition},
+        MatrixVersion,
+    },
     events::{
+        direct::DirectEventContent,
+        fully_read::FullyReadEventContent,
+        marked_unread::MarkedUnreadEventContent,
         presence::PresenceEvent,
+        push_rules::PushRulesEventContent,
         receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::{
+            avatar::RoomAvatarEventContent,
+            create::RoomCreateEventContent,
+            encryption::RoomEncryptionEventContent,
+            history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+            member::{MembershipState, RoomMemberEventContent, StrippedRoomMemberEvent},
+            power_levels::RoomPowerLevelsEventContent,
+        },
+        space::child::SpaceChildEventContent,
+        tag::{TagEventContent, TagName, Tags},
         AnyGlobalAccountDataEvent, AnyMessageLikeEventContent, AnyRoomAccountDataEvent,
-        EmptyStateKey, EventContent as _, GlobalAccountDataEvent, GlobalAccountDataEventContent,
-        GlobalAccountDataEventType, RawExt as _, RedactContent, RedactedStateEventContent,
-        RoomAccountDataEvent, RoomAccountDataEventContent, RoomAccountDataEventType,
-        StateEventType, StaticEventContent, StaticStateEventContent,
+        AnySyncTimelineEvent, EmptyStateKey, EventContent as _, GlobalAccountDataEvent,
+        GlobalAccountDataEventContent, GlobalAccountDataEventType, RawExt as _, RedactContent,
+        RedactedStateEventContent, RoomAccountDataEvent, RoomAccountDataEventContent,
+        RoomAccountDataEventType, StateEventType, StaticEventContent, StaticStateEventContent,
+        SyncStateEvent,
     },
+    room::RoomType,
     serde::Raw,
     time::SystemTime,
-    EventId, OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId,
-    TransactionId, UserId,
+    DeviceId, EventId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedEventId, OwnedMxcUri,
+    OwnedRoomAliasId, OwnedRoomId, OwnedServerName, OwnedTransactionId, OwnedUserId, RoomId,
+    ServerName, TransactionId, UInt, UserId,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 
 use super::{StateChanges, StoreError};
+#[cfg(feature = "experimental-sliding-sync")]
+use crate::latest_event::LatestEvent;
 use crate::{
-    deserialized_responses::{RawAnySyncOrStrippedState, RawMemberEvent, RawSyncOrStrippedState},
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships,
+    deserialized_responses::{
+        RawAnySyncOrStrippedState, RawMemberEvent, RawSyncOrStrippedState, SyncOrStrippedState,
+    },
+    notification_settings::{get_user_defined_room_notification_mode, RoomNotificationMode},
+    sync::UnreadNotificationsCount,
+    DisplayName, LazyRoomInfo, MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState,
+    SessionMeta,
 };
 
 /// An abstract state store trait that can be used to implement different stores
@@ -89,6 +122,11 @@ pub trait StateStore: AsyncTraitDeps {
     async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<(), Self::Error>;
 
     /// Save the set of state changes in the store.
+    ///
+    /// The changes must be visible to all the getters of the store as soon as
+    /// the returned future resolves: implementations that write in the
+    /// background or cache data must update what they read from before
+    /// returning.
     async fn save_changes(&self, changes: &StateChanges) -> Result<(), Self::Error>;
 
     /// Get the stored presence event for the given user.
@@ -156,6 +194,63 @@ pub trait StateStore: AsyncTraitDeps {
         state_keys: &[&str],
     ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error>;
 
+    /// Get the number of state events, including stripped ones, stored for the
+    /// given room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room to count events for.
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize, Self::Error>;
+
+    /// Get the event ids of the current state events of the given room.
+    ///
+    /// Only the `event_id` field of each stored event is deserialized. Stripped
+    /// state events don't have an event id and are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room to get the state event ids of.
+    async fn get_state_event_ids(&self, room_id: &RoomId)
+        -> Result<Vec<OwnedEventId>, Self::Error>;
+
+    /// Get all the current state events of the given room, of every type.
+    ///
+    /// If the room has stripped state events, only those are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room to get the state events of.
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error>;
+
+    /// Get all the current state events of several rooms at once.
+    ///
+    /// This returns the same events as calling [`Self::get_all_state_events`]
+    /// for every room, but lets the store fetch them in a single scan. Rooms
+    /// without any state event are missing from the returned map.
+    ///
+    /// The default implementation calls [`Self::get_all_state_events`] for
+    /// every room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_ids` - The ids of the rooms to get the state events of.
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        let mut state_events = BTreeMap::new();
+        for &room_id in room_ids {
+            let events = self.get_all_state_events(room_id).await?;
+            if !events.is_empty() {
+                state_events.insert(room_id.to_owned(), events);
+            }
+        }
+        Ok(state_events)
+    }
+
     /// Get the current profile for the given user in the given room.
     ///
     /// # Arguments
@@ -204,6 +299,28 @@ pub trait StateStore: AsyncTraitDeps {
     /// Get all the pure `RoomInfo`s the store knows about.
     async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error>;
 
+    /// Get the `RoomInfo` of the given room, if the store knows about it.
+    ///
+    /// The default implementation looks for it among all the `RoomInfo`s
+    /// returned by [`Self::get_room_infos`].
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room.
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>, Self::Error> {
+        Ok(self.get_room_infos().await?.into_iter().find(|info| info.room_id() == room_id))
+    }
+
+    /// Get all the `RoomInfo`s the store knows about, without deserializing
+    /// them.
+    ///
+    /// Each [`RoomInfo`] is only deserialized when calling
+    /// [`LazyRoomInfo::deserialize()`] on it. The default implementation
+    /// deserializes them all up front with [`Self::get_room_infos`].
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>, Self::Error> {
+        Ok(self.get_room_infos().await?.into_iter().map(Into::into).collect())
+    }
+
     /// Get all the pure `RoomInfo`s the store knows about.
     #[deprecated = "Use get_room_infos instead and filter by RoomState"]
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error>;
@@ -245,6 +362,9 @@ pub trait StateStore: AsyncTraitDeps {
         event_type: GlobalAccountDataEventType,
     ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>, Self::Error>;
 
+    /// Get the types of all the events in the account data store.
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>, Self::Error>;
+
     /// Get an event out of the room account data store.
     ///
     /// # Arguments
@@ -260,6 +380,15 @@ pub trait StateStore: AsyncTraitDeps {
         event_type: RoomAccountDataEventType,
     ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>, Self::Error>;
 
+    /// Get all the room account data events of the given room, by event type.
+    ///
+    /// This is useful to restore a room at once, instead of fetching every
+    /// event type separately.
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>, Self::Error>;
+
     /// Get an event out of the user room receipt store.
     ///
     /// # Arguments
@@ -301,6 +430,45 @@ pub trait StateStore: AsyncTraitDeps {
         event_id: &EventId,
     ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error>;
 
+    /// Get the latest event replacing the given event with an `m.replace`
+    /// relation, saved with [`StateChanges::edits`].
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room of the edited event.
+    ///
+    /// * `event_id` - The id of the edited event.
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>, Self::Error>;
+
+    /// Get the successive canonical aliases of the given room, oldest first,
+    /// along with the time they were set, saved with
+    /// [`StateChanges::alias_history`].
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room.
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>, Self::Error>;
+
+    /// Remove the edits and receipts that were sent before the given time.
+    ///
+    /// Edits and receipts without a timestamp are kept. Presence events are
+    /// kept too: they are the current state of the users, and their
+    /// `last_active_ago` is relative to when they were received, which isn't
+    /// stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `ts` - The time before which events are removed.
+    async fn remove_events_before(&self, ts: MilliSecondsSinceUnixEpoch)
+        -> Result<(), Self::Error>;
+
     /// Get arbitrary data from the custom store
     ///
     /// # Arguments
@@ -311,6 +479,9 @@ pub trait StateStore: AsyncTraitDeps {
     /// Put arbitrary data into the custom store, return the data previously
     /// stored
     ///
+    /// The keys starting with [`CUSTOM_VALUE_KEY_PREFIX`] are reserved for the
+    /// values of the SDK.
+    ///
     /// # Arguments
     ///
     /// * `key` - The key to insert data into
@@ -350,6 +521,25 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `key` - The key to remove data from
     async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error>;
 
+    /// Update arbitrary data of the custom store atomically, return the new
+    /// data
+    ///
+    /// No other write to the same key can happen between reading the current
+    /// value and writing the result of `update`, so concurrent updates don't
+    /// overwrite each other.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the data to update
+    ///
+    /// * `update` - The function computing the new value from the current
+    ///   one, returning `None` removes the data
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>, Self::Error>;
+
     /// Remove a room and all elements associated from the state store.
     ///
     /// # Arguments
@@ -357,6 +547,84 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `room_id` - The `RoomId` of the room to delete.
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error>;
 
+    /// Remove several rooms and all their associated elements from the state
+    /// store at once.
+    ///
+    /// Unlike calling [`StateStore::remove_room`] for each room, either all
+    /// the rooms are removed or none is.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_ids` - The `RoomId`s of the rooms to delete.
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<(), Self::Error>;
+
+    /// Mark the invite to the given room as rejected.
+    ///
+    /// The stripped state and the stripped members of the room are removed,
+    /// and its [`RoomInfo`], if any, is marked as left, all at once so a
+    /// rejected invite doesn't linger in the store.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The `RoomId` of the rejected invite.
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<(), Self::Error>;
+
+    /// Remove all the ephemeral data from the store: the presence of users and
+    /// the read receipts.
+    ///
+    /// The rooms, their state and members, and the session are kept, unlike
+    /// when the whole store is removed.
+    async fn clear_ephemeral(&self) -> Result<(), Self::Error>;
+
+    /// Get the members stored for rooms that have no [`RoomInfo`], as pairs of
+    /// room ID and user ID.
+    ///
+    /// Such members can be left behind by a partial removal of a room. Stores
+    /// that hash their keys can't recover the ID of the room of these members,
+    /// so they don't report them, but [`StateStore::prune_orphaned_members`]
+    /// still removes them. The default implementation doesn't report any.
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Remove the members stored for rooms that have no [`RoomInfo`].
+    async fn prune_orphaned_members(&self) -> Result<(), Self::Error>;
+
+    /// Compact the store if the space left by deleted data makes up more than
+    /// `threshold` of it.
+    ///
+    /// Returns whether the store was compacted. Stores that don't keep the
+    /// space of deleted data around never need it and return `false`, which
+    /// is what the default implementation does.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - The ratio of free to total space above which the store
+    ///   is compacted, between `0.0` and `1.0`.
+    async fn compact_if_needed(&self, threshold: f64) -> Result<bool, Self::Error> {
+        let _ = threshold;
+        Ok(false)
+    }
+
+    /// Remove the state events of the given room that aren't part of its
+    /// current state.
+    ///
+    /// Events left behind by a state reset, or stored with a key that was
+    /// encoded differently, are found by the event type and state key of the
+    /// events themselves. The member events that are removed are removed from
+    /// the members of the room too. The stripped state isn't affected.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The `RoomId` of the room to prune.
+    /// * `current_keys` - The event type and state key of every state event of
+    ///   the current state of the room.
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<(), Self::Error>;
+
     /// Save an event to be sent by a send queue later.
     ///
     /// # Arguments
@@ -407,6 +675,17 @@ pub trait StateStore: AsyncTraitDeps {
         room_id: &RoomId,
     ) -> Result<Vec<QueuedEvent>, Self::Error>;
 
+    /// Removes and returns, in a single transaction, up to `limit` send queue
+    /// events for the given room, oldest first.
+    ///
+    /// Either all the returned events have been removed from the store, or
+    /// none of them have, so a batch can't be processed twice nor lost midway.
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error>;
+
     /// Updates the send queue wedged status for a given send queue event.
     async fn update_send_queue_event_status(
         &self,
@@ -418,6 +697,13 @@ pub trait StateStore: AsyncTraitDeps {
     /// Loads all the rooms which have any pending events in their send queue.
     async fn load_rooms_with_unsent_events(&self) -> Result<Vec<OwnedRoomId>, Self::Error>;
 
+    /// Get the next send-order sequence number for the given room.
+    ///
+    /// Sequence numbers start at 0 and are incremented atomically on every
+    /// call, so that local echoes of a room can be ordered relative to each
+    /// other. The counter is persisted and keeps increasing across restarts.
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error>;
+
     /// Add a new entry to the list of dependent send queue event for an event.
     async fn save_dependent_send_queue_event(
         &self,
@@ -535,6 +821,31 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_state_events_for_keys(room_id, event_type, state_keys).await.map_err(Into::into)
     }
 
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize, Self::Error> {
+        self.0.count_room_events(room_id).await.map_err(Into::into)
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.0.get_all_state_events(room_id).await.map_err(Into::into)
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>, Self::Error> {
+        self.0.get_all_rooms_state_events(room_ids).await.map_err(Into::into)
+    }
+
+    async fn get_state_event_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedEventId>, Self::Error> {
+        self.0.get_state_event_ids(room_id).await.map_err(Into::into)
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -574,6 +885,14 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_room_infos().await.map_err(Into::into)
     }
 
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>, Self::Error> {
+        self.0.get_room_info(room_id).await.map_err(Into::into)
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>, Self::Error> {
+        self.0.get_lazy_room_infos().await.map_err(Into::into)
+    }
+
     #[allow(deprecated)]
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
         self.0.get_stripped_room_infos().await.map_err(Into::into)
@@ -602,6 +921,10 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_account_data_event(event_type).await.map_err(Into::into)
     }
 
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>, Self::Error> {
+        self.0.get_account_data_types().await.map_err(Into::into)
+    }
+
     async fn get_room_account_data_event(
         &self,
         room_id: &RoomId,
@@ -610,6 +933,13 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_room_account_data_event(room_id, event_type).await.map_err(Into::into)
     }
 
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.0.get_room_account_data_events(room_id).await.map_err(Into::into)
+    }
+
     async fn get_user_room_receipt_event(
         &self,
         room_id: &RoomId,
@@ -636,6 +966,28 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
             .map_err(Into::into)
     }
 
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>, Self::Error> {
+        self.0.get_latest_edit(room_id, event_id).await.map_err(Into::into)
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>, Self::Error> {
+        self.0.get_alias_history_entries(room_id).await.map_err(Into::into)
+    }
+
+    async fn remove_events_before(
+        &self,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.0.remove_events_before(ts).await.map_err(Into::into)
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
         self.0.get_custom_value(key).await.map_err(Into::into)
     }
@@ -652,10 +1004,50 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.remove_custom_value(key).await.map_err(Into::into)
     }
 
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.0.update_custom_value(key, update).await.map_err(Into::into)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
         self.0.remove_room(room_id).await.map_err(Into::into)
     }
 
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<(), Self::Error> {
+        self.0.remove_rooms(room_ids).await.map_err(Into::into)
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.0.mark_invite_rejected(room_id).await.map_err(Into::into)
+    }
+
+    async fn clear_ephemeral(&self) -> Result<(), Self::Error> {
+        self.0.clear_ephemeral().await.map_err(Into::into)
+    }
+
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>, Self::Error> {
+        self.0.find_orphaned_members().await.map_err(Into::into)
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<(), Self::Error> {
+        self.0.prune_orphaned_members().await.map_err(Into::into)
+    }
+
+    async fn compact_if_needed(&self, threshold: f64) -> Result<bool, Self::Error> {
+        self.0.compact_if_needed(threshold).await.map_err(Into::into)
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<(), Self::Error> {
+        self.0.prune_stale_state(room_id, current_keys).await.map_err(Into::into)
+    }
+
     async fn save_send_queue_event(
         &self,
         room_id: &RoomId,
@@ -689,6 +1081,14 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.load_send_queue_events(room_id).await.map_err(Into::into)
     }
 
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.0.drain_send_queue_events(room_id, limit).await.map_err(Into::into)
+    }
+
     async fn update_send_queue_event_status(
         &self,
         room_id: &RoomId,
@@ -705,6 +1105,10 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.load_rooms_with_unsent_events().await.map_err(Into::into)
     }
 
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error> {
+        self.0.next_send_sequence(room_id).await.map_err(Into::into)
+    }
+
     async fn save_dependent_send_queue_event(
         &self,
         room_id: &RoomId,
@@ -884,79 +1288,1696 @@ pub trait StateStoreExt: StateStore {
     ) -> Result<Option<RawMemberEvent>, Self::Error> {
         self.get_state_event_static_for_key(room_id, state_key).await
     }
-}
-
-#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
-#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
-impl<T: StateStore + ?Sized> StateStoreExt for T {}
 
-/// A type-erased [`StateStore`].
-pub type DynStateStore = dyn StateStore<Error = StoreError>;
+    /// Get the history visibility of the given room, from its
+    /// `m.room.history_visibility` state event.
+    ///
+    /// Returns `None` if the store doesn't have that state event.
+    async fn get_history_visibility(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<HistoryVisibility>, Self::Error> {
+        let Some(raw) =
+            self.get_state_event_static::<RoomHistoryVisibilityEventContent>(room_id).await?
+        else {
+            return Ok(None);
+        };
 
-/// A type that can be type-erased into `Arc<dyn StateStore>`.
-///
-/// This trait is not meant to be implemented directly outside
-/// `matrix-sdk-crypto`, but it is automatically implemented for everything that
-/// implements `StateStore`.
-pub trait IntoStateStore {
-    #[doc(hidden)]
-    fn into_state_store(self) -> Arc<DynStateStore>;
-}
+        let history_visibility = match raw.deserialize()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(ev)) => {
+                ev.content.history_visibility
+            }
+            SyncOrStrippedState::Sync(SyncStateEvent::Redacted(ev)) => {
+                ev.content.history_visibility
+            }
+            SyncOrStrippedState::Stripped(ev) => ev.content.history_visibility,
+        };
 
-impl<T> IntoStateStore for T
-where
-    T: StateStore + Sized + 'static,
-{
-    fn into_state_store(self) -> Arc<DynStateStore> {
-        Arc::new(EraseStateStoreError(self))
+        Ok(Some(history_visibility))
     }
-}
 
-// Turns a given `Arc<T>` into `Arc<DynStateStore>` by attaching the
-// StateStore impl vtable of `EraseStateStoreError<T>`.
-impl<T> IntoStateStore for Arc<T>
-where
-    T: StateStore + 'static,
-{
-    fn into_state_store(self) -> Arc<DynStateStore> {
-        let ptr: *const T = Arc::into_raw(self);
-        let ptr_erased = ptr as *const EraseStateStoreError<T>;
-        // SAFETY: EraseStateStoreError is repr(transparent) so T and
-        //         EraseStateStoreError<T> have the same layout and ABI
-        unsafe { Arc::from_raw(ptr_erased) }
+    /// Get the URI of the avatar of the given room.
+    ///
+    /// This is the URL of the `m.room.avatar` event of the room. If the room
+    /// doesn't have one and is a direct message room with a single other user,
+    /// according to the `m.direct` global account data event, the avatar of
+    /// that user in the room is returned instead.
+    async fn get_room_avatar(&self, room_id: &RoomId) -> Result<Option<OwnedMxcUri>, Self::Error> {
+        if let Some(raw) = self.get_state_event_static::<RoomAvatarEventContent>(room_id).await? {
+            let url = match raw.deserialize()? {
+                SyncOrStrippedState::Sync(SyncStateEvent::Original(ev)) => ev.content.url,
+                SyncOrStrippedState::Sync(SyncStateEvent::Redacted(_)) => None,
+                SyncOrStrippedState::Stripped(ev) => ev.content.url,
+            };
+
+            if url.is_some() {
+                return Ok(url);
+            }
+        }
+
+        let Some(raw) = self.get_account_data_event_static::<DirectEventContent>().await? else {
+            return Ok(None);
+        };
+        let peers: Vec<OwnedUserId> = raw
+            .deserialize()?
+            .content
+            .0
+            .into_iter()
+            .filter(|(_, room_ids)| room_ids.iter().any(|id| id == room_id))
+            .map(|(user_id, _)| user_id)
+            .collect();
+
+        let [peer] = peers.as_slice() else {
+            return Ok(None);
+        };
+        let Some(raw) = self.get_member_event(room_id, peer).await? else {
+            return Ok(None);
+        };
+
+        Ok(match raw.deserialize()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(ev)) => ev.content.avatar_url,
+            SyncOrStrippedState::Sync(SyncStateEvent::Redacted(_)) => None,
+            SyncOrStrippedState::Stripped(ev) => ev.content.avatar_url,
+        })
     }
-}
 
-/// Server capabilities returned by the /client/versions endpoint.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct ServerCapabilities {
-    /// Versions supported by the remote server.
+    /// Get the tags of the given room, from its `m.tag` room account data
+    /// event.
     ///
-    /// This contains [`MatrixVersion`]s converted to strings.
-    pub versions: Vec<String>,
-
-    /// List of unstable features and their enablement status.
-    pub unstable_features: BTreeMap<String, bool>,
+    /// Returns `None` if the store doesn't have that event.
+    async fn get_room_tags(&self, room_id: &RoomId) -> Result<Option<Tags>, Self::Error> {
+        let Some(raw) = self.get_room_account_data_event_static::<TagEventContent>(room_id).await?
+        else {
+            return Ok(None);
+        };
 
-    /// Last time we fetched this data from the server, in milliseconds since
-    /// epoch.
-    last_fetch_ts: f64,
-}
+        Ok(Some(raw.deserialize()?.content.tags))
+    }
 
-impl ServerCapabilities {
-    /// The number of milliseconds after which the data is considered stale.
-    pub const STALE_THRESHOLD: f64 = (1000 * 60 * 60 * 24 * 7) as _; // seven days
+    /// Whether the given room was manually marked as unread by the user, as
+    /// defined in [MSC2867].
+    ///
+    /// Returns `false` if the store doesn't have the room account data event
+    /// of the flag.
+    ///
+    /// [MSC2867]: https://github.com/matrix-org/matrix-spec-proposals/pull/2867
+    async fn is_marked_unread(&self, room_id: &RoomId) -> Result<bool, Self::Error> {
+        let Some(raw) =
+            self.get_room_account_data_event_static::<MarkedUnreadEventContent>(room_id).await?
+        else {
+            return Ok(false);
+        };
 
-    /// Encode server capabilities into this serializable struct.
-    pub fn new(versions: &[MatrixVersion], unstable_features: BTreeMap<String, bool>) -> Self {
-        Self {
-            versions: versions.iter().map(|item| item.to_string()).collect(),
-            unstable_features,
-            last_fetch_ts: now_timestamp_ms(),
-        }
+        Ok(raw.deserialize()?.content.unread)
     }
 
-    /// Decode server capabilities from this serializable struct.
+    /// Get the rooms that have the given tag, along with the order of the tag.
+    ///
+    /// The rooms are sorted by increasing order, and the rooms whose tag has
+    /// no order come last, as recommended by the specification.
+    async fn get_rooms_with_tag(
+        &self,
+        tag: &TagName,
+    ) -> Result<Vec<(OwnedRoomId, Option<f64>)>, Self::Error> {
+        let mut rooms = Vec::new();
+
+        for info in self.get_room_infos().await? {
+            if let Some(tag_info) =
+                self.get_room_tags(&info.room_id).await?.and_then(|mut tags| tags.remove(tag))
+            {
+                rooms.push((info.room_id, tag_info.order));
+            }
+        }
+
+        rooms.sort_by(|(a_id, a_order), (b_id, b_order)| match (a_order, b_order) {
+            (Some(a), Some(b)) => a.total_cmp(b).then_with(|| a_id.cmp(b_id)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a_id.cmp(b_id),
+        });
+
+        Ok(rooms)
+    }
+
+    /// Get the ID of a joined direct message room with the given user.
+    ///
+    /// The rooms are looked up in the `m.direct` global account data event,
+    /// and the first one that is joined is returned. Returns `None` if there
+    /// is no such room, in which case a new one needs to be created.
+    async fn get_dm_room(&self, user_id: &UserId) -> Result<Option<OwnedRoomId>, Self::Error> {
+        let Some(raw) = self.get_account_data_event_static::<DirectEventContent>().await? else {
+            return Ok(None);
+        };
+        let mut direct = raw.deserialize()?.content;
+
+        let Some(room_ids) = direct.remove(user_id) else {
+            return Ok(None);
+        };
+
+        let joined_rooms: Vec<_> = self
+            .get_room_infos()
+            .await?
+            .into_iter()
+            .filter(|info| info.state() == RoomState::Joined)
+            .map(|info| info.room_id)
+            .collect();
+
+        Ok(room_ids.into_iter().find(|room_id| joined_rooms.contains(room_id)))
+    }
+
+    /// Get the other participant of a direct message room, to name the room
+    /// after them.
+    ///
+    /// The room must be listed for a single user in the `m.direct` global
+    /// account data event, and that user must be one of exactly two joined
+    /// members of the room. Returns `None` otherwise, like for group rooms or
+    /// for a direct message room that other users joined.
+    async fn get_dm_peer(&self, room_id: &RoomId) -> Result<Option<OwnedUserId>, Self::Error> {
+        let Some(raw) = self.get_account_data_event_static::<DirectEventContent>().await? else {
+            return Ok(None);
+        };
+        let peers: Vec<OwnedUserId> = raw
+            .deserialize()?
+            .content
+            .0
+            .into_iter()
+            .filter(|(_, room_ids)| room_ids.iter().any(|id| id == room_id))
+            .map(|(user_id, _)| user_id)
+            .collect();
+
+        let [peer] = peers.as_slice() else {
+            return Ok(None);
+        };
+        let joined = self.get_user_ids(room_id, RoomMemberships::JOIN).await?;
+
+        Ok((joined.len() == 2 && joined.contains(peer)).then(|| peer.clone()))
+    }
+
+    /// Whether the given room is a space, according to the `type` of its
+    /// `m.room.create` event.
+    ///
+    /// Unlike [`Room::is_space`], the event is read from the store, so this
+    /// also works for rooms that aren't loaded. Returns `false` if the create
+    /// event of the room isn't known.
+    ///
+    /// [`Room::is_space`]: crate::Room::is_space
+    async fn is_space(&self, room_id: &RoomId) -> Result<bool, Self::Error> {
+        let Some(raw) = self.get_state_event_static::<RoomCreateEventContent>(room_id).await?
+        else {
+            return Ok(false);
+        };
+
+        let room_type = match raw.deserialize()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(event)) => event.content.room_type,
+            SyncOrStrippedState::Sync(SyncStateEvent::Redacted(event)) => event.content.room_type,
+            SyncOrStrippedState::Stripped(event) => event.content.room_type,
+        };
+
+        Ok(room_type == Some(RoomType::Space))
+    }
+
+    /// Get the [`RoomKind`] of every room of the store.
+    ///
+    /// A room that is listed in the `m.direct` global account data event is
+    /// a DM as long as it has at most two joined or invited members, so a DM
+    /// that other users joined is a group room. The `m.direct` event is read
+    /// only once for all the rooms.
+    async fn classify_rooms(&self) -> Result<BTreeMap<OwnedRoomId, RoomKind>, Self::Error> {
+        let direct_room_ids: BTreeSet<OwnedRoomId> =
+            match self.get_account_data_event_static::<DirectEventContent>().await? {
+                Some(raw) => raw.deserialize()?.content.0.into_values().flatten().collect(),
+                None => BTreeSet::new(),
+            };
+
+        let mut kinds = BTreeMap::new();
+
+        for info in self.get_room_infos().await? {
+            let kind = if info.room_type() == Some(&RoomType::Space) {
+                RoomKind::Space
+            } else if direct_room_ids.contains(&info.room_id)
+                && self.get_user_ids(&info.room_id, RoomMemberships::ACTIVE).await?.len() <= 2
+            {
+                RoomKind::Dm
+            } else {
+                RoomKind::Group
+            };
+
+            kinds.insert(info.room_id, kind);
+        }
+
+        Ok(kinds)
+    }
+
+    /// Get the rotation policy of the outbound group sessions of the given
+    /// room, from its `m.room.encryption` state event.
+    ///
+    /// Returns `None` if the room isn't encrypted. If the event was redacted,
+    /// the room is still encrypted, with the default policy.
+    async fn get_rotation_policy(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RotationPolicy>, Self::Error> {
+        let Some(raw) = self.get_state_event_static::<RoomEncryptionEventContent>(room_id).await?
+        else {
+            return Ok(None);
+        };
+
+        let (period_ms, period_msgs) = match raw.deserialize()? {
+            SyncOrStrippedState::Sync(SyncStateEvent::Original(event)) => {
+                (event.content.rotation_period_ms, event.content.rotation_period_msgs)
+            }
+            SyncOrStrippedState::Sync(SyncStateEvent::Redacted(_)) => (None, None),
+            SyncOrStrippedState::Stripped(event) => {
+                (event.content.rotation_period_ms, event.content.rotation_period_msgs)
+            }
+        };
+
+        Ok(Some(RotationPolicy {
+            rotation_period: period_ms.map(|ms| Duration::from_millis(ms.into())),
+            rotation_period_msgs: period_msgs.map(Into::into),
+        }))
+    }
+
+    /// Get the number of rooms the user is invited to.
+    ///
+    /// Only the state of the rooms is deserialized, see
+    /// [`LazyRoomInfo::state`].
+    async fn invited_rooms_count(&self) -> Result<usize, Self::Error> {
+        let mut count = 0;
+
+        for info in self.get_lazy_room_infos().await? {
+            if info.state()? == RoomState::Invited {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Get the rooms the given user is invited to, with the time of their
+    /// invite, newest first.
+    ///
+    /// The time of an invite is the `origin_server_ts` of the member event of
+    /// the user in the stripped state of the room. That field is optional in
+    /// stripped state, so the invites without it are returned last, with
+    /// `None`.
+    async fn get_invites_sorted(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Vec<(OwnedRoomId, Option<MilliSecondsSinceUnixEpoch>)>, Self::Error> {
+        let mut invites = Vec::new();
+
+        for info in self.get_lazy_room_infos().await? {
+            if info.state()? != RoomState::Invited {
+                continue;
+            }
+
+            let room_id = info.room_id().to_owned();
+            let invited_at = match self.get_member_event(&room_id, user_id).await? {
+                Some(RawSyncOrStrippedState::Stripped(raw)) => raw.get_field("origin_server_ts"),
+                Some(RawSyncOrStrippedState::Sync(raw)) => raw.get_field("origin_server_ts"),
+                None => Ok(None),
+            };
+            invites.push((room_id, invited_at.ok().flatten()));
+        }
+
+        // `None` is smaller than any timestamp, so it ends up last.
+        invites.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        Ok(invites)
+    }
+
+    /// Get the membership of every member of the given room.
+    ///
+    /// Only the state key and the membership of the member events are
+    /// deserialized, the events that can't be are ignored.
+    async fn get_room_memberships(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<OwnedUserId, MembershipState>, Self::Error> {
+        Ok(self
+            .get_state_events(room_id, StateEventType::RoomMember)
+            .await?
+            .into_iter()
+            .filter_map(|raw| {
+                let event = match raw {
+                    RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as(),
+                    RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as(),
+                };
+                let PartialMemberEvent { state_key, content } = event.ok()?;
+                Some((state_key, content.membership))
+            })
+            .collect())
+    }
+
+    /// Get the stripped member events of the given room.
+    ///
+    /// These are the members received with an invite or a knock, like the
+    /// user who sent the invite. Returns an empty list for the rooms that
+    /// have their full state.
+    async fn get_stripped_members(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<Raw<StrippedRoomMemberEvent>>, Self::Error> {
+        Ok(self
+            .get_state_events(room_id, StateEventType::RoomMember)
+            .await?
+            .into_iter()
+            .filter_map(|raw| match raw {
+                RawAnySyncOrStrippedState::Stripped(raw) => Some(raw.cast()),
+                RawAnySyncOrStrippedState::Sync(_) => None,
+            })
+            .collect())
+    }
+
+    /// Get the whole current state of the given room, by event type and state
+    /// key.
+    ///
+    /// All the state events are loaded at once with
+    /// [`StateStore::get_all_state_events`], which is more convenient than
+    /// querying every event type to inspect or export a room. The events
+    /// whose type or state key can't be read are skipped.
+    async fn get_room_state(&self, room_id: &RoomId) -> Result<StateMap, Self::Error> {
+        Ok(to_state_map(self.get_all_state_events(room_id).await?))
+    }
+
+    /// Get the whole current state of several rooms, by event type and state
+    /// key.
+    ///
+    /// This is the same as [`StateStoreExt::get_room_state`] for every room,
+    /// but the events are loaded with a single call to
+    /// [`StateStore::get_all_rooms_state_events`], to open a space and its
+    /// children for example. Rooms without any state event are missing from
+    /// the returned map.
+    async fn get_rooms_state(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, StateMap>, Self::Error> {
+        Ok(self
+            .get_all_rooms_state_events(room_ids)
+            .await?
+            .into_iter()
+            .map(|(room_id, events)| (room_id, to_state_map(events)))
+            .collect())
+    }
+
+    /// Get the pending invites of third parties, like email addresses, to the
+    /// given room.
+    ///
+    /// The invites are read from the `m.room.third_party_invite` state events.
+    /// An invite that was revoked by redacting its event isn't returned, nor
+    /// an invite that was already claimed by a member of the room.
+    async fn get_third_party_invites(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<ThirdPartyInvite>, Self::Error> {
+        let mut invites: Vec<ThirdPartyInvite> = self
+            .get_state_events(room_id, StateEventType::RoomThirdPartyInvite)
+            .await?
+            .into_iter()
+            .filter_map(|raw| {
+                let json = match &raw {
+                    RawAnySyncOrStrippedState::Sync(raw) => raw.json(),
+                    RawAnySyncOrStrippedState::Stripped(raw) => raw.json(),
+                };
+                let event: PartialThirdPartyInviteEvent = serde_json::from_str(json.get()).ok()?;
+                Some(ThirdPartyInvite {
+                    token: event.state_key,
+                    display_name: event.content.display_name?,
+                    sender: event.sender,
+                })
+            })
+            .collect();
+
+        if invites.is_empty() {
+            return Ok(invites);
+        }
+
+        // The member event of a user who claimed an invite contains its token.
+        let mut claimed_tokens = BTreeSet::new();
+        for raw in self.get_state_events_static::<RoomMemberEventContent>(room_id).await? {
+            let Ok(SyncOrStrippedState::Sync(SyncStateEvent::Original(event))) = raw.deserialize()
+            else {
+                continue;
+            };
+            if let Some(invite) = event.content.third_party_invite {
+                claimed_tokens.insert(invite.signed.token);
+            }
+        }
+        invites.retain(|invite| !claimed_tokens.contains(&invite.token));
+
+        Ok(invites)
+    }
+
+    /// Get the number of joined members of the given room, as reported by the
+    /// server in the summary of the room.
+    ///
+    /// With lazy-loading, only some of the member events are known locally, so
+    /// this can be larger than
+    /// [`StateStoreExt::get_loaded_joined_members_count`]. Returns `None` if
+    /// the room isn't in the store.
+    async fn get_summary_member_count(&self, room_id: &RoomId) -> Result<Option<u64>, Self::Error> {
+        Ok(self.get_room_info(room_id).await?.map(|info| info.joined_members_count()))
+    }
+
+    /// Get the number of joined members of the given room whose member event
+    /// is in the store.
+    async fn get_loaded_joined_members_count(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<usize, Self::Error> {
+        Ok(self.get_user_ids(room_id, RoomMemberships::JOIN).await?.len())
+    }
+
+    /// Get the users that asked to join the given room and are waiting for an
+    /// answer.
+    ///
+    /// A user that knocked and was then invited or let in isn't returned
+    /// anymore, since only the current membership of the users is tracked.
+    async fn get_knocking_members(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.get_user_ids(room_id, RoomMemberships::KNOCK).await
+    }
+
+    /// Get the rooms in which the given user is banned.
+    ///
+    /// The membership of the user is looked up with
+    /// [`StateStore::get_user_ids`] in every room, so no member event is
+    /// deserialized.
+    async fn get_banned_rooms_for_user(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        let mut room_ids = Vec::new();
+
+        for info in self.get_room_infos().await? {
+            let banned = self.get_user_ids(&info.room_id, RoomMemberships::BAN).await?;
+            if banned.iter().any(|banned_user_id| banned_user_id == user_id) {
+                room_ids.push(info.room_id);
+            }
+        }
+
+        Ok(room_ids)
+    }
+
+    /// Get the rooms in which the given user has at least the given power
+    /// level.
+    ///
+    /// In rooms without an `m.room.power_levels` event, the creator of the
+    /// room has a power level of 100 and everyone else has 0, as defined in
+    /// the spec.
+    async fn get_rooms_where_user_has_power(
+        &self,
+        user_id: &UserId,
+        min_level: i64,
+    ) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        let mut room_ids = Vec::new();
+
+        for info in self.get_room_infos().await? {
+            let level: i64 = match self
+                .get_state_event_static::<RoomPowerLevelsEventContent>(&info.room_id)
+                .await?
+            {
+                Some(raw) => raw.deserialize()?.power_levels().for_user(user_id).into(),
+                None if info.creator() == Some(user_id) => 100,
+                None => 0,
+            };
+
+            if level >= min_level {
+                room_ids.push(info.room_id);
+            }
+        }
+
+        Ok(room_ids)
+    }
+
+    /// Get the display name of the given user in every room they joined.
+    ///
+    /// The names come from the member events of the user, so they can differ
+    /// from one room to another. The rooms in which the user didn't set a
+    /// display name are left out.
+    async fn get_user_display_names(
+        &self,
+        user_id: &UserId,
+    ) -> Result<BTreeMap<OwnedRoomId, String>, Self::Error> {
+        let mut display_names = BTreeMap::new();
+
+        for info in self.get_room_infos().await? {
+            let Some(raw) = self.get_member_event(&info.room_id, user_id).await? else {
+                continue;
+            };
+            let event = raw.deserialize()?;
+
+            if *event.membership() != MembershipState::Join {
+                continue;
+            }
+            if let Some(name) = event.original_content().and_then(|c| c.displayname.clone()) {
+                display_names.insert(info.room_id, name);
+            }
+        }
+
+        Ok(display_names)
+    }
+
+    /// Get the rooms of the store that don't have any state event of the
+    /// given type.
+    ///
+    /// After a gappy sync, a room can lack its `m.room.create` event for
+    /// example; the returned rooms are the ones whose state should be fetched
+    /// again from the server.
+    async fn rooms_missing_state(
+        &self,
+        event_type: StateEventType,
+    ) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        let mut room_ids = Vec::new();
+
+        for info in self.get_room_infos().await? {
+            if self.get_state_events(&info.room_id, event_type.clone()).await?.is_empty() {
+                room_ids.push(info.room_id);
+            }
+        }
+
+        Ok(room_ids)
+    }
+
+    /// Estimate the number of bytes used by every room of the store.
+    ///
+    /// The footprint of a room is the length of the JSON of its [`RoomInfo`],
+    /// its state events and the profiles of its members. The actual size on
+    /// disk depends on the backend, but the estimate is enough to find the
+    /// heaviest rooms, for example to offer the user to forget them.
+    async fn room_storage_breakdown(&self) -> Result<BTreeMap<OwnedRoomId, u64>, Self::Error> {
+        let mut breakdown = BTreeMap::new();
+
+        for info in self.get_room_infos().await? {
+            let mut size = serde_json::to_vec(&info)?.len();
+
+            for raw in self.get_all_state_events(&info.room_id).await? {
+                size += match &raw {
+                    RawAnySyncOrStrippedState::Sync(raw) => raw.json().get().len(),
+                    RawAnySyncOrStrippedState::Stripped(raw) => raw.json().get().len(),
+                };
+            }
+
+            let user_ids = self.get_user_ids(&info.room_id, RoomMemberships::empty()).await?;
+            for profile in self.get_profiles(&info.room_id, &user_ids).await?.values() {
+                size += serde_json::to_vec(profile)?.len();
+            }
+
+            breakdown.insert(info.room_id, size as u64);
+        }
+
+        Ok(breakdown)
+    }
+
+    /// Get the `limit` rooms that received the most messages, with their
+    /// number of messages, busiest first.
+    ///
+    /// The messages are counted in the [`RoomInfo`] of every room as they are
+    /// received through sync, and only the messages sent during the last 24
+    /// hours count. Rooms that didn't receive any message during that time
+    /// aren't returned.
+    async fn get_rooms_by_activity(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<(OwnedRoomId, u64)>, Self::Error> {
+        let now = MilliSecondsSinceUnixEpoch::now();
+        let mut rooms: Vec<_> = self
+            .get_room_infos()
+            .await?
+            .into_iter()
+            .map(|info| {
+                let count = info.recent_messages.count(now);
+                (info.room_id, count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        rooms.sort_by(|(_, a), (_, b)| b.cmp(a));
+        rooms.truncate(limit);
+
+        Ok(rooms)
+    }
+
+    /// Get the latest event of each of the given rooms, in the same order.
+    ///
+    /// The latest events are kept in the [`RoomInfo`]s, which are all loaded
+    /// with a single call to [`StateStore::get_room_infos`] rather than once
+    /// per room, for example to show the previews of a room list. A room gets
+    /// `None` if it isn't in the store or if it doesn't have a latest event.
+    #[cfg(feature = "experimental-sliding-sync")]
+    async fn get_latest_events(
+        &self,
+        room_ids: &[OwnedRoomId],
+    ) -> Result<Vec<Option<LatestEvent>>, Self::Error> {
+        let latest_events: BTreeMap<OwnedRoomId, LatestEvent> = self
+            .get_room_infos()
+            .await?
+            .into_iter()
+            .filter_map(|info| Some((info.room_id, *info.latest_event?)))
+            .collect();
+
+        Ok(room_ids.iter().map(|room_id| latest_events.get(room_id).cloned()).collect())
+    }
+
+    /// Whether the given room has an event that the user didn't read, based
+    /// on the stored data rather than on the server counts.
+    ///
+    /// A room without a latest event, or whose latest event was sent by the
+    /// user, is read. Otherwise, if the user's latest read receipt,
+    /// main-threaded or unthreaded, was found in the timeline, the room is
+    /// unread if one of the events after it marks the room as unread, so a
+    /// receipt on a later event that doesn't, like a reaction, also reads the
+    /// latest event. If it wasn't found, the room is read if one of the user's
+    /// read receipts, public or private, is on the latest event or was sent
+    /// after it.
+    #[cfg(feature = "experimental-sliding-sync")]
+    async fn is_room_unread(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<bool, Self::Error> {
+        let Some(room_info) = self.get_room_info(room_id).await? else {
+            return Ok(false);
+        };
+        let Some(latest_event) = room_info.latest_event else {
+            return Ok(false);
+        };
+        let Some(latest_event_id) = latest_event.event_id() else {
+            return Ok(false);
+        };
+
+        let raw_event = &latest_event.event().event;
+        let sender = raw_event.get_field::<OwnedUserId>("sender").ok().flatten();
+        if sender.as_deref() == Some(user_id) {
+            return Ok(false);
+        }
+
+        // The receipt was matched against the events of the timeline, and the
+        // events after it were counted.
+        if room_info.read_receipts.has_latest_active() {
+            return Ok(room_info.read_receipts.num_unread > 0);
+        }
+
+        let latest_event_ts =
+            raw_event.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts").ok().flatten();
+
+        for receipt_type in [ReceiptType::Read, ReceiptType::ReadPrivate] {
+            for thread in [ReceiptThread::Unthreaded, ReceiptThread::Main] {
+                let Some((event_id, receipt)) = self
+                    .get_user_room_receipt_event(room_id, receipt_type.clone(), thread, user_id)
+                    .await?
+                else {
+                    continue;
+                };
+
+                let is_after_latest_event =
+                    receipt.ts.zip(latest_event_ts).is_some_and(|(ts, latest_ts)| ts >= latest_ts);
+                if event_id == latest_event_id || is_after_latest_event {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Get the notification mode the user defined for the given room in their
+    /// push rules.
+    ///
+    /// Returns `None` if there are no push rules in the store, or if the room
+    /// uses the default notification mode.
+    async fn get_room_notification_mode(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RoomNotificationMode>, Self::Error> {
+        let Some(raw) = self.get_account_data_event_static::<PushRulesEventContent>().await? else {
+            return Ok(None);
+        };
+        let ruleset = raw.deserialize()?.content.global;
+
+        Ok(get_user_defined_room_notification_mode(&ruleset, room_id))
+    }
+
+    /// Remember that the user acknowledged the item identified by `key`.
+    ///
+    /// This is meant for "don't show again" kind of features, like a server
+    /// notice about updated terms of service that was already dismissed.
+    async fn mark_acknowledged(&self, key: &str) -> Result<(), Self::Error> {
+        self.set_custom_value_no_read(acknowledged_key(key).as_bytes(), Vec::new()).await
+    }
+
+    /// Whether the item identified by `key` was marked as acknowledged with
+    /// [`StateStoreExt::mark_acknowledged`].
+    async fn is_acknowledged(&self, key: &str) -> Result<bool, Self::Error> {
+        Ok(self.get_custom_value(acknowledged_key(key).as_bytes()).await?.is_some())
+    }
+
+    /// Remember that the user reported the given event, so they aren't
+    /// offered to report it again.
+    async fn mark_event_reported(&self, event_id: &EventId) -> Result<(), Self::Error> {
+        self.set_custom_value_no_read(reported_event_key(event_id).as_bytes(), Vec::new()).await
+    }
+
+    /// Whether the given event was marked as reported with
+    /// [`StateStoreExt::mark_event_reported`].
+    async fn is_event_reported(&self, event_id: &EventId) -> Result<bool, Self::Error> {
+        Ok(self.get_custom_value(reported_event_key(event_id).as_bytes()).await?.is_some())
+    }
+
+    /// Get the state of the verification flow with the given flow ID, as
+    /// saved with [`StateStoreExt::save_verification_state`].
+    async fn get_verification_state(
+        &self,
+        flow_id: &str,
+    ) -> Result<Option<JsonValue>, Self::Error> {
+        let Some(value) = self.get_custom_value(verification_key(flow_id).as_bytes()).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Save the current state of the verification flow with the given flow ID,
+    /// so it can be resumed after a restart.
+    ///
+    /// The flow ID is the transaction ID of to-device verifications, or the
+    /// event ID of the request for in-room verifications.
+    async fn save_verification_state(
+        &self,
+        flow_id: &str,
+        state: &JsonValue,
+    ) -> Result<(), Self::Error> {
+        let value = serde_json::to_vec(state)?;
+        self.set_custom_value_no_read(verification_key(flow_id).as_bytes(), value).await
+    }
+
+    /// Forget the state of the verification flow with the given flow ID,
+    /// once it is done or cancelled.
+    async fn remove_verification_state(&self, flow_id: &str) -> Result<(), Self::Error> {
+        self.remove_custom_value(verification_key(flow_id).as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Get the rooms whose keys haven't been backed up to the server yet.
+    ///
+    /// Rooms are added with [`StateStoreExt::mark_needs_backup`] and removed
+    /// with [`StateStoreExt::clear_needs_backup`].
+    async fn rooms_needing_backup(&self) -> Result<BTreeSet<OwnedRoomId>, Self::Error> {
+        let Some(value) = self.get_custom_value(ROOMS_NEEDING_BACKUP_KEY).await? else {
+            return Ok(BTreeSet::new());
+        };
+
+        Ok(serde_json::from_slice(&value)?)
+    }
+
+    /// Remember that the keys of the given room need to be backed up.
+    async fn mark_needs_backup(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        let room_id = room_id.to_owned();
+        self.update_custom_value(
+            ROOMS_NEEDING_BACKUP_KEY,
+            Box::new(move |value| {
+                let mut room_ids: BTreeSet<OwnedRoomId> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+                room_ids.insert(room_id);
+                Ok(Some(serde_json::to_vec(&room_ids)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Forget that the keys of the given room need to be backed up, once they
+    /// have been.
+    async fn clear_needs_backup(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        let room_id = room_id.to_owned();
+        self.update_custom_value(
+            ROOMS_NEEDING_BACKUP_KEY,
+            Box::new(move |value| {
+                let mut room_ids: BTreeSet<OwnedRoomId> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+                room_ids.remove(&room_id);
+                Ok(Some(serde_json::to_vec(&room_ids)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the invites that were hidden with
+    /// [`StateStoreExt::mark_invite_hidden`].
+    async fn hidden_invites(&self) -> Result<BTreeSet<OwnedRoomId>, Self::Error> {
+        let mut room_ids = BTreeSet::new();
+
+        for info in self.get_room_infos().await? {
+            if self.is_invite_hidden(&info.room_id).await? {
+                room_ids.insert(info.room_id);
+            }
+        }
+
+        Ok(room_ids)
+    }
+
+    /// Hide the invite to the given room locally, without rejecting it.
+    ///
+    /// The room stays in the store, but it isn't returned by
+    /// [`StateStoreExt::get_visible_invites`] anymore. The invite is forgotten
+    /// when the room is removed.
+    async fn mark_invite_hidden(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.set_custom_value_no_read(hidden_invite_key(room_id).as_bytes(), Vec::new()).await
+    }
+
+    /// Whether the invite to the given room was hidden with
+    /// [`StateStoreExt::mark_invite_hidden`].
+    async fn is_invite_hidden(&self, room_id: &RoomId) -> Result<bool, Self::Error> {
+        Ok(self.get_custom_value(hidden_invite_key(room_id).as_bytes()).await?.is_some())
+    }
+
+    /// Get the rooms the user is invited to, except the ones whose invite was
+    /// hidden with [`StateStoreExt::mark_invite_hidden`].
+    async fn get_visible_invites(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        let mut invites = Vec::new();
+
+        for info in self.get_room_infos().await? {
+            if info.state() == RoomState::Invited && !self.is_invite_hidden(&info.room_id).await? {
+                invites.push(info);
+            }
+        }
+
+        Ok(invites)
+    }
+
+    /// Save many presence events at once, in a single call to
+    /// [`StateStore::save_changes`].
+    ///
+    /// When there are several events for the same user, only the freshest one
+    /// is kept: the one with the smallest `last_active_ago`, or the last one
+    /// in the list if that doesn't tell them apart. Events that fail to
+    /// deserialize are ignored.
+    async fn save_presence(&self, events: Vec<Raw<PresenceEvent>>) -> Result<(), Self::Error> {
+        let mut latest = BTreeMap::<OwnedUserId, (Option<UInt>, Raw<PresenceEvent>)>::new();
+
+        for raw in events {
+            let Ok(event) = raw.deserialize() else {
+                continue;
+            };
+            let last_active_ago = event.content.last_active_ago;
+
+            if let Some((Some(existing), _)) = latest.get(&event.sender) {
+                if last_active_ago.is_some_and(|ago| ago > *existing) {
+                    continue;
+                }
+            }
+
+            latest.insert(event.sender, (last_active_ago, raw));
+        }
+
+        let changes = StateChanges {
+            presence: latest.into_iter().map(|(user_id, (_, raw))| (user_id, raw)).collect(),
+            ..Default::default()
+        };
+
+        self.save_changes(&changes).await
+    }
+
+    /// Remember a send that ultimately failed, so the user can be offered to
+    /// retry it later, even after a restart.
+    ///
+    /// A previously recorded failure with the same transaction id is replaced.
+    /// The failed sends of a room are removed with the room.
+    async fn save_failed_send(&self, failed_send: FailedSend) -> Result<(), Self::Error> {
+        let key = failed_sends_key(&failed_send.room_id);
+        self.update_custom_value(
+            key.as_bytes(),
+            Box::new(move |value| {
+                let mut failed_sends: BTreeMap<OwnedTransactionId, FailedSend> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+                failed_sends.insert(failed_send.transaction_id.clone(), failed_send);
+                Ok(Some(serde_json::to_vec(&failed_sends)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the failed sends recorded with [`StateStoreExt::save_failed_send`]
+    /// for the given room.
+    async fn get_failed_sends(&self, room_id: &RoomId) -> Result<Vec<FailedSend>, Self::Error> {
+        let Some(value) = self.get_custom_value(failed_sends_key(room_id).as_bytes()).await? else {
+            return Ok(Vec::new());
+        };
+
+        let failed_sends: BTreeMap<OwnedTransactionId, FailedSend> =
+            serde_json::from_slice(&value)?;
+        Ok(failed_sends.into_values().collect())
+    }
+
+    /// Forget the failed send of the given room with the given transaction id,
+    /// once it has been retried or dismissed.
+    ///
+    /// Returns true if a failed send has been removed, or false otherwise.
+    async fn remove_failed_send(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+    ) -> Result<bool, Self::Error> {
+        let transaction_id = transaction_id.to_owned();
+        let removed = Arc::new(AtomicBool::new(false));
+        let removed_in_update = removed.clone();
+
+        self.update_custom_value(
+            failed_sends_key(room_id).as_bytes(),
+            Box::new(move |value| {
+                let Some(value) = value else {
+                    return Ok(None);
+                };
+
+                let mut failed_sends: BTreeMap<OwnedTransactionId, FailedSend> =
+                    serde_json::from_slice(value)?;
+                removed_in_update.store(
+                    failed_sends.remove(&transaction_id).is_some(),
+                    atomic::Ordering::SeqCst,
+                );
+
+                if failed_sends.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(serde_json::to_vec(&failed_sends)?))
+                }
+            }),
+        )
+        .await?;
+
+        Ok(removed.load(atomic::Ordering::SeqCst))
+    }
+
+    /// Save the information about one of the devices of the user, like its
+    /// display name and when and where it was last seen.
+    ///
+    /// This is meant to persist the devices returned by the server across
+    /// restarts. Previously saved information about the same device is
+    /// replaced.
+    async fn save_device_info(&self, device: Device) -> Result<(), Self::Error> {
+        let device_id = device.device_id.clone();
+        let value = serde_json::to_vec(&device)?;
+        self.set_custom_value_no_read(device_info_key(&device_id).as_bytes(), value).await?;
+
+        self.update_custom_value(
+            DEVICE_IDS_KEY,
+            Box::new(move |value| {
+                let mut device_ids: BTreeSet<OwnedDeviceId> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+                device_ids.insert(device_id);
+                Ok(Some(serde_json::to_vec(&device_ids)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the information saved with [`StateStoreExt::save_device_info`]
+    /// about the given device.
+    async fn get_device_info(&self, device_id: &DeviceId) -> Result<Option<Device>, Self::Error> {
+        let Some(value) = self.get_custom_value(device_info_key(device_id).as_bytes()).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Get the information saved with [`StateStoreExt::save_device_info`]
+    /// about all the devices of the user, sorted by device id.
+    async fn get_all_device_info(&self) -> Result<Vec<Device>, Self::Error> {
+        let Some(value) = self.get_custom_value(DEVICE_IDS_KEY).await? else {
+            return Ok(Vec::new());
+        };
+        let device_ids: BTreeSet<OwnedDeviceId> = serde_json::from_slice(&value)?;
+
+        let mut devices = Vec::with_capacity(device_ids.len());
+        for device_id in device_ids {
+            if let Some(device) = self.get_device_info(&device_id).await? {
+                devices.push(device);
+            }
+        }
+
+        Ok(devices)
+    }
+
+    /// Get the successive canonical aliases of the given room, oldest first,
+    /// along with the time they were set.
+    ///
+    /// The history starts when the client first saw an `m.room.canonical_alias`
+    /// event for the room, earlier changes aren't known. It's removed along
+    /// with the room.
+    async fn get_alias_history(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, SystemTime)>, Self::Error> {
+        Ok(self
+            .get_alias_history_entries(room_id)
+            .await?
+            .into_iter()
+            .filter_map(|(alias, ts)| Some((alias, ts.to_system_time()?)))
+            .collect())
+    }
+
+    /// Save the definition of the filter with the given name, next to the ID
+    /// it got when it was uploaded.
+    ///
+    /// This allows to detect that the desired definition of a filter changed
+    /// and that it needs to be uploaded again.
+    async fn save_filter_definition(
+        &self,
+        filter_name: &str,
+        definition: &FilterDefinition,
+    ) -> Result<(), Self::Error> {
+        let value = serde_json::to_vec(definition)?;
+        self.set_custom_value_no_read(filter_definition_key(filter_name).as_bytes(), value).await
+    }
+
+    /// Get the definition of the filter with the given name, as saved by
+    /// [`StateStoreExt::save_filter_definition`].
+    async fn get_filter_definition(
+        &self,
+        filter_name: &str,
+    ) -> Result<Option<FilterDefinition>, Self::Error> {
+        let Some(value) =
+            self.get_custom_value(filter_definition_key(filter_name).as_bytes()).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Set the read marker of the given room locally, before the server
+    /// confirmed it.
+    ///
+    /// The local marker is returned by [`StateStoreExt::get_read_marker`] until
+    /// the server sends a new `m.fully_read` event for the room, and is removed
+    /// along with the room.
+    async fn set_local_read_marker(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<(), Self::Error> {
+        let marker = LocalReadMarker {
+            event_id: event_id.to_owned(),
+            server_event_id: self.get_server_read_marker(room_id).await?,
+        };
+
+        let value = serde_json::to_vec(&marker)?;
+        self.set_custom_value_no_read(local_read_marker_key(room_id).as_bytes(), value).await
+    }
+
+    /// Get the read marker of the given room.
+    ///
+    /// This is the marker set with [`StateStoreExt::set_local_read_marker`],
+    /// unless the `m.fully_read` event of the room changed since, in which
+    /// case the server's marker is returned.
+    async fn get_read_marker(&self, room_id: &RoomId) -> Result<Option<OwnedEventId>, Self::Error> {
+        let server_event_id = self.get_server_read_marker(room_id).await?;
+
+        if let Some(value) =
+            self.get_custom_value(local_read_marker_key(room_id).as_bytes()).await?
+        {
+            let local: LocalReadMarker = serde_json::from_slice(&value)?;
+            if local.server_event_id == server_event_id {
+                return Ok(Some(local.event_id));
+            }
+        }
+
+        Ok(server_event_id)
+    }
+
+    /// Get the read marker of the given room, as found in its `m.fully_read`
+    /// room account data event.
+    async fn get_server_read_marker(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OwnedEventId>, Self::Error> {
+        let Some(raw) =
+            self.get_room_account_data_event_static::<FullyReadEventContent>(room_id).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(raw.deserialize()?.content.event_id))
+    }
+
+    /// Cache the result of the `.well-known` discovery.
+    async fn save_discovery(
+        &self,
+        homeserver_url: String,
+        identity_server_url: Option<String>,
+    ) -> Result<(), Self::Error> {
+        self.set_kv_data(
+            StateStoreDataKey::Discovery,
+            StateStoreDataValue::Discovery(DiscoveryInfo::new(homeserver_url, identity_server_url)),
+        )
+        .await
+    }
+
+    /// Get the cached result of the `.well-known` discovery.
+    ///
+    /// Returns `None` if nothing was cached, or if the cached data is stale.
+    ///
+    /// # Arguments
+    ///
+    /// * `ttl` - How long the cached data is considered fresh, usually
+    ///   [`DiscoveryInfo::DEFAULT_TTL`].
+    async fn get_discovery(&self, ttl: Duration) -> Result<Option<DiscoveryInfo>, Self::Error> {
+        let info = self
+            .get_kv_data(StateStoreDataKey::Discovery)
+            .await?
+            .and_then(StateStoreDataValue::into_discovery);
+        Ok(info.filter(|info| !info.is_stale(ttl)))
+    }
+
+    /// Save the feature flags computed from the capabilities of the server,
+    /// replacing the previously saved ones.
+    async fn save_features(&self, features: &Features) -> Result<(), Self::Error> {
+        let value = serde_json::to_vec(features)?;
+        self.set_custom_value_no_read(FEATURES_KEY, value).await
+    }
+
+    /// Get the feature flags saved with [`StateStoreExt::save_features`].
+    async fn get_features(&self) -> Result<Option<Features>, Self::Error> {
+        let Some(value) = self.get_custom_value(FEATURES_KEY).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Save the time of the last successful sync on its own.
+    ///
+    /// The client saves it with the changes of every sync response instead,
+    /// see [`StateChanges::last_sync_time`].
+    async fn save_last_sync_time(&self, time: SystemTime) -> Result<(), Self::Error> {
+        let Some(ts) = MilliSecondsSinceUnixEpoch::from_system_time(time) else {
+            return Ok(());
+        };
+
+        self.set_kv_data(StateStoreDataKey::LastSyncTime, StateStoreDataValue::LastSyncTime(ts))
+            .await
+    }
+
+    /// Get the time of the last successful sync, as saved with
+    /// [`StateChanges::last_sync_time`] or
+    /// [`StateStoreExt::save_last_sync_time`].
+    ///
+    /// Clients can use it to show how fresh the data is, or to decide whether
+    /// the state should be refreshed.
+    async fn get_last_sync_time(&self) -> Result<Option<SystemTime>, Self::Error> {
+        Ok(self
+            .get_kv_data(StateStoreDataKey::LastSyncTime)
+            .await?
+            .and_then(StateStoreDataValue::into_last_sync_time)
+            .and_then(|ts| ts.to_system_time()))
+    }
+
+    /// Save the sync token of the given sync connection.
+    ///
+    /// The token in [`StateChanges::sync_token`] is the one of the main sync
+    /// of the client. Other sync loops running next to it, like a quiet sync
+    /// for notifications, keep their own position with a connection ID.
+    async fn save_sync_token(&self, conn_id: &str, token: &str) -> Result<(), Self::Error> {
+        let value = serde_json::to_vec(token)?;
+        self.set_custom_value_no_read(sync_token_key(conn_id).as_bytes(), value).await
+    }
+
+    /// Get the sync token of the given sync connection, as saved with
+    /// [`StateStoreExt::save_sync_token`].
+    async fn get_sync_token(&self, conn_id: &str) -> Result<Option<String>, Self::Error> {
+        let Some(value) = self.get_custom_value(sync_token_key(conn_id).as_bytes()).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Record that the user reacted with the given emoji, to rank it in
+    /// [`StateStoreExt::get_recent_reactions`].
+    ///
+    /// At most 50 emoji are remembered, the least used ones are forgotten
+    /// first.
+    async fn record_reaction(&self, emoji: &str) -> Result<(), Self::Error> {
+        let emoji = emoji.to_owned();
+        self.update_custom_value(
+            RECENT_REACTIONS_KEY,
+            Box::new(move |value| {
+                let mut reactions: Vec<RecentReaction> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+
+                // The list is kept from the most to the least recently used emoji.
+                let count = match reactions.iter().position(|reaction| reaction.emoji == emoji) {
+                    Some(index) => reactions.remove(index).count,
+                    None => 0,
+                };
+                reactions.insert(0, RecentReaction { emoji, count: count + 1 });
+
+                if reactions.len() > MAX_RECENT_REACTIONS {
+                    // Forget the least recently used of the least used emoji.
+                    if let Some((index, _)) = reactions
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .min_by_key(|(_, reaction)| reaction.count)
+                    {
+                        reactions.remove(index);
+                    }
+                }
+
+                Ok(Some(serde_json::to_vec(&reactions)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the `limit` emoji the user reacted with the most, as recorded with
+    /// [`StateStoreExt::record_reaction`].
+    ///
+    /// The most used emoji come first, and the most recently used one among
+    /// emoji used as many times.
+    async fn get_recent_reactions(&self, limit: usize) -> Result<Vec<String>, Self::Error> {
+        let Some(value) = self.get_custom_value(RECENT_REACTIONS_KEY).await? else {
+            return Ok(Vec::new());
+        };
+        let mut reactions: Vec<RecentReaction> = serde_json::from_slice(&value)?;
+
+        // The sort is stable, so the recency order is kept for equal counts.
+        reactions.sort_by(|a, b| b.count.cmp(&a.count));
+
+        Ok(reactions.into_iter().take(limit).map(|reaction| reaction.emoji).collect())
+    }
+
+    /// Export the sync token and the IDs of the given filters, to resume the
+    /// session with another store.
+    ///
+    /// Only the data needed to resume syncing is exported, unlike the full
+    /// state of the rooms. The filter names must be given because the store
+    /// can't list them. The [`SessionMeta`] isn't part of the state store, so
+    /// it is left out of the bundle, use
+    /// [`BaseClient::export_credentials`] to include it.
+    ///
+    /// [`BaseClient::export_credentials`]: crate::BaseClient::export_credentials
+    async fn export_credentials(
+        &self,
+        filter_names: &[&str],
+    ) -> Result<CredentialBundle, Self::Error> {
+        let sync_token = self
+            .get_kv_data(StateStoreDataKey::SyncToken)
+            .await?
+            .and_then(StateStoreDataValue::into_sync_token);
+
+        let mut filters = BTreeMap::new();
+        for filter_name in filter_names {
+            if let Some(filter_id) = self
+                .get_kv_data(StateStoreDataKey::Filter(filter_name))
+                .await?
+                .and_then(StateStoreDataValue::into_filter)
+            {
+                filters.insert((*filter_name).to_owned(), filter_id);
+            }
+        }
+
+        Ok(CredentialBundle { session_meta: None, sync_token, filters })
+    }
+
+    /// Import a [`CredentialBundle`] created with
+    /// [`StateStoreExt::export_credentials`].
+    ///
+    /// Once the session is restored, the next sync starts from the imported
+    /// sync token instead of doing a full initial sync. The [`SessionMeta`] of
+    /// the bundle is ignored, use [`BaseClient::import_credentials`] to restore
+    /// it too.
+    ///
+    /// [`BaseClient::import_credentials`]: crate::BaseClient::import_credentials
+    async fn import_credentials(&self, bundle: &CredentialBundle) -> Result<(), Self::Error> {
+        if let Some(sync_token) = &bundle.sync_token {
+            self.set_kv_data(
+                StateStoreDataKey::SyncToken,
+                StateStoreDataValue::SyncToken(sync_token.clone()),
+            )
+            .await?;
+        }
+
+        for (filter_name, filter_id) in &bundle.filters {
+            self.set_kv_data(
+                StateStoreDataKey::Filter(filter_name),
+                StateStoreDataValue::Filter(filter_id.clone()),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the local nickname of the given room, or remove it with `None`.
+    ///
+    /// The nickname only lives in this store. It is never sent to the
+    /// homeserver and takes precedence over the name of the room when
+    /// computing its display name. It's removed along with the room.
+    async fn set_room_nickname(
+        &self,
+        room_id: &RoomId,
+        nickname: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let key = room_nickname_key(room_id);
+
+        match nickname {
+            Some(nickname) => {
+                let value = serde_json::to_vec(nickname)?;
+                self.set_custom_value_no_read(key.as_bytes(), value).await
+            }
+            None => self.remove_custom_value(key.as_bytes()).await.map(|_| ()),
+        }
+    }
+
+    /// Get the local nickname of the given room, as set with
+    /// [`StateStoreExt::set_room_nickname`].
+    async fn get_room_nickname(&self, room_id: &RoomId) -> Result<Option<String>, Self::Error> {
+        let Some(value) = self.get_custom_value(room_nickname_key(room_id).as_bytes()).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Get the servers that were blocked with
+    /// [`StateStoreExt::add_blocked_server`].
+    async fn blocked_servers(&self) -> Result<BTreeSet<OwnedServerName>, Self::Error> {
+        let Some(value) = self.get_custom_value(BLOCKED_SERVERS_KEY).await? else {
+            return Ok(BTreeSet::new());
+        };
+
+        Ok(serde_json::from_slice(&value)?)
+    }
+
+    /// Block the given server locally.
+    ///
+    /// This only records the server, so that clients can hide the events sent
+    /// by its users. The homeserver doesn't know about it, unlike the ignored
+    /// users.
+    async fn add_blocked_server(&self, server_name: &ServerName) -> Result<(), Self::Error> {
+        let server_name = server_name.to_owned();
+        self.update_custom_value(
+            BLOCKED_SERVERS_KEY,
+            Box::new(move |value| {
+                let mut server_names: BTreeSet<OwnedServerName> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+                server_names.insert(server_name);
+                Ok(Some(serde_json::to_vec(&server_names)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Unblock the given server, blocked with
+    /// [`StateStoreExt::add_blocked_server`].
+    async fn remove_blocked_server(&self, server_name: &ServerName) -> Result<(), Self::Error> {
+        let server_name = server_name.to_owned();
+        self.update_custom_value(
+            BLOCKED_SERVERS_KEY,
+            Box::new(move |value| {
+                let mut server_names: BTreeSet<OwnedServerName> =
+                    value.map(serde_json::from_slice).transpose()?.unwrap_or_default();
+                server_names.remove(&server_name);
+                Ok(Some(serde_json::to_vec(&server_names)?))
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// A read marker set locally with [`StateStoreExt::set_local_read_marker`].
+#[derive(Serialize, Deserialize)]
+struct LocalReadMarker {
+    /// The event the user has read up to.
+    event_id: OwnedEventId,
+    /// The event of the server's `m.fully_read` marker when the local marker
+    /// was set.
+    server_event_id: Option<OwnedEventId>,
+}
+
+/// An emoji recorded with [`StateStoreExt::record_reaction`].
+#[derive(Serialize, Deserialize)]
+struct RecentReaction {
+    /// The emoji of the reaction.
+    emoji: String,
+    /// How many times the user reacted with it.
+    count: u64,
+}
+
+/// The prefix of the keys of the custom values stored by [`StateStoreExt`].
+///
+/// It's reserved for the SDK, so that its values never clash with the ones that
+/// applications store with [`StateStore::set_custom_value`]: the keys of the
+/// latter must not start with it.
+pub const CUSTOM_VALUE_KEY_PREFIX: &str = "matrix_sdk::";
+
+/// The custom value key used to store the definition of the filter named
+/// `filter_name`.
+fn filter_definition_key(filter_name: &str) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}filter_definition:{filter_name}")
+}
+
+/// The custom value key used to store the local read marker of `room_id`.
+fn local_read_marker_key(room_id: &RoomId) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}local_read_marker:{room_id}")
+}
+
+/// The parts of a member event needed by [`StateStoreExt::get_room_memberships`].
+#[derive(Deserialize)]
+struct PartialMemberEvent {
+    state_key: OwnedUserId,
+    content: PartialMemberEventContent,
+}
+
+/// The parts of a member event content needed by
+/// [`StateStoreExt::get_room_memberships`].
+#[derive(Deserialize)]
+struct PartialMemberEventContent {
+    membership: MembershipState,
+}
+
+/// The parts of a third-party invite event needed by
+/// [`StateStoreExt::get_third_party_invites`].
+#[derive(Deserialize)]
+struct PartialThirdPartyInviteEvent {
+    state_key: String,
+    sender: OwnedUserId,
+    content: PartialThirdPartyInviteEventContent,
+}
+
+/// The parts of a third-party invite event content needed by
+/// [`StateStoreExt::get_third_party_invites`].
+///
+/// The display name is missing once the event is redacted.
+#[derive(Deserialize)]
+struct PartialThirdPartyInviteEventContent {
+    display_name: Option<String>,
+}
+
+/// The parts of a state event needed to build a [`StateMap`].
+#[derive(Deserialize)]
+struct StateKeyParts {
+    #[serde(rename = "type")]
+    event_type: StateEventType,
+    state_key: String,
+}
+
+/// Index the given state events by event type and state key, skipping the
+/// ones whose type or state key can't be read.
+fn to_state_map(events: Vec<RawAnySyncOrStrippedState>) -> StateMap {
+    events
+        .into_iter()
+        .filter_map(|raw| {
+            let json = match &raw {
+                RawAnySyncOrStrippedState::Sync(raw) => raw.json(),
+                RawAnySyncOrStrippedState::Stripped(raw) => raw.json(),
+            };
+            let key: StateKeyParts = serde_json::from_str(json.get()).ok()?;
+            Some(((key.event_type, key.state_key), raw))
+        })
+        .collect()
+}
+
+/// The custom value key used to store the acknowledgement of `key`.
+fn acknowledged_key(key: &str) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}acknowledged:{key}")
+}
+
+/// The custom value key used to store that `event_id` was reported.
+fn reported_event_key(event_id: &EventId) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}reported_event:{event_id}")
+}
+
+/// The custom value key used to store the state of the verification flow with
+/// the given flow ID.
+fn verification_key(flow_id: &str) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}verification:{flow_id}")
+}
+
+/// The custom value key used to store the sync token of the sync connection
+/// `conn_id`.
+fn sync_token_key(conn_id: &str) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}sync_token:{conn_id}")
+}
+
+/// The custom value key used to store the local nickname of the room
+/// `room_id`.
+fn room_nickname_key(room_id: &RoomId) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}room_nickname:{room_id}")
+}
+
+/// The custom value key used to store the information about the device
+/// `device_id` of the user.
+fn device_info_key(device_id: &DeviceId) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}device_info:{device_id}")
+}
+
+/// The custom value key used to store the failed sends of a room, by
+/// transaction id.
+fn failed_sends_key(room_id: &RoomId) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}failed_sends:{room_id}")
+}
+
+/// The custom value key used to remember that the invite to a room was hidden
+/// locally.
+fn hidden_invite_key(room_id: &RoomId) -> String {
+    format!("{CUSTOM_VALUE_KEY_PREFIX}hidden_invite:{room_id}")
+}
+
+/// The keys of the custom values holding data of the given room.
+///
+/// The stores remove these values with the room in
+/// [`StateStore::remove_room`] and [`StateStore::remove_rooms`].
+pub fn room_custom_value_keys(room_id: &RoomId) -> Vec<String> {
+    vec![
+        failed_sends_key(room_id),
+        hidden_invite_key(room_id),
+        room_nickname_key(room_id),
+        local_read_marker_key(room_id),
+    ]
+}
+
+/// The custom value key used to store the rooms whose keys need to be backed
+/// up.
+const ROOMS_NEEDING_BACKUP_KEY: &[u8] = b"matrix_sdk::rooms_needing_backup";
+
+/// The custom value key used to store the ids of the devices of the user whose
+/// information was saved.
+const DEVICE_IDS_KEY: &[u8] = b"matrix_sdk::device_ids";
+
+/// The custom value key used to store the feature flags of the client.
+const FEATURES_KEY: &[u8] = b"matrix_sdk::features";
+
+/// The custom value key used to store the emoji the user reacted with.
+const RECENT_REACTIONS_KEY: &[u8] = b"matrix_sdk::recent_reactions";
+
+/// The maximum number of emoji remembered by
+/// [`StateStoreExt::record_reaction`].
+const MAX_RECENT_REACTIONS: usize = 50;
+
+/// The custom value key used to store the servers that were blocked locally.
+const BLOCKED_SERVERS_KEY: &[u8] = b"matrix_sdk::blocked_servers";
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T: StateStore + ?Sized> StateStoreExt for T {}
+
+/// The current state events of a room, by event type and state key, as
+/// returned by [`StateStoreExt::get_room_state`].
+pub type StateMap = BTreeMap<(StateEventType, String), RawAnySyncOrStrippedState>;
+
+/// A type-erased [`StateStore`].
+pub type DynStateStore = dyn StateStore<Error = StoreError>;
+
+/// A function computing the new value of a custom store entry from its
+/// current one, see [`StateStore::update_custom_value`].
+pub type CustomValueUpdate =
+    Box<dyn FnOnce(Option<&[u8]>) -> Result<Option<Vec<u8>>, serde_json::Error> + Send>;
+
+/// A type that can be type-erased into `Arc<dyn StateStore>`.
+///
+/// This trait is not meant to be implemented directly outside
+/// `matrix-sdk-crypto`, but it is automatically implemented for everything that
+/// implements `StateStore`.
+pub trait IntoStateStore {
+    #[doc(hidden)]
+    fn into_state_store(self) -> Arc<DynStateStore>;
+}
+
+impl<T> IntoStateStore for T
+where
+    T: StateStore + Sized + 'static,
+{
+    fn into_state_store(self) -> Arc<DynStateStore> {
+        Arc::new(EraseStateStoreError(self))
+    }
+}
+
+// Turns a given `Arc<T>` into `Arc<DynStateStore>` by attaching the
+// StateStore impl vtable of `EraseStateStoreError<T>`.
+impl<T> IntoStateStore for Arc<T>
+where
+    T: StateStore + 'static,
+{
+    fn into_state_store(self) -> Arc<DynStateStore> {
+        let ptr: *const T = Arc::into_raw(self);
+        let ptr_erased = ptr as *const EraseStateStoreError<T>;
+        // SAFETY: EraseStateStoreError is repr(transparent) so T and
+        //         EraseStateStoreError<T> have the same layout and ABI
+        unsafe { Arc::from_raw(ptr_erased) }
+    }
+}
+
+/// Server capabilities returned by the /client/versions endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ServerCapabilities {
+    /// Versions supported by the remote server.
+    ///
+    /// This contains [`MatrixVersion`]s converted to strings.
+    pub versions: Vec<String>,
+
+    /// List of unstable features and their enablement status.
+    pub unstable_features: BTreeMap<String, bool>,
+
+    /// Last time we fetched this data from the server, in milliseconds since
+    /// epoch.
+    last_fetch_ts: f64,
+}
+
+impl ServerCapabilities {
+    /// The number of milliseconds after which the data is considered stale.
+    pub const STALE_THRESHOLD: f64 = (1000 * 60 * 60 * 24 * 7) as _; // seven days
+
+    /// Encode server capabilities into this serializable struct.
+    pub fn new(versions: &[MatrixVersion], unstable_features: BTreeMap<String, bool>) -> Self {
+        Self {
+            versions: versions.iter().map(|item| item.to_string()).collect(),
+            unstable_features,
+            last_fetch_ts: now_timestamp_ms(),
+        }
+    }
+
+    /// Decode server capabilities from this serializable struct.
     ///
     /// May return `None` if the data is considered stale, after
     /// [`Self::STALE_THRESHOLD`] milliseconds since the last time we stored
@@ -973,6 +2994,34 @@ impl ServerCapabilities {
     }
 }
 
+/// The result of a `.well-known` discovery, as cached in the store.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DiscoveryInfo {
+    /// The base URL of the homeserver.
+    pub homeserver_url: String,
+
+    /// The base URL of the identity server, if one was advertised.
+    pub identity_server_url: Option<String>,
+
+    /// Last time we resolved this data, in milliseconds since epoch.
+    last_fetch_ts: f64,
+}
+
+impl DiscoveryInfo {
+    /// The default duration after which the data is considered stale.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 24); // one day
+
+    /// Create a new discovery result, resolved just now.
+    pub fn new(homeserver_url: String, identity_server_url: Option<String>) -> Self {
+        Self { homeserver_url, identity_server_url, last_fetch_ts: now_timestamp_ms() }
+    }
+
+    /// Whether the data was resolved more than `ttl` ago.
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        now_timestamp_ms() - self.last_fetch_ts >= ttl.as_millis() as f64
+    }
+}
+
 /// Get the current timestamp as the number of milliseconds since Unix Epoch.
 fn now_timestamp_ms() -> f64 {
     SystemTime::now()
@@ -1009,6 +3058,54 @@ pub enum StateStoreDataValue {
     ///
     /// [`ComposerDraft`]: Self::ComposerDraft
     ComposerDraft(ComposerDraft),
+
+    /// The bookkeeping of the outbound group session of a room.
+    /// To learn more, see [`GroupSessionMeta`].
+    ///
+    /// [`GroupSessionMeta`]: Self::GroupSessionMeta
+    GroupSessionMeta(GroupSessionMeta),
+
+    /// The cached result of the `.well-known` discovery.
+    Discovery(DiscoveryInfo),
+
+    /// The time of the last successful sync.
+    LastSyncTime(MilliSecondsSinceUnixEpoch),
+}
+
+/// Bookkeeping about the outbound group session of an encrypted room.
+///
+/// This is used to decide when the session needs to be rotated, and is
+/// persisted so that the rotation policy is respected across restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GroupSessionMeta {
+    /// The number of messages sent with the current session.
+    pub messages_sent: u64,
+    /// When the session was last rotated, if it ever was.
+    pub last_rotation_ts: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+/// When the outbound group session of an encrypted room should be rotated, as
+/// set in its `m.room.encryption` state event.
+///
+/// A `None` value means that the room doesn't set it, so the default of the
+/// encryption layer applies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// How long a session can be used before it's rotated.
+    pub rotation_period: Option<Duration>,
+    /// How many messages can be sent with a session before it's rotated.
+    pub rotation_period_msgs: Option<u64>,
+}
+
+/// The kind of a room, as shown in a room list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomKind {
+    /// A direct message room with a single other user.
+    Dm,
+    /// Any room that is neither a direct message room nor a space.
+    Group,
+    /// A space, whose `m.room.create` event has the `m.space` type.
+    Space,
 }
 
 /// Current draft of the composer for the room.
@@ -1071,10 +3168,25 @@ impl StateStoreDataValue {
         as_variant!(self, Self::ComposerDraft)
     }
 
+    /// Get this value if it is the bookkeeping of an outbound group session.
+    pub fn into_group_session_meta(self) -> Option<GroupSessionMeta> {
+        as_variant!(self, Self::GroupSessionMeta)
+    }
+
     /// Get this value if it is the server capabilities metadata.
     pub fn into_server_capabilities(self) -> Option<ServerCapabilities> {
         as_variant!(self, Self::ServerCapabilities)
     }
+
+    /// Get this value if it is the cached discovery info.
+    pub fn into_discovery(self) -> Option<DiscoveryInfo> {
+        as_variant!(self, Self::Discovery)
+    }
+
+    /// Get this value if it is the time of the last successful sync.
+    pub fn into_last_sync_time(self) -> Option<MilliSecondsSinceUnixEpoch> {
+        as_variant!(self, Self::LastSyncTime)
+    }
 }
 
 /// A key for key-value data.
@@ -1104,6 +3216,18 @@ pub enum StateStoreDataKey<'a> {
     ///
     /// [`ComposerDraft`]: Self::ComposerDraft
     ComposerDraft(&'a RoomId),
+
+    /// The bookkeeping of the outbound group session of the room.
+    /// To learn more, see [`GroupSessionMeta`].
+    ///
+    /// [`GroupSessionMeta`]: Self::GroupSessionMeta
+    GroupSessionMeta(&'a RoomId),
+
+    /// The cached result of the `.well-known` discovery.
+    Discovery,
+
+    /// The time of the last successful sync.
+    LastSyncTime,
 }
 
 impl StateStoreDataKey<'_> {
@@ -1129,6 +3253,16 @@ impl StateStoreDataKey<'_> {
     /// Key prefix to use for the [`ComposerDraft`][Self::ComposerDraft]
     /// variant.
     pub const COMPOSER_DRAFT: &'static str = "composer_draft";
+
+    /// Key prefix to use for the
+    /// [`GroupSessionMeta`][Self::GroupSessionMeta] variant.
+    pub const GROUP_SESSION_META: &'static str = "group_session_meta";
+
+    /// Key to use for the [`Discovery`][Self::Discovery] variant.
+    pub const DISCOVERY: &'static str = "discovery";
+
+    /// Key to use for the [`LastSyncTime`][Self::LastSyncTime] variant.
+    pub const LAST_SYNC_TIME: &'static str = "last_sync_time";
 }
 
 /// A thin wrapper to serialize a `AnyMessageLikeEventContent`.
@@ -1290,9 +3424,105 @@ impl fmt::Debug for QueuedEvent {
     }
 }
 
+/// A pending invite of a third party to a room, as returned by
+/// [`StateStoreExt::get_third_party_invites`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThirdPartyInvite {
+    /// The token of the invite, which is the state key of its event.
+    pub token: String,
+
+    /// A user-readable representation of the invitee, like a partially
+    /// obfuscated email address.
+    pub display_name: String,
+
+    /// The user who sent the invite.
+    pub sender: OwnedUserId,
+}
+
+/// An event that couldn't be sent, even after retrying, kept around so the
+/// user can decide to send it again.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FailedSend {
+    /// The room the event was meant to be sent to.
+    pub room_id: OwnedRoomId,
+
+    /// The transaction id of the failed request, acting as a key.
+    pub transaction_id: OwnedTransactionId,
+
+    /// The content of the message-like event that couldn't be sent.
+    pub event: SerializableEventContent,
+
+    /// A description of the error that made the send fail.
+    pub error: String,
+}
+
+/// The features the client can offer to the user, as derived from the
+/// capabilities of the server.
+///
+/// They are persisted so the UI can be gated accordingly while offline.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Features {
+    /// Whether the user can change their password.
+    pub can_change_password: bool,
+
+    /// Whether the user can change their display name.
+    pub can_set_displayname: bool,
+
+    /// Whether the user can change their avatar.
+    pub can_set_avatar_url: bool,
+
+    /// The login types supported by the server, like `m.login.password`.
+    pub supported_login_types: Vec<String>,
+}
+
+/// The data needed to resume a session, exported with
+/// [`BaseClient::export_credentials`] or
+/// [`StateStoreExt::export_credentials`].
+///
+/// [`BaseClient::export_credentials`]: crate::BaseClient::export_credentials
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CredentialBundle {
+    /// The user and device of the session, if it was exported by a logged in
+    /// client.
+    #[serde(default)]
+    pub session_meta: Option<SessionMeta>,
+
+    /// The token of the last sync, if the session synced before.
+    pub sync_token: Option<String>,
+
+    /// The IDs of the filters uploaded to the server, by filter name.
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+}
+
+/// The data needed to show a room in a room list, returned by
+/// [`StateStoreExt::get_room_list_entry`].
+#[derive(Clone, Debug)]
+pub struct RoomListEntry {
+    /// The name of the room: its local nickname if it has one, or else its
+    /// last computed display name.
+    pub name: Option<DisplayName>,
+
+    /// The URI of the avatar of the room.
+    pub avatar_url: Option<OwnedMxcUri>,
+
+    /// The unread notification counts of the room, as sent by the server.
+    pub unread_notification_counts: UnreadNotificationsCount,
+
+    /// Whether the user marked the room as unread.
+    pub is_marked_unread: bool,
+
+    /// The latest event of the room, if it has one.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub latest_event: Option<LatestEvent>,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{now_timestamp_ms, ServerCapabilities};
+    use std::time::Duration;
+
+    use super::{now_timestamp_ms, DiscoveryInfo, ServerCapabilities};
 
     #[test]
     fn test_stale_server_capabilities() {
@@ -1309,4 +3539,19 @@ mod tests {
         caps.last_fetch_ts = now_timestamp_ms() - 1.0;
         assert!(caps.maybe_decode().is_some());
     }
+
+    #[test]
+    fn test_stale_discovery_info() {
+        let mut info = DiscoveryInfo::new("https://matrix.localhost".to_owned(), None);
+        assert!(!info.is_stale(DiscoveryInfo::DEFAULT_TTL));
+
+        info.last_fetch_ts = now_timestamp_ms() - 2000.0;
+        assert!(!info.is_stale(DiscoveryInfo::DEFAULT_TTL));
+        assert!(info.is_stale(Duration::from_secs(1)));
+
+        info.last_fetch_ts =
+            now_timestamp_ms() - DiscoveryInfo::DEFAULT_TTL.as_millis() as f64 - 1.0;
+        assert!(info.is_stale(DiscoveryInfo::DEFAULT_TTL));
+        assert!(!info.is_stale(DiscoveryInfo::DEFAULT_TTL * 2));
+    }
 }
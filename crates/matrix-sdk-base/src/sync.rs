@@ -54,8 +54,14 @@ pub struct SyncResponse {
     pub to_device: Vec<Raw<AnyToDeviceEvent>>,
     /// New notifications per room.
     pub notifications: BTreeMap<OwnedRoomId, Vec<Notification>>,
+    /// The timeline events of earlier syncs that couldn't be decrypted then,
+    /// and were decrypted with the room keys received in this one.
+    pub decrypted_events: DecryptedEvents,
 }
 
+/// Timeline events that were decrypted after they were received, per room.
+pub type DecryptedEvents = BTreeMap<OwnedRoomId, Vec<SyncTimelineEvent>>;
+
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for SyncResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -64,6 +70,7 @@ impl fmt::Debug for SyncResponse {
             .field("account_data", &DebugListOfRawEventsNoId(&self.account_data))
             .field("to_device", &DebugListOfRawEventsNoId(&self.to_device))
             .field("notifications", &self.notifications)
+            .field("decrypted_events", &self.decrypted_events)
             .finish_non_exhaustive()
     }
 }
@@ -46,7 +46,7 @@ use super::{
 };
 use crate::IndexeddbStateStoreError;
 
-const CURRENT_DB_VERSION: u32 = 11;
+const CURRENT_DB_VERSION: u32 = 13;
 const CURRENT_META_DB_VERSION: u32 = 2;
 
 /// Sometimes Migrations can't proceed without having to drop existing
@@ -235,6 +235,12 @@ pub async fn upgrade_inner_db(
             if old_version < 11 {
                 db = migrate_to_v11(db).await?;
             }
+            if old_version < 12 {
+                db = migrate_to_v12(db).await?;
+            }
+            if old_version < 13 {
+                db = migrate_to_v13(db).await?;
+            }
         }
 
         db.close();
@@ -771,6 +777,26 @@ async fn migrate_to_v11(db: IdbDatabase) -> Result<IdbDatabase> {
     apply_migration(db, 11, migration).await
 }
 
+/// Add the new [`keys::EDITS`] table.
+async fn migrate_to_v12(db: IdbDatabase) -> Result<IdbDatabase> {
+    let migration = OngoingMigration {
+        drop_stores: [].into(),
+        create_stores: [keys::EDITS].into_iter().collect(),
+        data: Default::default(),
+    };
+    apply_migration(db, 12, migration).await
+}
+
+/// Add the new [`keys::ALIAS_HISTORY`] table.
+async fn migrate_to_v13(db: IdbDatabase) -> Result<IdbDatabase> {
+    let migration = OngoingMigration {
+        drop_stores: [].into(),
+        create_stores: [keys::ALIAS_HISTORY].into_iter().collect(),
+        data: Default::default(),
+    };
+    apply_migration(db, 13, migration).await
+}
+
 #[cfg(all(test, target_arch = "wasm32"))]
 mod tests {
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
@@ -25,11 +25,12 @@ use indexed_db_futures::prelude::*;
 use matrix_sdk_base::{
     deserialized_responses::RawAnySyncOrStrippedState,
     store::{
-        ChildTransactionId, ComposerDraft, DependentQueuedEvent, DependentQueuedEventKind,
+        room_custom_value_keys, ChildTransactionId, ComposerDraft, CustomValueUpdate,
+        DependentQueuedEvent, DependentQueuedEventKind, DiscoveryInfo, GroupSessionMeta,
         QueuedEvent, SerializableEventContent, ServerCapabilities, StateChanges, StateStore,
         StoreError,
     },
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateStoreDataKey,
+    LazyRoomInfo, MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateStoreDataKey,
     StateStoreDataValue,
 };
 use matrix_sdk_store_encryption::{Error as EncryptionError, StoreCipher};
@@ -42,11 +43,13 @@ use ruma::{
             MembershipState, RoomMemberEventContent, StrippedRoomMemberEvent, SyncRoomMemberEvent,
         },
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncStateEvent,
-        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
+        AnySyncTimelineEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+        SyncStateEvent,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedTransactionId,
-    OwnedUserId, RoomId, RoomVersionId, TransactionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedMxcUri,
+    OwnedRoomAliasId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId, RoomVersionId,
+    TransactionId, UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{debug, warn};
@@ -57,7 +60,7 @@ mod migrations;
 
 pub use self::migrations::MigrationConflictStrategy;
 use self::migrations::{upgrade_inner_db, upgrade_meta_db};
-use crate::safe_encode::SafeEncode;
+use crate::safe_encode::{SafeEncode, KEY_SEPARATOR};
 
 #[derive(Debug, thiserror::Error)]
 pub enum IndexeddbStateStoreError {
@@ -119,6 +122,12 @@ mod keys {
     pub const ROOM_USER_RECEIPTS: &str = "room_user_receipts";
     pub const ROOM_EVENT_RECEIPTS: &str = "room_event_receipts";
 
+    /// Table used to save the latest edit of the edited events.
+    pub const EDITS: &str = "edits";
+
+    /// Table used to save the alias history of the rooms.
+    pub const ALIAS_HISTORY: &str = "alias_history";
+
     pub const CUSTOM: &str = "custom";
     pub const KV: &str = "kv";
 
@@ -138,6 +147,8 @@ mod keys {
         ROOM_EVENT_RECEIPTS,
         ROOM_SEND_QUEUE,
         DEPENDENT_SEND_QUEUE,
+        EDITS,
+        ALIAS_HISTORY,
         CUSTOM,
         KV,
     ];
@@ -334,6 +345,38 @@ impl IndexeddbStateStore {
         encode_to_range(self.store_cipher.as_deref(), table_name, key)
     }
 
+    /// Get the keys of the members whose room has no room info, with the name
+    /// of the object store they are in.
+    async fn get_orphaned_member_keys(&self) -> Result<Vec<(&'static str, String)>> {
+        let room_infos = self.get_room_infos().await?;
+        let stores = [keys::USER_IDS, keys::STRIPPED_USER_IDS];
+        let tx =
+            self.inner.transaction_on_multi_with_mode(&stores, IdbTransactionMode::Readonly)?;
+
+        let mut orphans = Vec::new();
+        for store_name in stores {
+            let room_ids: HashSet<_> = room_infos
+                .iter()
+                .filter_map(|room_info| {
+                    self.encode_key(store_name, room_info.room_id()).as_string()
+                })
+                .collect();
+
+            for key in tx.object_store(store_name)?.get_all_keys()?.await?.iter() {
+                let Some(key) = key.as_string() else {
+                    continue;
+                };
+                let room_id = key.split(KEY_SEPARATOR).next().unwrap_or_default();
+
+                if !room_ids.contains(room_id) {
+                    orphans.push((store_name, key));
+                }
+            }
+        }
+
+        Ok(orphans)
+    }
+
     /// Get user IDs for the given room with the given memberships and stripped
     /// state.
     pub async fn get_user_ids_inner(
@@ -419,6 +462,13 @@ impl IndexeddbStateStore {
             StateStoreDataKey::ComposerDraft(room_id) => {
                 self.encode_key(keys::KV, (StateStoreDataKey::COMPOSER_DRAFT, room_id))
             }
+            StateStoreDataKey::GroupSessionMeta(room_id) => {
+                self.encode_key(keys::KV, (StateStoreDataKey::GROUP_SESSION_META, room_id))
+            }
+            StateStoreDataKey::Discovery => self.encode_key(keys::KV, StateStoreDataKey::DISCOVERY),
+            StateStoreDataKey::LastSyncTime => {
+                self.encode_key(keys::KV, StateStoreDataKey::LAST_SYNC_TIME)
+            }
         }
     }
 }
@@ -505,6 +555,18 @@ impl_state_store!({
                 .map(|f| self.deserialize_value::<ComposerDraft>(&f))
                 .transpose()?
                 .map(StateStoreDataValue::ComposerDraft),
+            StateStoreDataKey::GroupSessionMeta(_) => value
+                .map(|f| self.deserialize_value::<GroupSessionMeta>(&f))
+                .transpose()?
+                .map(StateStoreDataValue::GroupSessionMeta),
+            StateStoreDataKey::Discovery => value
+                .map(|f| self.deserialize_value::<DiscoveryInfo>(&f))
+                .transpose()?
+                .map(StateStoreDataValue::Discovery),
+            StateStoreDataKey::LastSyncTime => value
+                .map(|f| self.deserialize_value::<MilliSecondsSinceUnixEpoch>(&f))
+                .transpose()?
+                .map(StateStoreDataValue::LastSyncTime),
         };
 
         Ok(value)
@@ -542,6 +604,17 @@ impl_state_store!({
             StateStoreDataKey::ComposerDraft(_) => self.serialize_value(
                 &value.into_composer_draft().expect("Session data not a composer draft"),
             ),
+            StateStoreDataKey::GroupSessionMeta(_) => self.serialize_value(
+                &value
+                    .into_group_session_meta()
+                    .expect("Session data not a group session bookkeeping"),
+            ),
+            StateStoreDataKey::Discovery => self.serialize_value(
+                &value.into_discovery().expect("Session data not a discovery info"),
+            ),
+            StateStoreDataKey::LastSyncTime => self.serialize_value(
+                &value.into_last_sync_time().expect("Session data not a sync time"),
+            ),
         };
 
         let tx =
@@ -572,7 +645,7 @@ impl_state_store!({
 
     async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
         let mut stores: HashSet<&'static str> = [
-            (changes.sync_token.is_some(), keys::KV),
+            (changes.sync_token.is_some() || changes.last_sync_time.is_some(), keys::KV),
             (!changes.ambiguity_maps.is_empty(), keys::DISPLAY_NAMES),
             (!changes.account_data.is_empty(), keys::ACCOUNT_DATA),
             (!changes.presence.is_empty(), keys::PRESENCE),
@@ -582,6 +655,8 @@ impl_state_store!({
             ),
             (!changes.room_account_data.is_empty(), keys::ROOM_ACCOUNT_DATA),
             (!changes.receipts.is_empty(), keys::ROOM_EVENT_RECEIPTS),
+            (!changes.edits.is_empty(), keys::EDITS),
+            (!changes.alias_history.is_empty(), keys::ALIAS_HISTORY),
         ]
         .iter()
         .filter_map(|(id, key)| if *id { Some(*key) } else { None })
@@ -597,6 +672,10 @@ impl_state_store!({
             ]);
         }
 
+        if !changes.members_to_delete.is_empty() {
+            stores.extend([keys::ROOM_STATE, keys::USER_IDS]);
+        }
+
         if !changes.redactions.is_empty() {
             stores.extend([keys::ROOM_STATE, keys::ROOM_INFOS]);
         }
@@ -629,6 +708,13 @@ impl_state_store!({
             )?;
         }
 
+        if let Some(ts) = &changes.last_sync_time {
+            tx.object_store(keys::KV)?.put_key_val(
+                &self.encode_kv_data_key(StateStoreDataKey::LastSyncTime),
+                &self.serialize_value(ts)?,
+            )?;
+        }
+
         if !changes.ambiguity_maps.is_empty() {
             let store = tx.object_store(keys::DISPLAY_NAMES)?;
             for (room_id, ambiguity_maps) in &changes.ambiguity_maps {
@@ -660,6 +746,19 @@ impl_state_store!({
             }
         }
 
+        if !changes.members_to_delete.is_empty() {
+            let state = tx.object_store(keys::ROOM_STATE)?;
+            let user_ids = tx.object_store(keys::USER_IDS)?;
+
+            for (room, members) in &changes.members_to_delete {
+                for user_id in members {
+                    let key = (room, &StateEventType::RoomMember, user_id.as_str());
+                    state.delete(&self.encode_key(keys::ROOM_STATE, key))?;
+                    user_ids.delete(&self.encode_key(keys::USER_IDS, (room, user_id.as_str())))?;
+                }
+            }
+        }
+
         if !changes.state.is_empty() {
             let state = tx.object_store(keys::ROOM_STATE)?;
             let profiles = tx.object_store(keys::PROFILES)?;
@@ -882,6 +981,59 @@ impl_state_store!({
             }
         }
 
+        if !changes.edits.is_empty() {
+            let store = tx.object_store(keys::EDITS)?;
+            let origin_server_ts = |event: &Raw<AnySyncTimelineEvent>| {
+                event.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts").ok().flatten()
+            };
+
+            for (room_id, edits) in &changes.edits {
+                for (event_id, edit) in edits {
+                    let key = self.encode_key(keys::EDITS, (room_id, event_id));
+
+                    // Only replace an edit with a newer one. On a tie, or if either of
+                    // the timestamps is unknown, the edit saved last wins.
+                    let existing_ts = store
+                        .get(&key)?
+                        .await?
+                        .and_then(|f| self.deserialize_value::<Raw<AnySyncTimelineEvent>>(&f).ok())
+                        .and_then(|existing| origin_server_ts(&existing));
+                    if existing_ts.zip(origin_server_ts(edit)).is_some_and(|(old, new)| new < old) {
+                        continue;
+                    }
+
+                    store.put_key_val(&key, &self.serialize_value(&edit)?)?;
+                }
+            }
+        }
+
+        if !changes.alias_history.is_empty() {
+            let store = tx.object_store(keys::ALIAS_HISTORY)?;
+
+            for (room_id, entries) in &changes.alias_history {
+                let key = self.encode_key(keys::ALIAS_HISTORY, room_id);
+                let mut history = store
+                    .get(&key)?
+                    .await?
+                    .map(|f| {
+                        self.deserialize_value::<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>>(
+                            &f,
+                        )
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+
+                for (alias, set_at) in entries {
+                    if history.last().is_some_and(|(latest, _)| latest == alias) {
+                        continue;
+                    }
+                    history.push((alias.clone(), *set_at));
+                }
+
+                store.put_key_val(&key, &self.serialize_value(&history)?)?;
+            }
+        }
+
         tx.await.into_result().map_err(|e| e.into())
     }
 
@@ -1030,6 +1182,110 @@ impl_state_store!({
         Ok(events)
     }
 
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize> {
+        let stores = &[keys::ROOM_STATE, keys::STRIPPED_ROOM_STATE];
+        let txn =
+            self.inner.transaction_on_multi_with_mode(stores, IdbTransactionMode::Readonly)?;
+
+        let mut count = 0;
+        for store_name in stores {
+            let range = self.encode_to_range(store_name, room_id)?;
+            count += txn.object_store(store_name)?.count_with_key(&range)?.await? as usize;
+        }
+
+        Ok(count)
+    }
+
+    async fn get_state_event_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedEventId>> {
+        let range = self.encode_to_range(keys::ROOM_STATE, room_id)?;
+        Ok(self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_STATE, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_STATE)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .filter_map(|f| self.deserialize_value::<Raw<AnySyncStateEvent>>(&f).ok())
+            .filter_map(|raw| raw.get_field::<OwnedEventId>("event_id").ok().flatten())
+            .collect())
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let stripped_range = self.encode_to_range(keys::STRIPPED_ROOM_STATE, room_id)?;
+        let stripped_events = self
+            .inner
+            .transaction_on_one_with_mode(keys::STRIPPED_ROOM_STATE, IdbTransactionMode::Readonly)?
+            .object_store(keys::STRIPPED_ROOM_STATE)?
+            .get_all_with_key(&stripped_range)?
+            .await?
+            .iter()
+            .filter_map(|f| {
+                self.deserialize_value(&f).ok().map(RawAnySyncOrStrippedState::Stripped)
+            })
+            .collect::<Vec<_>>();
+
+        if !stripped_events.is_empty() {
+            return Ok(stripped_events);
+        }
+
+        let range = self.encode_to_range(keys::ROOM_STATE, room_id)?;
+        Ok(self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_STATE, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_STATE)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .filter_map(|f| self.deserialize_value(&f).ok().map(RawAnySyncOrStrippedState::Sync))
+            .collect::<Vec<_>>())
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[keys::STRIPPED_ROOM_STATE, keys::ROOM_STATE],
+            IdbTransactionMode::Readonly,
+        )?;
+        let stripped_state = tx.object_store(keys::STRIPPED_ROOM_STATE)?;
+        let state = tx.object_store(keys::ROOM_STATE)?;
+
+        let mut rooms_state = BTreeMap::new();
+        for &room_id in room_ids {
+            let stripped_range = self.encode_to_range(keys::STRIPPED_ROOM_STATE, room_id)?;
+            let mut events = stripped_state
+                .get_all_with_key(&stripped_range)?
+                .await?
+                .iter()
+                .filter_map(|f| {
+                    self.deserialize_value(&f).ok().map(RawAnySyncOrStrippedState::Stripped)
+                })
+                .collect::<Vec<_>>();
+
+            if events.is_empty() {
+                let range = self.encode_to_range(keys::ROOM_STATE, room_id)?;
+                events = state
+                    .get_all_with_key(&range)?
+                    .await?
+                    .iter()
+                    .filter_map(|f| {
+                        self.deserialize_value(&f).ok().map(RawAnySyncOrStrippedState::Sync)
+                    })
+                    .collect();
+            }
+
+            if !events.is_empty() {
+                rooms_state.insert(room_id.to_owned(), events);
+            }
+        }
+
+        Ok(rooms_state)
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -1087,6 +1343,31 @@ impl_state_store!({
         Ok(entries)
     }
 
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>> {
+        self.inner
+            .transaction_on_one_with_mode(keys::ROOM_INFOS, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_INFOS)?
+            .get(&self.encode_key(keys::ROOM_INFOS, room_id))?
+            .await?
+            .map(|f| self.deserialize_value::<RoomInfo>(&f))
+            .transpose()
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>> {
+        let entries = self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_INFOS, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_INFOS)?
+            .get_all()?
+            .await?
+            .iter()
+            .filter_map(|f| self.deserialize_value::<Raw<RoomInfo>>(&f).ok())
+            .filter_map(|raw| LazyRoomInfo::from_raw(raw).ok())
+            .collect();
+
+        Ok(entries)
+    }
+
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
         let txn = self
             .inner
@@ -1101,7 +1382,7 @@ impl_state_store!({
                 let value = cursor.value();
                 let info = self.deserialize_value::<RoomInfo>(&value)?;
 
-                if info.state() == RoomState::Invited {
+                if matches!(info.state(), RoomState::Invited | RoomState::Knocked) {
                     infos.push(info);
                 }
 
@@ -1170,6 +1451,19 @@ impl_state_store!({
             .transpose()
     }
 
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>> {
+        Ok(self
+            .inner
+            .transaction_on_one_with_mode(keys::ACCOUNT_DATA, IdbTransactionMode::Readonly)?
+            .object_store(keys::ACCOUNT_DATA)?
+            .get_all()?
+            .await?
+            .iter()
+            .filter_map(|f| self.deserialize_value::<Raw<AnyGlobalAccountDataEvent>>(&f).ok())
+            .filter_map(|raw| raw.get_field::<GlobalAccountDataEventType>("type").ok().flatten())
+            .collect())
+    }
+
     async fn get_room_account_data_event(
         &self,
         room_id: &RoomId,
@@ -1184,6 +1478,27 @@ impl_state_store!({
             .transpose()
     }
 
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>> {
+        let range = self.encode_to_range(keys::ROOM_ACCOUNT_DATA, room_id)?;
+        Ok(self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_ACCOUNT_DATA, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_ACCOUNT_DATA)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .filter_map(|f| self.deserialize_value::<Raw<AnyRoomAccountDataEvent>>(&f).ok())
+            .filter_map(|raw| {
+                let event_type =
+                    raw.get_field::<RoomAccountDataEventType>("type").ok().flatten()?;
+                Some((event_type, raw))
+            })
+            .collect())
+    }
+
     async fn get_user_room_receipt_event(
         &self,
         room_id: &RoomId,
@@ -1235,6 +1550,88 @@ impl_state_store!({
             .collect::<Vec<_>>())
     }
 
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>> {
+        self.inner
+            .transaction_on_one_with_mode(keys::EDITS, IdbTransactionMode::Readonly)?
+            .object_store(keys::EDITS)?
+            .get(&self.encode_key(keys::EDITS, (room_id, event_id)))?
+            .await?
+            .map(|f| self.deserialize_value(&f))
+            .transpose()
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>> {
+        Ok(self
+            .inner
+            .transaction_on_one_with_mode(keys::ALIAS_HISTORY, IdbTransactionMode::Readonly)?
+            .object_store(keys::ALIAS_HISTORY)?
+            .get(&self.encode_key(keys::ALIAS_HISTORY, room_id))?
+            .await?
+            .map(|f| self.deserialize_value(&f))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn remove_events_before(&self, ts: MilliSecondsSinceUnixEpoch) -> Result<()> {
+        let stores = [keys::EDITS, keys::ROOM_USER_RECEIPTS, keys::ROOM_EVENT_RECEIPTS];
+        let tx =
+            self.inner.transaction_on_multi_with_mode(&stores, IdbTransactionMode::Readwrite)?;
+        let is_older_receipt =
+            |receipt: &Receipt| receipt.ts.is_some_and(|receipt_ts| receipt_ts < ts);
+
+        let edits = tx.object_store(keys::EDITS)?;
+        if let Some(cursor) = edits.open_cursor()?.await? {
+            while let Some(key) = cursor.key() {
+                let is_older = self
+                    .deserialize_value::<Raw<AnySyncTimelineEvent>>(&cursor.value())?
+                    .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                    .ok()
+                    .flatten()
+                    .is_some_and(|edit_ts| edit_ts < ts);
+                if is_older {
+                    edits.delete(&key)?;
+                }
+
+                cursor.advance(1)?.await?;
+            }
+        }
+
+        let user_receipts = tx.object_store(keys::ROOM_USER_RECEIPTS)?;
+        if let Some(cursor) = user_receipts.open_cursor()?.await? {
+            while let Some(key) = cursor.key() {
+                let (_, receipt) =
+                    self.deserialize_value::<(OwnedEventId, Receipt)>(&cursor.value())?;
+                if is_older_receipt(&receipt) {
+                    user_receipts.delete(&key)?;
+                }
+
+                cursor.advance(1)?.await?;
+            }
+        }
+
+        let event_receipts = tx.object_store(keys::ROOM_EVENT_RECEIPTS)?;
+        if let Some(cursor) = event_receipts.open_cursor()?.await? {
+            while let Some(key) = cursor.key() {
+                let (_, receipt) =
+                    self.deserialize_value::<(OwnedUserId, Receipt)>(&cursor.value())?;
+                if is_older_receipt(&receipt) {
+                    event_receipts.delete(&key)?;
+                }
+
+                cursor.advance(1)?.await?;
+            }
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let jskey = &JsValue::from_str(core::str::from_utf8(key).map_err(StoreError::Codec)?);
         self.get_custom_value_for_js(jskey).await
@@ -1268,9 +1665,44 @@ impl_state_store!({
         Ok(prev)
     }
 
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>> {
+        let jskey = JsValue::from_str(core::str::from_utf8(key).map_err(StoreError::Codec)?);
+
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::CUSTOM, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(keys::CUSTOM)?;
+
+        // Read and write in the same transaction, so no other write can happen
+        // in between.
+        let prev: Option<Vec<u8>> =
+            store.get(&jskey)?.await?.map(|f| self.deserialize_value(&f)).transpose()?;
+        let value = update(prev.as_deref())?;
+
+        match &value {
+            Some(value) => store.put_key_val(&jskey, &self.serialize_value(value)?)?,
+            None => store.delete(&jskey)?,
+        };
+
+        tx.await.into_result().map_err(IndexeddbStateStoreError::from)?;
+        Ok(value)
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
+        self.remove_rooms(&[room_id]).await
+    }
+
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<()> {
         // All the stores which use a RoomId as their key (and nothing additional).
-        let direct_stores = [keys::ROOM_INFOS, keys::ROOM_SEND_QUEUE, keys::DEPENDENT_SEND_QUEUE];
+        let direct_stores = [
+            keys::ROOM_INFOS,
+            keys::ROOM_SEND_QUEUE,
+            keys::DEPENDENT_SEND_QUEUE,
+            keys::ALIAS_HISTORY,
+        ];
 
         // All the stores which use a RoomId as the first part of their key, but may
         // have some additional data in the key.
@@ -1284,12 +1716,14 @@ impl_state_store!({
             keys::ROOM_USER_RECEIPTS,
             keys::STRIPPED_ROOM_STATE,
             keys::STRIPPED_USER_IDS,
+            keys::EDITS,
         ];
 
         let all_stores = {
             let mut v = Vec::new();
             v.extend(prefixed_stores);
             v.extend(direct_stores);
+            v.push(keys::CUSTOM);
             v
         };
 
@@ -1297,11 +1731,37 @@ impl_state_store!({
             .inner
             .transaction_on_multi_with_mode(&all_stores, IdbTransactionMode::Readwrite)?;
 
-        for store_name in direct_stores {
-            tx.object_store(store_name)?.delete(&self.encode_key(store_name, room_id))?;
+        for room_id in room_ids {
+            for store_name in direct_stores {
+                tx.object_store(store_name)?.delete(&self.encode_key(store_name, room_id))?;
+            }
+
+            for store_name in prefixed_stores {
+                let store = tx.object_store(store_name)?;
+                let range = self.encode_to_range(store_name, room_id)?;
+                for key in store.get_all_keys_with_key(&range)?.await?.iter() {
+                    store.delete(&key)?;
+                }
+            }
+
+            let custom = tx.object_store(keys::CUSTOM)?;
+            for key in room_custom_value_keys(room_id) {
+                custom.delete(&JsValue::from_str(&key))?;
+            }
         }
 
-        for store_name in prefixed_stores {
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<()> {
+        let stripped_stores = [keys::STRIPPED_ROOM_STATE, keys::STRIPPED_USER_IDS];
+
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[keys::ROOM_INFOS, keys::STRIPPED_ROOM_STATE, keys::STRIPPED_USER_IDS],
+            IdbTransactionMode::Readwrite,
+        )?;
+
+        for store_name in stripped_stores {
             let store = tx.object_store(store_name)?;
             let range = self.encode_to_range(store_name, room_id)?;
             for key in store.get_all_keys_with_key(&range)?.await?.iter() {
@@ -1309,6 +1769,102 @@ impl_state_store!({
             }
         }
 
+        let room_infos = tx.object_store(keys::ROOM_INFOS)?;
+        let key = self.encode_key(keys::ROOM_INFOS, room_id);
+        if let Some(value) = room_infos.get(&key)?.await? {
+            let mut room_info = self.deserialize_value::<RoomInfo>(&value)?;
+            room_info.mark_as_left();
+            room_infos.put_key_val(&key, &self.serialize_value(&room_info)?)?;
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>> {
+        // The room IDs are hashed in encrypted stores, so they can't be recovered.
+        if self.store_cipher.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let mut orphans = BTreeSet::new();
+        for (_, key) in self.get_orphaned_member_keys().await? {
+            let Some((room_id, user_id)) = key.split_once(KEY_SEPARATOR) else {
+                continue;
+            };
+            if let (Ok(room_id), Ok(user_id)) = (RoomId::parse(room_id), UserId::parse(user_id)) {
+                orphans.insert((room_id, user_id));
+            }
+        }
+
+        Ok(orphans.into_iter().collect())
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<()> {
+        let orphans = self.get_orphaned_member_keys().await?;
+        let stores = [keys::USER_IDS, keys::STRIPPED_USER_IDS];
+        let tx =
+            self.inner.transaction_on_multi_with_mode(&stores, IdbTransactionMode::Readwrite)?;
+
+        for (store_name, key) in orphans {
+            tx.object_store(store_name)?.delete(&JsValue::from(key))?;
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn compact_if_needed(&self, _threshold: f64) -> Result<bool> {
+        // The browser manages the space of the database, it can't be compacted
+        // from here.
+        Ok(false)
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<()> {
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[keys::ROOM_STATE, keys::USER_IDS],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let state = tx.object_store(keys::ROOM_STATE)?;
+        let user_ids = tx.object_store(keys::USER_IDS)?;
+
+        let range = self.encode_to_range(keys::ROOM_STATE, room_id)?;
+        if let Some(cursor) = state.open_cursor_with_range(&range)?.await? {
+            while let Some(key) = cursor.key() {
+                let raw = self.deserialize_value::<Raw<AnySyncStateEvent>>(&cursor.value())?;
+
+                if let (Ok(Some(event_type)), Ok(Some(state_key))) =
+                    (raw.get_field::<StateEventType>("type"), raw.get_field::<String>("state_key"))
+                {
+                    if !current_keys.contains(&(event_type.clone(), state_key.clone())) {
+                        state.delete(&key)?;
+
+                        if event_type == StateEventType::RoomMember {
+                            user_ids.delete(
+                                &self.encode_key(keys::USER_IDS, (room_id, state_key.as_str())),
+                            )?;
+                        }
+                    }
+                }
+
+                cursor.advance(1)?.await?;
+            }
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn clear_ephemeral(&self) -> Result<()> {
+        let stores = [keys::PRESENCE, keys::ROOM_USER_RECEIPTS, keys::ROOM_EVENT_RECEIPTS];
+        let tx =
+            self.inner.transaction_on_multi_with_mode(&stores, IdbTransactionMode::Readwrite)?;
+
+        for store_name in stores {
+            tx.object_store(store_name)?.clear()?;
+        }
+
         tx.await.into_result().map_err(|e| e.into())
     }
 
@@ -1473,6 +2029,45 @@ impl_state_store!({
             .collect())
     }
 
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>> {
+        let encoded_key = self.encode_key(keys::ROOM_SEND_QUEUE, room_id);
+
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_SEND_QUEUE, IdbTransactionMode::Readwrite)?;
+
+        let obj = tx.object_store(keys::ROOM_SEND_QUEUE)?;
+
+        // We store an encoded vector of the queued events, with their transaction ids.
+        let Some(val) = obj.get(&encoded_key)?.await? else {
+            return Ok(Vec::new());
+        };
+
+        let mut prev = self.deserialize_value::<Vec<PersistedQueuedEvent>>(&val)?;
+        let drained: Vec<_> = prev.drain(..limit.min(prev.len())).collect();
+
+        if prev.is_empty() {
+            obj.delete(&encoded_key)?;
+        } else {
+            obj.put_key_val(&encoded_key, &self.serialize_value(&prev)?)?;
+        }
+
+        tx.await.into_result()?;
+
+        Ok(drained
+            .into_iter()
+            .map(|item| QueuedEvent {
+                event: item.event,
+                transaction_id: item.transaction_id,
+                is_wedged: item.is_wedged,
+            })
+            .collect())
+    }
+
     async fn update_send_queue_event_status(
         &self,
         room_id: &RoomId,
@@ -1522,6 +2117,25 @@ impl_state_store!({
         Ok(all_entries.into_iter().collect())
     }
 
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64> {
+        let jskey = JsValue::from_str(&format!("send_sequence:{room_id}"));
+
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::CUSTOM, IdbTransactionMode::Readwrite)?;
+
+        let obj = tx.object_store(keys::CUSTOM)?;
+
+        let sequence = match obj.get(&jskey)?.await? {
+            Some(value) => self.deserialize_value::<u64>(&value)? + 1,
+            None => 0,
+        };
+        obj.put_key_val(&jskey, &self.serialize_value(&sequence)?)?;
+
+        tx.await.into_result()?;
+
+        Ok(sequence)
+    }
+
     async fn save_dependent_send_queue_event(
         &self,
         room_id: &RoomId,
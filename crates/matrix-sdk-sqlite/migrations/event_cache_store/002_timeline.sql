@@ -0,0 +1,7 @@
+CREATE TABLE "timeline_event" (
+    "room_id" BLOB NOT NULL,
+    "position" INTEGER NOT NULL,
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "position")
+);
@@ -0,0 +1,18 @@
+-- The latest edit of the edited events, keyed by room id and event id of the
+-- edited event.
+CREATE TABLE "edit" (
+    -- This is used as a key, thus hashed.
+    "room_id" BLOB NOT NULL,
+
+    -- This is used as a key, thus hashed.
+    "event_id" BLOB NOT NULL,
+
+    -- The `origin_server_ts` of the edit, used to only keep the latest edit,
+    -- can be null.
+    "origin_server_ts" INTEGER NULL,
+
+    -- Serialized edit event, used as a value (thus encrypted/decrypted).
+    "data" BLOB NOT NULL,
+
+    PRIMARY KEY ("room_id", "event_id")
+);
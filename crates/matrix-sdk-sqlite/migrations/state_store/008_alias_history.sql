@@ -0,0 +1,12 @@
+-- The successive canonical aliases of the rooms, in the order they were set.
+CREATE TABLE "alias_history" (
+    -- This is used as a key, thus hashed.
+    "room_id" BLOB NOT NULL,
+
+    -- Serialized alias and time it was set, used as a value (thus
+    -- encrypted/decrypted).
+    "data" BLOB NOT NULL
+);
+
+CREATE INDEX "alias_history_room_id"
+    ON "alias_history" ("room_id");
@@ -185,6 +185,26 @@ impl SqliteCryptoStore {
     async fn acquire(&self) -> Result<SqliteAsyncConn> {
         Ok(self.pool.get().await?)
     }
+
+    /// Change the passphrase that protects the encryption keys of the store.
+    ///
+    /// Like `SqliteStateStore::change_passphrase`, this doesn't rotate the
+    /// encryption keys, only the cipher holding them is encrypted again. The
+    /// passphrase of the state store and the event cache store opened with the same passphrase must be
+    /// changed separately.
+    ///
+    /// Fails if the store isn't encrypted or if `old_passphrase` is wrong.
+    pub async fn change_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<()> {
+        if self.store_cipher.is_none() {
+            return Err(Error::Unencrypted);
+        }
+
+        self.acquire().await?.change_store_cipher_passphrase(old_passphrase, new_passphrase).await
+    }
 }
 
 const DATABASE_VERSION: u8 = 9;
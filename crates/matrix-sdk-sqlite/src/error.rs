@@ -65,6 +65,10 @@ pub enum OpenStoreError {
     /// Failed to save the store cipher to the DB.
     #[error("Failed to save the store cipher to the DB")]
     SaveCipher(#[source] rusqlite::Error),
+
+    /// Failed to rotate the key that encrypts the values of the DB.
+    #[error("Failed to rotate the encryption key of the DB")]
+    KeyRotation(#[source] Error),
 }
 
 #[derive(Debug, Error)]
@@ -99,6 +103,9 @@ pub enum Error {
     #[error("An object failed to be decrypted while unpickling")]
     Unpickle,
 
+    #[error("The store isn't encrypted with a passphrase")]
+    Unencrypted,
+
     #[error("Redaction failed: {0}")]
     Redaction(#[source] ruma::canonical_json::RedactionError),
 }
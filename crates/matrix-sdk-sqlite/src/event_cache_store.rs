@@ -3,10 +3,12 @@ use std::{borrow::Cow, fmt, path::Path, sync::Arc};
 use async_trait::async_trait;
 use deadpool_sqlite::{Object as SqliteAsyncConn, Pool as SqlitePool, Runtime};
 use matrix_sdk_base::{
+    deserialized_responses::SyncTimelineEvent,
     event_cache_store::EventCacheStore,
     media::{MediaRequest, UniqueKey},
 };
 use matrix_sdk_store_encryption::StoreCipher;
+use ruma::{MilliSecondsSinceUnixEpoch, RoomId};
 use rusqlite::OptionalExtension;
 use tokio::fs;
 use tracing::debug;
@@ -20,6 +22,7 @@ use crate::{
 mod keys {
     // Tables
     pub const MEDIA: &str = "media";
+    pub const TIMELINE_EVENT: &str = "timeline_event";
 }
 
 /// Identifier of the latest database version.
@@ -27,7 +30,7 @@ mod keys {
 /// This is used to figure whether the SQLite database requires a migration.
 /// Every new SQL migration should imply a bump of this number, and changes in
 /// the [`SqliteEventCacheStore::run_migrations`] function.
-const DATABASE_VERSION: u8 = 1;
+const DATABASE_VERSION: u8 = 2;
 
 /// A SQLite-based event cache store.
 #[derive(Clone)]
@@ -104,6 +107,26 @@ impl SqliteEventCacheStore {
     async fn acquire(&self) -> Result<SqliteAsyncConn> {
         Ok(self.pool.get().await?)
     }
+
+    /// Change the passphrase that protects the encryption keys of the store.
+    ///
+    /// Like `SqliteStateStore::change_passphrase`, this doesn't rotate the
+    /// encryption keys, only the cipher holding them is encrypted again. The
+    /// passphrase of the state store and the crypto store opened with the same passphrase must be
+    /// changed separately.
+    ///
+    /// Fails if the store isn't encrypted or if `old_passphrase` is wrong.
+    pub async fn change_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<()> {
+        if self.store_cipher.is_none() {
+            return Err(Error::Unencrypted);
+        }
+
+        self.acquire().await?.change_store_cipher_passphrase(old_passphrase, new_passphrase).await
+    }
 }
 
 async fn create_pool(path: &Path) -> Result<SqlitePool, OpenStoreError> {
@@ -133,6 +156,14 @@ async fn run_migrations(conn: &SqliteAsyncConn, version: u8) -> Result<()> {
         .await?;
     }
 
+    if version < 2 {
+        conn.with_transaction(|txn| {
+            txn.execute_batch(include_str!("../migrations/event_cache_store/002_timeline.sql"))?;
+            txn.set_db_version(2)
+        })
+        .await?;
+    }
+
     Ok(())
 }
 
@@ -201,6 +232,96 @@ impl EventCacheStore for SqliteEventCacheStore {
 
         Ok(())
     }
+
+    async fn append_timeline_event(
+        &self,
+        room_id: &RoomId,
+        event: SyncTimelineEvent,
+    ) -> Result<u64> {
+        let room_id = self.encode_key(keys::TIMELINE_EVENT, room_id);
+        let data = self.encode_value(serde_json::to_vec(&event)?)?;
+
+        let conn = self.acquire().await?;
+        let position = conn
+            .with_transaction::<_, rusqlite::Error, _>(move |txn| {
+                // Computing the next position and inserting the event happen in the same
+                // transaction, so concurrent appends can't get the same position.
+                let position: i64 = txn.query_row(
+                    "SELECT COALESCE(MAX(position) + 1, 0) FROM timeline_event WHERE room_id = ?",
+                    (&room_id,),
+                    |row| row.get(0),
+                )?;
+                txn.execute(
+                    "INSERT INTO timeline_event (room_id, position, data) VALUES (?, ?, ?)",
+                    (&room_id, position, data),
+                )?;
+
+                Ok(position)
+            })
+            .await?;
+
+        Ok(position as u64)
+    }
+
+    async fn get_timeline(
+        &self,
+        room_id: &RoomId,
+        from: u64,
+        limit: usize,
+    ) -> Result<Vec<SyncTimelineEvent>> {
+        let room_id = self.encode_key(keys::TIMELINE_EVENT, room_id);
+        let from = i64::try_from(from).unwrap_or(i64::MAX);
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        let conn = self.acquire().await?;
+        let rows: Vec<Vec<u8>> = conn
+            .prepare(
+                "SELECT data FROM timeline_event WHERE room_id = ? AND position >= ? \
+                 ORDER BY position LIMIT ?",
+                move |mut stmt| {
+                    stmt.query((room_id, from, limit))?.mapped(|row| row.get(0)).collect()
+                },
+            )
+            .await?;
+
+        rows.iter().map(|data| Ok(serde_json::from_slice(&self.decode_value(data)?)?)).collect()
+    }
+
+    async fn remove_timeline_events_before(&self, ts: MilliSecondsSinceUnixEpoch) -> Result<()> {
+        let conn = self.acquire().await?;
+
+        // The timestamps of the events are only part of their data, which might be
+        // encrypted.
+        let rows: Vec<(i64, Vec<u8>)> = conn
+            .prepare("SELECT rowid, data FROM timeline_event", |mut stmt| {
+                stmt.query(())?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?;
+
+        let mut expired_rowids = Vec::new();
+        for (rowid, data) in rows {
+            let event: SyncTimelineEvent = serde_json::from_slice(&self.decode_value(&data)?)?;
+            let event_ts = event
+                .event
+                .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                .ok()
+                .flatten();
+            if event_ts.is_some_and(|event_ts| event_ts < ts) {
+                expired_rowids.push(rowid);
+            }
+        }
+
+        conn.with_transaction::<_, rusqlite::Error, _>(move |txn| {
+            for rowid in expired_rowids {
+                txn.execute("DELETE FROM timeline_event WHERE rowid = ?", (rowid,))?;
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -315,13 +436,16 @@ mod tests {
 mod encrypted_tests {
     use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
 
+    use assert_matches::assert_matches;
     use matrix_sdk_base::{
         event_cache_store::EventCacheStoreError, event_cache_store_integration_tests,
     };
+    use matrix_sdk_test::async_test;
     use once_cell::sync::Lazy;
     use tempfile::{tempdir, TempDir};
 
     use super::SqliteEventCacheStore;
+    use crate::error::Error;
 
     static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
     static NUM: AtomicU32 = AtomicU32::new(0);
@@ -341,4 +465,22 @@ mod encrypted_tests {
     }
 
     event_cache_store_integration_tests!();
+
+    #[async_test]
+    async fn test_change_passphrase() {
+        let tmpdir_path = TMP_DIR.path().join(NUM.fetch_add(1, SeqCst).to_string());
+
+        let store = SqliteEventCacheStore::open(&tmpdir_path, Some("old")).await.unwrap();
+        assert_matches!(store.change_passphrase("wrong", "new").await, Err(Error::Encryption(_)));
+        store.change_passphrase("old", "new").await.unwrap();
+        drop(store);
+
+        assert!(SqliteEventCacheStore::open(&tmpdir_path, Some("old")).await.is_err());
+        SqliteEventCacheStore::open(&tmpdir_path, Some("new")).await.unwrap();
+
+        // A store without a passphrase has nothing to change.
+        let unencrypted_path = TMP_DIR.path().join(NUM.fetch_add(1, SeqCst).to_string());
+        let store = SqliteEventCacheStore::open(&unencrypted_path, None).await.unwrap();
+        assert_matches!(store.change_passphrase("old", "new").await, Err(Error::Unencrypted));
+    }
 }
@@ -3,7 +3,11 @@ use std::{
     collections::{BTreeMap, BTreeSet},
     fmt, iter,
     path::Path,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
@@ -11,11 +15,12 @@ use deadpool_sqlite::{Object as SqliteAsyncConn, Pool as SqlitePool, Runtime};
 use matrix_sdk_base::{
     deserialized_responses::{RawAnySyncOrStrippedState, SyncOrStrippedState},
     store::{
-        migration_helpers::RoomInfoV1, ChildTransactionId, DependentQueuedEvent,
-        DependentQueuedEventKind, QueuedEvent, SerializableEventContent,
+        migration_helpers::RoomInfoV1, room_custom_value_keys, ChildTransactionId,
+        CustomValueUpdate, DependentQueuedEvent, DependentQueuedEventKind, QueuedEvent,
+        SerializableEventContent,
     },
-    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateChanges, StateStore,
-    StateStoreDataKey, StateStoreDataValue,
+    LazyRoomInfo, MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateChanges,
+    StateStore, StateStoreDataKey, StateStoreDataValue,
 };
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
@@ -28,11 +33,11 @@ use ruma::{
             member::{StrippedRoomMemberEvent, SyncRoomMemberEvent},
         },
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncStateEvent,
-        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+        AnySyncTimelineEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedRoomId, OwnedTransactionId, OwnedUserId,
-    RoomId, RoomVersionId, TransactionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomAliasId,
+    OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId, RoomVersionId, TransactionId, UserId,
 };
 use rusqlite::{OptionalExtension, Transaction};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -61,20 +66,99 @@ mod keys {
     pub const DISPLAY_NAME: &str = "display_name";
     pub const SEND_QUEUE: &str = "send_queue_events";
     pub const DEPENDENTS_SEND_QUEUE: &str = "dependent_send_queue_events";
+    pub const EDIT: &str = "edit";
+    pub const ALIAS_HISTORY: &str = "alias_history";
+
+    /// All the tables of the store.
+    pub const ALL_TABLES: &[&str] = &[
+        KV_BLOB,
+        ROOM_INFO,
+        STATE_EVENT,
+        GLOBAL_ACCOUNT_DATA,
+        ROOM_ACCOUNT_DATA,
+        MEMBER,
+        PROFILE,
+        RECEIPT,
+        DISPLAY_NAME,
+        SEND_QUEUE,
+        DEPENDENTS_SEND_QUEUE,
+        EDIT,
+        ALIAS_HISTORY,
+    ];
+
+    // Keys of the `kv` table
+    pub const PREVIOUS_CIPHER: &str = "previous_cipher";
+    pub const KEY_ROTATION_PROGRESS: &str = "key_rotation_progress";
 }
 
+/// The tables and columns of the values encrypted with the store cipher, in
+/// the order in which they are encrypted again when the encryption key is
+/// rotated.
+const ENCRYPTED_COLUMNS: &[(&str, &str)] = &[
+    (keys::KV_BLOB, "value"),
+    (keys::ROOM_INFO, "data"),
+    (keys::STATE_EVENT, "data"),
+    (keys::GLOBAL_ACCOUNT_DATA, "data"),
+    (keys::ROOM_ACCOUNT_DATA, "data"),
+    (keys::MEMBER, "data"),
+    (keys::PROFILE, "data"),
+    (keys::RECEIPT, "data"),
+    (keys::DISPLAY_NAME, "data"),
+    (keys::SEND_QUEUE, "room_id_val"),
+    (keys::SEND_QUEUE, "content"),
+    (keys::DEPENDENTS_SEND_QUEUE, "event_id"),
+    (keys::DEPENDENTS_SEND_QUEUE, "content"),
+    (keys::EDIT, "data"),
+    (keys::ALIAS_HISTORY, "data"),
+];
+
+/// The number of values that are encrypted again in each transaction when the
+/// encryption key is rotated.
+const KEY_ROTATION_BATCH_SIZE: usize = 500;
+
 /// Identifier of the latest database version.
 ///
 /// This is used to figure whether the sqlite database requires a migration.
 /// Every new SQL migration should imply a bump of this number, and changes in
 /// the [`SqliteStateStore::run_migrations`] function..
-const DATABASE_VERSION: u8 = 7;
+const DATABASE_VERSION: u8 = 9;
 
 /// A sqlite based cryptostore.
 #[derive(Clone)]
 pub struct SqliteStateStore {
     store_cipher: Option<Arc<StoreCipher>>,
     pool: SqlitePool,
+    record_save_timings: Arc<AtomicBool>,
+    last_save_timings: Arc<StdMutex<Option<SaveTimings>>>,
+}
+
+/// How long each section of the last [`StateStore::save_changes`] call took,
+/// as recorded when [`SqliteStateStore::set_record_save_timings`] is enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SaveTimings {
+    /// The time spent writing the global and room account data.
+    pub account_data: Duration,
+    /// The time spent writing the presence events.
+    pub presence: Duration,
+    /// The time spent writing the room infos.
+    pub room_infos: Duration,
+    /// The time spent writing the state events, except for the members.
+    pub state: Duration,
+    /// The time spent writing the members and their profiles.
+    pub members: Duration,
+    /// The time spent writing the stripped state events, including their
+    /// members.
+    pub stripped_state: Duration,
+    /// The time spent writing the receipts.
+    pub receipts: Duration,
+    /// The time spent applying the redactions.
+    pub redactions: Duration,
+    /// The time spent writing the display names of the members.
+    pub display_names: Duration,
+    /// The time spent writing the edits.
+    pub edits: Duration,
+    /// The time spent writing the alias history.
+    pub alias_history: Duration,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -114,12 +198,258 @@ impl SqliteStateStore {
             Some(p) => Some(Arc::new(conn.get_or_create_store_cipher(p).await?)),
             None => None,
         };
-        let this = Self { store_cipher, pool };
+        let this = Self {
+            store_cipher,
+            pool,
+            record_save_timings: Default::default(),
+            last_save_timings: Default::default(),
+        };
         this.run_migrations(&conn, version, None).await?;
 
+        if let Some(passphrase) = passphrase {
+            this.finish_key_rotation(&conn, passphrase)
+                .await
+                .map_err(OpenStoreError::KeyRotation)?;
+        }
+
         Ok(this)
     }
 
+    /// Set whether the time taken by each section of
+    /// [`StateStore::save_changes`] is recorded.
+    ///
+    /// This helps to find which part of the sync responses is slow to persist.
+    /// The timings of the last call are returned by
+    /// [`SqliteStateStore::last_save_timings`]. They are not recorded by
+    /// default.
+    pub fn set_record_save_timings(&self, enabled: bool) {
+        self.record_save_timings.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Get the timings of the last call to [`StateStore::save_changes`], if
+    /// they were recorded.
+    pub fn last_save_timings(&self) -> Option<SaveTimings> {
+        self.last_save_timings.lock().unwrap().clone()
+    }
+
+    /// Get the number of rows of every table of the store, by table name.
+    ///
+    /// Nothing is decoded, so this is a cheap overview of the database, for
+    /// example for a support tool.
+    pub async fn table_summary(&self) -> Result<BTreeMap<String, usize>> {
+        self.acquire()
+            .await?
+            .with_transaction(|txn| {
+                let mut summary = BTreeMap::new();
+                for table in keys::ALL_TABLES {
+                    let sql = format!("SELECT COUNT(*) FROM \"{table}\"");
+                    let count: usize = txn.query_row(&sql, (), |row| row.get(0))?;
+                    summary.insert((*table).to_owned(), count);
+                }
+                Ok(summary)
+            })
+            .await
+    }
+
+    /// Get the number of pages of the database, and how many of them are free
+    /// because the data they held was deleted.
+    async fn page_counts(&self) -> Result<(usize, usize)> {
+        Ok(self
+            .acquire()
+            .await?
+            .query_row("SELECT * FROM pragma_page_count(), pragma_freelist_count()", (), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .await?)
+    }
+
+    /// Find the state events of the given room that are stored more than once
+    /// for the same event type and state key.
+    ///
+    /// The table only allows one row per encoded key, but a key that was
+    /// encoded differently, for example by a migration or after a corruption,
+    /// creates a second row for the same state event. The event type and state
+    /// key are read from the events themselves to detect this. This is meant
+    /// to debug state resolution issues, an empty list is expected.
+    pub async fn find_duplicate_state(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(StateEventType, String)>> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let rows: Vec<Vec<u8>> = self
+            .acquire()
+            .await?
+            .prepare("SELECT data FROM state_event WHERE room_id = ?", |mut stmt| {
+                stmt.query((room_id,))?.mapped(|row| row.get(0)).collect()
+            })
+            .await?;
+
+        let mut counts = BTreeMap::<(String, String), usize>::new();
+        for data in rows {
+            let raw = self.deserialize_json::<Raw<AnySyncStateEvent>>(&data)?;
+            let (Some(event_type), Some(state_key)) = (
+                raw.get_field::<String>("type").ok().flatten(),
+                raw.get_field::<String>("state_key").ok().flatten(),
+            ) else {
+                continue;
+            };
+            *counts.entry((event_type, state_key)).or_default() += 1;
+        }
+
+        Ok(counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|((event_type, state_key), _)| (event_type.into(), state_key))
+            .collect())
+    }
+
+    /// Change the passphrase that protects the encryption keys of the store.
+    ///
+    /// This doesn't rotate the encryption keys: the data isn't encrypted with
+    /// the passphrase itself, but with the keys of a [`StoreCipher`] that is
+    /// saved encrypted with the passphrase. Only that cipher is encrypted
+    /// again, in a single write, and the data stays encrypted with the same
+    /// keys, see [`SqliteStateStore::rotate_encryption_key`] to rotate them.
+    /// Once this returns, the store can only be opened with `new_passphrase`.
+    ///
+    /// **Warning**: the crypto store and the event cache store have their own
+    /// cipher, even when they were opened with the same passphrase. Their
+    /// passphrase must be changed too, with
+    /// `SqliteCryptoStore::change_passphrase` and
+    /// `SqliteEventCacheStore::change_passphrase`, or they can only be opened
+    /// with `old_passphrase` anymore.
+    ///
+    /// Fails if the store isn't encrypted or if `old_passphrase` is wrong.
+    pub async fn change_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<()> {
+        if self.store_cipher.is_none() {
+            return Err(Error::Unencrypted);
+        }
+
+        self.acquire().await?.change_store_cipher_passphrase(old_passphrase, new_passphrase).await
+    }
+
+    /// Rotate the key that encrypts the values of the store at the given path,
+    /// and return the store opened with `new_passphrase`.
+    ///
+    /// Unlike [`SqliteStateStore::change_passphrase`], a new [`StoreCipher`] is
+    /// created and all the values of the store are encrypted again with it.
+    /// The new cipher hashes the keys like the previous one, so the keys don't
+    /// change. The values are encrypted again in batches, each in its own
+    /// transaction. If the rotation is interrupted, it's resumed the next time
+    /// the store is opened with `new_passphrase`. Once the rotation started,
+    /// the store can't be opened with `old_passphrase` anymore.
+    ///
+    /// The store must not be opened anywhere else during the rotation.
+    ///
+    /// Fails if `old_passphrase` is wrong.
+    pub async fn rotate_encryption_key(
+        path: impl AsRef<Path>,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<Self, OpenStoreError> {
+        let store = Self::open(path, Some(old_passphrase)).await?;
+        store.start_key_rotation(new_passphrase).await.map_err(OpenStoreError::KeyRotation)?;
+
+        // Opening the store finishes the rotation.
+        Self::open_with_pool(store.pool, Some(new_passphrase)).await
+    }
+
+    /// Save a new cipher protected by `new_passphrase`, along with the current
+    /// one, so the values can be encrypted again by
+    /// [`SqliteStateStore::finish_key_rotation`].
+    async fn start_key_rotation(&self, new_passphrase: &str) -> Result<()> {
+        let cipher = self.store_cipher.as_ref().ok_or(Error::Unencrypted)?;
+        let new_cipher = cipher.with_new_encryption_key()?;
+
+        let new_export = export_store_cipher(&new_cipher, new_passphrase)?;
+        let previous_export = export_store_cipher(cipher, new_passphrase)?;
+        let progress = rmp_serde::to_vec(&(0usize, 0i64))?;
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                txn.set_kv("cipher", &new_export)?;
+                txn.set_kv(keys::PREVIOUS_CIPHER, &previous_export)?;
+                txn.set_kv(keys::KEY_ROTATION_PROGRESS, &progress)?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Encrypt again with the cipher of this store the values that are still
+    /// encrypted with the previous cipher, if a rotation of the encryption key
+    /// was started.
+    ///
+    /// The progress is saved along with every batch of values, so this resumes
+    /// where it stopped if it's interrupted. The values that can't be
+    /// decrypted with the previous cipher, like the custom values, are left as
+    /// they are.
+    async fn finish_key_rotation(&self, conn: &SqliteAsyncConn, passphrase: &str) -> Result<()> {
+        let Some(previous_export) = conn.get_kv(keys::PREVIOUS_CIPHER).await? else {
+            return Ok(());
+        };
+        let previous_cipher = Arc::new(StoreCipher::import(passphrase, &previous_export)?);
+        let cipher = self.store_cipher.clone().ok_or(Error::Unencrypted)?;
+
+        let mut progress: (usize, i64) = match conn.get_kv(keys::KEY_ROTATION_PROGRESS).await? {
+            Some(progress) => rmp_serde::from_slice(&progress)?,
+            None => (0, 0),
+        };
+
+        while let Some((table, column)) = ENCRYPTED_COLUMNS.get(progress.0) {
+            let previous_cipher = previous_cipher.clone();
+            let cipher = cipher.clone();
+            let (index, last_rowid) = progress;
+
+            progress = conn
+                .with_transaction(move |txn| {
+                    let rows = txn
+                        .prepare(&format!(
+                            "SELECT rowid, \"{column}\" FROM \"{table}\"
+                             WHERE rowid > ? AND \"{column}\" IS NOT NULL
+                             ORDER BY rowid LIMIT ?"
+                        ))?
+                        .query_map((last_rowid, KEY_ROTATION_BATCH_SIZE), |row| {
+                            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+                        })?
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                    let mut update = txn.prepare(&format!(
+                        "UPDATE \"{table}\" SET \"{column}\" = ? WHERE rowid = ?"
+                    ))?;
+                    for (rowid, value) in &rows {
+                        if let Some(value) = reencrypt_value(&previous_cipher, &cipher, value)? {
+                            update.execute((value, rowid))?;
+                        }
+                    }
+
+                    let progress = match rows.last() {
+                        Some((rowid, _)) if rows.len() == KEY_ROTATION_BATCH_SIZE => {
+                            (index, *rowid)
+                        }
+                        _ => (index + 1, 0),
+                    };
+                    txn.set_kv(keys::KEY_ROTATION_PROGRESS, &rmp_serde::to_vec(&progress)?)?;
+
+                    Ok::<_, Error>(progress)
+                })
+                .await?;
+        }
+
+        conn.with_transaction(|txn| {
+            txn.execute(
+                "DELETE FROM kv WHERE key IN (?, ?)",
+                (keys::PREVIOUS_CIPHER, keys::KEY_ROTATION_PROGRESS),
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
     /// Run database migrations from the given `from` version to the given `to`
     /// version
     ///
@@ -261,6 +591,24 @@ impl SqliteStateStore {
             .await?;
         }
 
+        if from < 8 && to >= 8 {
+            conn.with_transaction(move |txn| {
+                // Create edits table.
+                txn.execute_batch(include_str!("../migrations/state_store/007_edits.sql"))?;
+                txn.set_db_version(8)
+            })
+            .await?;
+        }
+
+        if from < 9 && to >= 9 {
+            conn.with_transaction(move |txn| {
+                // Create alias history table.
+                txn.execute_batch(include_str!("../migrations/state_store/008_alias_history.sql"))?;
+                txn.set_db_version(9)
+            })
+            .await?;
+        }
+
         Ok(())
     }
 
@@ -333,6 +681,11 @@ impl SqliteStateStore {
             StateStoreDataKey::ComposerDraft(room_id) => {
                 Cow::Owned(format!("{}:{room_id}", StateStoreDataKey::COMPOSER_DRAFT))
             }
+            StateStoreDataKey::GroupSessionMeta(room_id) => {
+                Cow::Owned(format!("{}:{room_id}", StateStoreDataKey::GROUP_SESSION_META))
+            }
+            StateStoreDataKey::Discovery => Cow::Borrowed(StateStoreDataKey::DISCOVERY),
+            StateStoreDataKey::LastSyncTime => Cow::Borrowed(StateStoreDataKey::LAST_SYNC_TIME),
         };
 
         self.encode_key(keys::KV_BLOB, &*key_s)
@@ -352,6 +705,26 @@ impl SqliteStateStore {
         Ok(self.pool.get().await?)
     }
 
+    /// Get the encoded room ID and user ID of the members whose room has no
+    /// room info.
+    async fn get_orphaned_members(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let room_ids: BTreeSet<_> = self
+            .get_room_infos()
+            .await?
+            .iter()
+            .map(|room_info| self.encode_key(keys::MEMBER, room_info.room_id()).to_vec())
+            .collect();
+
+        Ok(self
+            .acquire()
+            .await?
+            .get_all_members()
+            .await?
+            .into_iter()
+            .filter(|(room_id, _)| !room_ids.contains(room_id))
+            .collect())
+    }
+
     fn remove_maybe_stripped_room_data(
         &self,
         txn: &Transaction<'_>,
@@ -364,6 +737,44 @@ impl SqliteStateStore {
         let member_room_id = self.encode_key(keys::MEMBER, room_id);
         txn.remove_room_members(&member_room_id, Some(stripped))
     }
+
+    fn remove_room_data(&self, txn: &Transaction<'_>, room_id: &RoomId) -> rusqlite::Result<()> {
+        let room_info_room_id = self.encode_key(keys::ROOM_INFO, room_id);
+        txn.remove_room_info(&room_info_room_id)?;
+
+        let state_event_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        txn.remove_room_state_events(&state_event_room_id, None)?;
+
+        let member_room_id = self.encode_key(keys::MEMBER, room_id);
+        txn.remove_room_members(&member_room_id, None)?;
+
+        let profile_room_id = self.encode_key(keys::PROFILE, room_id);
+        txn.remove_room_profiles(&profile_room_id)?;
+
+        let room_account_data_room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+        txn.remove_room_account_data(&room_account_data_room_id)?;
+
+        let receipt_room_id = self.encode_key(keys::RECEIPT, room_id);
+        txn.remove_room_receipts(&receipt_room_id)?;
+
+        let display_name_room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+        txn.remove_room_display_names(&display_name_room_id)?;
+
+        let send_queue_room_id = self.encode_key(keys::SEND_QUEUE, room_id);
+        txn.remove_room_send_queue(&send_queue_room_id)?;
+
+        let edit_room_id = self.encode_key(keys::EDIT, room_id);
+        txn.remove_room_edits(&edit_room_id)?;
+
+        let alias_history_room_id = self.encode_key(keys::ALIAS_HISTORY, room_id);
+        txn.remove_room_alias_history(&alias_history_room_id)?;
+
+        for key in room_custom_value_keys(room_id) {
+            txn.remove_kv_blob(&self.encode_custom_key(key.as_bytes()))?;
+        }
+
+        Ok(())
+    }
 }
 
 async fn create_pool(path: &Path) -> Result<SqlitePool, OpenStoreError> {
@@ -386,8 +797,40 @@ async fn init(conn: &SqliteAsyncConn) -> Result<()> {
     .await
 }
 
+/// Encrypt the given store cipher with `passphrase`, to save it in the
+/// database.
+fn export_store_cipher(cipher: &StoreCipher, passphrase: &str) -> Result<Vec<u8>> {
+    #[cfg(not(test))]
+    let export = cipher.export(passphrase);
+    #[cfg(test)]
+    let export = cipher._insecure_export_fast_for_testing(passphrase);
+
+    Ok(export?)
+}
+
+/// Decrypt the given value with `previous_cipher` and encrypt it again with
+/// `cipher`.
+///
+/// Returns `None` if the value isn't encrypted with `previous_cipher`.
+fn reencrypt_value(
+    previous_cipher: &StoreCipher,
+    cipher: &StoreCipher,
+    value: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    let Ok(encrypted) = rmp_serde::from_slice(value) else {
+        return Ok(None);
+    };
+    let Ok(decrypted) = previous_cipher.decrypt_value_data(encrypted) else {
+        return Ok(None);
+    };
+
+    Ok(Some(rmp_serde::to_vec_named(&cipher.encrypt_value_data(decrypted)?)?))
+}
+
 trait SqliteConnectionStateStoreExt {
+    fn get_kv_blob(&self, key: &[u8]) -> rusqlite::Result<Option<Vec<u8>>>;
     fn set_kv_blob(&self, key: &[u8], value: &[u8]) -> rusqlite::Result<()>;
+    fn remove_kv_blob(&self, key: &[u8]) -> rusqlite::Result<()>;
 
     fn set_global_account_data(&self, event_type: &[u8], data: &[u8]) -> rusqlite::Result<()>;
 
@@ -422,6 +865,12 @@ trait SqliteConnectionStateStoreExt {
         room_id: &[u8],
         stripped: Option<bool>,
     ) -> rusqlite::Result<()>;
+    fn remove_state_event(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+        state_key: &[u8],
+    ) -> rusqlite::Result<()>;
 
     fn set_member(
         &self,
@@ -432,6 +881,7 @@ trait SqliteConnectionStateStoreExt {
         data: &[u8],
     ) -> rusqlite::Result<()>;
     fn remove_room_members(&self, room_id: &[u8], stripped: Option<bool>) -> rusqlite::Result<()>;
+    fn remove_member(&self, room_id: &[u8], user_id: &[u8]) -> rusqlite::Result<()>;
 
     fn set_profile(&self, room_id: &[u8], user_id: &[u8], data: &[u8]) -> rusqlite::Result<()>;
     fn remove_room_profiles(&self, room_id: &[u8]) -> rusqlite::Result<()>;
@@ -447,19 +897,45 @@ trait SqliteConnectionStateStoreExt {
         data: &[u8],
     ) -> rusqlite::Result<()>;
     fn remove_room_receipts(&self, room_id: &[u8]) -> rusqlite::Result<()>;
+    fn remove_receipt(&self, rowid: i64) -> rusqlite::Result<()>;
+    fn remove_receipts(&self) -> rusqlite::Result<()>;
 
     fn set_display_name(&self, room_id: &[u8], name: &[u8], data: &[u8]) -> rusqlite::Result<()>;
     fn remove_display_name(&self, room_id: &[u8], name: &[u8]) -> rusqlite::Result<()>;
     fn remove_room_display_names(&self, room_id: &[u8]) -> rusqlite::Result<()>;
     fn remove_room_send_queue(&self, room_id: &[u8]) -> rusqlite::Result<()>;
+
+    fn set_edit(
+        &self,
+        room_id: &[u8],
+        event_id: &[u8],
+        origin_server_ts: Option<u64>,
+        data: &[u8],
+    ) -> rusqlite::Result<()>;
+    fn remove_room_edits(&self, room_id: &[u8]) -> rusqlite::Result<()>;
+    fn remove_edits_before(&self, origin_server_ts: u64) -> rusqlite::Result<()>;
+
+    fn get_latest_alias_history_entry(&self, room_id: &[u8]) -> rusqlite::Result<Option<Vec<u8>>>;
+    fn add_alias_history_entry(&self, room_id: &[u8], data: &[u8]) -> rusqlite::Result<()>;
+    fn remove_room_alias_history(&self, room_id: &[u8]) -> rusqlite::Result<()>;
 }
 
 impl SqliteConnectionStateStoreExt for rusqlite::Connection {
+    fn get_kv_blob(&self, key: &[u8]) -> rusqlite::Result<Option<Vec<u8>>> {
+        self.query_row("SELECT value FROM kv_blob WHERE key = ?", (key,), |row| row.get(0))
+            .optional()
+    }
+
     fn set_kv_blob(&self, key: &[u8], value: &[u8]) -> rusqlite::Result<()> {
         self.execute("INSERT OR REPLACE INTO kv_blob VALUES (?, ?)", (key, value))?;
         Ok(())
     }
 
+    fn remove_kv_blob(&self, key: &[u8]) -> rusqlite::Result<()> {
+        self.prepare_cached("DELETE FROM kv_blob WHERE key = ?")?.execute((key,))?;
+        Ok(())
+    }
+
     fn set_global_account_data(&self, event_type: &[u8], data: &[u8]) -> rusqlite::Result<()> {
         self.prepare_cached(
             "INSERT OR REPLACE INTO global_account_data (event_type, data)
@@ -563,6 +1039,20 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         Ok(())
     }
 
+    fn remove_state_event(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+        state_key: &[u8],
+    ) -> rusqlite::Result<()> {
+        self.prepare_cached(
+            "DELETE FROM state_event
+             WHERE room_id = ? AND event_type = ? AND state_key = ? AND stripped = FALSE",
+        )?
+        .execute((room_id, event_type, state_key))?;
+        Ok(())
+    }
+
     fn set_member(
         &self,
         room_id: &[u8],
@@ -594,6 +1084,12 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         Ok(())
     }
 
+    fn remove_member(&self, room_id: &[u8], user_id: &[u8]) -> rusqlite::Result<()> {
+        self.prepare_cached("DELETE FROM member WHERE room_id = ? AND user_id = ?")?
+            .execute((room_id, user_id))?;
+        Ok(())
+    }
+
     fn set_profile(&self, room_id: &[u8], user_id: &[u8], data: &[u8]) -> rusqlite::Result<()> {
         self.prepare_cached(
             "INSERT OR REPLACE
@@ -638,6 +1134,16 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         Ok(())
     }
 
+    fn remove_receipt(&self, rowid: i64) -> rusqlite::Result<()> {
+        self.prepare_cached("DELETE FROM receipt WHERE rowid = ?")?.execute((rowid,))?;
+        Ok(())
+    }
+
+    fn remove_receipts(&self) -> rusqlite::Result<()> {
+        self.prepare("DELETE FROM receipt")?.execute(())?;
+        Ok(())
+    }
+
     fn set_display_name(&self, room_id: &[u8], name: &[u8], data: &[u8]) -> rusqlite::Result<()> {
         self.prepare_cached(
             "INSERT OR REPLACE
@@ -663,6 +1169,59 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         self.prepare("DELETE FROM send_queue_events WHERE room_id = ?")?.execute((room_id,))?;
         Ok(())
     }
+
+    fn set_edit(
+        &self,
+        room_id: &[u8],
+        event_id: &[u8],
+        origin_server_ts: Option<u64>,
+        data: &[u8],
+    ) -> rusqlite::Result<()> {
+        // Only replace an edit with a newer one. On a tie, or if either of the
+        // timestamps is unknown, the edit saved last wins.
+        self.prepare_cached(
+            "INSERT INTO edit (room_id, event_id, origin_server_ts, data)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (room_id, event_id) DO UPDATE
+             SET origin_server_ts = excluded.origin_server_ts, data = excluded.data
+             WHERE excluded.origin_server_ts IS NULL
+                OR edit.origin_server_ts IS NULL
+                OR excluded.origin_server_ts >= edit.origin_server_ts",
+        )?
+        .execute((room_id, event_id, origin_server_ts, data))?;
+        Ok(())
+    }
+
+    fn remove_room_edits(&self, room_id: &[u8]) -> rusqlite::Result<()> {
+        self.prepare("DELETE FROM edit WHERE room_id = ?")?.execute((room_id,))?;
+        Ok(())
+    }
+
+    fn remove_edits_before(&self, origin_server_ts: u64) -> rusqlite::Result<()> {
+        self.prepare("DELETE FROM edit WHERE origin_server_ts < ?")?
+            .execute((origin_server_ts,))?;
+        Ok(())
+    }
+
+    fn get_latest_alias_history_entry(&self, room_id: &[u8]) -> rusqlite::Result<Option<Vec<u8>>> {
+        self.query_row(
+            "SELECT data FROM alias_history WHERE room_id = ? ORDER BY rowid DESC LIMIT 1",
+            (room_id,),
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    fn add_alias_history_entry(&self, room_id: &[u8], data: &[u8]) -> rusqlite::Result<()> {
+        self.prepare_cached("INSERT INTO alias_history (room_id, data) VALUES (?, ?)")?
+            .execute((room_id, data))?;
+        Ok(())
+    }
+
+    fn remove_room_alias_history(&self, room_id: &[u8]) -> rusqlite::Result<()> {
+        self.prepare("DELETE FROM alias_history WHERE room_id = ?")?.execute((room_id,))?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -694,11 +1253,42 @@ trait SqliteObjectStateStoreExt: SqliteAsyncConnExt {
 
     async fn set_kv_blob(&self, key: Key, value: Vec<u8>) -> Result<()>;
 
+    async fn get_all_members(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .prepare("SELECT room_id, data FROM member", |mut stmt| {
+                stmt.query(())?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
+    async fn get_all_receipts(&self) -> Result<Vec<(i64, Vec<u8>)>> {
+        Ok(self
+            .prepare("SELECT rowid, data FROM receipt", |mut stmt| {
+                stmt.query(())?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
+    async fn get_all_kv_blobs(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .prepare("SELECT key, value FROM kv_blob", |mut stmt| {
+                stmt.query(())?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
     async fn delete_kv_blob(&self, key: Key) -> Result<()> {
         self.execute("DELETE FROM kv_blob WHERE key = ?", (key,)).await?;
         Ok(())
     }
 
+    async fn get_room_info(&self, room_id: Key) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .query_row("SELECT data FROM room_info WHERE room_id = ?", (room_id,), |row| row.get(0))
+            .await
+            .optional()?)
+    }
+
     async fn get_room_infos(&self, states: Vec<Key>) -> Result<Vec<Vec<u8>>> {
         if states.is_empty() {
             Ok(self
@@ -765,6 +1355,53 @@ trait SqliteObjectStateStoreExt: SqliteAsyncConnExt {
             .await?)
     }
 
+    async fn count_state_events(&self, room_id: Key) -> Result<usize> {
+        Ok(self
+            .query_row("SELECT count(*) FROM state_event WHERE room_id = ?", (room_id,), |row| {
+                row.get(0)
+            })
+            .await?)
+    }
+
+    async fn get_all_maybe_stripped_state_events(
+        &self,
+        room_id: Key,
+    ) -> Result<Vec<(bool, Vec<u8>)>> {
+        Ok(self
+            .prepare("SELECT stripped, data FROM state_event WHERE room_id = ?", |mut stmt| {
+                stmt.query((room_id,))?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
+    async fn get_all_maybe_stripped_state_events_for_rooms(
+        &self,
+        room_ids: Vec<Key>,
+    ) -> Result<Vec<(Vec<u8>, bool, Vec<u8>)>> {
+        self.chunk_large_query_over(room_ids, None, |txn, room_ids| {
+            let sql_params = repeat_vars(room_ids.len());
+            let sql = format!(
+                "SELECT room_id, stripped, data FROM state_event WHERE room_id IN ({sql_params})"
+            );
+
+            Ok(txn
+                .prepare(&sql)?
+                .query(rusqlite::params_from_iter(room_ids))?
+                .mapped(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .collect::<Result<_, _>>()?)
+        })
+        .await
+    }
+
+    async fn get_state_events_data(&self, room_id: Key) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare(
+                "SELECT data FROM state_event WHERE room_id = ? AND stripped = ?",
+                |mut stmt| stmt.query((room_id, false))?.mapped(|row| row.get(0)).collect(),
+            )
+            .await?)
+    }
+
     async fn get_profiles(
         &self,
         room_id: Key,
@@ -828,8 +1465,16 @@ trait SqliteObjectStateStoreExt: SqliteAsyncConnExt {
             .optional()?)
     }
 
-    async fn get_room_account_data(
-        &self,
+    async fn get_all_global_account_data(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM global_account_data", |mut stmt| {
+                stmt.query(())?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
+    async fn get_room_account_data(
+        &self,
         room_id: Key,
         event_type: Key,
     ) -> Result<Option<Vec<u8>>> {
@@ -843,6 +1488,14 @@ trait SqliteObjectStateStoreExt: SqliteAsyncConnExt {
             .optional()?)
     }
 
+    async fn get_all_room_account_data(&self, room_id: Key) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM room_account_data WHERE room_id = ?", |mut stmt| {
+                stmt.query((room_id,))?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
     async fn get_display_names(
         &self,
         room_id: Key,
@@ -904,6 +1557,26 @@ trait SqliteObjectStateStoreExt: SqliteAsyncConnExt {
             )
             .await?)
     }
+
+    async fn get_edit(&self, room_id: Key, event_id: Key) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .query_row(
+                "SELECT data FROM edit WHERE room_id = ? AND event_id = ?",
+                (room_id, event_id),
+                |row| row.get(0),
+            )
+            .await
+            .optional()?)
+    }
+
+    async fn get_alias_history(&self, room_id: Key) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare(
+                "SELECT data FROM alias_history WHERE room_id = ? ORDER BY rowid",
+                move |mut stmt| stmt.query((room_id,))?.mapped(|row| row.get(0)).collect(),
+            )
+            .await?)
+    }
 }
 
 #[async_trait]
@@ -945,6 +1618,15 @@ impl StateStore for SqliteStateStore {
                     StateStoreDataKey::ComposerDraft(_) => {
                         StateStoreDataValue::ComposerDraft(self.deserialize_value(&data)?)
                     }
+                    StateStoreDataKey::GroupSessionMeta(_) => {
+                        StateStoreDataValue::GroupSessionMeta(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::Discovery => {
+                        StateStoreDataValue::Discovery(self.deserialize_value(&data)?)
+                    }
+                    StateStoreDataKey::LastSyncTime => {
+                        StateStoreDataValue::LastSyncTime(self.deserialize_value(&data)?)
+                    }
                 })
             })
             .transpose()
@@ -979,6 +1661,17 @@ impl StateStore for SqliteStateStore {
             StateStoreDataKey::ComposerDraft(_) => self.serialize_value(
                 &value.into_composer_draft().expect("Session data not a composer draft"),
             )?,
+            StateStoreDataKey::GroupSessionMeta(_) => self.serialize_value(
+                &value
+                    .into_group_session_meta()
+                    .expect("Session data not a group session bookkeeping"),
+            )?,
+            StateStoreDataKey::Discovery => self.serialize_value(
+                &value.into_discovery().expect("Session data not a discovery info"),
+            )?,
+            StateStoreDataKey::LastSyncTime => self.serialize_value(
+                &value.into_last_sync_time().expect("Session data not a sync time"),
+            )?,
         };
 
         self.acquire()
@@ -999,10 +1692,12 @@ impl StateStore for SqliteStateStore {
             .with_transaction(move |txn| {
                 let StateChanges {
                     sync_token,
+                    last_sync_time,
                     account_data,
                     presence,
                     profiles,
                     profiles_to_delete,
+                    members_to_delete,
                     state,
                     room_account_data,
                     room_infos,
@@ -1010,14 +1705,31 @@ impl StateStore for SqliteStateStore {
                     redactions,
                     stripped_state,
                     ambiguity_maps,
+                    edits,
+                    alias_history,
                 } = changes;
 
+                let mut timings = SaveTimings::default();
+                let mut section_start = Instant::now();
+                // The time elapsed since the end of the previous section.
+                let mut lap = || {
+                    let elapsed = section_start.elapsed();
+                    section_start = Instant::now();
+                    elapsed
+                };
+
                 if let Some(sync_token) = sync_token {
                     let key = this.encode_state_store_data_key(StateStoreDataKey::SyncToken);
                     let value = this.serialize_value(&sync_token)?;
                     txn.set_kv_blob(&key, &value)?;
                 }
 
+                if let Some(last_sync_time) = last_sync_time {
+                    let key = this.encode_state_store_data_key(StateStoreDataKey::LastSyncTime);
+                    let value = this.serialize_value(&last_sync_time)?;
+                    txn.set_kv_blob(&key, &value)?;
+                }
+
                 for (event_type, event) in account_data {
                     let event_type =
                         this.encode_key(keys::GLOBAL_ACCOUNT_DATA, event_type.to_string());
@@ -1034,15 +1746,32 @@ impl StateStore for SqliteStateStore {
                         txn.set_room_account_data(&room_id, &event_type, &data)?;
                     }
                 }
+                timings.account_data = lap();
 
                 for (user_id, event) in presence {
                     let key = this.encode_presence_key(&user_id);
-                    let value = this.serialize_json(&event)?;
-                    txn.set_kv_blob(&key, &value)?;
+                    let json = serde_json::to_vec(&event)?;
+
+                    // Presence is often sent again without any change, don't
+                    // rewrite it in that case.
+                    let stored: Option<Vec<u8>> = txn
+                        .query_row("SELECT value FROM kv_blob WHERE key = ?", (&key,), |row| {
+                            row.get(0)
+                        })
+                        .optional()?;
+                    if let Some(stored) = stored {
+                        if *this.decode_value(&stored)? == *json {
+                            continue;
+                        }
+                    }
+
+                    txn.set_kv_blob(&key, &this.encode_value(json)?)?;
                 }
+                timings.presence = lap();
 
                 for (room_id, room_info) in room_infos {
-                    let stripped = room_info.state() == RoomState::Invited;
+                    let stripped =
+                        matches!(room_info.state(), RoomState::Invited | RoomState::Knocked);
                     // Remove non-stripped data for stripped rooms and vice-versa.
                     this.remove_maybe_stripped_room_data(txn, &room_id, !stripped)?;
 
@@ -1052,6 +1781,7 @@ impl StateStore for SqliteStateStore {
                     let data = this.serialize_json(&room_info)?;
                     txn.set_room_info(&room_id, &state, &data)?;
                 }
+                timings.room_infos = lap();
 
                 for (room_id, user_ids) in profiles_to_delete {
                     let room_id = this.encode_key(keys::PROFILE, room_id);
@@ -1061,6 +1791,29 @@ impl StateStore for SqliteStateStore {
                     }
                 }
 
+                let member_event_type =
+                    this.encode_key(keys::STATE_EVENT, StateEventType::RoomMember.to_string());
+                for (room_id, user_ids) in members_to_delete {
+                    let state_event_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
+                    let member_room_id = this.encode_key(keys::MEMBER, &room_id);
+                    for user_id in user_ids {
+                        let state_key = this.encode_key(keys::STATE_EVENT, &user_id);
+                        txn.remove_state_event(
+                            &state_event_room_id,
+                            &member_event_type,
+                            &state_key,
+                        )?;
+                        txn.remove_member(
+                            &member_room_id,
+                            &this.encode_key(keys::MEMBER, &user_id),
+                        )?;
+                    }
+                }
+                timings.members = lap();
+
+                // The members are written along with the state events, so their
+                // time is counted separately.
+                let mut state_members = Duration::ZERO;
                 for (room_id, state_event_types) in state {
                     let profiles = profiles.get(&room_id);
                     let encoded_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
@@ -1088,6 +1841,7 @@ impl StateStore for SqliteStateStore {
                             )?;
 
                             if event_type == StateEventType::RoomMember {
+                                let member_start = Instant::now();
                                 let member_event = match raw_state_event
                                     .deserialize_as::<SyncRoomMemberEvent>()
                                 {
@@ -1120,10 +1874,14 @@ impl StateStore for SqliteStateStore {
                                     let data = this.serialize_json(&profile)?;
                                     txn.set_profile(&room_id, &user_id, &data)?;
                                 }
+
+                                state_members += member_start.elapsed();
                             }
                         }
                     }
                 }
+                timings.state = lap().saturating_sub(state_members);
+                timings.members += state_members;
 
                 for (room_id, stripped_state_event_types) in stripped_state {
                     let encoded_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
@@ -1168,6 +1926,7 @@ impl StateStore for SqliteStateStore {
                         }
                     }
                 }
+                timings.stripped_state = lap();
 
                 for (room_id, receipt_event) in receipts {
                     let room_id = this.encode_key(keys::RECEIPT, room_id);
@@ -1205,6 +1964,7 @@ impl StateStore for SqliteStateStore {
                         }
                     }
                 }
+                timings.receipts = lap();
 
                 for (room_id, redactions) in redactions {
                     let make_room_version = || {
@@ -1257,6 +2017,7 @@ impl StateStore for SqliteStateStore {
                         }
                     }
                 }
+                timings.redactions = lap();
 
                 for (room_id, display_names) in ambiguity_maps {
                     let room_id = this.encode_key(keys::DISPLAY_NAME, room_id);
@@ -1272,6 +2033,50 @@ impl StateStore for SqliteStateStore {
                         }
                     }
                 }
+                timings.display_names = lap();
+
+                for (room_id, room_edits) in edits {
+                    let room_id = this.encode_key(keys::EDIT, room_id);
+
+                    for (event_id, edit) in room_edits {
+                        let event_id = this.encode_key(keys::EDIT, event_id);
+                        let origin_server_ts = edit
+                            .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                            .ok()
+                            .flatten()
+                            .map(|ts| u64::from(ts.get()));
+                        let data = this.serialize_json(&edit)?;
+                        txn.set_edit(&room_id, &event_id, origin_server_ts, &data)?;
+                    }
+                }
+                timings.edits = lap();
+
+                for (room_id, entries) in alias_history {
+                    let room_id = this.encode_key(keys::ALIAS_HISTORY, room_id);
+                    let mut latest_alias = txn
+                        .get_latest_alias_history_entry(&room_id)?
+                        .map(|data| {
+                            this.deserialize_json::<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>(
+                                &data,
+                            )
+                        })
+                        .transpose()?
+                        .map(|(alias, _)| alias);
+
+                    for entry in entries {
+                        if latest_alias.as_ref() == Some(&entry.0) {
+                            continue;
+                        }
+
+                        txn.add_alias_history_entry(&room_id, &this.serialize_json(entry)?)?;
+                        latest_alias = Some(entry.0.clone());
+                    }
+                }
+                timings.alias_history = lap();
+
+                if this.record_save_timings.load(Ordering::Relaxed) {
+                    *this.last_save_timings.lock().unwrap() = Some(timings);
+                }
 
                 Ok::<_, Error>(())
             })
@@ -1374,6 +2179,97 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn count_room_events(&self, room_id: &RoomId) -> Result<usize> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        self.acquire().await?.count_state_events(room_id).await
+    }
+
+    async fn get_state_event_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedEventId>> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        self.acquire()
+            .await?
+            .get_state_events_data(room_id)
+            .await?
+            .into_iter()
+            .map(|data| {
+                let raw = self.deserialize_json::<Raw<AnySyncStateEvent>>(&data)?;
+                Ok(raw.get_field::<OwnedEventId>("event_id").ok().flatten())
+            })
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let mut events = self.acquire().await?.get_all_maybe_stripped_state_events(room_id).await?;
+
+        if events.iter().any(|(stripped, _)| *stripped) {
+            events.retain(|(stripped, _)| *stripped);
+        }
+
+        events
+            .into_iter()
+            .map(|(stripped, data)| {
+                let ev = if stripped {
+                    RawAnySyncOrStrippedState::Stripped(self.deserialize_json(&data)?)
+                } else {
+                    RawAnySyncOrStrippedState::Sync(self.deserialize_json(&data)?)
+                };
+
+                Ok(ev)
+            })
+            .collect()
+    }
+
+    async fn get_all_rooms_state_events(
+        &self,
+        room_ids: &[&RoomId],
+    ) -> Result<BTreeMap<OwnedRoomId, Vec<RawAnySyncOrStrippedState>>> {
+        let room_ids: BTreeMap<_, _> = room_ids
+            .iter()
+            .map(|&room_id| (self.encode_key(keys::STATE_EVENT, room_id), room_id))
+            .collect();
+        let events = self
+            .acquire()
+            .await?
+            .get_all_maybe_stripped_state_events_for_rooms(room_ids.keys().cloned().collect())
+            .await?;
+
+        let mut rooms_events: BTreeMap<OwnedRoomId, Vec<(bool, Vec<u8>)>> = BTreeMap::new();
+        for (encoded_room_id, stripped, data) in events {
+            if let Some(&room_id) = room_ids.get(encoded_room_id.as_slice()) {
+                rooms_events.entry(room_id.to_owned()).or_default().push((stripped, data));
+            }
+        }
+
+        rooms_events
+            .into_iter()
+            .map(|(room_id, mut events)| {
+                if events.iter().any(|(stripped, _)| *stripped) {
+                    events.retain(|(stripped, _)| *stripped);
+                }
+
+                let events = events
+                    .into_iter()
+                    .map(|(stripped, data)| {
+                        let ev = if stripped {
+                            RawAnySyncOrStrippedState::Stripped(self.deserialize_json(&data)?)
+                        } else {
+                            RawAnySyncOrStrippedState::Sync(self.deserialize_json(&data)?)
+                        };
+
+                        Ok(ev)
+                    })
+                    .collect::<Result<_>>()?;
+
+                Ok((room_id, events))
+            })
+            .collect()
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -1462,9 +2358,30 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn get_room_info(&self, room_id: &RoomId) -> Result<Option<RoomInfo>> {
+        self.acquire()
+            .await?
+            .get_room_info(self.encode_key(keys::ROOM_INFO, room_id))
+            .await?
+            .map(|data| self.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_lazy_room_infos(&self) -> Result<Vec<LazyRoomInfo>> {
+        self.acquire()
+            .await?
+            .get_room_infos(Vec::new())
+            .await?
+            .into_iter()
+            .map(|data| Ok(LazyRoomInfo::from_raw(self.deserialize_json(&data)?)?))
+            .collect()
+    }
+
     async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
-        let states =
-            vec![self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Invited)?)];
+        let states = vec![
+            self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Invited)?),
+            self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Knocked)?),
+        ];
         self.acquire()
             .await?
             .get_room_infos(states)
@@ -1539,6 +2456,21 @@ impl StateStore for SqliteStateStore {
             .transpose()
     }
 
+    async fn get_account_data_types(&self) -> Result<Vec<GlobalAccountDataEventType>> {
+        // The event types might be hashed in the table, so they are read from the events.
+        self.acquire()
+            .await?
+            .get_all_global_account_data()
+            .await?
+            .into_iter()
+            .map(|data| {
+                let raw = self.deserialize_json::<Raw<AnyGlobalAccountDataEvent>>(&data)?;
+                Ok(raw.get_field::<GlobalAccountDataEventType>("type").ok().flatten())
+            })
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
     async fn get_room_account_data_event(
         &self,
         room_id: &RoomId,
@@ -1554,6 +2486,29 @@ impl StateStore for SqliteStateStore {
             .transpose()
     }
 
+    async fn get_room_account_data_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<BTreeMap<RoomAccountDataEventType, Raw<AnyRoomAccountDataEvent>>> {
+        // The event types might be hashed in the table, so they are read from the events.
+        let room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+        self.acquire()
+            .await?
+            .get_all_room_account_data(room_id)
+            .await?
+            .into_iter()
+            .map(|data| {
+                let raw = self.deserialize_json::<Raw<AnyRoomAccountDataEvent>>(&data)?;
+                Ok(raw
+                    .get_field::<RoomAccountDataEventType>("type")
+                    .ok()
+                    .flatten()
+                    .map(|event_type| (event_type, raw)))
+            })
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
     async fn get_user_room_receipt_event(
         &self,
         room_id: &RoomId,
@@ -1603,6 +2558,65 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn get_latest_edit(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<Raw<AnySyncTimelineEvent>>> {
+        let room_id = self.encode_key(keys::EDIT, room_id);
+        let event_id = self.encode_key(keys::EDIT, event_id);
+
+        self.acquire()
+            .await?
+            .get_edit(room_id, event_id)
+            .await?
+            .map(|data| self.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_alias_history_entries(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<(OwnedRoomAliasId, MilliSecondsSinceUnixEpoch)>> {
+        let room_id = self.encode_key(keys::ALIAS_HISTORY, room_id);
+
+        self.acquire()
+            .await?
+            .get_alias_history(room_id)
+            .await?
+            .iter()
+            .map(|data| self.deserialize_json(data))
+            .collect()
+    }
+
+    async fn remove_events_before(&self, ts: MilliSecondsSinceUnixEpoch) -> Result<()> {
+        let conn = self.acquire().await?;
+
+        // The timestamps of the receipts are only part of their data, which might
+        // be encrypted.
+        let receipt_rowids: Vec<_> = conn
+            .get_all_receipts()
+            .await?
+            .into_iter()
+            .filter(|(_, data)| {
+                self.deserialize_json::<ReceiptData>(data)
+                    .is_ok_and(|data| data.receipt.ts.is_some_and(|receipt_ts| receipt_ts < ts))
+            })
+            .map(|(rowid, _)| rowid)
+            .collect();
+
+        let ts = u64::from(ts.get());
+        conn.with_transaction(move |txn| {
+            txn.remove_edits_before(ts)?;
+            for rowid in receipt_rowids {
+                txn.remove_receipt(rowid)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
     async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.acquire().await?.get_kv_blob(self.encode_custom_key(key)).await
     }
@@ -1632,36 +2646,208 @@ impl StateStore for SqliteStateStore {
         Ok(previous)
     }
 
+    async fn update_custom_value(
+        &self,
+        key: &[u8],
+        update: CustomValueUpdate,
+    ) -> Result<Option<Vec<u8>>> {
+        let key = self.encode_custom_key(key);
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                let value = update(txn.get_kv_blob(&key)?.as_deref())?;
+
+                match &value {
+                    Some(value) => txn.set_kv_blob(&key, value)?,
+                    None => txn.remove_kv_blob(&key)?,
+                }
+
+                Ok::<_, Error>(value)
+            })
+            .await
+    }
+
     async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
         let this = self.clone();
         let room_id = room_id.to_owned();
 
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| Ok(this.remove_room_data(txn, &room_id)?))
+            .await
+    }
+
+    async fn remove_rooms(&self, room_ids: &[&RoomId]) -> Result<()> {
+        let this = self.clone();
+        let room_ids: Vec<OwnedRoomId> =
+            room_ids.iter().map(|&room_id| room_id.to_owned()).collect();
+
         self.acquire()
             .await?
             .with_transaction(move |txn| {
+                for room_id in &room_ids {
+                    this.remove_room_data(txn, room_id)?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn mark_invite_rejected(&self, room_id: &RoomId) -> Result<()> {
+        let this = self.clone();
+        let room_id = room_id.to_owned();
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                this.remove_maybe_stripped_room_data(txn, &room_id, true)?;
+
                 let room_info_room_id = this.encode_key(keys::ROOM_INFO, &room_id);
-                txn.remove_room_info(&room_info_room_id)?;
+                if let Some(data) = txn.get_room_info(&room_info_room_id)? {
+                    let mut room_info: RoomInfo = this.deserialize_json(&data)?;
+                    room_info.mark_as_left();
 
-                let state_event_room_id = this.encode_key(keys::STATE_EVENT, &room_id);
-                txn.remove_room_state_events(&state_event_room_id, None)?;
+                    let state = this
+                        .encode_key(keys::ROOM_INFO, serde_json::to_string(&room_info.state())?);
+                    let data = this.serialize_json(&room_info)?;
+                    txn.set_room_info(&room_info_room_id, &state, &data)?;
+                }
 
-                let member_room_id = this.encode_key(keys::MEMBER, &room_id);
-                txn.remove_room_members(&member_room_id, None)?;
+                Ok(())
+            })
+            .await
+    }
 
-                let profile_room_id = this.encode_key(keys::PROFILE, &room_id);
-                txn.remove_room_profiles(&profile_room_id)?;
+    async fn clear_ephemeral(&self) -> Result<()> {
+        let conn = self.acquire().await?;
 
-                let room_account_data_room_id = this.encode_key(keys::ROOM_ACCOUNT_DATA, &room_id);
-                txn.remove_room_account_data(&room_account_data_room_id)?;
+        // Presence events are stored among other values in the key-value table,
+        // with keys that might be hashed, so they can only be found by their
+        // content.
+        let presence_keys: Vec<_> = conn
+            .get_all_kv_blobs()
+            .await?
+            .into_iter()
+            .filter(|(_, value)| {
+                self.deserialize_json::<Raw<PresenceEvent>>(value).is_ok_and(|raw| {
+                    raw.get_field::<String>("type").ok().flatten().as_deref() == Some("m.presence")
+                })
+            })
+            .map(|(key, _)| key)
+            .collect();
 
-                let receipt_room_id = this.encode_key(keys::RECEIPT, &room_id);
-                txn.remove_room_receipts(&receipt_room_id)?;
+        conn.with_transaction(move |txn| {
+            for key in presence_keys {
+                txn.remove_kv_blob(&key)?;
+            }
+            txn.remove_receipts()?;
 
-                let display_name_room_id = this.encode_key(keys::DISPLAY_NAME, &room_id);
-                txn.remove_room_display_names(&display_name_room_id)?;
+            Ok(())
+        })
+        .await
+    }
 
-                let send_queue_room_id = this.encode_key(keys::SEND_QUEUE, &room_id);
-                txn.remove_room_send_queue(&send_queue_room_id)?;
+    async fn find_orphaned_members(&self) -> Result<Vec<(OwnedRoomId, OwnedUserId)>> {
+        // The room IDs are hashed in encrypted stores, so they can't be recovered.
+        if self.store_cipher.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let mut orphans = BTreeSet::new();
+        for (room_id, data) in self.get_orphaned_members().await? {
+            let Some(room_id) =
+                std::str::from_utf8(&room_id).ok().and_then(|room_id| RoomId::parse(room_id).ok())
+            else {
+                continue;
+            };
+            orphans.insert((room_id, self.deserialize_value(&data)?));
+        }
+
+        Ok(orphans.into_iter().collect())
+    }
+
+    async fn prune_orphaned_members(&self) -> Result<()> {
+        let room_ids: BTreeSet<_> =
+            self.get_orphaned_members().await?.into_iter().map(|(room_id, _)| room_id).collect();
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                for room_id in room_ids {
+                    txn.remove_room_members(&room_id, None)?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn compact_if_needed(&self, threshold: f64) -> Result<bool> {
+        let (page_count, free_page_count) = self.page_counts().await?;
+        if page_count == 0 || free_page_count as f64 / page_count as f64 <= threshold {
+            return Ok(false);
+        }
+
+        // Rebuild the database without the free pages, which shrinks the file.
+        self.acquire().await?.execute_batch("VACUUM").await?;
+
+        Ok(true)
+    }
+
+    async fn prune_stale_state(
+        &self,
+        room_id: &RoomId,
+        current_keys: &BTreeSet<(StateEventType, String)>,
+    ) -> Result<()> {
+        let this = self.clone();
+        let state_event_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let member_room_id = self.encode_key(keys::MEMBER, room_id);
+        let current_keys = current_keys.clone();
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                // The stored keys are used as they are to delete the rows, since they might
+                // not be the ones that the event type and state key encode to anymore.
+                let rows = txn
+                    .prepare(
+                        "SELECT event_type, state_key, data FROM state_event
+                         WHERE room_id = ? AND stripped = FALSE",
+                    )?
+                    .query((&state_event_room_id,))?
+                    .mapped(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                    .collect::<rusqlite::Result<Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>>>()?;
+
+                for (event_type_key, state_key_key, data) in rows {
+                    let raw = this.deserialize_json::<Raw<AnySyncStateEvent>>(&data)?;
+                    let (Some(event_type), Some(state_key)) = (
+                        raw.get_field::<StateEventType>("type").ok().flatten(),
+                        raw.get_field::<String>("state_key").ok().flatten(),
+                    ) else {
+                        continue;
+                    };
+
+                    if current_keys.contains(&(event_type.clone(), state_key.clone())) {
+                        continue;
+                    }
+
+                    txn.prepare_cached(
+                        "DELETE FROM state_event
+                         WHERE room_id = ? AND event_type = ? AND state_key = ?",
+                    )?
+                    .execute((
+                        &state_event_room_id,
+                        event_type_key,
+                        state_key_key,
+                    ))?;
+
+                    if event_type == StateEventType::RoomMember {
+                        let user_id = this.encode_key(keys::MEMBER, &state_key);
+                        txn.remove_member(&member_room_id, &user_id)?;
+                    }
+                }
 
                 Ok(())
             })
@@ -1774,6 +2960,54 @@ impl StateStore for SqliteStateStore {
         Ok(queued_events)
     }
 
+    async fn drain_send_queue_events(
+        &self,
+        room_id: &RoomId,
+        limit: usize,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        let room_id = self.encode_key(keys::SEND_QUEUE, room_id);
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        // Read and delete the oldest events in the same transaction, so they're either
+        // all drained or not at all. See `load_send_queue_events` for the ordering.
+        let res: Vec<(String, Vec<u8>, bool)> = self
+            .acquire()
+            .await?
+            .with_transaction(move |txn| {
+                let rows: Vec<(i64, String, Vec<u8>, bool)> = txn
+                    .prepare_cached(
+                        "SELECT ROWID, transaction_id, content, wedged FROM send_queue_events WHERE room_id = ? ORDER BY ROWID LIMIT ?",
+                    )?
+                    .query((&room_id, limit))?
+                    .mapped(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                    .collect::<rusqlite::Result<_>>()?;
+
+                let mut delete =
+                    txn.prepare_cached("DELETE FROM send_queue_events WHERE ROWID = ?")?;
+                for (rowid, ..) in &rows {
+                    delete.execute((rowid,))?;
+                }
+
+                Ok::<_, Error>(
+                    rows.into_iter()
+                        .map(|(_, txn_id, content, wedged)| (txn_id, content, wedged))
+                        .collect(),
+                )
+            })
+            .await?;
+
+        let mut queued_events = Vec::with_capacity(res.len());
+        for entry in res {
+            queued_events.push(QueuedEvent {
+                transaction_id: entry.0.into(),
+                event: self.deserialize_json(&entry.1)?,
+                is_wedged: entry.2,
+            });
+        }
+
+        Ok(queued_events)
+    }
+
     async fn update_send_queue_event_status(
         &self,
         room_id: &RoomId,
@@ -1817,6 +3051,28 @@ impl StateStore for SqliteStateStore {
             .collect())
     }
 
+    async fn next_send_sequence(&self, room_id: &RoomId) -> Result<u64, Self::Error> {
+        let key = self.encode_key(keys::KV_BLOB, format!("send_sequence:{room_id}"));
+        let this = self.clone();
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                let previous: Option<Vec<u8>> = txn
+                    .query_row("SELECT value FROM kv_blob WHERE key = ?", (&key,), |row| row.get(0))
+                    .optional()?;
+
+                let sequence = match previous {
+                    Some(value) => this.deserialize_value::<u64>(&value)? + 1,
+                    None => 0,
+                };
+                txn.set_kv_blob(&key, &this.serialize_value(&sequence)?)?;
+
+                Ok(sequence)
+            })
+            .await
+    }
+
     async fn save_dependent_send_queue_event(
         &self,
         room_id: &RoomId,
@@ -1935,10 +3191,29 @@ struct ReceiptData {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering::SeqCst},
+            Arc,
+        },
+        time::Duration,
+    };
 
-    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use matrix_sdk_base::{
+        statestore_integration_tests,
+        store::{ComposerDraft, ComposerDraftType, StateStoreExt, StoreConfig},
+        BaseClient, StateChanges, StateStore, StateStoreDataKey, StateStoreDataValue, StoreError,
+    };
+    use matrix_sdk_test::async_test;
     use once_cell::sync::Lazy;
+    use ruma::{
+        event_id,
+        events::{presence::PresenceEvent, AnySyncStateEvent, StateEventType},
+        room_id,
+        serde::Raw,
+        server_name, user_id,
+    };
+    use serde_json::json;
     use tempfile::{tempdir, TempDir};
 
     use super::SqliteStateStore;
@@ -1956,17 +3231,289 @@ mod tests {
     }
 
     statestore_integration_tests!();
+
+    #[async_test]
+    async fn test_acknowledged_survives_reopen() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let notice = "m.server_notice.terms:2024-06";
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        store.mark_acknowledged(notice).await.unwrap();
+        drop(store);
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        assert!(store.is_acknowledged(notice).await.unwrap());
+    }
+
+    #[async_test]
+    async fn test_reported_event_survives_reopen() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let event_id = event_id!("$reported");
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        store.mark_event_reported(event_id).await.unwrap();
+        drop(store);
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        assert!(store.is_event_reported(event_id).await.unwrap());
+    }
+
+    #[async_test]
+    async fn test_blocked_servers_survive_reopen() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let server_name = server_name!("spam.localhost");
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        store.add_blocked_server(server_name).await.unwrap();
+        drop(store);
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        assert!(store.blocked_servers().await.unwrap().contains(server_name));
+    }
+
+    #[async_test]
+    async fn test_send_sequence_survives_reopen() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let room_id = room_id!("!room:localhost");
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        assert_eq!(store.next_send_sequence(room_id).await.unwrap(), 0);
+        assert_eq!(store.next_send_sequence(room_id).await.unwrap(), 1);
+        drop(store);
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        assert_eq!(store.next_send_sequence(room_id).await.unwrap(), 2);
+    }
+
+    #[async_test]
+    async fn test_composer_draft_survives_reopen() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let room_id = room_id!("!room:localhost");
+        let draft = ComposerDraft {
+            plain_text: "Hello".to_owned(),
+            html_text: None,
+            draft_type: ComposerDraftType::NewMessage,
+        };
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        store
+            .set_kv_data(
+                StateStoreDataKey::ComposerDraft(room_id),
+                StateStoreDataValue::ComposerDraft(draft.clone()),
+            )
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        let stored = store
+            .get_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+            .await
+            .unwrap()
+            .and_then(StateStoreDataValue::into_composer_draft);
+        assert_eq!(stored, Some(draft));
+
+        store.remove_kv_data(StateStoreDataKey::ComposerDraft(room_id)).await.unwrap();
+        assert!(store
+            .get_kv_data(StateStoreDataKey::ComposerDraft(room_id))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_test]
+    async fn test_saved_changes_survive_drop() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let room_id = room_id!("!room:localhost");
+
+        let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "content": { "topic": "Kept" },
+            "event_id": "$topic",
+            "origin_server_ts": 1,
+            "sender": "@alice:localhost",
+            "state_key": "",
+            "type": "m.room.topic",
+        }))
+        .unwrap()
+        .cast();
+        let mut changes = StateChanges::default();
+        changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+
+        // The changes are committed once `save_changes` returns, so dropping
+        // the store right after doesn't lose them.
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        store.save_changes(&changes).await.unwrap();
+        drop(store);
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        assert!(store
+            .get_state_event(room_id, StateEventType::RoomTopic, "")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[async_test]
+    async fn test_save_timings_are_recorded() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let room_id = room_id!("!room:localhost");
+        let user_id = user_id!("@alice:localhost");
+
+        let mut changes = StateChanges::default();
+        let presence: Raw<PresenceEvent> = Raw::new(&json!({
+            "content": { "presence": "online" },
+            "sender": user_id,
+            "type": "m.presence",
+        }))
+        .unwrap()
+        .cast();
+        changes.add_presence_event(presence.deserialize().unwrap(), presence);
+        for event in [
+            json!({
+                "content": { "membership": "join" },
+                "event_id": "$member",
+                "origin_server_ts": 1,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }),
+            json!({
+                "content": { "topic": "Timed" },
+                "event_id": "$topic",
+                "origin_server_ts": 2,
+                "sender": user_id,
+                "state_key": "",
+                "type": "m.room.topic",
+            }),
+        ] {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&event).unwrap().cast();
+            changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        }
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+
+        // Nothing is recorded by default.
+        store.save_changes(&changes).await.unwrap();
+        assert!(store.last_save_timings().is_none());
+
+        store.set_record_save_timings(true);
+        store.save_changes(&changes).await.unwrap();
+        let timings = store.last_save_timings().unwrap();
+        assert!(timings.presence > Duration::ZERO);
+        assert!(timings.members > Duration::ZERO);
+        assert!(timings.state > Duration::ZERO);
+    }
+
+    #[async_test]
+    async fn test_table_summary() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let room_id = room_id!("!room:localhost");
+        let user_id = user_id!("@alice:localhost");
+
+        let store = SqliteStateStore::open(&tmpdir_path, None).await.unwrap();
+        let summary = store.table_summary().await.unwrap();
+        assert_eq!(summary["member"], 0);
+        assert_eq!(summary["state_event"], 0);
+
+        let mut changes = StateChanges::default();
+        for event in [
+            json!({
+                "content": { "membership": "join" },
+                "event_id": "$member",
+                "origin_server_ts": 1,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }),
+            json!({
+                "content": { "topic": "Counted" },
+                "event_id": "$topic",
+                "origin_server_ts": 2,
+                "sender": user_id,
+                "state_key": "",
+                "type": "m.room.topic",
+            }),
+        ] {
+            let raw: Raw<AnySyncStateEvent> = Raw::new(&event).unwrap().cast();
+            changes.add_state_event(room_id, raw.deserialize().unwrap(), raw);
+        }
+        store.save_changes(&changes).await.unwrap();
+
+        let summary = store.table_summary().await.unwrap();
+        assert_eq!(summary["member"], 1);
+        assert_eq!(summary["state_event"], 2);
+        assert_eq!(summary["send_queue_events"], 0);
+    }
+
+    #[async_test]
+    async fn test_maintenance_compacts_fragmented_store() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        let store = Arc::new(SqliteStateStore::open(&tmpdir_path, None).await.unwrap());
+        for i in 0..200 {
+            let key = format!("key{i}");
+            store.set_custom_value(key.as_bytes(), vec![i as u8; 4096]).await.unwrap();
+        }
+        for i in 0..200 {
+            let key = format!("key{i}");
+            store.remove_custom_value(key.as_bytes()).await.unwrap();
+        }
+
+        let (page_count, freelist_count) = store.page_counts().await.unwrap();
+        assert!(freelist_count as f64 / page_count as f64 > 0.5);
+        // The free space doesn't exceed this threshold, nothing is done.
+        assert!(!store.compact_if_needed(0.99).await.unwrap());
+
+        let client = BaseClient::with_store_config(
+            StoreConfig::new().state_store(store.clone()).compaction_threshold(Some(0.25)),
+        );
+        client
+            .spawn_store_maintenance()
+            .expect("the maintenance shouldn't be running")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (compacted_page_count, freelist_count) = store.page_counts().await.unwrap();
+        assert!(compacted_page_count < page_count);
+        assert_eq!(freelist_count, 0);
+    }
 }
 
 #[cfg(test)]
 mod encrypted_tests {
     use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
 
-    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+    use assert_matches::assert_matches;
+    use matrix_sdk_base::{
+        statestore_integration_tests, StateChanges, StateStore, StateStoreDataKey,
+        StateStoreDataValue, StoreError,
+    };
+    use matrix_sdk_test::async_test;
     use once_cell::sync::Lazy;
+    use ruma::{
+        events::{presence::PresenceEvent, AnySyncStateEvent, StateEventType},
+        room_id,
+        serde::Raw,
+        user_id,
+    };
+    use serde_json::json;
     use tempfile::{tempdir, TempDir};
 
-    use super::SqliteStateStore;
+    use super::{keys, SqliteObjectStateStoreExt, SqliteStateStore};
+    use crate::{
+        utils::{SqliteAsyncConnExt, SqliteKeyValueStoreAsyncConnExt},
+        Error,
+    };
 
     static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
     static NUM: AtomicU32 = AtomicU32::new(0);
@@ -1983,6 +3530,203 @@ mod encrypted_tests {
     }
 
     statestore_integration_tests!();
+
+    #[async_test]
+    async fn test_unchanged_presence_is_not_rewritten() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let user_id = user_id!("@alice:localhost");
+
+        let presence_changes = |presence: &str| {
+            let raw: Raw<PresenceEvent> = Raw::new(&json!({
+                "content": { "presence": presence },
+                "sender": user_id,
+                "type": "m.presence",
+            }))
+            .unwrap()
+            .cast();
+            let mut changes = StateChanges::default();
+            changes.add_presence_event(raw.deserialize().unwrap(), raw);
+            changes
+        };
+
+        let store =
+            SqliteStateStore::open(&tmpdir_path, Some("default_test_password")).await.unwrap();
+        // The values are encrypted with a random nonce, so every write changes the
+        // stored blob.
+        let stored_blob = || async {
+            let key = store.encode_presence_key(user_id);
+            store.acquire().await.unwrap().get_kv_blob(key).await.unwrap().unwrap()
+        };
+
+        store.save_changes(&presence_changes("online")).await.unwrap();
+        let first = stored_blob().await;
+
+        store.save_changes(&presence_changes("online")).await.unwrap();
+        assert_eq!(stored_blob().await, first);
+
+        store.save_changes(&presence_changes("offline")).await.unwrap();
+        assert_ne!(stored_blob().await, first);
+    }
+
+    #[async_test]
+    async fn test_find_duplicate_state() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+        let room_id = room_id!("!room:localhost");
+
+        let store =
+            SqliteStateStore::open(&tmpdir_path, Some("default_test_password")).await.unwrap();
+
+        let topic: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "content": { "topic": "Duplicated" },
+            "event_id": "$topic",
+            "origin_server_ts": 1,
+            "sender": "@alice:localhost",
+            "state_key": "",
+            "type": "m.room.topic",
+        }))
+        .unwrap()
+        .cast();
+        let mut changes = StateChanges::default();
+        changes.add_state_event(room_id, topic.deserialize().unwrap(), topic.clone());
+        store.save_changes(&changes).await.unwrap();
+
+        assert!(store.find_duplicate_state(room_id).await.unwrap().is_empty());
+
+        // Store the same event again with a plain event type instead of a hashed one.
+        let room_key = store.encode_key(keys::STATE_EVENT, room_id);
+        let state_key = store.encode_key(keys::STATE_EVENT, "");
+        let data = store.serialize_json(&topic).unwrap();
+        store
+            .acquire()
+            .await
+            .unwrap()
+            .execute(
+                "INSERT INTO state_event (room_id, event_type, state_key, stripped, data)
+                 VALUES (?, ?, ?, FALSE, ?)",
+                (room_key, b"m.room.topic".to_vec(), state_key, data),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.find_duplicate_state(room_id).await.unwrap(),
+            [(StateEventType::RoomTopic, String::new())]
+        );
+        assert!(store.find_duplicate_state(room_id!("!other:localhost")).await.unwrap().is_empty());
+    }
+
+    #[async_test]
+    async fn test_change_passphrase() {
+        let name = NUM.fetch_add(1, SeqCst).to_string();
+        let tmpdir_path = TMP_DIR.path().join(name);
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("old")).await.unwrap();
+        store
+            .set_kv_data(
+                StateStoreDataKey::SyncToken,
+                StateStoreDataValue::SyncToken("s42".to_owned()),
+            )
+            .await
+            .unwrap();
+
+        assert_matches!(store.change_passphrase("wrong", "new").await, Err(Error::Encryption(_)));
+        store.change_passphrase("old", "new").await.unwrap();
+        drop(store);
+
+        assert!(SqliteStateStore::open(&tmpdir_path, Some("old")).await.is_err());
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("new")).await.unwrap();
+        let sync_token = store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap();
+        assert_eq!(
+            sync_token.and_then(StateStoreDataValue::into_sync_token).as_deref(),
+            Some("s42")
+        );
+
+        // A store without a passphrase has nothing to change.
+        let unencrypted_path = TMP_DIR.path().join(NUM.fetch_add(1, SeqCst).to_string());
+        let store = SqliteStateStore::open(&unencrypted_path, None).await.unwrap();
+        assert_matches!(store.change_passphrase("old", "new").await, Err(Error::Unencrypted));
+    }
+
+    async fn save_topic_and_custom_value(store: &SqliteStateStore) {
+        let raw: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "content": { "topic": "Rotated" },
+            "event_id": "$topic",
+            "origin_server_ts": 1,
+            "sender": "@alice:localhost",
+            "state_key": "",
+            "type": "m.room.topic",
+        }))
+        .unwrap()
+        .cast();
+        let mut changes = StateChanges::default();
+        changes.add_state_event(room_id!("!room:localhost"), raw.deserialize().unwrap(), raw);
+        store.save_changes(&changes).await.unwrap();
+
+        store.set_custom_value(b"custom", b"plain".to_vec()).await.unwrap();
+    }
+
+    async fn assert_topic_and_custom_value(store: &SqliteStateStore) {
+        let topic = store
+            .get_state_event(room_id!("!room:localhost"), StateEventType::RoomTopic, "")
+            .await
+            .unwrap();
+        assert!(topic.unwrap().deserialize().is_ok());
+
+        let custom = store.get_custom_value(b"custom").await.unwrap();
+        assert_eq!(custom.as_deref(), Some(&b"plain"[..]));
+    }
+
+    #[async_test]
+    async fn test_rotate_encryption_key() {
+        let tmpdir_path = TMP_DIR.path().join(NUM.fetch_add(1, SeqCst).to_string());
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("old")).await.unwrap();
+        save_topic_and_custom_value(&store).await;
+        drop(store);
+
+        assert!(SqliteStateStore::rotate_encryption_key(&tmpdir_path, "wrong", "new")
+            .await
+            .is_err());
+
+        // The values are encrypted with the new key, so the rotated store can read
+        // them.
+        let store =
+            SqliteStateStore::rotate_encryption_key(&tmpdir_path, "old", "new").await.unwrap();
+        assert_topic_and_custom_value(&store).await;
+        drop(store);
+
+        assert!(SqliteStateStore::open(&tmpdir_path, Some("old")).await.is_err());
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("new")).await.unwrap();
+        assert_topic_and_custom_value(&store).await;
+        let conn = store.acquire().await.unwrap();
+        assert!(conn.get_kv(keys::PREVIOUS_CIPHER).await.unwrap().is_none());
+        assert!(conn.get_kv(keys::KEY_ROTATION_PROGRESS).await.unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn test_interrupted_key_rotation_is_resumed() {
+        let tmpdir_path = TMP_DIR.path().join(NUM.fetch_add(1, SeqCst).to_string());
+
+        let store = SqliteStateStore::open(&tmpdir_path, Some("old")).await.unwrap();
+        save_topic_and_custom_value(&store).await;
+
+        // Stop right after the rotation started, before any value is encrypted
+        // again.
+        store.start_key_rotation("new").await.unwrap();
+        drop(store);
+
+        assert!(SqliteStateStore::open(&tmpdir_path, Some("old")).await.is_err());
+
+        // Opening the store with the new passphrase finishes the rotation.
+        let store = SqliteStateStore::open(&tmpdir_path, Some("new")).await.unwrap();
+        assert_topic_and_custom_value(&store).await;
+        let conn = store.acquire().await.unwrap();
+        assert!(conn.get_kv(keys::PREVIOUS_CIPHER).await.unwrap().is_none());
+    }
 }
 
 #[cfg(test)]
@@ -2028,7 +3772,12 @@ mod migration_tests {
         init(&conn).await?;
 
         let store_cipher = Some(Arc::new(conn.get_or_create_store_cipher(SECRET).await.unwrap()));
-        let this = SqliteStateStore { store_cipher, pool };
+        let this = SqliteStateStore {
+            store_cipher,
+            pool,
+            record_save_timings: Default::default(),
+            last_save_timings: Default::default(),
+        };
         this.run_migrations(&conn, 1, Some(version)).await?;
 
         Ok(this)
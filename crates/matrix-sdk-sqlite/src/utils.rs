@@ -343,6 +343,27 @@ pub(crate) trait SqliteKeyValueStoreAsyncConnExt: SqliteAsyncConnExt {
 
         Ok(cipher)
     }
+
+    /// Encrypt the [`StoreCipher`] of the database with a new passphrase.
+    ///
+    /// Only the cipher is encrypted again, the keys it contains, and thus the
+    /// data of the database, don't change.
+    async fn change_store_cipher_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<()> {
+        let encrypted = self.get_kv("cipher").await?.ok_or(Error::Unencrypted)?;
+        let cipher = StoreCipher::import(old_passphrase, &encrypted)?;
+
+        #[cfg(not(test))]
+        let export = cipher.export(new_passphrase);
+        #[cfg(test)]
+        let export = cipher._insecure_export_fast_for_testing(new_passphrase);
+        self.set_kv("cipher", export?).await?;
+
+        Ok(())
+    }
 }
 
 #[async_trait]
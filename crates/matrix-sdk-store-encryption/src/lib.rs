@@ -116,6 +116,38 @@ impl StoreCipher {
         Ok(Self { inner: Keys::new()? })
     }
 
+    /// Create a store cipher with a new random encryption key, that hashes the
+    /// keys like this one.
+    ///
+    /// The values encrypted with this cipher can't be decrypted with the new
+    /// one, but [`StoreCipher::hash_key`] returns the same hashes with both.
+    /// This allows to rotate the key that encrypts the values of a key/value
+    /// store by encrypting them again, without having to know their keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let example = || {
+    /// use matrix_sdk_store_encryption::StoreCipher;
+    /// use serde_json::{json, value::Value};
+    ///
+    /// let store_cipher = StoreCipher::new()?;
+    /// let encrypted = store_cipher.encrypt_value(&json!({ "some": "data" }))?;
+    ///
+    /// let new_store_cipher = store_cipher.with_new_encryption_key()?;
+    ///
+    /// // Encrypt the value again with the new cipher.
+    /// let value: Value = store_cipher.decrypt_value(&encrypted)?;
+    /// let encrypted = new_store_cipher.encrypt_value(&value)?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn with_new_encryption_key(&self) -> Result<Self, Error> {
+        let mut encryption_key = Box::new([0u8; 32]);
+        encryption_key.try_fill(&mut thread_rng())?;
+
+        Ok(Self { inner: Keys { encryption_key, mac_key_seed: self.inner.mac_key_seed.clone() } })
+    }
+
     /// Encrypt the store cipher using the given passphrase and export it.
     ///
     /// This method can be used to persist the `StoreCipher` in an unencrypted
@@ -974,6 +1006,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn rotating_the_encryption_key() -> Result<(), Error> {
+        let value = json!({ "some": "data" });
+
+        let store_cipher = StoreCipher::new()?;
+        let new_store_cipher = store_cipher.with_new_encryption_key()?;
+
+        // The values encrypted with one cipher can't be decrypted with the other.
+        let encrypted = store_cipher.encrypt_value(&value)?;
+        new_store_cipher
+            .decrypt_value::<Value>(&encrypted)
+            .expect_err("The new cipher shouldn't decrypt the old values");
+
+        let encrypted = new_store_cipher.encrypt_value(&value)?;
+        let decrypted: Value = new_store_cipher.decrypt_value(&encrypted)?;
+        assert_eq!(value, decrypted);
+
+        // The keys are hashed the same way by both.
+        assert_eq!(
+            store_cipher.hash_key("some_table", b"It's dangerous to go alone"),
+            new_store_cipher.hash_key("some_table", b"It's dangerous to go alone")
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn can_round_trip_normal_to_base64_encrypted_values() {
         let normal1 = EncryptedValue { version: 2, ciphertext: vec![1, 2, 4], nonce: make_nonce() };
@@ -478,7 +478,7 @@ mod tests {
                 user_id: user_id!("@example:localhost").to_owned(),
                 device_id: device_id!("DEVICEID").to_owned(),
             },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         };
 
         let server = MockServer::start().await;
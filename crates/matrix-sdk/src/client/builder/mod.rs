@@ -21,7 +21,7 @@ use homeserver_config::*;
 use matrix_sdk_base::{store::StoreConfig, BaseClient};
 use ruma::{
     api::{error::FromHttpResponseError, MatrixVersion},
-    OwnedServerName, ServerName,
+    OwnedServerName, RoomVersionId, ServerName,
 };
 use thiserror::Error;
 use tokio::sync::{broadcast, Mutex, OnceCell};
@@ -94,6 +94,7 @@ pub struct ClientBuilder {
     respect_login_well_known: bool,
     server_versions: Option<Box<[MatrixVersion]>>,
     handle_refresh_tokens: bool,
+    default_room_version: Option<RoomVersionId>,
     base_client: Option<BaseClient>,
     #[cfg(feature = "e2e-encryption")]
     encryption_settings: EncryptionSettings,
@@ -113,6 +114,7 @@ impl ClientBuilder {
             respect_login_well_known: true,
             server_versions: None,
             handle_refresh_tokens: false,
+            default_room_version: None,
             base_client: None,
             #[cfg(feature = "e2e-encryption")]
             encryption_settings: Default::default(),
@@ -266,6 +268,16 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the room version to use when creating a room with
+    /// [`Client::create_room()`], if the request doesn't specify one.
+    ///
+    /// If this isn't set, the room version is left to the homeserver, which
+    /// uses the default version it advertises in its capabilities.
+    pub fn default_room_version(mut self, version: RoomVersionId) -> Self {
+        self.default_room_version = Some(version);
+        self
+    }
+
     /// Set the default timeout, fail and retry behavior for all HTTP requests.
     pub fn request_config(mut self, request_config: RequestConfig) -> Self {
         self.request_config = request_config;
@@ -330,6 +342,70 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the minimum TLS version the HTTP client accepts when connecting to
+    /// the homeserver.
+    ///
+    /// Connections to servers that only support older versions of TLS will
+    /// fail. Whether a given version can be required depends on the TLS
+    /// backend: for example `native-tls` doesn't support requiring TLS 1.3,
+    /// and building the client fails in that case.
+    ///
+    /// The cipher suites can't be restricted with this builder. To do so,
+    /// build a [`reqwest::Client`] with a preconfigured TLS backend, see
+    /// [`reqwest::ClientBuilder::use_preconfigured_tls()`], and pass it to
+    /// [`http_client()`][ClientBuilder::http_client].
+    ///
+    /// Internally this will call the
+    /// [`reqwest::ClientBuilder::min_tls_version()`] method.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{reqwest::tls, Client};
+    ///
+    /// let client_config = Client::builder().min_tls_version(tls::Version::TLS_1_2);
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn min_tls_version(mut self, version: reqwest::tls::Version) -> Self {
+        self.http_settings().min_tls_version = Some(version);
+        self
+    }
+
+    /// Add headers that are sent with every request made by the HTTP client.
+    ///
+    /// This is useful when the homeserver sits behind a gateway that requires
+    /// extra headers, like an API key, on each request. Headers set by the SDK
+    /// for a specific request, like `Authorization`, take precedence over the
+    /// ones given here.
+    ///
+    /// The values of the headers are marked as sensitive, so they aren't
+    /// printed in the logs.
+    ///
+    /// Internally this will call the
+    /// [`reqwest::ClientBuilder::default_headers()`] method.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{
+    ///     reqwest::header::{HeaderMap, HeaderValue},
+    ///     Client,
+    /// };
+    ///
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert("x-api-key", HeaderValue::from_static("secret"));
+    ///
+    /// let client_config = Client::builder().additional_headers(headers);
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn additional_headers(mut self, mut headers: reqwest::header::HeaderMap) -> Self {
+        for value in headers.values_mut() {
+            value.set_sensitive(true);
+        }
+        self.http_settings().additional_headers = headers;
+        self
+    }
+
     /// Specify a [`reqwest::Client`] instance to handle sending requests and
     /// receiving responses.
     ///
@@ -338,6 +414,8 @@ impl ClientBuilder {
     /// [`disable_ssl_verification`][ClientBuilder::disable_ssl_verification],
     /// [`add_root_certificates`][ClientBuilder::add_root_certificates],
     /// [`disable_built_in_root_certificates`][ClientBuilder::disable_built_in_root_certificates],
+    /// [`min_tls_version()`][ClientBuilder::min_tls_version],
+    /// [`additional_headers()`][ClientBuilder::additional_headers],
     /// and [`user_agent()`][ClientBuilder::user_agent].
     pub fn http_client(mut self, client: reqwest::Client) -> Self {
         self.http_cfg = Some(HttpConfig::Custom(client));
@@ -505,6 +583,7 @@ impl ClientBuilder {
             base_client,
             server_capabilities,
             self.respect_login_well_known,
+            self.default_room_version,
             event_cache,
             send_queue,
             #[cfg(feature = "e2e-encryption")]
@@ -762,6 +841,28 @@ pub(crate) mod tests {
     // isn't a problem as the builder first strips the scheme and then checks if the
     // name is a valid server name, so it is a close enough approximation.
 
+    #[test]
+    fn test_min_tls_version() {
+        let mut builder = ClientBuilder::new().min_tls_version(reqwest::tls::Version::TLS_1_2);
+
+        let settings = builder.http_settings();
+        assert_eq!(settings.min_tls_version, Some(reqwest::tls::Version::TLS_1_2));
+        settings.make_client().expect("the HTTP client should build with a minimum TLS version");
+    }
+
+    #[test]
+    fn test_additional_headers_are_redacted() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-api-key", reqwest::header::HeaderValue::from_static("secret_api_key"));
+        let mut builder = ClientBuilder::new().additional_headers(headers);
+
+        assert!(!format!("{builder:?}").contains("secret_api_key"));
+
+        let settings = builder.http_settings();
+        assert!(settings.additional_headers["x-api-key"].is_sensitive());
+        assert_eq!(settings.additional_headers["x-api-key"], "secret_api_key");
+    }
+
     #[async_test]
     async fn test_discovery_invalid_server() {
         // Given a new client builder.
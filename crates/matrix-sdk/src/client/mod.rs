@@ -34,7 +34,7 @@ use imbl::Vector;
 use matrix_sdk_base::crypto::store::LockableCryptoStore;
 use matrix_sdk_base::{
     event_cache_store::DynEventCacheStore,
-    store::{DynStateStore, ServerCapabilities},
+    store::{DynStateStore, ServerCapabilities, StateStoreExt},
     sync::{Notification, RoomUpdates},
     BaseClient, RoomInfoNotableUpdate, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta,
     StateStoreDataKey, StateStoreDataValue, SyncOutsideWasm,
@@ -67,7 +67,7 @@ use ruma::{
     push::Ruleset,
     time::Instant,
     DeviceId, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId,
-    RoomOrAliasId, ServerName, UInt, UserId,
+    RoomOrAliasId, RoomVersionId, ServerName, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 use tokio::sync::{broadcast, Mutex, OnceCell, RwLock, RwLockReadGuard};
@@ -292,6 +292,10 @@ pub(crate) struct ClientInner {
     /// store.
     pub(crate) sync_beat: event_listener::Event,
 
+    /// The room version to use when creating a room, if the request doesn't
+    /// specify one.
+    pub(crate) default_room_version: Option<RoomVersionId>,
+
     /// A central cache for events, inactive first.
     ///
     /// It becomes active when [`EventCache::subscribe`] is called.
@@ -327,6 +331,7 @@ impl ClientInner {
         base_client: BaseClient,
         server_capabilities: ClientServerCapabilities,
         respect_login_well_known: bool,
+        default_room_version: Option<RoomVersionId>,
         event_cache: OnceCell<EventCache>,
         send_queue: Arc<SendQueueData>,
         #[cfg(feature = "e2e-encryption")] encryption_settings: EncryptionSettings,
@@ -350,6 +355,7 @@ impl ClientInner {
             room_updates_sender: broadcast::Sender::new(32),
             respect_login_well_known,
             sync_beat: event_listener::Event::new(),
+            default_room_version,
             event_cache,
             send_queue_data: send_queue,
             #[cfg(feature = "e2e-encryption")]
@@ -990,6 +996,16 @@ impl Client {
             .collect()
     }
 
+    /// Returns the rooms this client has knocked on and which are awaiting
+    /// approval.
+    pub fn knocked_rooms(&self) -> Vec<Room> {
+        self.base_client()
+            .rooms_filtered(RoomStateFilter::KNOCKED)
+            .into_iter()
+            .map(|room| Room::new(self.clone(), room))
+            .collect()
+    }
+
     /// Get a room with the given room id.
     ///
     /// # Arguments
@@ -1113,6 +1129,9 @@ impl Client {
     /// This method will either get a filter ID from the store or upload the
     /// filter definition to the homeserver and return the new filter ID.
     ///
+    /// The definition is saved along with the filter ID, so the filter is
+    /// uploaded again if its definition changed since the last upload.
+    ///
     /// # Arguments
     ///
     /// * `filter_name` - The unique name of the filter, this name will be used
@@ -1159,19 +1178,30 @@ impl Client {
         filter_name: &str,
         definition: FilterDefinition,
     ) -> Result<String> {
-        if let Some(filter) = self.inner.base_client.get_filter(filter_name).await? {
-            debug!("Found filter locally");
-            Ok(filter)
-        } else {
-            debug!("Didn't find filter locally");
-            let user_id = self.user_id().ok_or(Error::AuthenticationRequired)?;
-            let request = FilterUploadRequest::new(user_id.to_owned(), definition);
-            let response = self.send(request, None).await?;
-
-            self.inner.base_client.receive_filter_upload(filter_name, &response).await?;
-
-            Ok(response.filter_id)
+        // Definitions weren't saved before, so a missing one doesn't mean that the
+        // filter changed.
+        let definition_changed =
+            self.store().get_filter_definition(filter_name).await?.is_some_and(|saved| {
+                serde_json::to_value(saved).ok() != serde_json::to_value(&definition).ok()
+            });
+
+        match self.inner.base_client.get_filter(filter_name).await? {
+            Some(filter) if !definition_changed => {
+                debug!("Found filter locally");
+                return Ok(filter);
+            }
+            Some(_) => debug!("The definition of the filter changed"),
+            None => debug!("Didn't find filter locally"),
         }
+
+        let user_id = self.user_id().ok_or(Error::AuthenticationRequired)?;
+        let request = FilterUploadRequest::new(user_id.to_owned(), definition.clone());
+        let response = self.send(request, None).await?;
+
+        self.inner.base_client.receive_filter_upload(filter_name, &response).await?;
+        self.store().save_filter_definition(filter_name, &definition).await?;
+
+        Ok(response.filter_id)
     }
 
     /// Join a room by `RoomId`.
@@ -1269,6 +1299,9 @@ impl Client {
     /// one user is invited, the room will be automatically added to the direct
     /// rooms in the account data.
     ///
+    /// If the request doesn't specify a room version, the one configured with
+    /// [`ClientBuilder::default_room_version()`] is used, if any.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -1285,7 +1318,11 @@ impl Client {
     /// assert!(client.create_room(request).await.is_ok());
     /// # };
     /// ```
-    pub async fn create_room(&self, request: create_room::v3::Request) -> Result<Room> {
+    pub async fn create_room(&self, mut request: create_room::v3::Request) -> Result<Room> {
+        if request.room_version.is_none() {
+            request.room_version = self.inner.default_room_version.clone();
+        }
+
         let invite = request.invite.clone();
         let is_direct_room = request.is_direct;
         let response = self.send(request, None).await?;
@@ -2207,6 +2244,7 @@ impl Client {
                 self.inner.base_client.clone_with_in_memory_state_store(),
                 self.inner.server_capabilities.read().await.clone(),
                 self.inner.respect_login_well_known,
+                self.inner.default_room_version.clone(),
                 self.inner.event_cache.clone(),
                 self.inner.send_queue_data.clone(),
                 #[cfg(feature = "e2e-encryption")]
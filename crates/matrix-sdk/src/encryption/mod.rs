@@ -1907,7 +1907,7 @@ mod tests {
                 user_id: user_id!("@example:localhost").to_owned(),
                 device_id: device_id!("DEVICEID").to_owned(),
             },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         };
 
         let client1 = Client::builder()
@@ -2015,7 +2015,7 @@ mod tests {
                 user_id: user_id!("@example:localhost").to_owned(),
                 device_id: device_id!("DEVICEID").to_owned(),
             },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         };
 
         let client = Client::builder()
@@ -24,7 +24,7 @@ use bytes::Bytes;
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
 use http::header::CONTENT_LENGTH;
-use reqwest::Certificate;
+use reqwest::{header::HeaderMap, Certificate};
 use ruma::api::{error::FromHttpResponseError, IncomingResponse, OutgoingRequest};
 use tracing::{debug, info, warn};
 
@@ -127,6 +127,8 @@ pub(crate) struct HttpSettings {
     pub(crate) timeout: Duration,
     pub(crate) additional_root_certificates: Vec<Certificate>,
     pub(crate) disable_built_in_root_certificates: bool,
+    pub(crate) min_tls_version: Option<reqwest::tls::Version>,
+    pub(crate) additional_headers: HeaderMap,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -139,6 +141,8 @@ impl Default for HttpSettings {
             timeout: DEFAULT_REQUEST_TIMEOUT,
             additional_root_certificates: Default::default(),
             disable_built_in_root_certificates: false,
+            min_tls_version: None,
+            additional_headers: HeaderMap::new(),
         }
     }
 }
@@ -172,6 +176,19 @@ impl HttpSettings {
             http_client = http_client.tls_built_in_root_certs(false);
         }
 
+        if let Some(version) = self.min_tls_version {
+            info!(?version, "Setting the minimum TLS version for the HTTP client");
+            http_client = http_client.min_tls_version(version);
+        }
+
+        if !self.additional_headers.is_empty() {
+            info!(
+                "Adding {} additional headers to the requests of the HTTP client",
+                self.additional_headers.len()
+            );
+            http_client = http_client.default_headers(self.additional_headers.clone());
+        }
+
         if let Some(p) = &self.proxy {
             info!(proxy_url = p, "Setting the proxy for the HTTP client");
             http_client = http_client.proxy(reqwest::Proxy::all(p.as_str())?);
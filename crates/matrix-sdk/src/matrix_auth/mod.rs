@@ -16,14 +16,22 @@
 
 //! Types to interact with the native Matrix authentication API.
 
-use std::fmt;
 #[cfg(feature = "sso-login")]
 use std::future::Future;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fmt,
+    hash::{Hash, Hasher},
+    time::Duration,
+};
 
 use eyeball::SharedObservable;
 use futures_core::Stream;
 use futures_util::StreamExt;
-use matrix_sdk_base::SessionMeta;
+use matrix_sdk_base::{
+    store::{CredentialBundle, StateStoreExt},
+    SessionMeta,
+};
 use ruma::{
     api::{
         client::{
@@ -36,6 +44,8 @@ use ruma::{
         OutgoingRequest, SendAccessToken,
     },
     serde::JsonObject,
+    time::SystemTime,
+    MilliSecondsSinceUnixEpoch,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -539,6 +549,7 @@ impl MatrixAuth {
                 *guard = Ok(());
 
                 session_tokens.access_token = res.access_token;
+                session_tokens.expires_at = expires_at(res.expires_in_ms);
                 if let Some(refresh_token) = res.refresh_token {
                     session_tokens.refresh_token = Some(refresh_token);
                 }
@@ -693,6 +704,14 @@ impl MatrixAuth {
         self.session_tokens().and_then(|tokens| tokens.refresh_token)
     }
 
+    /// Get the time at which the current access token expires.
+    ///
+    /// Will be `None` if the client has not been logged in with the native
+    /// Matrix Authentication API, or if the access token doesn't expire.
+    pub fn access_token_expires_at(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.session_tokens().and_then(|tokens| tokens.expires_at)
+    }
+
     /// [`Stream`] to get notified when the current access token and optional
     /// refresh token for this session change.
     ///
@@ -850,10 +869,7 @@ impl MatrixAuth {
     ///         user_id: user_id!("@example:localhost").to_owned(),
     ///         device_id: device_id!("MYDEVICEID").to_owned(),
     ///     },
-    ///     tokens: MatrixSessionTokens {
-    ///         access_token: "My-Token".to_owned(),
-    ///         refresh_token: None,
-    ///     },
+    ///     tokens: MatrixSessionTokens::new("My-Token".to_owned(), None),
     /// };
     ///
     /// client.restore_session(session).await?;
@@ -895,6 +911,49 @@ impl MatrixAuth {
         Ok(())
     }
 
+    /// Export the session of this client with its tokens, the sync token and
+    /// the IDs of the given filters, to resume it with another store.
+    ///
+    /// Will be `None` if the client has not been logged in with the native
+    /// Matrix Authentication API.
+    ///
+    /// Can be used with [`MatrixAuth::restore_credentials`] to restore the
+    /// session without doing a full initial sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter_names` - The names of the filters whose IDs are exported.
+    pub async fn export_credentials(
+        &self,
+        filter_names: &[&str],
+    ) -> Result<Option<MatrixCredentials>> {
+        let Some(session) = self.session() else {
+            return Ok(None);
+        };
+        let CredentialBundle { sync_token, filters, .. } =
+            self.client.store().export_credentials(filter_names).await?;
+
+        Ok(Some(MatrixCredentials { session, sync_token, filters }))
+    }
+
+    /// Restore a session exported with [`MatrixAuth::export_credentials`].
+    ///
+    /// The sync token and the filter IDs are saved in the state store before
+    /// the session is restored, so the next sync continues from the exported
+    /// sync token.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a session was already restored or logged in.
+    #[instrument(skip_all)]
+    pub async fn restore_credentials(&self, credentials: MatrixCredentials) -> Result<()> {
+        let MatrixCredentials { session, sync_token, filters } = credentials;
+        let bundle = CredentialBundle { session_meta: None, sync_token, filters };
+        self.client.store().import_credentials(&bundle).await?;
+
+        self.restore_session(session).await
+    }
+
     /// Receive a login response and update the homeserver and the base client
     /// if needed.
     ///
@@ -969,10 +1028,7 @@ impl MatrixAuth {
 ///         user_id: user_id!("@example:localhost").to_owned(),
 ///         device_id: device_id!("MYDEVICEID").to_owned(),
 ///     },
-///     tokens: MatrixSessionTokens {
-///         access_token: "My-Token".to_owned(),
-///         refresh_token: None,
-///     },
+///     tokens: MatrixSessionTokens::new("My-Token".to_owned(), None),
 /// };
 ///
 /// assert_eq!(session.meta.device_id.as_str(), "MYDEVICEID");
@@ -991,18 +1047,24 @@ pub struct MatrixSession {
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for MatrixSession {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("MatrixSession").field("meta", &self.meta).finish_non_exhaustive()
+        f.debug_struct("MatrixSession")
+            .field("meta", &self.meta)
+            .field("tokens", &self.tokens)
+            .finish()
     }
 }
 
 impl From<&login::v3::Response> for MatrixSession {
     fn from(response: &login::v3::Response) -> Self {
-        let login::v3::Response { user_id, access_token, device_id, refresh_token, .. } = response;
+        let login::v3::Response {
+            user_id, access_token, device_id, refresh_token, expires_in, ..
+        } = response;
         Self {
             meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
             tokens: MatrixSessionTokens {
                 access_token: access_token.clone(),
                 refresh_token: refresh_token.clone(),
+                expires_at: expires_at(*expires_in),
             },
         }
     }
@@ -1011,22 +1073,44 @@ impl From<&login::v3::Response> for MatrixSession {
 impl MatrixSession {
     #[allow(clippy::question_mark)] // clippy falsely complains about the let-unpacking
     fn from_register_response(response: &register::v3::Response) -> Option<Self> {
-        let register::v3::Response { user_id, access_token, device_id, refresh_token, .. } =
-            response;
+        let register::v3::Response {
+            user_id,
+            access_token,
+            device_id,
+            refresh_token,
+            expires_in,
+            ..
+        } = response;
         Some(Self {
             meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone()? },
             tokens: MatrixSessionTokens {
                 access_token: access_token.clone()?,
                 refresh_token: refresh_token.clone(),
+                expires_at: expires_at(*expires_in),
             },
         })
     }
 }
 
+/// A user session using the native Matrix authentication API, with the data
+/// needed to resume syncing, exported with [`MatrixAuth::export_credentials`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixCredentials {
+    /// The session info and its tokens.
+    #[serde(flatten)]
+    pub session: MatrixSession,
+
+    /// The token of the last sync, if the session synced before.
+    pub sync_token: Option<String>,
+
+    /// The IDs of the filters uploaded to the server, by filter name.
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+}
+
 /// The tokens for a user session obtained with the native Matrix authentication
 /// API.
 #[derive(Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
-#[allow(missing_debug_implementations)]
 pub struct MatrixSessionTokens {
     /// The access token used for this session.
     pub access_token: String,
@@ -1036,4 +1120,118 @@ pub struct MatrixSessionTokens {
     /// [refreshing the access token]: https://spec.matrix.org/v1.3/client-server-api/#refreshing-access-tokens
     #[serde(skip_serializing_if = "Option::is_none")]
     pub refresh_token: Option<String>,
+
+    /// The time at which the access token expires, if it does.
+    ///
+    /// Sessions that were serialized before this field existed don't have
+    /// it, and are deserialized with `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+/// The tokens are replaced by their fingerprint, so they can be told apart in
+/// logs without being leaked.
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for MatrixSessionTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MatrixSessionTokens")
+            .field("access_token", &TokenFingerprint(&self.access_token))
+            .field("refresh_token", &self.refresh_token.as_deref().map(TokenFingerprint))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Formats a token as a short hash of its value.
+struct TokenFingerprint<'a>(&'a str);
+
+impl fmt::Debug for TokenFingerprint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hasher = DefaultHasher::new();
+        self.0.hash(&mut hasher);
+        write!(f, "<redacted {:08x}>", hasher.finish() as u32)
+    }
+}
+
+impl MatrixSessionTokens {
+    /// Create new tokens with the given access token and refresh token, and
+    /// without an expiration time.
+    pub fn new(access_token: String, refresh_token: Option<String>) -> Self {
+        Self { access_token, refresh_token, expires_at: None }
+    }
+
+    /// Whether the access token has expired, according to
+    /// [`MatrixSessionTokens::expires_at`].
+    ///
+    /// An access token without an expiration time never expires.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= MilliSecondsSinceUnixEpoch::now())
+    }
+}
+
+/// The time at which a token that is valid for `expires_in` from now expires.
+fn expires_at(expires_in: Option<Duration>) -> Option<MilliSecondsSinceUnixEpoch> {
+    MilliSecondsSinceUnixEpoch::from_system_time(SystemTime::now().checked_add(expires_in?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{uint, MilliSecondsSinceUnixEpoch};
+    use serde_json::json;
+
+    use super::MatrixSession;
+
+    #[test]
+    fn test_debug_session_redacts_tokens() {
+        let session: MatrixSession = serde_json::from_value(json!({
+            "user_id": "@example:localhost",
+            "device_id": "DEVICEID",
+            "access_token": "secret_access_token",
+            "refresh_token": "secret_refresh_token",
+        }))
+        .unwrap();
+
+        let debug = format!("{session:?}");
+        assert!(!debug.contains("secret_access_token"));
+        assert!(!debug.contains("secret_refresh_token"));
+        assert!(debug.contains("<redacted "));
+
+        // The same token always has the same fingerprint.
+        assert_eq!(debug, format!("{:?}", session.clone()));
+    }
+
+    #[test]
+    fn test_deserialize_session_without_expiration() {
+        let session: MatrixSession = serde_json::from_value(json!({
+            "user_id": "@example:localhost",
+            "device_id": "DEVICEID",
+            "access_token": "1234",
+            "refresh_token": "abcd",
+        }))
+        .unwrap();
+
+        assert_eq!(session.tokens.refresh_token.as_deref(), Some("abcd"));
+        assert_eq!(session.tokens.expires_at, None);
+        assert!(!session.tokens.is_expired());
+    }
+
+    #[test]
+    fn test_session_expiration_round_trip() {
+        let mut session: MatrixSession = serde_json::from_value(json!({
+            "user_id": "@example:localhost",
+            "device_id": "DEVICEID",
+            "access_token": "1234",
+        }))
+        .unwrap();
+        session.tokens.refresh_token = Some("abcd".to_owned());
+        session.tokens.expires_at = Some(MilliSecondsSinceUnixEpoch(uint!(1000)));
+
+        let json = serde_json::to_value(&session).unwrap();
+        assert_eq!(json["expires_at"], 1000);
+
+        let session: MatrixSession = serde_json::from_value(json).unwrap();
+        assert_eq!(session.tokens.refresh_token.as_deref(), Some("abcd"));
+        assert_eq!(session.tokens.expires_at, Some(MilliSecondsSinceUnixEpoch(uint!(1000))));
+        assert!(session.tokens.is_expired());
+    }
 }
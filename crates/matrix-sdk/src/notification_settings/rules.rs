@@ -2,6 +2,7 @@
 
 use imbl::HashSet;
 use indexmap::IndexSet;
+use matrix_sdk_base::notification_settings::get_user_defined_room_notification_mode;
 use ruma::{
     push::{
         AnyPushRuleRef, PatternedPushRule, PredefinedContentRuleId, PredefinedOverrideRuleId,
@@ -67,33 +68,7 @@ impl Rules {
         &self,
         room_id: &RoomId,
     ) -> Option<RoomNotificationMode> {
-        // Search for an enabled `Override` rule
-        if self.ruleset.override_.iter().any(|x| {
-            // enabled
-            x.enabled &&
-            // with a condition of type `EventMatch` for this `room_id`
-            // (checking on x.rule_id is not sufficient here as more than one override rule may have a condition matching on `room_id`)
-            x.conditions.iter().any(|x| matches!(
-                x,
-                PushCondition::EventMatch { key, pattern } if key == "room_id" && pattern == room_id
-            )) &&
-            // and without a Notify action
-            !x.actions.iter().any(|x| x.should_notify())
-        }) {
-            return Some(RoomNotificationMode::Mute);
-        }
-
-        // Search for an enabled `Room` rule where `rule_id` is the `room_id`
-        if let Some(rule) = self.ruleset.get(RuleKind::Room, room_id) {
-            // if this rule contains a `Notify` action
-            if rule.triggers_notification() {
-                return Some(RoomNotificationMode::AllMessages);
-            }
-            return Some(RoomNotificationMode::MentionsAndKeywordsOnly);
-        }
-
-        // There is no custom rule matching this `room_id`
-        None
+        get_user_defined_room_notification_mode(&self.ruleset, room_id)
     }
 
     /// Gets the default notification mode for a room.
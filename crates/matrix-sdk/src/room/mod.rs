@@ -104,7 +104,7 @@ use crate::{
     event_handler::{EventHandler, EventHandlerDropGuard, EventHandlerHandle, SyncEvent},
     media::{MediaFormat, MediaRequest},
     notification_settings::{IsEncrypted, IsOneToOne, RoomNotificationMode},
-    room::power_levels::{RoomPowerLevelChanges, RoomPowerLevelsExt},
+    room::power_levels::{PowerLevelRequirements, RoomPowerLevelChanges, RoomPowerLevelsExt},
     sync::RoomUpdate,
     utils::{IntoRawMessageLikeEventContent, IntoRawStateEventContent},
     BaseRoom, Client, Error, HttpResult, Result, RoomState, TransmissionProgress,
@@ -1994,6 +1994,12 @@ impl Room {
             .power_levels())
     }
 
+    /// Get the power levels required for the various actions in this room,
+    /// like inviting, kicking or banning users, or sending a given event type.
+    pub async fn get_power_level_requirements(&self) -> Result<PowerLevelRequirements> {
+        Ok(self.room_power_levels().await?.into())
+    }
+
     /// Resets the room's power levels to the default values
     ///
     /// [spec]: https://spec.matrix.org/v1.9/client-server-api/#mroompower_levels
@@ -3241,7 +3247,7 @@ mod tests {
                 user_id: user_id!("@example:localhost").to_owned(),
                 device_id: device_id!("DEVICEID").to_owned(),
             },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         };
 
         let client = Client::builder()
@@ -1,6 +1,6 @@
 //! Power level configuration types used in [the `room` module][super].
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use ruma::{
     events::{
@@ -8,7 +8,7 @@ use ruma::{
             PossiblyRedactedRoomPowerLevelsEventContent, RoomPowerLevels,
             RoomPowerLevelsEventContent,
         },
-        StateEventType,
+        MessageLikeEventType, StateEventType, TimelineEventType,
     },
     OwnedUserId,
 };
@@ -109,6 +109,63 @@ impl From<RoomPowerLevels> for RoomPowerLevelChanges {
     }
 }
 
+/// The power levels required for the various actions within a room, as
+/// defined by its `m.room.power_levels` event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PowerLevelRequirements {
+    /// The level required to ban a user.
+    pub ban: i64,
+    /// The level required to invite a user.
+    pub invite: i64,
+    /// The level required to kick a user.
+    pub kick: i64,
+    /// The level required to redact an event sent by another user.
+    pub redact: i64,
+    /// The level required to trigger an `@room` notification.
+    pub room_notification: i64,
+    /// The default level required to send message events.
+    pub events_default: i64,
+    /// The default level required to send state events.
+    pub state_default: i64,
+    /// The default power level for every user in the room.
+    pub users_default: i64,
+    /// The levels required to send specific event types, overriding
+    /// `events_default` and `state_default`.
+    pub events: BTreeMap<TimelineEventType, i64>,
+}
+
+impl PowerLevelRequirements {
+    /// The level required to send a message event of the given type.
+    pub fn for_message(&self, event_type: MessageLikeEventType) -> i64 {
+        self.events.get(&event_type.into()).copied().unwrap_or(self.events_default)
+    }
+
+    /// The level required to send a state event of the given type.
+    pub fn for_state(&self, event_type: StateEventType) -> i64 {
+        self.events.get(&event_type.into()).copied().unwrap_or(self.state_default)
+    }
+}
+
+impl From<RoomPowerLevels> for PowerLevelRequirements {
+    fn from(value: RoomPowerLevels) -> Self {
+        Self {
+            ban: value.ban.into(),
+            invite: value.invite.into(),
+            kick: value.kick.into(),
+            redact: value.redact.into(),
+            room_notification: value.notifications.room.into(),
+            events_default: value.events_default.into(),
+            state_default: value.state_default.into(),
+            users_default: value.users_default.into(),
+            events: value
+                .events
+                .into_iter()
+                .map(|(event_type, level)| (event_type, level.into()))
+                .collect(),
+        }
+    }
+}
+
 pub(crate) trait RoomPowerLevelsExt {
     /// Applies the updated settings to the power levels. Any levels that are
     /// `None` will remain unchanged. Unlike with members, we don't remove the
@@ -423,6 +480,34 @@ mod tests {
         assert!(changes.is_empty());
     }
 
+    #[test]
+    fn test_power_level_requirements_with_custom_event_levels() {
+        // Given power levels with custom levels for some event types.
+        let mut content = default_power_levels_event_content();
+        content.events = BTreeMap::from_iter([
+            (TimelineEventType::RoomName, int!(75)),
+            (TimelineEventType::Reaction, int!(10)),
+        ]);
+        content.notifications.room = int!(25);
+
+        // When getting the requirements from the power levels.
+        let requirements = PowerLevelRequirements::from(RoomPowerLevels::from(content));
+
+        // Then the actions match the event.
+        assert_eq!(requirements.ban, 50);
+        assert_eq!(requirements.invite, 50);
+        assert_eq!(requirements.kick, 50);
+        assert_eq!(requirements.redact, 50);
+        assert_eq!(requirements.room_notification, 25);
+        assert_eq!(requirements.users_default, 0);
+
+        // And the custom event levels take precedence over the defaults.
+        assert_eq!(requirements.for_state(StateEventType::RoomName), 75);
+        assert_eq!(requirements.for_state(StateEventType::RoomTopic), 50);
+        assert_eq!(requirements.for_message(MessageLikeEventType::Reaction), 10);
+        assert_eq!(requirements.for_message(MessageLikeEventType::RoomMessage), 0);
+    }
+
     fn default_power_levels() -> RoomPowerLevels {
         default_power_levels_event_content().into()
     }
@@ -2,7 +2,11 @@ use std::collections::BTreeMap;
 
 use as_variant::as_variant;
 use imbl::Vector;
-use matrix_sdk_base::{sliding_sync::http, sync::SyncResponse, PreviousEventsProvider};
+use matrix_sdk_base::{
+    sliding_sync::http,
+    sync::{DecryptedEvents, SyncResponse},
+    PreviousEventsProvider,
+};
 use ruma::{
     api::{
         client::discovery::{discover_homeserver, get_supported_versions},
@@ -238,13 +242,20 @@ impl<'a> PreviousEventsProvider for SlidingSyncPreviousEventsProvider<'a> {
 pub(crate) struct SlidingSyncResponseProcessor<'a> {
     client: Client,
     to_device_events: Vec<Raw<AnyToDeviceEvent>>,
+    decrypted_events: DecryptedEvents,
     response: Option<SyncResponse>,
     rooms: &'a BTreeMap<OwnedRoomId, SlidingSyncRoom>,
 }
 
 impl<'a> SlidingSyncResponseProcessor<'a> {
     pub fn new(client: Client, rooms: &'a BTreeMap<OwnedRoomId, SlidingSyncRoom>) -> Self {
-        Self { client, to_device_events: Vec::new(), response: None, rooms }
+        Self {
+            client,
+            to_device_events: Vec::new(),
+            decrypted_events: DecryptedEvents::new(),
+            response: None,
+            rooms,
+        }
     }
 
     #[cfg(feature = "e2e-encryption")]
@@ -256,7 +267,7 @@ impl<'a> SlidingSyncResponseProcessor<'a> {
         // `handle_room_response` before this function), so panic is fine.
         assert!(self.response.is_none());
 
-        self.to_device_events =
+        (self.to_device_events, self.decrypted_events) =
             self.client.base_client().process_sliding_sync_e2ee(extensions).await?;
 
         // Some new keys might have been received, so trigger a backup if needed.
@@ -297,6 +308,7 @@ impl<'a> SlidingSyncResponseProcessor<'a> {
         let mut response = self.response.take().unwrap_or_default();
 
         response.to_device.extend(self.to_device_events);
+        response.decrypted_events.extend(self.decrypted_events);
 
         self.client.call_sync_response_handlers(&response).await?;
 
@@ -52,14 +52,31 @@ pub struct SyncResponse {
     pub to_device: Vec<Raw<AnyToDeviceEvent>>,
     /// New notifications per room.
     pub notifications: BTreeMap<OwnedRoomId, Vec<Notification>>,
+    /// The timeline events of earlier syncs that couldn't be decrypted then,
+    /// and were decrypted with the room keys received in this one.
+    pub decrypted_events: DecryptedEvents,
 }
 
 impl SyncResponse {
     pub(crate) fn new(next_batch: String, base_response: BaseSyncResponse) -> Self {
-        let BaseSyncResponse { rooms, presence, account_data, to_device, notifications } =
-            base_response;
-
-        Self { next_batch, rooms, presence, account_data, to_device, notifications }
+        let BaseSyncResponse {
+            rooms,
+            presence,
+            account_data,
+            to_device,
+            notifications,
+            decrypted_events,
+        } = base_response;
+
+        Self {
+            next_batch,
+            rooms,
+            presence,
+            account_data,
+            to_device,
+            notifications,
+            decrypted_events,
+        }
     }
 }
 
@@ -72,6 +89,7 @@ impl fmt::Debug for SyncResponse {
             .field("account_data", &DebugListOfRawEventsNoId(&self.account_data))
             .field("to_device", &DebugListOfRawEventsNoId(&self.to_device))
             .field("notifications", &self.notifications)
+            .field("decrypted_events", &self.decrypted_events)
             .finish_non_exhaustive()
     }
 }
@@ -152,7 +170,8 @@ impl Client {
         &self,
         response: &BaseSyncResponse,
     ) -> Result<()> {
-        let BaseSyncResponse { rooms, presence, account_data, to_device, notifications } = response;
+        let BaseSyncResponse { rooms, presence, account_data, to_device, notifications, .. } =
+            response;
 
         let now = Instant::now();
         self.handle_sync_events(HandlerKind::GlobalAccountData, None, account_data).await?;
@@ -61,7 +61,7 @@ pub async fn set_client_session(client: &Client) {
                 user_id: user_id!("@example:localhost").to_owned(),
                 device_id: device_id!("DEVICEID").to_owned(),
             },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         })
         .await
         .unwrap();
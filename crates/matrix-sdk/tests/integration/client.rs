@@ -4,7 +4,12 @@ use assert_matches2::{assert_let, assert_matches};
 use eyeball_im::VectorDiff;
 use futures_util::FutureExt;
 use matrix_sdk::{
-    config::SyncSettings, sync::RoomUpdate, test_utils::no_retry_test_client_with_server,
+    config::SyncSettings,
+    reqwest::header::{HeaderMap, HeaderValue},
+    sync::RoomUpdate,
+    test_utils::{
+        no_retry_test_client_with_server, set_client_session, test_client_builder_with_server,
+    },
 };
 use matrix_sdk_base::{sync::RoomUpdates, RoomState};
 use matrix_sdk_test::{
@@ -21,6 +26,7 @@ use ruma::{
             get_public_rooms,
             get_public_rooms_filtered::{self, v3::Request as PublicRoomsFilterRequest},
         },
+        room::create_room,
         uiaa,
     },
     assign, device_id,
@@ -29,7 +35,7 @@ use ruma::{
     events::{direct::DirectEventContent, AnyInitialStateEvent},
     room_id,
     serde::Raw,
-    user_id, OwnedUserId,
+    user_id, OwnedUserId, RoomVersionId,
 };
 use serde_json::{json, Value as JsonValue};
 use stream_assert::{assert_next_matches, assert_pending};
@@ -813,6 +819,74 @@ async fn test_create_dm_non_encrypted() {
     client.create_dm(user_id).await.unwrap();
 }
 
+#[async_test]
+async fn test_create_room_uses_default_room_version() {
+    let (builder, server) = test_client_builder_with_server().await;
+    let client = builder.default_room_version(RoomVersionId::V11).build().await.unwrap();
+    set_client_session(&client).await;
+
+    let room_version_is = |expected: &'static str| {
+        move |request: &Request| {
+            request.body_json::<Raw<JsonValue>>().is_ok_and(|body| {
+                body.get_field::<String>("room_version")
+                    .is_ok_and(|version| version.as_deref() == Some(expected))
+            })
+        }
+    };
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .and(room_version_is("11"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "room_id": "!sefiuhWgwghwWgh:example.com"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/createRoom"))
+        .and(room_version_is("10"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "room_id": "!wiufhWgwghwWgh:example.com"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // Without a room version in the request, the configured default is used.
+    client.create_room(create_room::v3::Request::new()).await.unwrap();
+
+    // An explicit room version takes precedence over the default.
+    client
+        .create_room(assign!(create_room::v3::Request::new(), {
+            room_version: Some(RoomVersionId::V10),
+        }))
+        .await
+        .unwrap();
+}
+
+#[async_test]
+async fn test_additional_headers_are_sent_with_requests() {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-gateway-key", HeaderValue::from_static("secret"));
+
+    let (builder, server) = test_client_builder_with_server().await;
+    let client = builder.additional_headers(headers).build().await.unwrap();
+    set_client_session(&client).await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(header("x-gateway-key", "secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::WHOAMI))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.whoami().await.unwrap();
+}
+
 #[cfg(feature = "e2e-encryption")]
 #[async_test]
 async fn test_create_dm_encrypted() {
@@ -93,7 +93,7 @@ async fn test_create() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     let (client, server) = no_retry_test_client_with_server().await;
@@ -169,7 +169,7 @@ async fn test_creation_failure() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (client, server) = no_retry_test_client_with_server().await;
     client.restore_session(session).await.unwrap();
@@ -250,7 +250,7 @@ async fn test_disabling() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (client, server) = no_retry_test_client_with_server().await;
     client.restore_session(session).await.unwrap();
@@ -337,7 +337,7 @@ async fn test_disable_if_only_enabled_remotely() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (client, server) = no_retry_test_client_with_server().await;
     client.restore_session(session).await.unwrap();
@@ -381,7 +381,7 @@ async fn test_backup_resumption() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     Mock::given(method("POST"))
@@ -463,7 +463,7 @@ async fn test_steady_state_waiting() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (client, server) = no_retry_test_client_with_server().await;
     client.restore_session(session).await.unwrap();
@@ -647,7 +647,7 @@ async fn test_incremental_upload_of_keys() -> Result<()> {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (client, server) = no_retry_test_client_with_server().await;
     client.restore_session(session).await.unwrap();
@@ -720,7 +720,7 @@ async fn test_incremental_upload_of_keys_sliding_sync() -> Result<()> {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let server = wiremock::MockServer::start().await;
     let builder = Client::builder()
@@ -827,7 +827,7 @@ async fn test_steady_state_waiting_errors() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (client, server) = no_retry_test_client_with_server().await;
     client.restore_session(session).await.unwrap();
@@ -914,7 +914,7 @@ async fn test_enable_from_secret_storage() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let encryption_settings = EncryptionSettings {
@@ -1052,7 +1052,7 @@ async fn test_enable_from_secret_storage_no_existing_backup() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let encryption_settings = EncryptionSettings {
@@ -1095,7 +1095,7 @@ async fn test_enable_from_secret_storage_mismatched_key() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let encryption_settings = EncryptionSettings {
@@ -1147,7 +1147,7 @@ async fn test_enable_from_secret_storage_manual_download() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let client =
@@ -1179,7 +1179,7 @@ async fn test_enable_from_secret_storage_and_manual_download() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let encryption_settings = EncryptionSettings {
@@ -1301,7 +1301,7 @@ async fn test_enable_from_secret_storage_and_download_after_utd() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let encryption_settings = EncryptionSettings {
@@ -1416,7 +1416,7 @@ async fn test_enable_from_secret_storage_and_download_after_utd_from_old_message
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
     let (builder, server) = test_client_builder_with_server().await;
     let encryption_settings = EncryptionSettings {
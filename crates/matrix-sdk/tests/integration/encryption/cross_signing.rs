@@ -33,7 +33,7 @@ async fn test_reset_legacy_auth() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     let (client, server) = no_retry_test_client_with_server().await;
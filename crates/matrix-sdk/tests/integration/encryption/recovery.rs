@@ -43,7 +43,7 @@ use crate::{encryption::mock_secret_store_with_backup_key, logged_in_client_with
 async fn test_client(user_id: &UserId) -> (Client, wiremock::MockServer) {
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     let (builder, server) = test_client_builder_with_server().await;
@@ -168,7 +168,7 @@ async fn test_recovery_status_secret_storage_set_up() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     let (client, server) = no_retry_test_client_with_server().await;
@@ -189,7 +189,7 @@ async fn test_recovery_status_secret_storage_not_set_up() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     let (client, server) = no_retry_test_client_with_server().await;
@@ -709,7 +709,7 @@ async fn test_recover_and_reset() {
 
     let session = MatrixSession {
         meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     };
 
     let (client, server) = no_retry_test_client_with_server().await;
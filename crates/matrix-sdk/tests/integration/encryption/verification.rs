@@ -331,7 +331,7 @@ async fn test_own_verification() {
     alice
         .restore_session(MatrixSession {
             meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         })
         .await
         .unwrap();
@@ -415,7 +415,7 @@ async fn test_reset_cross_signing_resets_verification() {
     alice
         .restore_session(MatrixSession {
             meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         })
         .await
         .unwrap();
@@ -468,7 +468,7 @@ async fn test_reset_cross_signing_resets_verification() {
     alice2
         .restore_session(MatrixSession {
             meta: SessionMeta { user_id: user_id.clone(), device_id: device_id.clone() },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         })
         .await
         .unwrap();
@@ -515,7 +515,7 @@ async fn test_unchecked_mutual_verification() {
                 user_id: alice_user_id.clone(),
                 device_id: alice_device_id.clone(),
             },
-            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+            tokens: MatrixSessionTokens::new("1234".to_owned(), None),
         })
         .await
         .unwrap();
@@ -532,7 +532,7 @@ async fn test_unchecked_mutual_verification() {
     let bob_device_id = owned_device_id!("B0B0B0B0B");
     bob.restore_session(MatrixSession {
         meta: SessionMeta { user_id: bob_user_id.clone(), device_id: bob_device_id.clone() },
-        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), None),
     })
     .await
     .unwrap();
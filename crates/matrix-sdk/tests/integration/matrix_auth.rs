@@ -2,12 +2,15 @@ use std::{collections::BTreeMap, sync::Mutex};
 
 use assert_matches::assert_matches;
 use matrix_sdk::{
-    config::RequestConfig,
+    config::{RequestConfig, SyncSettings},
     matrix_auth::{MatrixSession, MatrixSessionTokens},
     test_utils::{logged_in_client_with_server, no_retry_test_client_with_server},
-    AuthApi, AuthSession, Client, RumaApiError,
+    AuthApi, AuthSession, Client, LoopCtrl, RumaApiError,
+};
+use matrix_sdk_base::{
+    store::{StateStoreDataKey, StateStoreDataValue},
+    SessionMeta,
 };
-use matrix_sdk_base::SessionMeta;
 use matrix_sdk_test::{async_test, test_json};
 use ruma::{
     api::{
@@ -32,6 +35,8 @@ use wiremock::{
     Mock, MockServer, Request, ResponseTemplate,
 };
 
+use crate::mock_sync;
+
 #[async_test]
 async fn test_restore_session() {
     let (client, _) = logged_in_client_with_server().await;
@@ -43,6 +48,36 @@ async fn test_restore_session() {
     assert_matches!(client.session(), Some(AuthSession::Matrix(_)));
 }
 
+#[async_test]
+async fn test_restore_credentials() {
+    let (client, _) = logged_in_client_with_server().await;
+    client
+        .store()
+        .set_kv_data(
+            StateStoreDataKey::SyncToken,
+            StateStoreDataValue::SyncToken("s42_t1".to_owned()),
+        )
+        .await
+        .unwrap();
+
+    let credentials = client.matrix_auth().export_credentials(&[]).await.unwrap().unwrap();
+    assert_eq!(Some(&credentials.session), client.matrix_auth().session().as_ref());
+    assert_eq!(credentials.sync_token.as_deref(), Some("s42_t1"));
+
+    // Restore the session in a fresh client.
+    let (restored, server) = no_retry_test_client_with_server().await;
+    assert!(restored.matrix_auth().export_credentials(&[]).await.unwrap().is_none());
+    restored.matrix_auth().restore_credentials(credentials).await.unwrap();
+    assert_eq!(restored.matrix_auth().session(), client.matrix_auth().session());
+
+    // The next sync continues from the exported sync token.
+    mock_sync(&server, &*test_json::SYNC, Some("s42_t1".to_owned())).await;
+    restored
+        .sync_with_callback(SyncSettings::default(), |_| async { LoopCtrl::Break })
+        .await
+        .unwrap();
+}
+
 #[async_test]
 async fn test_login() {
     let (client, server) = no_retry_test_client_with_server().await;
@@ -335,7 +370,7 @@ fn test_serialize_session() {
             user_id: user_id!("@user:localhost").to_owned(),
             device_id: device_id!("EFGHIJ").to_owned(),
         },
-        tokens: MatrixSessionTokens { access_token: "abcd".to_owned(), refresh_token: None },
+        tokens: MatrixSessionTokens::new("abcd".to_owned(), None),
     };
     assert_eq!(
         to_json_value(session.clone()).unwrap(),
@@ -38,10 +38,7 @@ fn session() -> MatrixSession {
             user_id: user_id!("@example:localhost").to_owned(),
             device_id: device_id!("DEVICEID").to_owned(),
         },
-        tokens: MatrixSessionTokens {
-            access_token: "1234".to_owned(),
-            refresh_token: Some("abcd".to_owned()),
-        },
+        tokens: MatrixSessionTokens::new("1234".to_owned(), Some("abcd".to_owned())),
     }
 }
 
@@ -115,10 +115,7 @@ async fn restore_client(cli: &Cli) -> Result<Client> {
     // TODO: We should be able to get the device id from `/whoami`.
     let session = AuthSession::Matrix(MatrixSession {
         meta: SessionMeta { user_id: cli.user_id.to_owned(), device_id: cli.device_id.to_owned() },
-        tokens: MatrixSessionTokens {
-            access_token: cli.access_token.to_owned(),
-            refresh_token: None,
-        },
+        tokens: MatrixSessionTokens::new(cli.access_token.to_owned(), None),
     });
 
     client.restore_session(session).await?;